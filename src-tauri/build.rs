@@ -1,3 +1,15 @@
 fn main() {
-    tauri_build::build()
+    // Listing the app's commands gives each one an `allow-*` permission, so the
+    // capabilities can grant them one by one
+    tauri_build::try_build(tauri_build::Attributes::new().app_manifest(
+        tauri_build::AppManifest::new().commands(&[
+            "list_files",
+            "upload_local_file",
+            "download_to_disk",
+            "enqueue_upload",
+            "delete_file",
+            "take_deep_links",
+        ]),
+    ))
+    .expect("failed to run tauri-build");
 }
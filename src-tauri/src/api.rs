@@ -1,25 +1,60 @@
 /// api.rs — All Axum route handlers.
 use axum::{
     body::Body,
-    extract::{Path, Query, State},
-    http::{header, StatusCode},
-    response::{IntoResponse, Response},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, Method, StatusCode, Version},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
     Json,
 };
 use bytes::Bytes;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::{collections::HashMap, io::Cursor};
-use tokio::sync::oneshot;
-use tracing::info;
+use std::{collections::{BTreeMap, HashMap, HashSet}, io::Cursor, net::{IpAddr, SocketAddr}};
+use tokio::sync::{broadcast, oneshot};
+use tracing::{info, warn};
 
 use crate::{
+    audit,
+    cache,
+    compression::{self, DecodeError},
+    config::{self, Config},
+    dedup,
+    deep_link,
     discord_bot,
+    disposition,
     download,
+    extract,
+    folder_rules,
+    folder_tree,
+    health,
+    http_server,
+    keyring::{self, KeyError, UnlockError},
+    lifecycle,
+    manifest,
+    media_pool,
+    offline,
+    opds,
+    optimize,
+    permissions,
+    phash,
+    preview_tokens::TokenError,
+    progress,
+    queue,
+    settings::{self, parse_env, SaveError},
+    shares::{self, InboxDenied, ShareDenied},
+    simulate,
     state::AppState,
-    storage::{current_datetime_display, current_timestamp_ms, FileRecord, Folder},
-    upload::{create_session, delete_session_record, get_session, mark_chunk_received,
-             update_session, SenderArgs, SenderEntry},
+    storage::{current_datetime_display, current_timestamp_ms, FileRecord, Folder, FolderRule, Inbox,
+              LifecycleRule, PartInfo, Share, TrashEntry, UploadSession, UsageSummary},
+    telegram,
+    throughput,
+    thumbnails,
+    trash,
+    upload::{create_session, delete_session_record, get_diagnostics, get_session, mark_chunk_received,
+             new_diagnostics, touch_session, transition_session, transition_session_with, update_session, ChannelSlot, ChunkWindow, SenderArgs,
+             FailureReport, SenderEntry, SenderResult, TransitionError, UploadFailed, UploadTarget, STATUS_CANCELLED, STATUS_DONE,
+             STATUS_FAILED, STATUS_FINALIZING, STATUS_UPLOADING},
+    verify,
 };
 
 // ── Error helper ───────────────────────────────────────────────────────────────
@@ -28,338 +63,2542 @@ fn err(status: StatusCode, msg: impl Into<String>) -> Response {
     (status, Json(json!({ "detail": msg.into() }))).into_response()
 }
 
+/// Like `err`, plus a machine-readable `code` clients can branch on.
+fn err_code(status: StatusCode, code: &str, msg: impl Into<String>) -> Response {
+    (status, Json(json!({ "detail": msg.into(), "code": code }))).into_response()
+}
+
+fn session_conflict(actual: &str) -> Response {
+    ApiError::session_conflict(actual).into_response()
+}
+
+/// Error from an upload step shared by the HTTP handlers and background workers
+/// (upload queue); renders exactly like `err` / `err_code`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code:   Option<String>,
+    pub detail: String,
+    pub report: Option<Value>,   // structured detail sent next to `detail` (failed uploads)
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self { status, code: None, detail: detail.into(), report: None }
+    }
+
+    fn session_conflict(actual: &str) -> Self {
+        Self {
+            status: StatusCode::CONFLICT,
+            code:   Some(format!("session_{actual}")),
+            detail: format!("Session đang ở trạng thái: {actual}"),
+            report: None,
+        }
+    }
+}
+
+/// A file whose data key is wrapped by the keyring can't be read while it is locked.
+impl From<KeyError> for ApiError {
+    fn from(e: KeyError) -> Self {
+        match e {
+            KeyError::Locked => Self {
+                status: StatusCode::LOCKED, code: Some("keyring_locked".to_string()),
+                detail: "File được mã hoá bằng keyring — hãy mở khoá (POST /api/keys/unlock)".to_string(), report: None,
+            },
+            KeyError::Invalid(e) => Self::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Không giải được khoá của file: {e}")),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str(&self.detail) }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let Some(report) = self.report {
+            return (self.status, Json(json!({ "detail": self.detail, "code": self.code, "report": report }))).into_response();
+        }
+        match self.code {
+            Some(code) => err_code(self.status, &code, self.detail),
+            None       => err(self.status, self.detail),
+        }
+    }
+}
+
+fn audit(st: &AppState, file_id: i64, action: &str, detail: Value) {
+    audit::record(&*st.store, &st.cfg.audit_file, Some(file_id), action, detail);
+}
+
 // ── Health ─────────────────────────────────────────────────────────────────────
 
-pub async fn health() -> impl IntoResponse {
-    Json(json!({ "ok": true }))
+/// Liveness plus readiness: 503 until this profile's stores are loaded into memory.
+pub async fn health(State(st): State<AppState>) -> Response {
+    match st.store.warmup() {
+        Some(w) => Json(json!({ "ok": true, "ready": true, "warmup": w, "media": media_pool::pool().stats() })).into_response(),
+        None    => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "ok": true, "ready": false }))).into_response(),
+    }
+}
+
+/// Scored drive health (degraded files, quotas, stale sessions, orphans, cache, platforms)
+/// for the dashboard widget; each check carries drill-down links.
+pub async fn health_summary(State(st): State<AppState>) -> impl IntoResponse {
+    Json(health::summary(&st).await)
+}
+
+/// What this server accepts, so clients can pick options without trial and error.
+pub async fn capabilities(State(st): State<AppState>) -> impl IntoResponse {
+    Json(json!({
+        "chunk_encodings":    compression::CHUNK_ENCODINGS,
+        "client_chunk_bytes": st.cfg.client_chunk_bytes,
+        "max_chunk_bytes":    st.cfg.chunk_body_limit(),
+        "parallel_chunks":    st.cfg.parallel_chunks,
+        "telegram":           st.tg_enabled,
+        "upload_platforms":   UploadTarget::ALL.iter().filter(|t| st.tg_enabled || !t.needs_telegram())
+                                  .map(|t| t.as_str()).collect::<Vec<_>>(),
+        "default_platform":   UploadTarget::default_for(st.tg_enabled).as_str(),
+        "preview_tokens":     true,
+    }))
+}
+
+// ── Folders ────────────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+pub struct FoldersQuery { include: Option<String> }
+
+/// `?include=stats` adds file count and sizes to each folder (plus the root) in one pass.
+pub async fn get_folders(State(st): State<AppState>, Query(q): Query<FoldersQuery>) -> impl IntoResponse {
+    let mut folders = st.store.load_folders(&st.cfg.folders_file);
+    // Folders saved before nesting have no path yet
+    folder_tree::refresh_paths(&mut folders);
+    let with_stats = q.include.as_deref().is_some_and(|i| i.split(',').any(|p| p.trim() == "stats"));
+    if !with_stats {
+        return Json(json!({ "folders": folders }));
+    }
+    let usage = st.store.folder_usage(&st.cfg.history_file);
+    let stats = |key: &str| {
+        let u = usage.get(key).cloned().unwrap_or_default();
+        json!({
+            "file_count":   u.files,
+            "total_mb":     (u.total_mb * 100.0).round() / 100.0,
+            "stored_bytes": u.stored_bytes,
+        })
+    };
+    let folders: Vec<Value> = folders.iter().map(|f| {
+        let mut v = json!(f);
+        v["stats"] = stats(&f.id.to_string());
+        v
+    }).collect();
+    Json(json!({ "folders": folders, "root_stats": stats("") }))
+}
+
+/// Body: `name`, optional `parent_id` to create it as a subfolder.
+pub async fn create_folder(State(st): State<AppState>, Json(body): Json<Value>) -> Response {
+    let name = body["name"].as_str().unwrap_or("").trim().to_string();
+    if name.is_empty() { return err(StatusCode::BAD_REQUEST, "Tên folder không được trống"); }
+    if name.contains('/') { return err(StatusCode::BAD_REQUEST, "Tên folder không được chứa /"); }
+    let parent = match parent_arg(&body["parent_id"]) {
+        Ok(p)  => p,
+        Err(e) => return e.into_response(),
+    };
+    match new_folder(&st, name, parent).await {
+        Ok(folder) => Json(json!({ "success": true, "folder": folder })).into_response(),
+        Err(e)     => e.into_response(),
+    }
+}
+
+/// `parent_id` as a number or a string; null or "" is the top level.
+fn parent_arg(v: &Value) -> Result<Option<i64>, ApiError> {
+    match v {
+        Value::Null => Ok(None),
+        Value::String(s) if s.trim().is_empty() => Ok(None),
+        v => v.as_i64().or_else(|| v.as_str().and_then(|s| s.trim().parse().ok())).map(Some)
+            .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "parent_id không hợp lệ")),
+    }
+}
+
+/// Create the folder's Discord category and record, newest first.
+async fn new_folder(st: &AppState, name: String, parent: Option<i64>) -> Result<Folder, ApiError> {
+    let mut folder = Folder {
+        id:                  current_timestamp_ms(),
+        name,
+        discord_category_id: 0,
+        created_at:          current_datetime_display(),
+        overflow_category_ids: vec![],
+        lifecycle:           vec![],
+        tg_topic_id:         None,
+        parent_id:           parent,
+        path:                String::new(),
+    };
+    if let Some(p) = parent {
+        let folders = st.store.load_folders(&st.cfg.folders_file);
+        if !folders.iter().any(|f| f.id == p) {
+            return Err(ApiError::new(StatusCode::NOT_FOUND, "Folder cha không tồn tại"));
+        }
+        if folder_tree::depth(&folders, p) + 1 > folder_tree::MAX_DEPTH {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, format!("Folder lồng quá {} cấp", folder_tree::MAX_DEPTH)));
+        }
+        let mut with_new = vec![folder.clone()];
+        with_new.extend(folders);
+        folder_tree::refresh_paths(&mut with_new);
+        folder.path = with_new.swap_remove(0).path;
+    } else {
+        folder.path = folder.name.clone();
+    }
+    let category = if st.cfg.simulate {
+        Ok(simulate::channel(&folder.name).0)
+    } else {
+        // A subfolder always gets its own category: matching by name could hand it another folder's
+        match folder.parent_id {
+            Some(_) => discord_bot::create_category(&st.http, st.guild_id, &folder.category_name()).await,
+            None    => discord_bot::get_or_create_category(&st.http, st.guild_id, &folder.category_name()).await,
+        }.map(|c| c.id)
+    };
+    folder.discord_category_id = category.map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .get() as i64;
+    st.store.update_json(&st.cfg.folders_file, |folders: &mut Vec<Folder>| {
+        folders.insert(0, folder.clone());
+        folder_tree::refresh_paths(folders);
+    }).map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // Mirror the folder as a Telegram topic right away; uploads retry if this fails
+    Ok(Folder { tg_topic_id: folder_topic(st, folder.id).await, ..folder })
+}
+
+/// Body: `parent_id` (null or "" = top level). Files stay where they are; the
+/// categories of the folder and of those below it are renamed after their new path.
+pub async fn move_folder(State(st): State<AppState>, Path(folder_id): Path<i64>, Json(body): Json<Value>) -> Response {
+    let parent = match parent_arg(&body["parent_id"]) {
+        Ok(p)  => p,
+        Err(e) => return e.into_response(),
+    };
+    let moved = st.store.locked(&st.cfg.folders_file, || -> Result<Vec<Folder>, ApiError> {
+        let mut folders = st.store.load_folders(&st.cfg.folders_file);
+        let Some(at) = folders.iter().position(|f| f.id == folder_id) else {
+            return Err(ApiError::new(StatusCode::NOT_FOUND, "Folder không tồn tại"));
+        };
+        let subtree = folder_tree::subtree(&folders, folder_id);
+        if let Some(p) = parent {
+            if subtree.contains(&p) {
+                return Err(ApiError::new(StatusCode::BAD_REQUEST, "Không thể chuyển folder vào chính nó hoặc folder con của nó"));
+            }
+            if !folders.iter().any(|f| f.id == p) {
+                return Err(ApiError::new(StatusCode::NOT_FOUND, "Folder cha không tồn tại"));
+            }
+        }
+        folders[at].parent_id = parent;
+        if subtree.iter().any(|id| folder_tree::depth(&folders, *id) > folder_tree::MAX_DEPTH) {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, format!("Folder lồng quá {} cấp", folder_tree::MAX_DEPTH)));
+        }
+        folder_tree::refresh_paths(&mut folders);
+        st.store.save_folders(&st.cfg.folders_file, &folders)
+            .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        Ok(folders.into_iter().filter(|f| subtree.contains(&f.id)).collect())
+    });
+    let moved = match moved {
+        Ok(m)  => m,
+        Err(e) => return e.into_response(),
+    };
+    let renamed = rename_categories(&st, &moved).await;
+    let folder = moved.into_iter().find(|f| f.id == folder_id);
+    info!("🗂️ Folder {folder_id} moved under {parent:?}, {renamed} category(ies) renamed");
+    Json(json!({ "success": true, "folder": folder, "categories_renamed": renamed })).into_response()
+}
+
+/// Spacing between category renames: moving a big subtree renames every category
+/// in it, and Discord rate-limits channel edits per guild.
+const CATEGORY_RENAME_INTERVAL_MS: u64 = 1_000;
+
+/// Rename the categories of `folders` after their paths, now that these changed,
+/// one at a time. Returns how many primary categories were renamed.
+async fn rename_categories(st: &AppState, folders: &[Folder]) -> usize {
+    if st.cfg.simulate { return 0; }
+    let gate = crate::upload::SendGate::new(1, CATEGORY_RENAME_INTERVAL_MS);
+    let mut renamed = 0;
+    for f in folders {
+        let base = f.category_name();
+        // Overflow categories follow the primary one's name
+        let categories = std::iter::once((f.discord_category_id, base.clone()))
+            .chain(f.overflow_category_ids.iter().enumerate().map(|(i, id)| (*id, discord_bot::overflow_name(&base, i + 2))));
+        for (n, (id, name)) in categories.enumerate() {
+            let _permit = gate.acquire().await;
+            match discord_bot::rename_category(&st.http, id, &name).await {
+                Ok(true) if n == 0 => renamed += 1,
+                Ok(_)  => {}
+                Err(e) => warn!("⚠️ Category {name} of folder {} not renamed: {e}", f.path),
+            }
+        }
+    }
+    renamed
+}
+
+/// Uploads of one dropped directory arrive in parallel; this keeps them from
+/// each creating the same folder.
+static PATH_FOLDERS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// The folder standing for directory `dir` (from `relative_path`) under `base`,
+/// one subfolder per directory, created on first use: photos/2024/a.jpg dropped
+/// into Work lands in Work/photos/2024.
+async fn folder_for_path(st: &AppState, base: &str, dir: &[String]) -> Result<String, ApiError> {
+    let _guard = PATH_FOLDERS.lock().await;
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    let mut parent = folders.iter().find(|f| !base.is_empty() && f.id.to_string() == base).map(|f| f.id);
+    for name in dir {
+        let folders = st.store.load_folders(&st.cfg.folders_file);
+        let id = match folder_tree::child(&folders, parent, name) {
+            Some(f) => f.id,
+            None => match adopt_flat_folder(st, parent, name).await {
+                Some(f) => f.id,
+                None => {
+                    info!("🗂️ Creating folder {name} for a dropped directory");
+                    new_folder(st, name.clone(), parent).await?.id
+                }
+            },
+        };
+        parent = Some(id);
+    }
+    Ok(parent.map(|id| id.to_string()).unwrap_or_default())
+}
+
+/// Before folders nested, a dropped directory became one top-level folder named
+/// after its whole path ("Work/photos/2024"). Move such a folder to where `name`
+/// belongs under `parent`, so its files join the tree instead of a new twin.
+async fn adopt_flat_folder(st: &AppState, parent: Option<i64>, name: &str) -> Option<Folder> {
+    let adopted = st.store.locked(&st.cfg.folders_file, || {
+        let mut folders = st.store.load_folders(&st.cfg.folders_file);
+        let parent_path = parent.and_then(|p| folders.iter().find(|f| f.id == p)).map(|f| f.path.clone());
+        let flat = match parent_path {
+            Some(p) => format!("{p}/{name}"),
+            None    => return None,
+        };
+        let f = folders.iter_mut().find(|f| f.parent_id.is_none() && f.name == flat)?;
+        f.parent_id = parent;
+        f.name = name.to_string();
+        let id = f.id;
+        folder_tree::refresh_paths(&mut folders);
+        st.store.save_folders(&st.cfg.folders_file, &folders).ok()?;
+        let subtree = folder_tree::subtree(&folders, id);
+        Some(folders.into_iter().filter(|f| subtree.contains(&f.id)).collect::<Vec<_>>())
+    })?;
+    info!("🗂️ Folder {name} moved into the tree from its flat path");
+    rename_categories(st, &adopted).await;
+    adopted.into_iter().find(|f| f.parent_id == parent)
+}
+
+/// Longest `relative_path` accepted, and the most directories in it.
+const MAX_RELATIVE_PATH: usize = 1024;
+const MAX_PATH_DEPTH:    usize = 16;
+
+/// init_upload's `relative_path` ("photos/2024/a.jpg", either slash) split into
+/// components, the file name last. "." and empty components are dropped.
+fn relative_path(raw: &str) -> Result<Vec<String>, ApiError> {
+    let bad = |detail: &str| ApiError {
+        status: StatusCode::BAD_REQUEST, code: Some("bad_relative_path".to_string()), detail: detail.to_string(), report: None,
+    };
+    if raw.len() > MAX_RELATIVE_PATH { return Err(bad("relative_path quá dài")); }
+    let parts: Vec<String> = raw.split(['/', '\\'])
+        .map(str::trim)
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(str::to_string)
+        .collect();
+    if parts.iter().any(|c| c == "..") { return Err(bad("relative_path không được chứa ..")); }
+    if parts.len() > MAX_PATH_DEPTH + 1 { return Err(bad("relative_path lồng quá nhiều thư mục")); }
+    Ok(parts)
+}
+
+/// Reconcile a folder with its Discord categories after manual edits there
+/// (see `discord_bot::resync_folder`).
+pub async fn resync_folder(State(st): State<AppState>, Path(folder_id): Path<i64>) -> Response {
+    if st.cfg.simulate {
+        return err(StatusCode::CONFLICT, "Chế độ mô phỏng không có category Discord để đồng bộ");
+    }
+    match discord_bot::resync_folder(&st.http, st.guild_id, &*st.store, &st.cfg.folders_file, &st.cfg.history_file, folder_id).await {
+        Ok(Some(report)) => {
+            if report.changed() { info!("🔄 Folder {folder_id} resynced: {}", report.summary()); }
+            Json(json!({ "success": true, "resync": report })).into_response()
+        }
+        Ok(None) => err(StatusCode::NOT_FOUND, "Folder không tồn tại"),
+        Err(e)   => err(StatusCode::BAD_GATEWAY, e.to_string()),
+    }
+}
+
+// ── Dry runs ───────────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+pub struct DryRunQuery { dry_run: Option<bool> }
+
+/// What a destructive call would remove, returned instead of acting when `?dry_run=true`.
+#[derive(Serialize, Default)]
+struct RemovalPlan {
+    records:  Vec<Value>,
+    channels: Vec<Value>,
+    messages: usize,
+    bytes:    u64,      // bytes freed on Discord/Telegram
+    shared:   usize,    // parts left in place because other files reference them
+}
+
+impl RemovalPlan {
+    fn respond(self) -> Response {
+        let mut body = json!(self);
+        body["dry_run"] = json!(true);
+        Json(body).into_response()
+    }
+}
+
+fn plan_file_removal(st: &AppState, rec: &FileRecord, delete_channel: bool, plan: &mut RemovalPlan) {
+    plan.records.push(json!({ "type": "file", "id": rec.id, "filename": rec.filename }));
+    let others: Vec<FileRecord> = dedup::live_records(st).into_iter().filter(|r| r.id != rec.id).collect();
+    let release = dedup::release(rec, &others);
+    plan.shared += release.shared;
+    if delete_channel && !rec.channel_id.is_empty() && !release.channel_used {
+        plan.channels.push(json!({ "id": rec.channel_id, "name": rec.channel_name, "kind": "text" }));
+    }
+    let tg_deletable = st.tg_enabled && current_timestamp_ms() - rec.id < telegram::DELETE_WINDOW_MS;
+    for p in &release.parts {
+        let removed = if p.platform == "telegram" { tg_deletable } else { delete_channel };
+        if removed {
+            plan.messages += 1;
+            plan.bytes += p.stored_bytes;
+        }
+    }
+    // Legacy records have no per-part sizes; count the whole file once its channel goes
+    if rec.stored_bytes == 0 && delete_channel && !rec.imported { plan.bytes += rec.footprint_bytes(); }
+}
+
+pub async fn delete_folder(
+    State(st): State<AppState>,
+    Path(folder_id): Path<i64>,
+    Query(q): Query<DryRunQuery>,
+) -> Response {
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    if q.dry_run.unwrap_or(false) {
+        let mut plan = RemovalPlan::default();
+        if let Some(f) = folders.iter().find(|f| f.id == folder_id) {
+            plan.records.push(json!({ "type": "folder", "id": f.id, "name": f.name }));
+            // Simulated categories exist nowhere to delete
+            if st.cfg.simulate { return plan.respond(); }
+            // Categories are only deleted when empty; files in the folder are kept
+            let channels = match st.guild_id.channels(&st.http).await {
+                Ok(c)  => c,
+                Err(e) => return err(StatusCode::BAD_GATEWAY, format!("Không lấy được danh sách kênh: {e}")),
+            };
+            for cat in f.category_ids() {
+                let cat = serenity::model::id::ChannelId::new(cat as u64);
+                if !channels.values().any(|c| c.parent_id == Some(cat)) {
+                    plan.channels.push(json!({ "id": cat.get().to_string(), "kind": "category" }));
+                }
+            }
+        }
+        return plan.respond();
+    }
+    if let Some(f) = folders.iter().find(|f| f.id == folder_id).filter(|_| !st.cfg.simulate) {
+        for cat_id in f.category_ids() {
+            let _ = discord_bot::delete_category(&st.http, st.guild_id, cat_id as u64).await;
+        }
+    }
+    // Subfolders move up a level rather than going with it
+    let lifted = st.store.update_json(&st.cfg.folders_file, |folders: &mut Vec<Folder>| {
+        let parent = folders.iter().find(|f| f.id == folder_id).and_then(|f| f.parent_id);
+        folders.retain(|f| f.id != folder_id);
+        let mut children = vec![];
+        for f in folders.iter_mut().filter(|f| f.parent_id == Some(folder_id)) {
+            f.parent_id = parent;
+            children.push(f.id);
+        }
+        let lifted: HashSet<i64> = children.iter().flat_map(|id| folder_tree::subtree(folders, *id)).collect();
+        folder_tree::refresh_paths(folders);
+        folders.iter().filter(|f| lifted.contains(&f.id)).cloned().collect::<Vec<_>>()
+    });
+    let lifted = match lifted {
+        Ok(l)  => l,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    rename_categories(&st, &lifted).await;
+    let _ = st.store.update_json(&st.cfg.inboxes_file, |inboxes: &mut Vec<Inbox>| {
+        inboxes.retain(|i| i.folder_id != folder_id.to_string());
+    });
+    Json(json!({ "success": true })).into_response()
+}
+
+// ── Auto-foldering rules ───────────────────────────────────────────────────────
+
+pub async fn list_rules(State(st): State<AppState>) -> Response {
+    Json(json!({ "rules": st.store.load_folder_rules(&st.cfg.rules_file) })).into_response()
+}
+
+/// Body: `pattern` (glob), `folder_id` or `folder` (name), optional `position` (default: last).
+pub async fn create_rule(State(st): State<AppState>, Json(body): Json<Value>) -> Response {
+    let pattern = body["pattern"].as_str().unwrap_or("").trim().to_string();
+    if pattern.is_empty() || pattern.len() > folder_rules::MAX_PATTERN_LEN {
+        return err(StatusCode::BAD_REQUEST, format!("pattern phải có 1–{} ký tự", folder_rules::MAX_PATTERN_LEN));
+    }
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    let folder = match (&body["folder_id"], body["folder"].as_str()) {
+        (Value::Null, Some(name)) => folders.iter().find(|f| f.name.eq_ignore_ascii_case(name.trim()) || f.path.eq_ignore_ascii_case(name.trim())),
+        (Value::Null, None)       => return err(StatusCode::BAD_REQUEST, "Thiếu folder_id hoặc folder"),
+        (v, _) => {
+            let id = v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string());
+            folders.iter().find(|f| f.id.to_string() == id)
+        }
+    };
+    let Some(folder) = folder else {
+        return err(StatusCode::NOT_FOUND, "Folder không tồn tại");
+    };
+    let rule = FolderRule {
+        id:         uuid::Uuid::new_v4().simple().to_string()[..12].to_string(),
+        pattern,
+        folder_id:  folder.id.to_string(),
+        created_at: current_datetime_display(),
+    };
+    let position = body["position"].as_u64();
+    let saved = st.store.update_json(&st.cfg.rules_file, |rules: &mut Vec<FolderRule>| {
+        let at = position.map(|p| (p as usize).min(rules.len())).unwrap_or(rules.len());
+        rules.insert(at, rule.clone());
+    });
+    if let Err(e) = saved {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    info!("🗂️ Rule added: {} → {}", rule.pattern, folder.name);
+    Json(json!({ "success": true, "rule": rule, "folder_name": folder.name })).into_response()
+}
+
+pub async fn delete_rule(State(st): State<AppState>, Path(rule_id): Path<String>) -> Response {
+    let removed = st.store.update_json(&st.cfg.rules_file, |rules: &mut Vec<FolderRule>| {
+        let before = rules.len();
+        rules.retain(|r| r.id != rule_id);
+        rules.len() < before
+    });
+    match removed {
+        Ok(true)  => Json(json!({ "success": true })).into_response(),
+        Ok(false) => err(StatusCode::NOT_FOUND, "Rule không tồn tại"),
+        Err(e)    => err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+// ── Lifecycle ──────────────────────────────────────────────────────────────────
+
+/// Rules in effect and what they would do right now (dry run).
+pub async fn get_lifecycle(State(st): State<AppState>) -> Response {
+    let folder_rules: Vec<Value> = st.store.load_folders(&st.cfg.folders_file).into_iter()
+        .filter(|f| !f.lifecycle.is_empty())
+        .map(|f| json!({ "folder_id": f.id, "name": f.name, "rules": f.lifecycle }))
+        .collect();
+    let plan = lifecycle::plan(&st);
+    Json(json!({
+        "enforce":          st.cfg.lifecycle_enforce,
+        "interval_minutes": st.cfg.lifecycle_interval_s / 60,
+        "rules":            st.cfg.lifecycle_rules,
+        "folder_rules":     folder_rules,
+        "bytes":            plan.iter().map(|p| p.bytes).sum::<u64>(),
+        "plan":             plan,
+    })).into_response()
+}
+
+/// Apply the rules now, regardless of `lifecycle.enforce`. `?dry_run=true` returns the plan.
+pub async fn run_lifecycle(State(st): State<AppState>, Query(q): Query<DryRunQuery>) -> Response {
+    let Ok(_running) = st.lifecycle_lock.try_lock() else {
+        return err_code(StatusCode::CONFLICT, "lifecycle_running", "Lifecycle đang chạy");
+    };
+    let plan = lifecycle::plan(&st);
+    if q.dry_run.unwrap_or(false) {
+        return Json(json!({ "dry_run": true, "plan": plan })).into_response();
+    }
+    let report = lifecycle::enforce(&st, plan).await;
+    Json(json!({ "success": report.failed.is_empty(), "report": report })).into_response()
+}
+
+/// Replace a folder's own retention rules. Body: `{ "rules": [...] }`.
+pub async fn set_folder_lifecycle(
+    State(st): State<AppState>,
+    Path(folder_id): Path<i64>,
+    Json(body): Json<Value>,
+) -> Response {
+    let rules: Vec<LifecycleRule> = match serde_json::from_value(body["rules"].clone()) {
+        Ok(r)  => r,
+        Err(e) => return err(StatusCode::BAD_REQUEST, format!("rules không hợp lệ: {e}")),
+    };
+    if let Some(e) = rules.iter().find_map(|r| lifecycle::validate(r).err()) {
+        return err(StatusCode::BAD_REQUEST, e);
+    }
+    let updated = st.store.update_json(&st.cfg.folders_file, |folders: &mut Vec<Folder>| {
+        let folder = folders.iter_mut().find(|f| f.id == folder_id)?;
+        folder.lifecycle = rules;
+        Some(folder.clone())
+    });
+    match updated {
+        Ok(Some(folder)) => Json(json!({ "success": true, "folder": folder })).into_response(),
+        Ok(None)         => err(StatusCode::NOT_FOUND, "Folder không tồn tại"),
+        Err(e)           => err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+// ── Files ──────────────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+pub struct FolderQuery { folder_id: Option<String>, meta: Option<String> }
+
+#[derive(Deserialize)]
+pub struct DeleteFileQuery { delete_channel: Option<bool>, dry_run: Option<bool>, wait: Option<bool>, permanent: Option<bool> }
+
+pub async fn get_files(State(st): State<AppState>, Query(q): Query<FolderQuery>) -> impl IntoResponse {
+    let mut files = files_in(&st, q.folder_id.as_deref().unwrap_or(""));
+    if let Some(meta) = q.meta.as_deref() { files.retain(|f| meta_matches(f, meta)); }
+    Json(json!({ "files": files }))
+}
+
+/// `?meta=key=value` matches records whose client metadata has exactly that value
+/// (key case-insensitive); `?meta=key` matches any record that has the key.
+fn meta_matches(f: &FileRecord, filter: &str) -> bool {
+    let (key, want) = match filter.split_once('=') {
+        Some((k, v)) => (k.trim(), Some(v)),
+        None         => (filter.trim(), None),
+    };
+    f.metadata.iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .is_some_and(|(_, v)| want.is_none_or(|w| v == w))
+}
+
+/// Distinct client metadata values with their file counts, e.g. every device
+/// files came from, for the filter menu.
+pub async fn metadata_values(State(st): State<AppState>) -> impl IntoResponse {
+    let mut keys: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for f in st.store.load_history(&st.cfg.history_file) {
+        for (k, v) in f.metadata {
+            *keys.entry(k).or_default().entry(v).or_default() += 1;
+        }
+    }
+    Json(json!({ "keys": keys }))
+}
+
+/// Records directly in `folder_id` (`""` = root).
+pub fn files_in(st: &AppState, folder_id: &str) -> Vec<FileRecord> {
+    let files = st.store.load_history(&st.cfg.history_file);
+    if folder_id.is_empty() {
+        files.into_iter().filter(|f| f.folder_id.is_none()).collect()
+    } else {
+        files.into_iter().filter(|f| f.in_folder(folder_id)).collect()
+    }
+}
+
+pub async fn delete_file(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Query(q): Query<DeleteFileQuery>,
+) -> Response {
+    let history = st.store.load_history(&st.cfg.history_file);
+    // With the trash on, nothing leaves Discord until the entry is purged
+    let to_trash = st.cfg.trash_ttl_s > 0 && !q.permanent.unwrap_or(false);
+    if q.dry_run.unwrap_or(false) {
+        let Some(rec) = history.iter().find(|f| f.id == file_id) else { return RemovalPlan::default().respond() };
+        if to_trash {
+            let entry = TrashEntry {
+                record: rec.clone(), trashed_at: chrono::Utc::now().timestamp(),
+                reason: "deleted".to_string(), delete_channel: q.delete_channel.unwrap_or(false),
+            };
+            let mut body = trash::trashed_reply(&st, &entry);
+            body["dry_run"] = json!(true);
+            body["records"] = json!([{ "type": "file", "id": rec.id, "filename": rec.filename }]);
+            return Json(body).into_response();
+        }
+        let mut plan = RemovalPlan::default();
+        plan_file_removal(&st, rec, q.delete_channel.unwrap_or(false), &mut plan);
+        return plan.respond();
+    }
+    if to_trash {
+        return match trash::trash_file(&st, file_id, q.delete_channel.unwrap_or(false)) {
+            Ok(entry) => {
+                let mut body = trash::trashed_reply(&st, &entry);
+                body["success"] = json!(true);
+                Json(body).into_response()
+            }
+            Err(e) => e.into_response(),
+        };
+    }
+    match remove_file(&st, file_id, q.delete_channel.unwrap_or(false), q.wait.unwrap_or(false)).await {
+        Ok(telegram) => Json(json!({ "success": true, "telegram": telegram })).into_response(),
+        Err(e)       => e.into_response(),
+    }
+}
+
+/// Delete a record (and optionally its channel), its Telegram copies, thumbnail and
+/// share links. Returns the Telegram cleanup report.
+pub async fn remove_file(st: &AppState, file_id: i64, delete_channel: bool, wait: bool) -> Result<Value, ApiError> {
+    // Downloads hold read locks while they fetch parts; fail fast unless the caller
+    // asked to wait for them, then re-read history since it may have changed meanwhile
+    let _lock = if wait {
+        st.file_locks.write(file_id).await
+    } else {
+        match st.file_locks.try_write(file_id) {
+            Some(g) => g,
+            None    => return Err(ApiError {
+                status: StatusCode::CONFLICT, code: Some("file_busy".to_string()),
+                detail: "File đang được tải xuống, thử lại sau hoặc dùng ?wait=true".to_string(),
+                report: None,
+            }),
+        }
+    };
+    if !st.connectivity.is_online() {
+        return Ok(remove_offline(st, file_id, delete_channel).await);
+    }
+    let (mut telegram, mut shared) = (json!(null), json!(null));
+    if let Some(rec) = find_record(st, file_id) {
+        let others: Vec<FileRecord> = dedup::live_records(st).into_iter().filter(|r| r.id != file_id).collect();
+        (telegram, shared) = drop_stored(st, rec, &others, delete_channel).await;
+    }
+    let _ = st.store.update_history(&st.cfg.history_file, |history| history.retain(|f| f.id != file_id));
+    let revoked = forget_file(st, file_id).await;
+    audit(st, file_id, "deleted", json!({
+        "delete_channel": delete_channel,
+        "telegram":       telegram,
+        "shares_revoked": revoked,
+        "shared":         shared,
+    }));
+    Ok(telegram)
+}
+
+/// `remove_file` with Discord away: the record goes now, its parts once the bot
+/// is back.
+async fn remove_offline(st: &AppState, file_id: i64, delete_channel: bool) -> Value {
+    let removed = st.store.update_history(&st.cfg.history_file, |history| {
+        let at = history.iter().position(|f| f.id == file_id)?;
+        Some(history.remove(at))
+    }).ok().flatten();
+    let Some(rec) = removed else { return json!(null) };
+    let pending = offline::defer_delete(st, rec, delete_channel);
+    let revoked = forget_file(st, file_id).await;
+    audit(st, file_id, "deleted", json!({
+        "delete_channel": delete_channel,
+        "shares_revoked": revoked,
+        "pending":        pending.id,
+    }));
+    json!({ "queued_offline": true, "pending": pending.id })
+}
+
+/// Delete what `rec` stored on Discord and Telegram, and with `delete_channel` its
+/// channel. Messages one of `others` or an upload in progress references
+/// (deduplicated parts) stay, and so does a channel still holding any of them. Returns the Telegram cleanup report
+/// and what was kept for other records.
+pub async fn drop_stored(st: &AppState, rec: FileRecord, others: &[FileRecord], delete_channel: bool) -> (Value, Value) {
+    let mut telegram = json!(null);
+    let mut shared = json!(null);
+    let release = st.part_claims.begin_delete(&rec, others);
+    let deleting = release.parts.clone();
+    if release.shared > 0 || (delete_channel && release.channel_used) {
+        shared = json!({ "parts_kept": release.shared, "channel_kept": delete_channel && release.channel_used });
+    }
+    if st.cfg.simulate {
+        for p in &release.parts {
+            if let Err(e) = simulate::delete_part(&st.cfg, p).await {
+                warn!("⚠️ [sim] part {} of {}: {e}", p.part, rec.filename);
+            }
+        }
+    } else if delete_channel && release.channel_used {
+        let mut seen = HashSet::new();
+        for p in release.parts.iter().filter(|p| p.platform != "telegram" && seen.insert(p.message_id)) {
+            let Some(ch) = p.channel_id.as_deref().and_then(|c| c.parse::<u64>().ok()) else { continue };
+            if let Err(e) = discord_bot::delete_message(&st.http, ch, p.message_id as u64).await {
+                warn!("⚠️ Could not delete part {} of {}: {e}", p.part, rec.filename);
+            }
+        }
+    } else if delete_channel {
+        if let Ok(ch_id) = rec.channel_id.parse::<u64>() {
+            let _ = discord_bot::delete_channel(&st.http, ch_id).await;
+        }
+    }
+    // Telegram copies are not tied to the channel, so they are always cleaned up
+    if !st.cfg.simulate && release.parts.iter().any(|p| p.platform == "telegram") {
+        telegram = delete_telegram_parts(st, &FileRecord { parts_info: release.parts, ..rec }).await;
+    }
+    st.part_claims.end_delete(&deleting);
+    (telegram, shared)
+}
+
+/// Drop what this machine keeps for a deleted file: thumbnail, cached parts and
+/// share links. Returns how many links were revoked.
+pub async fn forget_file(st: &AppState, file_id: i64) -> usize {
+    let _ = std::fs::remove_file(st.thumbnail_dir.join(format!("{file_id}.jpg")));
+    download::forget_cached_parts(&st.part_cache_dir, file_id);
+    // Share links die with the file; preview tokens need no cleanup (stateless, short-lived)
+    st.store.update_json(&st.cfg.shares_file, |list: &mut Vec<Share>| {
+        let before = list.len();
+        list.retain(|s| s.file_id != file_id);
+        before - list.len()
+    }).unwrap_or(0)
+}
+
+/// Delete every Telegram part of `rec`. Parts past Telegram's 48h window are skipped
+/// (the API would refuse); those and any failures are reported so they can be cleaned by hand.
+pub async fn delete_telegram_parts(st: &AppState, rec: &FileRecord) -> Value {
+    let parts: Vec<&PartInfo> = rec.parts_info.iter().filter(|p| p.platform == "telegram").collect();
+    let expired = current_timestamp_ms() - rec.id >= telegram::DELETE_WINDOW_MS;
+    let mut deleted = 0;
+    let mut failed  = vec![];
+    if !st.tg_enabled || expired {
+        let reason = if expired { "older than 48h" } else { "telegram not configured" };
+        failed.extend(parts.iter().map(|p| json!({ "part": p.part, "message_id": p.message_id, "error": reason })));
+    } else {
+        let client = reqwest::Client::new();
+        for p in &parts {
+            match telegram::delete_message(&client, &st.cfg, &st.tg_token, &st.tg_chat_id, p.message_id).await {
+                Ok(()) => deleted += 1,
+                Err(e) => failed.push(json!({ "part": p.part, "message_id": p.message_id, "error": e.to_string() })),
+            }
+        }
+    }
+    if !failed.is_empty() {
+        warn!("⚠️  {}: {} Telegram part(s) not deleted", rec.filename, failed.len());
+    }
+    json!({ "deleted": deleted, "failed": failed })
+}
+
+// ── Trash ──────────────────────────────────────────────────────────────────────
+
+/// Trashed files, most recently deleted first, each with when it will be purged.
+pub async fn list_trash(State(st): State<AppState>) -> impl IntoResponse {
+    let mut entries = st.store.load_trash(&st.cfg.trash_file);
+    entries.sort_by_key(|t| std::cmp::Reverse(t.trashed_at));
+    let items: Vec<Value> = entries.iter().map(|t| {
+        let mut item = json!(t);
+        item["purge_at"] = json!(trash::purge_at(&st, t));
+        item
+    }).collect();
+    Json(json!({ "trash": items, "ttl_days": st.cfg.trash_ttl_s / 86400 }))
+}
+
+pub async fn restore_trash(State(st): State<AppState>, Path(file_id): Path<i64>) -> Response {
+    match trash::restore(&st, file_id).await {
+        Ok(record) => Json(json!({ "success": true, "file": record })).into_response(),
+        Err(e)     => e.into_response(),
+    }
+}
+
+/// Purge one entry now instead of waiting for `trash.ttl_days`.
+pub async fn purge_trash(State(st): State<AppState>, Path(file_id): Path<i64>) -> Response {
+    match trash::purge(&st, file_id).await {
+        Ok(telegram) => Json(json!({ "success": true, "telegram": telegram })).into_response(),
+        Err(e)       => e.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RenameQuery { rename_channel: Option<bool> }
+
+pub async fn rename_file(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Query(q): Query<RenameQuery>,
+    Json(body): Json<Value>,
+) -> Response {
+    let new_name = body["filename"].as_str().unwrap_or("").trim().to_string();
+    if new_name.is_empty() { return err(StatusCode::BAD_REQUEST, "Tên không được trống"); }
+    let old_name = st.store.update_history(&st.cfg.history_file, |history| {
+        history.iter_mut().find(|f| f.id == file_id)
+            .map(|f| std::mem::replace(&mut f.filename, new_name.clone()))
+    }).unwrap_or_default();
+    let Some(old) = old_name else { return Json(json!({ "success": true })).into_response() };
+
+    let discord = match q.rename_channel.unwrap_or(false) {
+        false => Value::Null,
+        true if !st.connectivity.is_online() => {
+            let pending = offline::defer_rename(&st, file_id, &old, &new_name);
+            json!({ "queued_offline": true, "pending": pending.id })
+        }
+        true => rename_on_discord(&st, file_id, &old, &new_name).await,
+    };
+    audit(&st, file_id, "renamed", json!({ "from": old, "to": new_name, "discord": discord }));
+    Json(json!({ "success": true, "discord": discord })).into_response()
+}
+
+/// Bring Discord in line with a renamed record: channel name and part captions.
+/// Attachment filenames can't be edited, and a channel or message shared with
+/// another record (same channel, deduplicated parts) is left alone.
+pub async fn rename_on_discord(st: &AppState, file_id: i64, old: &str, new_name: &str) -> Value {
+    let Some(rec) = find_record(st, file_id) else { return Value::Null };
+    let others: Vec<FileRecord> = dedup::live_records(st).into_iter().filter(|f| f.id != file_id).collect();
+    let held: HashSet<dedup::MessageKey> = others.iter()
+        .flat_map(download::normalize_parts)
+        .map(|p| dedup::message_key(&p))
+        .collect();
+    let mut errors = vec![];
+    let mut channel_renamed = false;
+    if let Ok(ch_id) = rec.channel_id.parse::<u64>() {
+        let shared = others.iter().any(|f| f.channel_id == rec.channel_id);
+        if shared {
+            errors.push("Kênh dùng chung với file khác → không đổi tên kênh".to_string());
+        } else {
+            match discord_bot::rename_channel(&st.http, ch_id, new_name).await {
+                Ok(name) => {
+                    channel_renamed = true;
+                    let _ = st.store.update_history(&st.cfg.history_file, |history| {
+                        if let Some(f) = history.iter_mut().find(|f| f.id == file_id) { f.channel_name = name; }
+                    });
+                }
+                Err(e) => errors.push(format!("channel: {e}")),
+            }
+        }
+    }
+    let mut captions_updated = 0;
+    let mut seen = HashSet::new();
+    for p in rec.parts_info.iter().filter(|p| p.platform == "discord" && seen.insert(p.message_id)) {
+        if held.contains(&dedup::message_key(p)) { continue; }
+        let Some(ch_id) = p.channel_id.as_deref().and_then(|c| c.parse::<u64>().ok()) else { continue };
+        match discord_bot::retitle_part(&st.http, ch_id, p.message_id as u64, old, new_name).await {
+            Ok(true)  => captions_updated += 1,
+            Ok(false) => {}
+            Err(e)    => errors.push(format!("part {}: {e}", p.part)),
+        }
+    }
+    json!({ "channel_renamed": channel_renamed, "captions_updated": captions_updated, "errors": errors })
+}
+
+pub async fn move_file(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Json(body): Json<Value>,
+) -> Response {
+    let target = body.get("folder_id").cloned();
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    let folder_name = target.as_ref().and_then(|v| {
+        if v.is_null() { return None; }
+        let fid = v.as_str().map(|s| s.to_string())
+            .or_else(|| v.as_i64().map(|n| n.to_string()))?;
+        folders.iter().find(|f| f.id.to_string() == fid).map(|f| f.name.clone())
+    });
+    let moved = st.store.update_history(&st.cfg.history_file, |history| {
+        let Some(f) = history.iter_mut().find(|f| f.id == file_id) else { return false };
+        f.folder_id = target;
+        f.folder_name = folder_name.clone();
+        true
+    }).unwrap_or(false);
+    if moved {
+        audit(&st, file_id, "moved", json!({ "folder_name": folder_name }));
+    }
+    Json(json!({ "success": true })).into_response()
+}
+
+/// Most files one metadata batch may touch.
+const METADATA_BATCH_MAX: usize = 5000;
+const TAG_MAX_CHARS: usize = 64;
+const DESCRIPTION_MAX_CHARS: usize = 2000;
+
+/// Tells a field that is absent (None) from one set to null (Some(None)).
+fn present<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(d).map(Some)
+}
+
+#[derive(Deserialize)]
+pub struct MetadataBatch {
+    ids:   Vec<i64>,
+    patch: MetadataPatch,
+}
+
+/// Absent fields are left alone; `null` (or "") clears a field.
+#[derive(Deserialize)]
+pub struct MetadataPatch {
+    #[serde(default)]
+    add_tags:    Vec<String>,
+    #[serde(default)]
+    remove_tags: Vec<String>,
+    /// Folder id; null or "" = root.
+    #[serde(default, deserialize_with = "present")]
+    folder_id:   Option<Option<Value>>,
+    /// Unix seconds.
+    #[serde(default, deserialize_with = "present")]
+    expires_at:  Option<Option<i64>>,
+    #[serde(default, deserialize_with = "present")]
+    description: Option<Option<String>>,
+}
+
+fn clean_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    tags.iter().map(|t| {
+        let t = t.trim();
+        if t.is_empty() || t.chars().count() > TAG_MAX_CHARS {
+            return Err(format!("Tag phải dài 1–{TAG_MAX_CHARS} ký tự: '{t}'"));
+        }
+        Ok(t.to_string())
+    }).collect()
+}
+
+/// Apply one patch to many files: tags, folder, expiry, description. All changed
+/// records are saved in a single store transaction. Ids that don't exist are
+/// listed in `missing`; the rest are still updated.
+pub async fn metadata_batch(State(st): State<AppState>, Json(body): Json<MetadataBatch>) -> Response {
+    let p = body.patch;
+    if body.ids.is_empty() { return err(StatusCode::BAD_REQUEST, "Danh sách file trống"); }
+    if body.ids.len() > METADATA_BATCH_MAX {
+        return err(StatusCode::BAD_REQUEST, format!("Tối đa {METADATA_BATCH_MAX} file mỗi lần"));
+    }
+    let (add, remove) = match (clean_tags(&p.add_tags), clean_tags(&p.remove_tags)) {
+        (Ok(a), Ok(r)) => (a, r),
+        (Err(e), _) | (_, Err(e)) => return err(StatusCode::BAD_REQUEST, e),
+    };
+    // Some(None) = move to root, Some(Some((id, name))) = into that folder
+    let folder = match &p.folder_id {
+        None    => None,
+        Some(v) => {
+            let fid = match v {
+                None                    => String::new(),
+                Some(Value::String(s))  => s.trim().to_string(),
+                Some(other)             => other.to_string(),
+            };
+            if fid.is_empty() {
+                Some(None)
+            } else {
+                match st.store.load_folders(&st.cfg.folders_file).into_iter().find(|f| f.id.to_string() == fid) {
+                    Some(f) => Some(Some((fid, f.name))),
+                    None    => return err(StatusCode::NOT_FOUND, "Folder không tồn tại"),
+                }
+            }
+        }
+    };
+    if let Some(Some(t)) = p.expires_at {
+        if t <= chrono::Utc::now().timestamp() {
+            return err(StatusCode::BAD_REQUEST, "expires_at phải ở tương lai");
+        }
+    }
+    let description = p.description.as_ref().map(|d| {
+        d.as_deref().map(str::trim).filter(|d| !d.is_empty()).map(str::to_string)
+    });
+    if description.as_ref().and_then(|d| d.as_ref()).is_some_and(|d| d.chars().count() > DESCRIPTION_MAX_CHARS) {
+        return err(StatusCode::BAD_REQUEST, format!("Mô tả tối đa {DESCRIPTION_MAX_CHARS} ký tự"));
+    }
+
+    let wanted: HashSet<i64> = body.ids.iter().copied().collect();
+    let saved = st.store.locked(&st.cfg.history_file, || -> anyhow::Result<(Vec<i64>, HashSet<i64>)> {
+        let mut history = st.store.load_history(&st.cfg.history_file);
+        let mut updated = vec![];
+        for rec in history.iter_mut().filter(|r| wanted.contains(&r.id)) {
+            let before = (rec.tags.clone(), rec.folder_id.clone(), rec.expires_at, rec.description.clone());
+            rec.tags.retain(|t| !remove.contains(t));
+            rec.tags.extend(add.iter().cloned());
+            rec.tags.sort();
+            rec.tags.dedup();
+            if let Some(f) = &folder {
+                rec.folder_id   = f.as_ref().map(|(id, _)| Value::String(id.clone()));
+                rec.folder_name = f.as_ref().map(|(_, name)| name.clone());
+            }
+            if let Some(t) = p.expires_at { rec.expires_at = t; }
+            if let Some(d) = &description { rec.description = d.clone(); }
+            if before != (rec.tags.clone(), rec.folder_id.clone(), rec.expires_at, rec.description.clone()) {
+                updated.push(rec.id);
+            }
+        }
+        let found = history.iter().filter(|r| wanted.contains(&r.id)).map(|r| r.id).collect();
+        if !updated.is_empty() {
+            let mut tx = st.store.transaction();
+            tx.save_history(&st.cfg.history_file, &history);
+            tx.commit()?;
+        }
+        Ok((updated, found))
+    });
+    let (updated, found) = match saved {
+        Ok(v)  => v,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, format!("Lưu thất bại: {e}")),
+    };
+    let missing: Vec<i64> = body.ids.iter().copied().filter(|id| !found.contains(id)).collect();
+
+    if !updated.is_empty() {
+        audit::record_many(&*st.store, &st.cfg.audit_file, &updated, "metadata", json!({
+            "add_tags":    add,
+            "remove_tags": remove,
+            "folder_name": folder.as_ref().map(|f| f.as_ref().map(|(_, name)| name.clone())),
+            "expires_at":  p.expires_at,
+            "description": description.is_some(),
+        }));
+        info!("🏷️ Metadata batch: {} of {} file(s) changed", updated.len(), body.ids.len());
+    }
+    Json(json!({ "success": true, "updated": updated, "unchanged": found.len() - updated.len(), "missing": missing }))
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct VerifyQuery { deep: Option<bool> }
+
+/// Check that every part is still stored (`?deep=true` downloads and unzips them).
+/// With `upload.status_reactions` the part messages get ✅ or ⚠️.
+pub async fn verify_file(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Query(q): Query<VerifyQuery>,
+) -> Response {
+    let Some(record) = find_record(&st, file_id) else {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    };
+    let report = verify::verify(&st, &record, q.deep.unwrap_or(false)).await;
+    audit(&st, file_id, "verified", json!({
+        "deep": report.deep, "healthy": report.healthy, "degraded": report.degraded,
+    }));
+    Json(report).into_response()
+}
+
+/// Re-download the whole file and check it against the BLAKE3 hashes recorded at
+/// upload; the report names corrupt and missing parts.
+pub async fn verify_file_hash(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+) -> Response {
+    let Some(record) = find_record(&st, file_id) else {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    };
+    let report = match verify::verify_hash(&st, &record).await {
+        Ok(r)  => r,
+        Err(e) => return ApiError::from(e).into_response(),
+    };
+    let mut degraded: Vec<u32> = report.corrupt.iter().chain(&report.missing).copied().collect();
+    degraded.sort_unstable();
+    audit(&st, file_id, "verified", json!({
+        "deep": true, "hash": true, "healthy": report.healthy, "degraded": degraded,
+    }));
+    // Every byte was just counted: legacy records get the exact size for Content-Length
+    if report.healthy && record.size_bytes == 0 && report.actual_bytes > 0 {
+        let res = st.store.update_history(&st.cfg.history_file, |history| {
+            if let Some(r) = history.iter_mut().find(|r| r.id == file_id && r.size_bytes == 0) {
+                r.size_bytes = report.actual_bytes;
+            }
+        });
+        match res {
+            Ok(()) => info!("📏 {}: recorded exact size {} bytes", record.filename, report.actual_bytes),
+            Err(e) => warn!("⚠️ Could not record exact size of {}: {e}", record.filename),
+        }
+    }
+    Json(report).into_response()
+}
+
+/// Unpack a stored ZIP and upload each entry as its own file.
+/// Body: `folder_id` (defaults to the archive's folder, "" = root), `concurrency` (1–4, default 2).
+pub async fn extract_all(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    body: Option<Json<Value>>,
+) -> Response {
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+    let Some(record) = find_record(&st, file_id) else {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    };
+    let folder_id = match body.get("folder_id") {
+        Some(v) if !v.is_null() => v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string()),
+        _ => record.folder_key().unwrap_or_default(),
+    };
+    if !folder_id.is_empty()
+        && !st.store.load_folders(&st.cfg.folders_file).iter().any(|f| f.id.to_string() == folder_id)
+    {
+        return err(StatusCode::NOT_FOUND, "Folder không tồn tại");
+    }
+    let concurrency = body["concurrency"].as_u64().unwrap_or(2) as usize;
+
+    let source = record.filename.clone();
+    match extract::extract_all(&st, record, &folder_id, concurrency).await {
+        Ok(report) => {
+            audit(&st, file_id, "extracted", json!({
+                "extracted": report.extracted.len(), "failed": report.failed.len(), "folder_id": folder_id,
+            }));
+            info!("📦 {source}: {} extracted, {} failed, {} skipped",
+                report.extracted.len(), report.failed.len(), report.skipped.len());
+            Json(json!({ "success": report.failed.is_empty(), "report": report })).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Running download-to-disk jobs, and those that finished in the last hour.
+pub async fn list_download_jobs(State(st): State<AppState>) -> Response {
+    Json(json!({ "jobs": st.disk_jobs.list() })).into_response()
+}
+
+/// Timeline of everything that happened to one file, assembled from the audit log.
+pub async fn file_activity(State(st): State<AppState>, Path(file_id): Path<i64>) -> Response {
+    let events = audit::for_file(&*st.store, &st.cfg.audit_file, file_id);
+    let record = find_record(&st, file_id);
+    if record.is_none() && events.is_empty() {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    }
+    let downloads = events.iter().filter(|e| e.action == "downloaded").count();
+    let last_activity = events.last().map(|e| e.ts.clone());
+    Json(json!({
+        "file_id":       file_id,
+        "filename":      record.map(|r| r.filename),
+        "downloads":     downloads,
+        "last_activity": last_activity,
+        "events":        events,
+    })).into_response()
+}
+
+/// Where each part of a file is stored: platform, message, size (0 = legacy,
+/// unknown), BLAKE3 and a link to the message. Mirrored uploads list every copy; `shared` marks parts
+/// another file (in history or the trash) points at too.
+pub async fn file_parts(State(st): State<AppState>, Path(file_id): Path<i64>) -> Response {
+    let Some(rec) = find_record(&st, file_id) else {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    };
+    let others: Vec<FileRecord> = dedup::live_records(&st).into_iter().filter(|r| r.id != file_id).collect();
+    let held: HashSet<dedup::MessageKey> = others.iter()
+        .flat_map(download::normalize_parts)
+        .map(|p| dedup::message_key(&p))
+        .collect();
+    let parts: Vec<Value> = download::part_copies(&rec).into_iter().flatten().map(|p| json!({
+        "part":         p.part,
+        "platform":     p.platform,
+        "channel_id":   p.channel_id,
+        "message_id":   p.message_id.to_string(),
+        "attachment":   p.attachment,
+        "topic_id":     p.topic_id,
+        "size":         p.raw_bytes,
+        "stored_bytes": p.stored_bytes,
+        "checksum":     p.blake3.as_ref().map(|h| format!("blake3:{h}")),
+        "encrypted":    p.encrypted,
+        "shared":       held.contains(&dedup::message_key(&p)),
+        "link":         part_link(&st, &p),
+    })).collect();
+    Json(json!({
+        "file_id":      rec.id,
+        "filename":     rec.filename,
+        "part_count":   rec.parts,
+        "stored_bytes": rec.stored_bytes,
+        "parts":        parts,
+    })).into_response()
+}
+
+/// A link to the message holding `p`: its recorded jump URL, else one built from
+/// the ids (legacy Discord parts, Telegram supergroups). None when there is none.
+fn part_link(st: &AppState, p: &PartInfo) -> Option<String> {
+    if let Some(url) = &p.jump_url { return Some(url.clone()); }
+    match p.platform.as_str() {
+        "discord" => p.channel_id.as_ref().filter(|c| !c.is_empty())
+            .map(|c| format!("https://discord.com/channels/{}/{c}/{}", st.guild_id, p.message_id)),
+        // Only supergroups (-100…) have t.me/c links
+        "telegram" => st.tg_chat_id.strip_prefix("-100")
+            .map(|chat| format!("https://t.me/c/{chat}/{}", p.message_id)),
+        _ => None,
+    }
+}
+
+// ── Stream helpers ─────────────────────────────────────────────────────────────
+
+fn find_record(st: &AppState, file_id: i64) -> Option<FileRecord> {
+    st.store.find_record(&st.cfg.history_file, file_id)
+}
+
+/// `find_record` for serving the file's bytes: past its `expires_at` it is gone.
+fn find_servable(st: &AppState, file_id: i64) -> Result<FileRecord, Response> {
+    let r = find_record(st, file_id).ok_or_else(|| err(StatusCode::NOT_FOUND, "File không tồn tại"))?;
+    if r.is_expired() { return Err(err_code(StatusCode::GONE, "expired", "File đã hết hạn")); }
+    Ok(r)
+}
+
+fn make_stream_response(record: FileRecord, st: AppState, inline: bool, peer: IpAddr) -> Response {
+    stream_response(record, st, inline, None, peer, true)
+}
+
+/// Stream the merged file, or only the inclusive byte `range` of it (206).
+/// Only the parts covering the range are fetched when the record has their sizes
+/// (`PartInfo::raw_bytes`); otherwise bytes before it are fetched and dropped.
+/// A `peer` on this machine or LAN is served from the part cache when it holds
+/// every part needed (`download.lan_bypass`), marked `X-Served-From: cache`.
+/// `count_access` is false for HEAD probes, which don't reset `unaccessed_days`.
+fn stream_response(record: FileRecord, st: AppState, inline: bool, range: Option<(u64, u64)>, peer: IpAddr, count_access: bool) -> Response {
+    let mime        = mime_for(&record.filename);
+    let size        = record.exact_size();
+    let filename    = record.filename.clone();
+    let disposition = disposition::header_value(if inline { "inline" } else { "attachment" }, &filename);
+    let http     = std::sync::Arc::clone(&st.http);
+    let cfg      = match keyring::config_for(&st, record.wrapped_key.as_deref()) {
+        Ok(c)  => c,
+        Err(e) => return ApiError::from(e).into_response(),
+    };
+    let tg_token = st.tg_token.clone();
+    let (start, end) = range.unwrap_or((0, u64::MAX));
+    let locks    = std::sync::Arc::clone(&st.file_locks);
+    let store    = std::sync::Arc::clone(&st.store);
+    let part_cache = st.part_cache_dir.clone();
+    let cached   = (st.cfg.download_lan_bypass && http_server::is_local_peer(peer))
+        .then(|| download::merge_cached(&record, &part_cache, start, cfg.read_buffer_bytes))
+        .flatten();
+    let from_cache = cached.is_some();
+    if count_access {
+        lifecycle::record_access(&*st.store, &st.cfg, record.id);
+    }
+    let body = Body::from_stream(async_stream::stream! {
+        // Held until the body is dropped, so a delete can't remove parts mid-merge
+        let _lock = locks.read(record.id).await;
+        if store.find_record(&cfg.history_file, record.id).is_none() {
+            yield Err(std::io::Error::new(std::io::ErrorKind::NotFound, "file was deleted"));
+            return;
+        }
+        let started = std::time::Instant::now();
+        // Parts before the range are skipped when their sizes are recorded
+        let (mut rx, skipped) = match cached {
+            Some(cached) => cached,
+            None => download::merge_from(record, http, std::sync::Arc::clone(&cfg), tg_token, part_cache, start).await,
+        };
+        let mut pos = skipped;
+        while let Some(chunk) = rx.recv().await {
+            let chunk = match chunk {
+                Ok(c)  => c,
+                Err(e) => { yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())); return; }
+            };
+            let (from, to) = (pos, pos + chunk.len() as u64);
+            pos = to;
+            if to <= start { continue; }
+            let lo = start.saturating_sub(from) as usize;
+            let hi = (end.saturating_add(1).min(to) - from) as usize;
+            yield Ok(chunk.slice(lo..hi));
+            if to > end { break; }   // dropping rx stops the part fetcher
+        }
+        throughput::record(&*store, &cfg.throughput_file, throughput::Direction::Download, pos - skipped, started.elapsed());
+    });
+    let mut resp = Response::builder()
+        .header(header::CONTENT_TYPE, mime)
+        .header(header::CONTENT_DISPOSITION, disposition);
+    if from_cache {
+        resp = resp.header("X-Served-From", "cache");
+    }
+    resp = match (range, size) {
+        (Some((a, b)), Some(total)) => resp
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {a}-{b}/{total}"))
+            .header(header::CONTENT_LENGTH, b - a + 1),
+        (None, Some(total)) => resp.status(StatusCode::OK).header(header::CONTENT_LENGTH, total),
+        _ => resp.status(StatusCode::OK),
+    };
+    resp.body(body).unwrap()
+}
+
+/// Single `bytes=` range against a known size. `Ok(None)` means "ignore the header and
+/// send everything" (multi-range or malformed); `Err` means unsatisfiable (416).
+fn parse_range(value: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else { return Ok(None) };
+    if spec.contains(',') { return Ok(None); }
+    let Some((a, b)) = spec.split_once('-') else { return Ok(None) };
+    let (a, b) = (a.trim(), b.trim());
+    let range = match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) if a <= b => (a, b.min(size.saturating_sub(1))),
+        (Ok(a), Err(_)) if b.is_empty() => (a, size.saturating_sub(1)),
+        (Err(_), Ok(n)) if a.is_empty() && n > 0 => (size.saturating_sub(n), size.saturating_sub(1)),
+        _ => return Ok(None),
+    };
+    if size == 0 || range.0 >= size { return Err(()); }
+    Ok(Some(range))
+}
+
+/// `If-Match` list check against our strong ETag (`*` matches anything for If-Match).
+fn etag_matches(header_value: &str, etag: &str) -> bool {
+    header_value.split(',').map(str::trim).any(|v| v == etag || v == "*")
+}
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    pub token: Option<String>,
+}
+
+/// A `?token=` is optional, but one that is present must be valid for this file.
+fn check_preview_token(st: &AppState, file_id: i64, q: &TokenQuery) -> Option<Response> {
+    let token = q.token.as_deref()?;
+    let now = chrono::Utc::now().timestamp();
+    match st.preview_signer.verify(token, file_id, now) {
+        Ok(()) => None,
+        Err(TokenError::Expired) => Some(err_code(StatusCode::UNAUTHORIZED, "token_expired", "Token đã hết hạn")),
+        Err(_) => Some(err_code(StatusCode::UNAUTHORIZED, "token_invalid", "Token không hợp lệ")),
+    }
+}
+
+pub async fn create_preview_token(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    body: Option<Json<Value>>,
+) -> Response {
+    if find_record(&st, file_id).is_none() {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    }
+    let ttl = body.and_then(|Json(b)| b["ttl_s"].as_u64())
+        .unwrap_or(st.cfg.preview_token_ttl_s)
+        .clamp(1, st.cfg.preview_token_ttl_s);
+    let expires_at = chrono::Utc::now().timestamp() + ttl as i64;
+    let token = st.preview_signer.mint(file_id, expires_at);
+    Json(json!({ "token": token, "expires_at": expires_at })).into_response()
+}
+
+/// Full download. Carries a strong ETag so download managers can resume with
+/// `Range` + `If-Range`; a stale validator gets the whole (new) file or 412 for `If-Match`.
+/// The HEAD they send first to learn size and validators isn't logged as a download.
+pub async fn merge_file(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Query(q): Query<TokenQuery>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(denied) = check_preview_token(&st, file_id, &q) { return denied; }
+    let r = match find_servable(&st, file_id) {
+        Ok(r)     => r,
+        Err(resp) => return resp,
+    };
+    let etag = download::etag(&r);
+    let range = match requested_range(&r, &headers, &etag) {
+        Ok(range) => range,
+        Err(resp) => return *resp,
+    };
+    if method != Method::HEAD {
+        audit(&st, file_id, "downloaded", json!({ "via": "merge", "range": range.map(|(a, b)| format!("{a}-{b}")) }));
+    }
+    let accept_ranges = r.exact_size().is_some();
+    with_validators(stream_response(r, st, false, range, peer.ip(), method != Method::HEAD), &etag, accept_ranges)
+}
+
+/// The byte range a GET asks for, after `If-Match`/`If-Range`. `Err` is the
+/// response to send instead: 412 for a failed `If-Match`, 416 for a bad range.
+fn requested_range(r: &FileRecord, headers: &HeaderMap, etag: &str) -> Result<Option<(u64, u64)>, Box<Response>> {
+    let header_str = |name| headers.get(name).and_then(|v: &header::HeaderValue| v.to_str().ok());
+
+    if let Some(im) = header_str(header::IF_MATCH) {
+        if !etag_matches(im, etag) {
+            return Err(Box::new((StatusCode::PRECONDITION_FAILED, [(header::ETAG, etag.to_string())]).into_response()));
+        }
+    }
+    // Only honour Range when the exact size is known and If-Range (if sent) still matches;
+    // an HTTP-date If-Range is treated as a mismatch since records carry no mtime.
+    let range_ok = header_str(header::IF_RANGE).map(|v| v.trim() == etag).unwrap_or(true);
+    match (r.exact_size(), header_str(header::RANGE)) {
+        (Some(size), Some(v)) if range_ok => parse_range(v, size).map_err(|()| Box::new((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{size}"))],
+        ).into_response())),
+        _ => Ok(None),
+    }
+}
+
+fn with_validators(mut resp: Response, etag: &str, accept_ranges: bool) -> Response {
+    let h = resp.headers_mut();
+    h.insert(header::ETAG, header::HeaderValue::from_str(etag).unwrap());
+    if accept_ranges {
+        h.insert(header::ACCEPT_RANGES, header::HeaderValue::from_static("bytes"));
+    }
+    resp
+}
+
+/// Inline stream for `<video>`/`<audio>`: answers the player's `Range` requests
+/// with 206 so seeking works, like `merge_file`.
+pub async fn preview_file(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Query(q): Query<TokenQuery>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(denied) = check_preview_token(&st, file_id, &q) { return denied; }
+    let r = match find_servable(&st, file_id) {
+        Ok(r)     => r,
+        Err(resp) => return resp,
+    };
+    let etag = download::etag(&r);
+    let range = match requested_range(&r, &headers, &etag) {
+        Ok(range) => range,
+        Err(resp) => return *resp,
+    };
+    let accept_ranges = r.exact_size().is_some();
+    with_validators(stream_response(r, st, true, range, peer.ip(), method != Method::HEAD), &etag, accept_ranges)
+}
+
+pub async fn thumbnail(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Query(q): Query<TokenQuery>,
+) -> Response {
+    if let Some(denied) = check_preview_token(&st, file_id, &q) { return denied; }
+    let record = match find_record(&st, file_id) {
+        None    => return err(StatusCode::NOT_FOUND, "File không tồn tại"),
+        Some(r) => r,
+    };
+    let cat = file_category(&record.filename);
+    let doc = thumbnails::kind_of(&record.filename);
+    if cat != "image" && cat != "video" && doc.is_none() {
+        return err(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Không hỗ trợ thumbnail");
+    }
+    let cache = st.thumbnail_dir.join(format!("{file_id}.jpg"));
+    if let Some(data) = read_cached_thumbnail(&cache) {
+        return ([(header::CONTENT_TYPE, "image/jpeg")], data).into_response();
+    }
+    if record.size_mb > 200.0 && cat == "video" {
+        return err(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Video quá lớn để tạo thumbnail");
+    }
+
+    // All media workers busy: generate in the background and let the client poll
+    let pool = media_pool::pool();
+    let key  = format!("thumb:{}:{file_id}", st.profile);
+    if pool.is_busy() || pool.is_queued(&key) {
+        let job_st = st.clone();
+        let queued = pool.enqueue(key, async move {
+            if let Err(r) = thumbnail_once(&job_st, record, &cache, doc).await {
+                warn!("⚠️ Thumbnail {file_id} failed in background: {}", r.status());
+            }
+        });
+        return match queued {
+            Ok(q)  => retry_later(StatusCode::ACCEPTED, q.retry_after_s, json!({ "status": "queued", "position": q.position })),
+            Err(f) => retry_later(StatusCode::SERVICE_UNAVAILABLE, f.retry_after_s, json!({ "error": "Hàng đợi xử lý media đã đầy" })),
+        };
+    }
+    match thumbnail_once(&st, record, &cache, doc).await {
+        Ok(jpeg) => ([(header::CONTENT_TYPE, "image/jpeg")], jpeg).into_response(),
+        Err(r)   => r,
+    }
+}
+
+/// `body` plus `retry_after_s` and a matching Retry-After header.
+fn retry_later(status: StatusCode, retry_after_s: u64, mut body: Value) -> Response {
+    body["retry_after_s"] = json!(retry_after_s);
+    (status, [(header::RETRY_AFTER, retry_after_s.to_string())], Json(body)).into_response()
+}
+
+/// Singleflight: concurrent requests for the same id queue behind one generator
+/// and then pick its result up from the cache file.
+async fn thumbnail_once(
+    st:     &AppState,
+    record: FileRecord,
+    cache:  &std::path::Path,
+    doc:    Option<thumbnails::Kind>,
+) -> Result<Vec<u8>, Response> {
+    let file_id = record.id;
+    let gate = {
+        let mut inflight = st.thumb_inflight.lock().await;
+        std::sync::Arc::clone(inflight.entry(file_id).or_default())
+    };
+    let res = {
+        let _turn = gate.lock().await;
+        match read_cached_thumbnail(cache) {
+            Some(data) => Ok(data),
+            None       => media_pool::pool().run(build_thumbnail(st, record, cache, doc)).await,
+        }
+    };
+    {
+        let mut inflight = st.thumb_inflight.lock().await;
+        // Only the map and this request still hold the gate → nobody is waiting
+        if std::sync::Arc::strong_count(&gate) <= 2 { inflight.remove(&file_id); }
+    }
+    res
+}
+
+fn read_cached_thumbnail(cache: &std::path::Path) -> Option<Vec<u8>> {
+    let data = if cache.exists() { std::fs::read(cache).ok() } else { None };
+    if data.is_some() { cache::touch(cache); }
+    data
+}
+
+/// Images and videos are decoded from their first 10MB; PDFs and text files are
+/// drawn by `thumbnails` from a prefix.
+async fn build_thumbnail(
+    st:     &AppState,
+    record: FileRecord,
+    cache:  &std::path::Path,
+    doc:    Option<thumbnails::Kind>,
+) -> Result<Vec<u8>, Response> {
+    let limit    = if doc == Some(thumbnails::Kind::Text) { thumbnails::TEXT_PREFIX } else { 10 * 1024 * 1024 };
+    let file_id  = record.id;
+    let is_image = file_category(&record.filename) == "image";
+    let http     = std::sync::Arc::clone(&st.http);
+    let cfg      = keyring::config_for(st, record.wrapped_key.as_deref()).map_err(|e| ApiError::from(e).into_response())?;
+    let tg_token = st.tg_token.clone();
+    let _lock    = st.file_locks.read(record.id).await;
+    let mut rx   = download::merge_to_channel(record, http, cfg, tg_token, st.part_cache_dir.clone()).await;
+    let mut buf  = Vec::new();
+    while let Some(chunk) = rx.recv().await {
+        match chunk {
+            Ok(data) => { buf.extend_from_slice(&data); if buf.len() >= limit { break; } }
+            Err(e)   => return Err(err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        }
+    }
+    let cache = cache.to_path_buf();
+    let res = tokio::task::spawn_blocking(move || match doc {
+        Some(kind) => thumbnails::render(kind, &buf).and_then(|img| encode_thumbnail(img, &cache)).map(|(jpeg, _)| (jpeg, None)),
+        None       => generate_thumbnail(&buf, &cache).map(|(jpeg, thumb)| (jpeg, is_image.then(|| phash::hash(&thumb)))),
+    }).await.unwrap_or_else(|e| Err(anyhow::anyhow!(e)));
+    let (jpeg, hash) = res.map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, format!("Không thể tạo thumbnail: {e}")))?;
+    if let Some(hash) = hash {
+        store_phashes(st, &[(file_id, hash)]);
+    }
+    Ok(jpeg)
 }
 
-// ── Folders ────────────────────────────────────────────────────────────────────
+fn generate_thumbnail(buf: &[u8], cache: &std::path::Path) -> anyhow::Result<(Vec<u8>, image::DynamicImage)> {
+    encode_thumbnail(image::load_from_memory(buf)?, cache)
+}
 
-pub async fn get_folders(State(st): State<AppState>) -> impl IntoResponse {
-    Json(json!({ "folders": st.store.load_folders(&st.cfg.folders_file) }))
+/// The JPEG written to `cache`, and the thumbnail it was encoded from.
+fn encode_thumbnail(img: image::DynamicImage, cache: &std::path::Path) -> anyhow::Result<(Vec<u8>, image::DynamicImage)> {
+    let thumb = img.thumbnail(256, 256);
+    let mut out = Vec::new();
+    thumb.to_rgb8().write_to(&mut Cursor::new(&mut out), image::ImageFormat::Jpeg)?;
+    let _ = std::fs::write(cache, &out);
+    Ok((out, thumb))
 }
 
-pub async fn create_folder(State(st): State<AppState>, Json(body): Json<Value>) -> Response {
-    let name = body["name"].as_str().unwrap_or("").trim().to_string();
-    if name.is_empty() { return err(StatusCode::BAD_REQUEST, "Tên folder không được trống"); }
-    match discord_bot::get_or_create_category(&st.http, st.guild_id, &name).await {
-        Ok(cat) => {
-            let mut folders = st.store.load_folders(&st.cfg.folders_file);
-            let folder = Folder {
-                id:                  current_timestamp_ms(),
-                name,
-                discord_category_id: cat.id.get() as i64,
-                created_at:          current_datetime_display(),
-            };
-            folders.insert(0, folder.clone());
-            let _ = st.store.save_folders(&st.cfg.folders_file, &folders);
-            Json(json!({ "success": true, "folder": folder })).into_response()
+/// Record pHashes on their files (`FileRecord::phash`).
+fn store_phashes(st: &AppState, hashes: &[(i64, u64)]) {
+    let res = st.store.update_history(&st.cfg.history_file, |history| {
+        for (id, hash) in hashes {
+            if let Some(r) = history.iter_mut().find(|r| r.id == *id) {
+                r.phash = Some(phash::to_hex(*hash));
+            }
         }
-        Err(e) => err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    });
+    if let Err(e) = res {
+        warn!("⚠️ Could not save pHash for {} file(s): {e}", hashes.len());
     }
 }
 
-pub async fn delete_folder(State(st): State<AppState>, Path(folder_id): Path<i64>) -> impl IntoResponse {
-    let mut folders = st.store.load_folders(&st.cfg.folders_file);
-    if let Some(f) = folders.iter().find(|f| f.id == folder_id) {
-        let _ = discord_bot::delete_category(&st.http, st.guild_id, f.discord_category_id as u64).await;
-    }
-    folders.retain(|f| f.id != folder_id);
-    let _ = st.store.save_folders(&st.cfg.folders_file, &folders);
-    Json(json!({ "success": true }))
+#[derive(Deserialize)]
+pub struct SimilarQuery {
+    max_distance: Option<u32>,
+    limit:        Option<usize>,
 }
 
-// ── Files ──────────────────────────────────────────────────────────────────────
+/// Stored images that look like this one, closest first. Images whose thumbnail
+/// was made before pHashes existed get one from the cached thumbnail here.
+pub async fn similar_files(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Query(q): Query<SimilarQuery>,
+) -> Response {
+    let history = st.store.load_history(&st.cfg.history_file);
+    let Some(target) = history.iter().find(|r| r.id == file_id) else {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    };
+    if file_category(&target.filename) != "image" {
+        return err(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Chỉ tìm ảnh tương tự cho file ảnh");
+    }
+    let max_distance = q.max_distance.unwrap_or(phash::DEFAULT_MAX_DISTANCE).min(32);
+    let limit = q.limit.unwrap_or(20).clamp(1, 200);
 
-#[derive(Deserialize)]
-pub struct FolderQuery { folder_id: Option<String> }
+    let known: HashMap<i64, u64> = history.iter()
+        .filter_map(|r| Some((r.id, phash::from_hex(r.phash.as_ref()?)?)))
+        .collect();
+    let missing: Vec<(i64, std::path::PathBuf)> = history.iter()
+        .filter(|r| !known.contains_key(&r.id) && file_category(&r.filename) == "image")
+        .map(|r| (r.id, st.thumbnail_dir.join(format!("{}.jpg", r.id))))
+        .filter(|(_, path)| path.exists())
+        .collect();
+    let backfilled: Vec<(i64, u64)> = if missing.is_empty() { vec![] } else {
+        media_pool::pool().run(async move {
+            tokio::task::spawn_blocking(move || missing.into_iter()
+                .filter_map(|(id, path)| Some((id, phash::hash(&image::open(path).ok()?))))
+                .collect::<Vec<_>>()).await.unwrap_or_default()
+        }).await
+    };
+    if !backfilled.is_empty() {
+        info!("🖼️ Backfilled pHash for {} image(s) from cached thumbnails", backfilled.len());
+        store_phashes(&st, &backfilled);
+    }
+    let hashes: HashMap<i64, u64> = known.into_iter().chain(backfilled).collect();
+    let Some(&hash) = hashes.get(&file_id) else {
+        return err_code(StatusCode::CONFLICT, "no_phash", "Ảnh chưa có thumbnail, chưa thể so sánh");
+    };
+
+    let mut similar: Vec<(u32, &FileRecord)> = history.iter()
+        .filter(|r| r.id != file_id)
+        .filter_map(|r| Some((phash::distance(hash, *hashes.get(&r.id)?), r)))
+        .filter(|(d, _)| *d <= max_distance)
+        .collect();
+    similar.sort_by_key(|(d, r)| (*d, r.id));
+    similar.truncate(limit);
+    Json(json!({
+        "file_id":      file_id,
+        "phash":        phash::to_hex(hash),
+        "max_distance": max_distance,
+        "similar":      similar.into_iter().map(|(d, r)| json!({ "distance": d, "file": r })).collect::<Vec<_>>(),
+    })).into_response()
+}
 
 #[derive(Deserialize)]
-pub struct DeleteFileQuery { delete_channel: Option<bool> }
+pub struct PrefetchQuery { parts: Option<usize> }
 
-pub async fn get_files(State(st): State<AppState>, Query(q): Query<FolderQuery>) -> impl IntoResponse {
-    let files = st.store.load_history(&st.cfg.history_file);
-    let filtered: Vec<_> = if let Some(ref fid) = q.folder_id {
-        if fid.is_empty() {
-            files.into_iter().filter(|f| f.folder_id.is_none()).collect()
-        } else {
-            files.into_iter().filter(|f| {
-                f.folder_id.as_ref().map(|v|
-                    v.as_str().map(|s| s == fid).unwrap_or_else(|| v.to_string() == *fid)
-                ).unwrap_or(false)
-            }).collect()
-        }
-    } else {
-        files.into_iter().filter(|f| f.folder_id.is_none()).collect()
-    };
-    Json(json!({ "files": filtered }))
-}
+/// Most parts one prefetch may pull ahead.
+const MAX_PREFETCH_PARTS: usize = 8;
 
-pub async fn delete_file(
+/// Pull the first `?parts=` (default 2) parts into the part cache so a preview that
+/// follows starts without waiting on Discord/Telegram.
+pub async fn prefetch_file(
     State(st): State<AppState>,
     Path(file_id): Path<i64>,
-    Query(q): Query<DeleteFileQuery>,
-) -> impl IntoResponse {
-    let mut history = st.store.load_history(&st.cfg.history_file);
-    if q.delete_channel.unwrap_or(false) {
-        if let Some(rec) = history.iter().find(|f| f.id == file_id) {
-            if let Ok(ch_id) = rec.channel_id.parse::<u64>() {
-                let _ = discord_bot::delete_channel(&st.http, ch_id).await;
-            }
-        }
+    Query(q): Query<PrefetchQuery>,
+) -> Response {
+    let Some(record) = find_record(&st, file_id) else {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    };
+    let count = q.parts.unwrap_or(2).clamp(1, MAX_PREFETCH_PARTS);
+    let cfg = match keyring::config_for(&st, record.wrapped_key.as_deref()) {
+        Ok(c)  => c,
+        Err(e) => return ApiError::from(e).into_response(),
+    };
+    let started = std::time::Instant::now();
+    let _lock = st.file_locks.read(file_id).await;
+    match download::prefetch_parts(&record, count, &st.http, &cfg, &st.tg_token, &st.part_cache_dir).await {
+        Ok(parts) => Json(json!({
+            "file_id": file_id,
+            "parts":   parts,
+            "ms":      started.elapsed().as_millis() as u64,
+        })).into_response(),
+        Err(e) => err(StatusCode::BAD_GATEWAY, format!("Prefetch lỗi: {e}")),
     }
-    history.retain(|f| f.id != file_id);
-    let _ = st.store.save_history(&st.cfg.history_file, &history);
-    let _ = std::fs::remove_file(st.thumbnail_dir.join(format!("{file_id}.jpg")));
-    Json(json!({ "success": true }))
 }
 
-pub async fn rename_file(
+/// Pin a file to the part cache: every part is brought onto the disk in the
+/// background and kept out of eviction, so it opens without Discord/Telegram.
+pub async fn pin_file(
     State(st): State<AppState>,
     Path(file_id): Path<i64>,
-    Json(body): Json<Value>,
 ) -> Response {
-    let new_name = body["filename"].as_str().unwrap_or("").trim().to_string();
-    if new_name.is_empty() { return err(StatusCode::BAD_REQUEST, "Tên không được trống"); }
-    let mut history = st.store.load_history(&st.cfg.history_file);
-    for f in &mut history { if f.id == file_id { f.filename = new_name; break; } }
-    let _ = st.store.save_history(&st.cfg.history_file, &history);
-    Json(json!({ "success": true })).into_response()
+    // Pinned parts sit on disk in clear, which an encrypted file must not do
+    let pinned = st.store.update_history(&st.cfg.history_file, |history| {
+        history.iter_mut().find(|f| f.id == file_id).map(|f| {
+            if f.is_encrypted() { return None; }
+            f.pinned = true;
+            Some(f.clone())
+        })
+    });
+    let record = match pinned {
+        Ok(Some(Some(r))) => r,
+        Ok(Some(None)) => return err_code(StatusCode::CONFLICT, "encrypted",
+            "File mã hoá không ghim được — phần đã giải mã sẽ nằm trên đĩa"),
+        Ok(None)    => return err(StatusCode::NOT_FOUND, "File không tồn tại"),
+        Err(e)      => return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let cfg = match keyring::config_for(&st, record.wrapped_key.as_deref()) {
+        Ok(c)  => c,
+        Err(e) => return ApiError::from(e).into_response(),
+    };
+    audit(&st, file_id, "pinned", json!({}));
+    let parts = download::part_copies(&record).len();
+    tokio::spawn(async move {
+        let _lock = st.file_locks.read(file_id).await;
+        match download::pin_parts(&record, &st.http, &cfg, &st.tg_token, &st.part_cache_dir).await {
+            Ok(p)  => info!("📌 Pinned {} ({file_id}): {} part(s), {:.1}MB, {} fetched", record.filename,
+                p.parts, p.bytes as f64 / 1024.0 / 1024.0, p.fetched),
+            Err(e) => warn!("⚠️ Pinning {file_id} stopped: {e} (missing parts are kept as they stream)"),
+        }
+    });
+    (StatusCode::ACCEPTED, Json(json!({ "file_id": file_id, "pinned": true, "parts": parts }))).into_response()
 }
 
-pub async fn move_file(
+/// Unpin: the file's parts go back to the LRU cache.
+pub async fn unpin_file(
     State(st): State<AppState>,
     Path(file_id): Path<i64>,
-    Json(body): Json<Value>,
 ) -> Response {
-    let target = body.get("folder_id").cloned();
-    let folders = st.store.load_folders(&st.cfg.folders_file);
-    let folder_name = target.as_ref().and_then(|v| {
-        if v.is_null() { return None; }
-        let fid = v.as_str().map(|s| s.to_string())
-            .or_else(|| v.as_i64().map(|n| n.to_string()))?;
-        folders.iter().find(|f| f.id.to_string() == fid).map(|f| f.name.clone())
+    let found = st.store.update_history(&st.cfg.history_file, |history| {
+        history.iter_mut().find(|f| f.id == file_id).map(|f| f.pinned = false).is_some()
     });
-    let mut history = st.store.load_history(&st.cfg.history_file);
-    for f in &mut history {
-        if f.id == file_id { f.folder_id = target; f.folder_name = folder_name; break; }
+    match found {
+        Ok(true)  => {}
+        Ok(false) => return err(StatusCode::NOT_FOUND, "File không tồn tại"),
+        Err(e)    => return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
     }
-    let _ = st.store.save_history(&st.cfg.history_file, &history);
-    Json(json!({ "success": true })).into_response()
+    let _lock = st.file_locks.read(file_id).await;
+    let (dir, limit) = (st.part_cache_dir.clone(), download::part_cache_budget(&st.cfg));
+    let parts = tokio::task::spawn_blocking(move || download::unpin_parts(&dir, file_id, limit)).await.unwrap_or(0);
+    audit(&st, file_id, "unpinned", json!({}));
+    Json(json!({ "file_id": file_id, "pinned": false, "parts": parts })).into_response()
 }
 
-// ── Stream helpers ─────────────────────────────────────────────────────────────
-
-fn find_record(st: &AppState, file_id: i64) -> Option<FileRecord> {
-    st.store.load_history(&st.cfg.history_file).into_iter().find(|f| f.id == file_id)
-}
+#[derive(Deserialize)]
+pub struct FileManifestQuery { #[serde(default)] checksums: bool }
 
-fn make_stream_response(record: FileRecord, st: AppState, inline: bool) -> Response {
-    let mime        = mime_for(&record.filename);
-    let filename    = record.filename.clone();
-    let disposition = if inline {
-        format!("inline; filename=\"{filename}\"")
-    } else {
-        format!("attachment; filename=\"{filename}\"")
+/// Download a signed `.ddrive` manifest of the file's parts. `?checksums=true`
+/// fetches every part once to add per-part and whole-file MD5s.
+pub async fn export_file_manifest(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Query(q): Query<FileManifestQuery>,
+) -> Response {
+    let Some(record) = find_record(&st, file_id) else {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
     };
-    let http     = std::sync::Arc::clone(&st.http);
-    let cfg      = std::sync::Arc::clone(&st.cfg);
-    let tg_token = st.tg_token.clone();
-    let body = Body::from_stream(async_stream::stream! {
-        let mut rx = download::merge_to_channel(record, http, cfg, tg_token).await;
-        while let Some(chunk) = rx.recv().await {
-            yield chunk.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
+    let (md5s, file_md5) = if q.checksums {
+        let _lock = st.file_locks.read(file_id).await;
+        match part_checksums(&st, &record).await {
+            Ok(sums) => sums,
+            Err(e)   => return err(StatusCode::BAD_GATEWAY, format!("Không tải được part để tính checksum: {e}")),
         }
-    });
-    Response::builder()
-        .status(200)
-        .header(header::CONTENT_TYPE, mime)
-        .header(header::CONTENT_DISPOSITION, disposition)
-        .body(body).unwrap()
+    } else {
+        (HashMap::new(), None)
+    };
+    let tg_chat_id = (!st.tg_chat_id.is_empty()).then_some(st.tg_chat_id.as_str());
+    let manifest = manifest::Manifest::of(
+        &record, &st.guild_id.to_string(), tg_chat_id, st.cfg.zip_password.is_some(), &md5s, file_md5,
+    ).sign(&st.manifest_key);
+    let body = match serde_json::to_vec_pretty(&manifest) {
+        Ok(b)  => b,
+        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    audit::record(&*st.store, &st.cfg.audit_file, Some(file_id), "manifest_exported", json!({ "checksums": q.checksums }));
+    let filename = format!("{}.{}", record.filename, manifest::EXTENSION);
+    (
+        [
+            (header::CONTENT_TYPE, format!("application/vnd.{}+json", manifest::EXTENSION)),
+            (header::CONTENT_DISPOSITION, disposition::header_value("attachment", &filename)),
+        ],
+        body,
+    ).into_response()
 }
 
-pub async fn merge_file(State(st): State<AppState>, Path(file_id): Path<i64>) -> Response {
-    match find_record(&st, file_id) {
-        None    => err(StatusCode::NOT_FOUND, "File không tồn tại"),
-        Some(r) => make_stream_response(r, st, false),
-    }
+#[derive(Deserialize)]
+pub struct ImportManifestQuery {
+    folder_id: Option<String>,
+    /// Download and unzip every part instead of only asking the platform.
+    #[serde(default)]
+    deep:      bool,
+    /// Keep the record even when some parts could not be reached.
+    #[serde(default)]
+    force:     bool,
 }
 
-pub async fn preview_file(State(st): State<AppState>, Path(file_id): Path<i64>) -> Response {
-    match find_record(&st, file_id) {
-        None    => err(StatusCode::NOT_FOUND, "File không tồn tại"),
-        Some(r) => make_stream_response(r, st, true),
+/// Turn a `.ddrive` manifest (the request body) into a history record. The
+/// signature must match this profile's manifest key; the parts are probed first
+/// and a missing one fails the import (422) unless `?force=true`.
+pub async fn import_manifest(
+    State(st): State<AppState>,
+    Query(q): Query<ImportManifestQuery>,
+    body: Bytes,
+) -> Response {
+    let manifest: manifest::Manifest = match serde_json::from_slice(&body) {
+        Ok(m)  => m,
+        Err(e) => return err(StatusCode::BAD_REQUEST, format!("Manifest không hợp lệ: {e}")),
+    };
+    if let Err(e) = manifest.check_format() {
+        return err(StatusCode::BAD_REQUEST, e);
+    }
+    if !manifest::Manifest::verify(&body, &st.manifest_key) {
+        return err_code(StatusCode::FORBIDDEN, "bad_signature",
+            "Chữ ký manifest không khớp — hai máy cần dùng chung MANIFEST_KEY");
     }
-}
 
-pub async fn thumbnail(State(st): State<AppState>, Path(file_id): Path<i64>) -> Response {
-    let record = match find_record(&st, file_id) {
-        None    => return err(StatusCode::NOT_FOUND, "File không tồn tại"),
-        Some(r) => r,
+    let first = &manifest.parts[0].info;
+    let already_imported = |history: &[FileRecord]| history.iter().find(|r| r.parts_info.iter().any(|p| {
+        p.platform == first.platform && p.message_id == first.message_id && p.file_id == first.file_id
+    })).map(|existing| ApiError {
+        status: StatusCode::CONFLICT,
+        code:   Some("already_imported".to_string()),
+        detail: format!("File đã có trong danh sách: {}", existing.filename),
+        report: Some(json!({ "file_id": existing.id })),
+    });
+    if let Some(e) = already_imported(&st.store.load_history(&st.cfg.history_file)) {
+        return e.into_response();
+    }
+
+    let folder_id = q.folder_id.filter(|f| !f.is_empty());
+    let folder_name = match &folder_id {
+        None      => None,
+        Some(fid) => match st.store.load_folders(&st.cfg.folders_file).into_iter().find(|f| f.id.to_string() == *fid) {
+            Some(f) => Some(f.name),
+            None    => return err(StatusCode::NOT_FOUND, "Folder không tồn tại"),
+        },
     };
-    let cat = file_category(&record.filename);
-    if cat != "image" && cat != "video" {
-        return err(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Không hỗ trợ thumbnail");
+    let record = manifest.to_record(folder_id, folder_name);
+    let requirements = manifest.requirements(&st.guild_id.to_string(), !st.tg_token.is_empty(), st.cfg.zip_password.is_some(),
+        st.cfg.encryption_key.is_some(), st.keyring.is_unlocked());
+    let checks = verify::probe(&st, &record, q.deep).await;
+    if !checks.healthy && !q.force {
+        return ApiError {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            code:   Some("parts_unavailable".to_string()),
+            detail: format!("{} part không truy cập được", checks.degraded.len()),
+            report: Some(json!({ "checks": checks, "requirements": requirements })),
+        }.into_response();
     }
-    let cache = st.thumbnail_dir.join(format!("{file_id}.jpg"));
-    if cache.exists() {
-        if let Ok(data) = std::fs::read(&cache) {
-            return ([(header::CONTENT_TYPE, "image/jpeg")], data).into_response();
-        }
+
+    // Checked again: the same manifest may have been imported while the parts were probed
+    let inserted = st.store.update_history(&st.cfg.history_file, |history| match already_imported(history) {
+        Some(e) => Err(e),
+        None    => { history.insert(0, record.clone()); Ok(()) }
+    });
+    match inserted {
+        Ok(Ok(()))  => {}
+        Ok(Err(e))  => return e.into_response(),
+        Err(e)      => return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
     }
-    if record.size_mb > 200.0 && cat == "video" {
-        return err(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Video quá lớn để tạo thumbnail");
+    audit(&st, record.id, "imported", json!({
+        "filename": record.filename, "parts": record.parts, "source_guild": manifest.source.guild_id,
+        "healthy": checks.healthy,
+    }));
+    info!("🧾 Imported {} from manifest ({} parts)", record.filename, record.parts);
+    Json(json!({ "file": record, "checks": checks, "requirements": requirements })).into_response()
+}
+
+/// MD5 per part number (first copy of each) and of the whole file, parts in order.
+async fn part_checksums(st: &AppState, record: &FileRecord) -> anyhow::Result<(HashMap<u32, String>, Option<String>)> {
+    let mut parts = download::normalize_parts(record);
+    parts.sort_by_key(|p| p.part);
+    parts.dedup_by_key(|p| p.part);
+    let cfg = keyring::config_for(st, record.wrapped_key.as_deref()).map_err(|e| anyhow::anyhow!("{e}"))?;
+    let tg_client = reqwest::Client::new();
+    let mut whole = md5::Context::new();
+    let mut sums = HashMap::new();
+    for p in &parts {
+        let data = download::fetch_part(p, &st.http, &cfg, &tg_client, &st.tg_token).await?;
+        whole.consume(&data);
+        sums.insert(p.part, format!("{:x}", md5::compute(&data)));
     }
-    let http     = std::sync::Arc::clone(&st.http);
-    let cfg      = std::sync::Arc::clone(&st.cfg);
-    let tg_token = st.tg_token.clone();
-    let mut rx   = download::merge_to_channel(record, http, cfg, tg_token).await;
-    let mut buf  = Vec::new();
-    while let Some(chunk) = rx.recv().await {
-        match chunk {
-            Ok(data) => { buf.extend_from_slice(&data); if buf.len() >= 10*1024*1024 { break; } }
-            Err(e)   => return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    Ok((sums, Some(format!("{:x}", whole.compute()))))
+}
+
+// ── Keyring ────────────────────────────────────────────────────────────────────
+
+/// Whether a keyring exists and is unlocked in this process.
+pub async fn keys_status(State(st): State<AppState>) -> impl IntoResponse {
+    Json(st.keyring.status())
+}
+
+/// Body: `passphrase`. The master key stays in memory until /api/keys/lock or a
+/// restart, so the UI asks once per session; the first call creates the keyring.
+pub async fn unlock_keys(State(st): State<AppState>, Json(body): Json<Value>) -> Response {
+    let Some(passphrase) = body["passphrase"].as_str().map(str::to_string) else {
+        return err(StatusCode::BAD_REQUEST, "Thiếu passphrase");
+    };
+    let keyring = std::sync::Arc::clone(&st.keyring);
+    // PBKDF2 takes a good fraction of a second on purpose
+    match tokio::task::spawn_blocking(move || keyring.unlock(&passphrase)).await {
+        Ok(Ok(created)) => {
+            info!("🔑 Keyring {}", if created { "created and unlocked" } else { "unlocked" });
+            Json(json!({ "success": true, "created": created, "status": st.keyring.status() })).into_response()
         }
-    }
-    match generate_thumbnail(&buf, &cache) {
-        Ok(jpeg) => ([(header::CONTENT_TYPE, "image/jpeg")], jpeg).into_response(),
-        Err(e)   => err(StatusCode::INTERNAL_SERVER_ERROR, format!("Không thể tạo thumbnail: {e}")),
+        Ok(Err(UnlockError::Wrong))    => err_code(StatusCode::UNAUTHORIZED, "wrong_passphrase", "Sai passphrase"),
+        Ok(Err(UnlockError::TooShort)) => err_code(StatusCode::BAD_REQUEST, "passphrase_too_short", "Passphrase cần ít nhất 8 ký tự"),
+        Ok(Err(UnlockError::Throttled(wait))) => err_code(StatusCode::TOO_MANY_REQUESTS, "too_many_attempts",
+            format!("Sai passphrase quá nhiều lần — thử lại sau {}s", wait.as_secs().max(1))),
+        Ok(Err(UnlockError::Orphaned(n))) => err_code(StatusCode::CONFLICT, "keyring_missing",
+            format!("Không tìm thấy keyring nhưng {n} file vẫn dùng khoá của nó — khôi phục file keyring thay vì tạo mới")),
+        Ok(Err(UnlockError::Other(e))) => err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        Err(e)                         => err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
     }
 }
 
-fn generate_thumbnail(buf: &[u8], cache: &std::path::Path) -> anyhow::Result<Vec<u8>> {
-    let img   = image::load_from_memory(buf)?;
-    let thumb = img.thumbnail(256, 256).to_rgb8();
-    let mut out = Vec::new();
-    thumb.write_to(&mut Cursor::new(&mut out), image::ImageFormat::Jpeg)?;
-    let _ = std::fs::write(cache, &out);
-    Ok(out)
+/// Forget the master key; keyring-encrypted files answer 423 until the next unlock.
+pub async fn lock_keys(State(st): State<AppState>) -> impl IntoResponse {
+    st.keyring.lock();
+    info!("🔒 Keyring locked");
+    Json(json!({ "success": true, "status": st.keyring.status() }))
 }
 
 // ── Upload ─────────────────────────────────────────────────────────────────────
 
-pub async fn init_upload(State(st): State<AppState>, Json(body): Json<Value>) -> Response {
+/// 413 when `size` is over `upload.max_file_gb`.
+fn file_too_large(st: &AppState, size: u64) -> Option<ApiError> {
+    let gb = |b: u64| b as f64 / 1024.0 / 1024.0 / 1024.0;
+    (st.cfg.max_file_bytes > 0 && size > st.cfg.max_file_bytes).then(|| ApiError {
+        status: StatusCode::PAYLOAD_TOO_LARGE, code: Some("file_too_large".to_string()),
+        detail: format!("File {:.1}GB vượt giới hạn upload.max_file_gb ({:.0}GB)", gb(size), gb(st.cfg.max_file_bytes)),
+        report: None,
+    })
+}
+
+pub async fn init_upload(State(st): State<AppState>, headers: HeaderMap, Json(body): Json<Value>) -> Response {
     let filename     = body["filename"].as_str().unwrap_or("file").to_string();
     let file_size    = body["file_size"].as_u64().unwrap_or(0);
     let total_chunks = body["total_chunks"].as_u64().unwrap_or(1) as usize;
     let folder_id    = body["folder_id"].as_str().unwrap_or("").to_string();
     let message      = body["message"].as_str().unwrap_or("").to_string();
     let resume_id    = body["session_id"].as_str().unwrap_or("").to_string();
+    let pre_split    = body["pre_split"].as_bool().unwrap_or(false);
+    let optimize     = body["optimize_images"].as_bool().unwrap_or(false);
+    let mut metadata = match client_metadata(&body["metadata"]) {
+        Ok(m)  => m,
+        Err(e) => return e.into_response(),
+    };
+    let path = match body["relative_path"].as_str().map(relative_path).transpose() {
+        Ok(p)  => p.unwrap_or_default(),
+        Err(e) => return e.into_response(),
+    };
+    let target = match upload_target(&st, body["platform"].as_str()) {
+        Ok(t)  => t,
+        Err(e) => return e.into_response(),
+    };
+    // Parts stream to Discord as chunks arrive, so this can't start offline
+    if !st.connectivity.is_online() {
+        return err_code(StatusCode::SERVICE_UNAVAILABLE, "discord_offline",
+            "Discord đang mất kết nối — gửi file qua PUT /api/upload/offline để xếp hàng");
+    }
 
-    // Resume check
+    // Resume check — only the holder of the session secret may resume or replace it
     if !resume_id.is_empty() {
-        let session    = get_session(&st.store, &st.cfg.sessions_file, &resume_id);
+        if let Err(e) = check_session_secret(&st, &resume_id, &headers) { return e.into_response(); }
+        let session    = get_session(&st.sessions, &resume_id);
         let task_alive = st.sender_map.lock().await.contains_key(&resume_id);
         if let Some(s) = session {
-            if s.status == "uploading" && task_alive {
+            if s.status == STATUS_FINALIZING {
+                return session_conflict(&s.status);
+            }
+            if s.status == STATUS_UPLOADING && task_alive {
                 return Json(json!({
                     "session_id": resume_id,
+                    "upload_secret": s.secret,
                     "received_chunks": s.received_chunks,
                     "chunk_size": st.cfg.client_chunk_bytes,
+                    "chunk_window": chunk_window(&st, &resume_id).await,
                 })).into_response();
             }
         }
         st.sender_map.lock().await.remove(&resume_id);
-        delete_session_record(&st.store, &st.cfg.sessions_file, &resume_id);
+        delete_session_record(&st.sessions, &resume_id);
     }
 
-    // Resolve category
-    let (category_id, folder_name) = if !folder_id.is_empty() {
+    // A file from a dropped directory goes to that directory's folder, and keeps
+    // its path so the tree can be rebuilt on download
+    let mut folder_id = folder_id;
+    let mut auto_rule = None;
+    if !path.is_empty() {
+        metadata.insert("relative_path".to_string(), path.join("/"));
+    }
+    if path.len() > 1 {
+        folder_id = match folder_for_path(&st, &folder_id, &path[..path.len() - 1]).await {
+            Ok(id) => id,
+            Err(e) => return e.into_response(),
+        };
+    } else if folder_id.is_empty() {
+        // No folder chosen: let the auto-foldering rules sort it
+        let rules   = st.store.load_folder_rules(&st.cfg.rules_file);
         let folders = st.store.load_folders(&st.cfg.folders_file);
-        if let Some(f) = folders.iter().find(|f| f.id.to_string() == folder_id) {
-            (Some(serenity::model::id::ChannelId::new(f.discord_category_id as u64)), Some(f.name.clone()))
-        } else { (None, None) }
-    } else { (None, None) };
+        if let Some(rule) = folder_rules::resolve(&rules, &folders, &filename) {
+            info!("🗂️ {filename} matches rule {} → folder {}", rule.pattern, rule.folder_id);
+            folder_id = rule.folder_id.clone();
+            auto_rule = Some(rule.clone());
+        }
+    }
 
-    let channel = match discord_bot::get_or_create_channel(&st.http, st.guild_id, &filename, category_id).await {
-        Ok(ch) => ch,
-        Err(e) => return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    let req = NewUpload {
+        filename, file_size, total_chunks, folder_id: folder_id.clone(), message, platform: target, zip_level: None,
+        pre_split, optimize, metadata, defer_channel: body["defer_channel"].as_bool().unwrap_or(false),
+    };
+    let session_id = match start_session(&st, req).await {
+        Ok(id) => id,
+        Err(e) => return e.into_response(),
+    };
+
+    let (secret, max_part_bytes, optimizing) = get_session(&st.sessions, &session_id)
+        .map(|s| (s.secret, s.max_part_bytes, s.optimize_images)).unwrap_or_default();
+    let mut out = json!({
+        "session_id": session_id,
+        "upload_secret": secret,
+        "received_chunks": [],
+        "chunk_size": st.cfg.client_chunk_bytes,
+        "chunk_window": chunk_window(&st, &session_id).await,
+        "folder_id": folder_id,
+        "auto_rule": auto_rule,
+    });
+    if pre_split {
+        out["pre_split"]      = json!(true);
+        out["max_part_bytes"] = json!(max_part_bytes);
+    }
+    // Asked for but not applicable (not a JPEG/PNG, too large, pre-split) → false
+    if optimize { out["optimize_images"] = json!(optimizing); }
+    Json(out).into_response()
+}
+
+/// Limits on init_upload's `metadata`; it is stored on every record, so keep it small.
+const MAX_METADATA_KEYS:  usize = 32;
+const MAX_METADATA_KEY:   usize = 64;
+const MAX_METADATA_VALUE: usize = 1024;
+
+/// init_upload's `metadata`: a flat object of short keys. Numbers and booleans are
+/// kept as text, nulls dropped; nested values are rejected.
+fn client_metadata(v: &Value) -> Result<BTreeMap<String, String>, ApiError> {
+    let bad = |detail: String| ApiError {
+        status: StatusCode::BAD_REQUEST, code: Some("bad_metadata".to_string()), detail, report: None,
+    };
+    let obj = match v {
+        Value::Null        => return Ok(BTreeMap::new()),
+        Value::Object(obj) => obj,
+        _                  => return Err(bad("metadata phải là object".to_string())),
+    };
+    if obj.len() > MAX_METADATA_KEYS {
+        return Err(bad(format!("metadata tối đa {MAX_METADATA_KEYS} khoá")));
+    }
+    let mut out = BTreeMap::new();
+    for (key, val) in obj {
+        let key = key.trim();
+        if key.is_empty() || key.chars().count() > MAX_METADATA_KEY {
+            return Err(bad(format!("Khoá metadata phải dài 1–{MAX_METADATA_KEY} ký tự")));
+        }
+        let val = match val {
+            Value::Null      => continue,
+            Value::String(s) => s.clone(),
+            Value::Number(_) | Value::Bool(_) => val.to_string(),
+            _ => return Err(bad(format!("metadata.{key} phải là chuỗi, số hoặc boolean"))),
+        };
+        if val.chars().count() > MAX_METADATA_VALUE {
+            return Err(bad(format!("metadata.{key} dài quá {MAX_METADATA_VALUE} ký tự")));
+        }
+        out.insert(key.to_string(), val);
+    }
+    Ok(out)
+}
+
+/// `platform` from init_upload, defaulting to the server-wide routing.
+fn upload_target(st: &AppState, platform: Option<&str>) -> Result<UploadTarget, ApiError> {
+    let Some(p) = platform.filter(|p| !p.is_empty()) else {
+        return Ok(UploadTarget::default_for(st.tg_enabled));
+    };
+    let target = UploadTarget::parse(p).ok_or_else(|| ApiError {
+        status: StatusCode::BAD_REQUEST, code: Some("bad_platform".to_string()),
+        detail: format!("platform không hợp lệ: {p} (discord | telegram | dual | mirror)"),
+        report: None,
+    })?;
+    if target.needs_telegram() && !st.tg_enabled {
+        return Err(ApiError {
+            status: StatusCode::BAD_REQUEST, code: Some("platform_unavailable".to_string()),
+            detail: format!("Telegram chưa được cấu hình, không thể dùng platform={p}"),
+            report: None,
+        });
+    }
+    Ok(target)
+}
+
+/// Header carrying the per-session secret returned by init_upload.
+pub const UPLOAD_SECRET_HEADER: &str = "x-upload-secret";
+
+/// Reject calls on someone else's session. Unknown sessions pass so the handler
+/// can answer 404 (or stay idempotent); sessions without a secret are not checked.
+fn check_session_secret(st: &AppState, session_id: &str, headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(session) = get_session(&st.sessions, session_id) else { return Ok(()) };
+    if session.secret.is_empty() { return Ok(()); }
+    let given = headers.get(UPLOAD_SECRET_HEADER).and_then(|v| v.to_str().ok()).unwrap_or("");
+    // Compare every byte so timing doesn't leak how much of a guess was right
+    let same = given.len() == session.secret.len()
+        && given.bytes().zip(session.secret.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0;
+    if same { Ok(()) } else {
+        Err(ApiError { status: StatusCode::FORBIDDEN, code: Some("bad_upload_secret".to_string()),
+                       detail: "Sai hoặc thiếu X-Upload-Secret".to_string(), report: None })
+    }
+}
+
+/// Parameters of a brand-new upload session.
+pub struct NewUpload {
+    pub filename:     String,
+    pub file_size:    u64,
+    pub total_chunks: usize,
+    pub folder_id:    String,
+    pub message:      String,
+    pub platform:     UploadTarget,
+    pub zip_level:    Option<u32>,     // None = upload.zip_compress_level
+    /// Every chunk is sent as exactly one part (the client picked the boundaries).
+    pub pre_split:    bool,
+    /// Recompress a JPEG/PNG before splitting; ignored for other files, pre-split
+    /// uploads and images over `upload.image_optimize_max_mb`.
+    pub optimize:     bool,
+    /// Client metadata (see `client_metadata`), copied onto the record.
+    pub metadata:     BTreeMap<String, String>,
+    /// Create the Discord channel when the first chunk arrives instead of now, so
+    /// an init that never sends anything leaves nothing behind in the guild.
+    pub defer_channel: bool,
+}
+
+/// Create the Discord channel (a simulated one with `--simulate`), the session record and its sender task.
+pub async fn start_session(st: &AppState, req: NewUpload) -> Result<String, ApiError> {
+    let NewUpload { filename, file_size, total_chunks, folder_id, message, platform, zip_level, pre_split, optimize, metadata, defer_channel } = req;
+    let optimize = optimize::kind_of(&filename)
+        .filter(|_| optimize && !pre_split && file_size <= st.cfg.image_optimize_max_bytes);
+    let part_size = check_upload_size(st, file_size, platform, pre_split.then_some(total_chunks)).await?;
+    // With the keyring unlocked, every encrypted upload gets its own data key
+    let file_key = match st.cfg.encryption_enabled.then(|| st.keyring.new_file_key()).flatten() {
+        Some(Ok(k))  => Some(k),
+        Some(Err(e)) => return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Không tạo được khoá cho file: {e}"))),
+        None         => None,
+    };
+
+    // Resolve categories (primary + overflow)
+    let folder = if !folder_id.is_empty() {
+        st.store.load_folders(&st.cfg.folders_file).into_iter().find(|f| f.id.to_string() == folder_id)
+    } else { None };
+    let folder_name = folder.as_ref().map(|f| f.name.clone());
+    let (channel, channel_name, channel_tx) = if defer_channel {
+        let (tx, rx) = oneshot::channel();
+        (ChannelSlot::Deferred(rx), None, Some(tx))
+    } else {
+        let (id, name) = open_channel(st, &filename, folder.as_ref()).await?;
+        (ChannelSlot::Ready(id), Some(name), None)
     };
 
     let session_id = create_session(
-        &st.store, &st.cfg.sessions_file,
+        &st.sessions,
         &filename, file_size, total_chunks, &folder_id, &message,
     );
-    update_session(&st.store, &st.cfg.sessions_file, &session_id, |s| {
-        s.channel_id   = Some(channel.id.get().to_string());
-        s.channel_name = Some(channel.name.clone());
+    update_session(&st.sessions, &session_id, |s| {
+        if let ChannelSlot::Ready(id) = &channel {
+            s.channel_id   = Some(id.get().to_string());
+            s.channel_name = channel_name.clone();
+        }
         s.folder_name  = folder_name.clone();
+        s.zip_level    = zip_level.unwrap_or(st.cfg.zip_compress_level);
+        s.pre_split    = pre_split;
+        s.optimize_images = optimize.is_some();
+        s.metadata     = metadata;
+        s.wrapped_key  = file_key.as_ref().map(|(_, wrapped)| wrapped.clone());
+        // A pre-split chunk is a whole part, so it must also fit in one chunk request
+        if pre_split { s.max_part_bytes = part_size.min(st.cfg.chunk_body_limit() as u64); }
     });
+    // A recompress (explicit zip level) wants its parts stored afresh, and an
+    // encrypted part's bytes depend on its key: neither references stored parts
+    let known_parts = (st.cfg.dedup_parts && zip_level.is_none() && !st.cfg.encryption_enabled)
+        .then(|| std::sync::Arc::new(dedup::PartIndex::build(&dedup::live_records(st), &session_id, &st.part_claims)));
+    // The sender reads the level and key from its config, so overrides get their own copy
+    let cfg = match (zip_level.filter(|&l| l != st.cfg.zip_compress_level), file_key) {
+        (None, None) => std::sync::Arc::clone(&st.cfg),
+        (level, key) => std::sync::Arc::new(Config {
+            zip_compress_level: level.unwrap_or(st.cfg.zip_compress_level),
+            encryption_key:     key.map(|(k, _)| k).or_else(|| st.cfg.encryption_key.clone()),
+            ..(*st.cfg).clone()
+        }),
+    };
 
     let (chunk_tx, chunk_rx) = tokio::sync::mpsc::channel(64);
     let (result_tx, result_rx) = oneshot::channel();
+    let diag = new_diagnostics(&session_id, &filename);
+    let window = ChunkWindow::new(st.cfg.parallel_chunks);
     let handle = crate::upload::spawn_sender(SenderArgs {
-        session_id: session_id.clone(), filename, message, total_chunks,
-        channel_id: channel.id,
+        session_id: session_id.clone(), filename, message, total_chunks, file_size,
+        channel,
         http:       std::sync::Arc::clone(&st.http),
+        send_pool:  std::sync::Arc::clone(&st.send_pool),
+        gates:      std::sync::Arc::clone(&st.send_gates),
         guild_id:   st.guild_id,
-        cfg:        std::sync::Arc::clone(&st.cfg),
-        tg_enabled: st.tg_enabled,
+        cfg,
+        target:     platform,
+        pre_split,
+        optimize,
         tg_token:   st.tg_token.clone(),
-        tg_chat_id: st.tg_chat_id.clone(),
+        tg_chat:    telegram_chat(st, folder.as_ref(), platform).await,
         chunk_rx, result_tx,
+        store:      std::sync::Arc::clone(&st.store),
+        diag:       std::sync::Arc::clone(&diag),
+        window:     std::sync::Arc::clone(&window),
+        known_parts,
     });
-    st.sender_map.lock().await.insert(session_id.clone(), SenderEntry { chunk_tx, result_rx, handle });
+    st.sender_map.lock().await.insert(session_id.clone(), SenderEntry { chunk_tx, result_rx, handle, diag, window, channel_tx });
 
     info!("🚀 Sender task started for session {session_id}");
-    Json(json!({
-        "session_id": session_id,
-        "received_chunks": [],
-        "chunk_size": st.cfg.client_chunk_bytes,
-    })).into_response()
+    Ok(session_id)
+}
+
+/// Find or create the upload's channel in the folder's categories, recording a
+/// new overflow category on the folder.
+async fn open_channel(
+    st: &AppState, filename: &str, folder: Option<&Folder>,
+) -> Result<(serenity::model::id::ChannelId, String), ApiError> {
+    if st.cfg.simulate { return Ok(simulate::channel(filename)); }
+    let category_ids: Vec<serenity::model::id::ChannelId> = folder
+        .map(|f| f.category_ids().into_iter().map(|id| serenity::model::id::ChannelId::new(id as u64)).collect())
+        .unwrap_or_default();
+    let category_name = folder.map(|f| f.category_name()).unwrap_or_default();
+    let target = folder.map(|_| discord_bot::CategoryTarget {
+        folder_name:  &category_name,
+        category_ids: &category_ids,
+    });
+    let (ch, new_category) = discord_bot::get_or_create_channel(&st.http, st.guild_id, filename, target).await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let (Some(cat), Some(f)) = (new_category, folder) {
+        let _ = st.store.update_json(&st.cfg.folders_file, |folders: &mut Vec<Folder>| {
+            if let Some(ff) = folders.iter_mut().find(|ff| ff.id == f.id) {
+                ff.overflow_category_ids.push(cat.get() as i64);
+            }
+        });
+    }
+    Ok((ch.id, ch.name))
+}
+
+/// Refuse files over `upload.max_file_gb` or needing more than `upload.max_parts`
+/// parts at the part size `platform` allows, before any channel is created.
+/// `pre_split` is the client's own part count. Returns the largest part allowed.
+/// Nothing is accepted while `encryption.enabled` has no usable key: the parts
+/// would only fail later, and must never go out in clear instead.
+async fn check_upload_size(
+    st: &AppState, file_size: u64, platform: UploadTarget, pre_split: Option<usize>,
+) -> Result<u64, ApiError> {
+    if st.cfg.encryption_enabled && st.cfg.encryption_key.is_none() && !st.keyring.is_unlocked() {
+        if st.keyring.status().initialized { return Err(KeyError::Locked.into()); }
+        return Err(ApiError {
+            status: StatusCode::SERVICE_UNAVAILABLE, code: Some("encryption_key_invalid".to_string()),
+            detail: "encryption.enabled đang bật nhưng encryption.key không hợp lệ (cần 64 ký tự hex) và chưa có keyring (POST /api/keys/unlock)".to_string(),
+            report: None,
+        });
+    }
+    if let Some(e) = file_too_large(st, file_size) { return Err(e); }
+    let gb = |b: u64| b as f64 / 1024.0 / 1024.0 / 1024.0;
+    let (_, part_size) = crate::upload::part_limits(&st.http, st.guild_id, &st.cfg, platform).await
+        .map_err(|e| ApiError::new(StatusCode::BAD_GATEWAY, e.to_string()))?;
+    let parts = pre_split.map_or_else(|| file_size.div_ceil(part_size.max(1)), |n| n as u64);
+    if parts > st.cfg.max_parts {
+        let part_mb = part_size as f64 / 1024.0 / 1024.0;
+        let hint = if platform.needs_telegram() {
+            "chạy local Bot API server và tăng telegram.file_limit_mb"
+        } else {
+            "boost server Discord, hoặc dùng Telegram với local Bot API server (telegram.file_limit_mb)"
+        };
+        return Err(ApiError {
+            status: StatusCode::PAYLOAD_TOO_LARGE, code: Some("too_many_parts".to_string()),
+            detail: format!(
+                "File {:.1}GB cần {parts} part ({part_mb:.1}MB/part), vượt upload.max_parts = {}. \
+                 Tăng cỡ part ({hint}) hoặc tăng upload.max_parts.",
+                gb(file_size), st.cfg.max_parts,
+            ),
+            report: None,
+        });
+    }
+    Ok(part_size)
+}
+
+/// Telegram destination for parts of a file in `folder`: the folder's topic when
+/// `telegram.folder_topics` is on (created on first use), else `TELEGRAM_TOPIC_ID`.
+async fn telegram_chat(st: &AppState, folder: Option<&Folder>, platform: UploadTarget) -> telegram::Chat {
+    let topic = match folder {
+        Some(f) if platform.needs_telegram() => match f.tg_topic_id {
+            Some(t) => Some(t),
+            None    => folder_topic(st, f.id).await,
+        },
+        _ => None,
+    };
+    telegram::Chat { id: st.tg_chat_id.clone(), topic: topic.or(st.tg_topic_id) }
+}
+
+/// The forum topic mirroring folder `folder_id`, creating it if needed. None when
+/// folder topics are off or Telegram refuses (e.g. the chat is not a forum).
+async fn folder_topic(st: &AppState, folder_id: i64) -> Option<i64> {
+    if !st.tg_enabled || !st.cfg.tg_folder_topics || st.cfg.simulate { return None; }
+    // Two uploads into a new folder must not create two topics
+    static TOPIC_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+    let _guard = TOPIC_LOCK.lock().await;
+    let folder = st.store.load_folders(&st.cfg.folders_file).into_iter().find(|f| f.id == folder_id)?;
+    if folder.tg_topic_id.is_some() { return folder.tg_topic_id; }
+    let topic = match telegram::create_topic(&reqwest::Client::new(), &st.tg_token, &st.tg_chat_id, &folder.name).await {
+        Ok(t)  => t,
+        Err(e) => { warn!("⚠️ No Telegram topic for folder {}: {e}", folder.name); return None; }
+    };
+    let _ = st.store.update_json(&st.cfg.folders_file, |folders: &mut Vec<Folder>| {
+        if let Some(f) = folders.iter_mut().find(|f| f.id == folder_id) {
+            f.tg_topic_id = Some(topic);
+        }
+    });
+    Some(topic)
+}
+
+/// Hand one chunk to the session's sender. With `wait` the call awaits the chunk
+/// window and channel capacity (background ingest); without it a chunk outside the
+/// window is rejected with 429 `chunk_window` (HTTP clients retry). Returns (received, total).
+pub async fn feed_chunk(
+    st: &AppState, session_id: &str, chunk_index: usize, body: Bytes, wait: bool,
+) -> Result<(usize, usize), ApiError> {
+    let session = get_session(&st.sessions, session_id)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "Session không tồn tại"))?;
+    if session.status != STATUS_UPLOADING {
+        return Err(ApiError::session_conflict(&session.status));
+    }
+    if body.is_empty() { return Err(ApiError::new(StatusCode::BAD_REQUEST, "Chunk rỗng")); }
+    if session.pre_split && body.len() as u64 > session.max_part_bytes {
+        return Err(ApiError {
+            status: StatusCode::PAYLOAD_TOO_LARGE, code: Some("part_too_large".to_string()),
+            detail: format!("pre_split: chunk {chunk_index} ({} byte) vượt cỡ part tối đa {} byte",
+                            body.len(), session.max_part_bytes),
+            report: None,
+        });
+    }
+
+    let entry = st.sender_map.lock().await.get_mut(session_id)
+        .map(|e| (e.chunk_tx.clone(), std::sync::Arc::clone(&e.window), e.channel_tx.take()));
+    let Some((tx, window, channel_tx)) = entry else {
+        return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Sender task không còn hoạt động"));
+    };
+    // First chunk of a defer_channel upload; later chunks queue up until the sender has it.
+    // Failing here drops channel_tx, which fails the sender too.
+    if let Some(channel_tx) = channel_tx {
+        let folder = st.store.load_folders(&st.cfg.folders_file).into_iter()
+            .find(|f| !session.folder_id.is_empty() && f.id.to_string() == session.folder_id);
+        let (channel_id, channel_name) = open_channel(st, &session.filename, folder.as_ref()).await?;
+        update_session(&st.sessions, session_id, |s| {
+            s.channel_id   = Some(channel_id.get().to_string());
+            s.channel_name = Some(channel_name.clone());
+        });
+        let _ = channel_tx.send(channel_id);
+    }
+    let len = body.len();
+    if wait {
+        while !window.admits(chunk_index) && !tx.is_closed() {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    } else if !window.admits(chunk_index) {
+        return Err(window_full(&window));
+    }
+    let sent = if wait {
+        tx.send((chunk_index, body)).await.map_err(|_| ())
+    } else {
+        tx.try_send((chunk_index, body)).map_err(|_| ())
+    };
+    if sent.is_err() {
+        return Err(if tx.is_closed() {
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Sender task không còn hoạt động")
+        } else {
+            window_full(&window)
+        });
+    }
+
+    mark_chunk_received(&st.sessions, session_id, chunk_index);
+    let received = get_session(&st.sessions, session_id)
+        .map(|s| s.received_chunks.len()).unwrap_or(0);
+    let total = session.total_chunks;
+    info!("  📥 Chunk {}/{} ({:.0}KB)", chunk_index+1, total, len as f64/1024.0);
+    Ok((received, total))
+}
+
+fn window_full(window: &ChunkWindow) -> ApiError {
+    ApiError {
+        status: StatusCode::TOO_MANY_REQUESTS,
+        code:   Some("chunk_window".to_string()),
+        detail: format!("Chunk nằm ngoài cửa sổ cho phép (từ chunk {}, tối đa {} chunk) — thử lại sau",
+                        window.next_expected(), window.size()),
+        report: None,
+    }
+}
+
+/// Current in-flight chunk window for a live session.
+async fn chunk_window(st: &AppState, session_id: &str) -> Option<usize> {
+    st.sender_map.lock().await.get(session_id).map(|e| e.window.size())
 }
 
 pub async fn upload_chunk(
     State(st): State<AppState>,
     Path((session_id, chunk_index)): Path<(String, usize)>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    version: Version,
+    headers: HeaderMap,
     body: Bytes,
 ) -> Response {
-    let session = match get_session(&st.store, &st.cfg.sessions_file, &session_id) {
-        None    => return err(StatusCode::NOT_FOUND, "Session không tồn tại"),
-        Some(s) => s,
+    if let Err(e) = check_session_secret(&st, &session_id, &headers) { return e.into_response(); }
+    if let Some(entry) = st.sender_map.lock().await.get(&session_id) {
+        let mut diag = entry.diag.lock().unwrap_or_else(|e| e.into_inner());
+        *diag.transport.versions.entry(format!("{version:?}")).or_insert(0) += 1;
+        diag.transport.connections.insert(peer.to_string());
+    }
+    let body = match decode_chunk(&st, &headers, body).await {
+        Ok(b)  => b,
+        Err(e) => return e.into_response(),
     };
-    if session.status != "uploading" && session.status != "sending" {
-        return err(StatusCode::BAD_REQUEST, format!("Session status: {}", session.status));
+    match feed_chunk(&st, &session_id, chunk_index, body, false).await {
+        Ok((received, total)) => Json(json!({
+            "success": true, "received": received, "total": total,
+            "chunk_window": chunk_window(&st, &session_id).await,
+        })).into_response(),
+        Err(e) => e.into_response(),
     }
-    if body.is_empty() { return err(StatusCode::BAD_REQUEST, "Chunk rỗng"); }
+}
 
-    let sent = {
-        let map = st.sender_map.lock().await;
-        if let Some(entry) = map.get(&session_id) {
-            entry.chunk_tx.try_send((chunk_index, body.clone())).is_ok()
-        } else { false }
-    };
-    if !sent { return err(StatusCode::INTERNAL_SERVER_ERROR, "Sender task không còn hoạt động"); }
+/// Undo `Content-Encoding: gzip|zstd` so the sender always sees raw file bytes.
+async fn decode_chunk(st: &AppState, headers: &HeaderMap, body: Bytes) -> Result<Bytes, ApiError> {
+    let encoding = headers.get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    if matches!(encoding.trim(), "" | "identity") { return Ok(body); }
+    let limit = st.cfg.chunk_body_limit();
+    let decoded = tokio::task::spawn_blocking(move || compression::decode(&encoding, &body, limit)).await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    match decoded {
+        Ok(data) => Ok(Bytes::from(data)),
+        Err(e @ DecodeError::Unsupported(_)) =>
+            Err(ApiError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Content-Encoding không hỗ trợ ({e})"))),
+        Err(DecodeError::TooLarge) =>
+            Err(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "Chunk sau khi giải nén vượt giới hạn")),
+        Err(e) => Err(ApiError::new(StatusCode::BAD_REQUEST, format!("Không giải nén được chunk: {e}"))),
+    }
+}
 
-    mark_chunk_received(&st.store, &st.cfg.sessions_file, &session_id, chunk_index);
-    let received = get_session(&st.store, &st.cfg.sessions_file, &session_id)
-        .map(|s| s.received_chunks.len()).unwrap_or(0);
-    let total = session.total_chunks;
-    info!("  📥 Chunk {}/{} ({:.0}KB)", chunk_index+1, total, body.len() as f64/1024.0);
-    Json(json!({ "success": true, "received": received, "total": total })).into_response()
+pub async fn get_upload_session(State(st): State<AppState>, Path(session_id): Path<String>) -> Response {
+    match get_session(&st.sessions, &session_id) {
+        None    => err(StatusCode::NOT_FOUND, "Session không tồn tại"),
+        Some(s) => {
+            let mut v = json!(s);
+            if let Some(obj) = v.as_object_mut() { obj.remove("secret"); }
+            Json(v).into_response()
+        }
+    }
+}
+
+/// Keep an idle (paused) upload from being garbage-collected.
+pub async fn keepalive_upload(
+    State(st): State<AppState>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(e) = check_session_secret(&st, &session_id, &headers) { return e.into_response(); }
+    match touch_session(&st.sessions, &session_id) {
+        Ok(s) => Json(json!({
+            "success":       true,
+            "last_activity": s.last_activity,
+            "ttl_s":         st.cfg.session_ttl_s,
+        })).into_response(),
+        Err(TransitionError::NotFound) => err(StatusCode::NOT_FOUND, "Session không tồn tại"),
+        Err(TransitionError::Conflict { actual }) => ApiError::session_conflict(&actual).into_response(),
+    }
+}
+
+/// Per-part timings: live while the sender runs, from disk once it has finished.
+pub async fn get_upload_diagnostics(State(st): State<AppState>, Path(session_id): Path<String>) -> Response {
+    let live = st.sender_map.lock().await
+        .get(&session_id)
+        .map(|e| e.diag.lock().unwrap_or_else(|e| e.into_inner()).clone());
+    match live.or_else(|| get_diagnostics(&*st.store, &st.cfg.diagnostics_file, &session_id)) {
+        None    => err(StatusCode::NOT_FOUND, "Không có dữ liệu chẩn đoán cho session này"),
+        Some(d) => Json(d).into_response(),
+    }
+}
+
+/// Aggregate upload/download progress (the same numbers the desktop taskbar shows).
+pub async fn get_progress() -> Response {
+    Json(progress::snapshot()).into_response()
+}
+
+/// Buffer stats of merges in flight and the last few finished ones.
+pub async fn get_download_diagnostics() -> Response {
+    Json(json!({
+        "active": download::active_downloads(),
+        "recent": download::recent_downloads(),
+    })).into_response()
+}
+
+/// Which Discord permissions each bot lacks in the guild and the folder categories.
+pub async fn permission_diagnostics(State(st): State<AppState>) -> Response {
+    Json(permissions::check(&st).await).into_response()
 }
 
-pub async fn get_upload_session(State(st): State<AppState>, Path(session_id): Path<String>) -> Response {
-    match get_session(&st.store, &st.cfg.sessions_file, &session_id) {
-        None    => err(StatusCode::NOT_FOUND, "Session không tồn tại"),
-        Some(s) => Json(s).into_response(),
+pub async fn complete_upload(
+    State(st): State<AppState>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(e) = check_session_secret(&st, &session_id, &headers) { return e.into_response(); }
+    match finalize_session(&st, &session_id).await {
+        Ok(record) => Json(json!({ "success": true, "record": record })).into_response(),
+        Err(e)     => e.into_response(),
     }
 }
 
-pub async fn complete_upload(State(st): State<AppState>, Path(session_id): Path<String>) -> Response {
-    let session = match get_session(&st.store, &st.cfg.sessions_file, &session_id) {
-        None    => return err(StatusCode::NOT_FOUND, "Session không tồn tại"),
+/// Close the chunk stream, wait for the sender and turn the session into a history record.
+pub async fn finalize_session(st: &AppState, session_id: &str) -> Result<FileRecord, ApiError> {
+    let session_id = session_id.to_string();
+    let session = match get_session(&st.sessions, &session_id) {
+        None    => return Err(ApiError::new(StatusCode::NOT_FOUND, "Session không tồn tại")),
         Some(s) => s,
     };
-    if session.received_chunks.len() < session.total_chunks {
-        return err(StatusCode::BAD_REQUEST, format!(
-            "Chưa đủ chunk: {}/{}", session.received_chunks.len(), session.total_chunks));
+    if session.status == STATUS_UPLOADING && session.received_chunks.len() < session.total_chunks {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, format!(
+            "Chưa đủ chunk: {}/{}", session.received_chunks.len(), session.total_chunks)));
+    }
+    // Only one of complete/cancel may leave `uploading`; the loser gets a 409 with the winner's state.
+    match transition_session(&st.sessions, &session_id, &[STATUS_UPLOADING], STATUS_FINALIZING) {
+        Ok(_) => {}
+        Err(TransitionError::NotFound)           => return Err(ApiError::new(StatusCode::NOT_FOUND, "Session không tồn tại")),
+        Err(TransitionError::Conflict { actual }) => return Err(ApiError::session_conflict(&actual)),
     }
-    update_session(&st.store, &st.cfg.sessions_file, &session_id, |s| { s.status = "sending".to_string(); });
 
     let entry = match st.sender_map.lock().await.remove(&session_id) {
-        None    => return err(StatusCode::BAD_REQUEST, "Không tìm thấy sender task"),
+        None    => {
+            delete_session_record(&st.sessions, &session_id);
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "Không tìm thấy sender task"));
+        }
         Some(e) => e,
     };
     // Drop chunk_tx → signals EOF to receiver
@@ -367,21 +2606,34 @@ pub async fn complete_upload(State(st): State<AppState>, Path(session_id): Path<
 
     let result = match entry.result_rx.await {
         Ok(Ok(r))  => r,
+        Ok(Err(e)) if e.is::<UploadFailed>() => {
+            let failure = e.downcast::<UploadFailed>().expect("checked above");
+            return Err(upload_failed(st, &session, failure.0));
+        }
         Ok(Err(e)) => {
-            delete_session_record(&st.store, &st.cfg.sessions_file, &session_id);
-            return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+            delete_session_record(&st.sessions, &session_id);
+            return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
         }
         Err(_) => {
-            delete_session_record(&st.store, &st.cfg.sessions_file, &session_id);
-            return err(StatusCode::INTERNAL_SERVER_ERROR, "Sender task bị huỷ");
+            delete_session_record(&st.sessions, &session_id);
+            return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Sender task bị huỷ"));
         }
     };
+    Ok(save_uploaded(st, &session, result))
+}
 
-    let size_mb = (session.file_size as f64 / 1024.0 / 1024.0 * 100.0).round() / 100.0;
+/// Turn a finished (`finalizing`) session into a history record.
+fn save_uploaded(st: &AppState, session: &UploadSession, result: SenderResult) -> FileRecord {
+    let session_id = &session.session_id;
+    // An optimized image is stored (and downloaded) at its new size
+    let size = if result.optimized.is_some() { result.total_bytes } else { session.file_size };
+    let size_mb = (size as f64 / 1024.0 / 1024.0 * 100.0).round() / 100.0;
     let method_label = match result.method.as_str() {
         "direct" => "Gửi thẳng".to_string(),
         "split"  => format!("Chia {} phần (Discord)", result.parts),
         "dual"   => format!("Chia {} phần (Discord+Telegram)", result.parts),
+        "telegram" => format!("Chia {} phần (Telegram)", result.parts),
+        "mirror" => format!("Nhân bản {} phần (Discord+Telegram)", result.parts),
         _        => format!("Chia {} phần", result.parts),
     };
     let jump_url = result.jump_urls.first().cloned();
@@ -402,35 +2654,674 @@ pub async fn complete_upload(State(st): State<AppState>, Path(session_id): Path<
         message_ids:  result.message_ids.clone(),
         jump_url,
         sent_at:      current_datetime_display(),
+        stored_bytes: result.parts_info.iter().map(|p| p.stored_bytes).sum(),
+        size_bytes:   result.total_bytes,
+        zip_level:    Some(session.zip_level),
+        optimized:    result.optimized.clone(),
+        metadata:     session.metadata.clone(),
+        blake3:       result.blake3.clone(),
+        wrapped_key:  session.wrapped_key.clone(),
+        ..Default::default()
     };
-    let mut history = st.store.load_history(&st.cfg.history_file);
-    history.insert(0, record.clone());
-    let _ = st.store.save_history(&st.cfg.history_file, &history);
-    delete_session_record(&st.store, &st.cfg.sessions_file, &session_id);
+    // Record and `done` land together, so a crash can't leave a stored file whose
+    // session still looks unfinished. Kept as `done` (not deleted) so late
+    // cancel/complete calls get a deterministic 409; GC purges it.
+    let _ = st.store.locked(&st.cfg.history_file, || {
+        let mut history = st.store.load_history(&st.cfg.history_file);
+        history.insert(0, record.clone());
+        let mut tx = st.store.transaction();
+        tx.save_history(&st.cfg.history_file, &history);
+        transition_session_with(tx, &st.sessions, session_id, &[STATUS_FINALIZING], STATUS_DONE)
+    });
+    // The record now holds the parts it reused
+    st.part_claims.release_session(session_id);
 
+    audit(st, record.id, "uploaded", json!({
+        "filename": record.filename, "size_mb": record.size_mb, "parts": record.parts, "method": record.method_key,
+        "optimized": record.optimized,
+    }));
     info!("✅ Upload complete: {} ({} parts)", session.filename, result.parts);
-    Json(json!({ "success": true, "record": record })).into_response()
+    if st.cfg.status_reactions {
+        let (st, rec) = (st.clone(), record.clone());
+        tokio::spawn(async move { verify::verify(&st, &rec, false).await; });
+    }
+    record
+}
+
+/// Keep a session whose parts failed as `failed`, with the report in
+/// `discord_result`, and build the 502 the client gets. Stored parts are listed
+/// so they can be reused or cleaned up; byte ranges say what still has to be sent.
+fn upload_failed(st: &AppState, session: &UploadSession, report: FailureReport) -> ApiError {
+    update_session(&st.sessions, &session.session_id, |s| {
+        s.status         = STATUS_FAILED.to_string();
+        s.discord_result = serde_json::to_value(&report).ok();
+    });
+    let value = failure_view(session, &report);
+    let detail = format!("Upload lỗi: {}", UploadFailed(report));
+    warn!("❌ Upload failed: {} — {detail}", session.filename);
+    ApiError { status: StatusCode::BAD_GATEWAY, code: Some("parts_failed".to_string()), detail, report: Some(value) }
+}
+
+/// Client-facing failure report: what is stored, what failed and what
+/// `POST /api/upload/retry/:sid?part=` still needs.
+fn failure_view(session: &UploadSession, report: &FailureReport) -> Value {
+    let mut stored: Vec<u32> = report.succeeded.iter().map(|p| p.part).collect();
+    stored.sort_unstable();
+    stored.dedup();
+    json!({
+        "session_id":   session.session_id,
+        "file_size":    session.file_size,
+        "part_bytes":   report.part_bytes,
+        "stored_parts": stored,
+        "succeeded":    report.succeeded,
+        "failed":       report.failed,
+        "missing":      report.missing(session.file_size),
+        // Stored parts stay with the session, so a retry only resends the missing ones.
+        // Not for optimized images: the parts hold bytes the client never had.
+        "retryable":    !report.optimized,
+        "retry_url":    format!("/api/upload/retry/{}", session.session_id),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct RetryQuery { part: Option<u32> }
+
+/// Parts being resent right now, so a double-submitted part isn't stored twice.
+static RETRY_INFLIGHT: std::sync::Mutex<std::collections::BTreeSet<(String, u32)>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+/// Resume a `failed` upload without starting over. Without `?part=` it returns the
+/// failure report, whose `missing` list gives each part's byte range. With
+/// `?part=N` the body is exactly that range; it is stored and, once nothing is
+/// missing, the file is saved like a normal `complete`.
+pub async fn retry_upload(
+    State(st): State<AppState>,
+    Path(session_id): Path<String>,
+    Query(q): Query<RetryQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if let Err(e) = check_session_secret(&st, &session_id, &headers) { return e.into_response(); }
+    let Some(session) = get_session(&st.sessions, &session_id) else {
+        return err(StatusCode::NOT_FOUND, "Session không tồn tại");
+    };
+    if session.status != STATUS_FAILED { return session_conflict(&session.status); }
+    let Some(report) = session.discord_result.clone().and_then(|v| serde_json::from_value::<FailureReport>(v).ok()) else {
+        return err(StatusCode::CONFLICT, "Session không có báo cáo lỗi để thử lại");
+    };
+    let Some(part) = q.part else {
+        return Json(failure_view(&session, &report)).into_response();
+    };
+    if report.optimized {
+        return err_code(StatusCode::CONFLICT, "not_retryable", "Ảnh đã được nén lại khi upload — hãy upload lại từ đầu");
+    }
+    let missing = report.missing(session.file_size);
+    let Some(wanted) = missing.iter().find(|m| m.part == part) else {
+        return err_code(StatusCode::CONFLICT, "part_not_missing", format!("Part {part} không cần gửi lại"));
+    };
+    let data = match decode_chunk(&st, &headers, body).await {
+        Ok(b)  => b,
+        Err(e) => return e.into_response(),
+    };
+    if data.len() as u64 != wanted.length {
+        return err(StatusCode::BAD_REQUEST, format!(
+            "Part {part} cần đúng {} byte (offset {}), nhận {}", wanted.length, wanted.offset, data.len()));
+    }
+    let key = (session_id.clone(), part);
+    if !RETRY_INFLIGHT.lock().unwrap_or_else(|e| e.into_inner()).insert(key.clone()) {
+        return err_code(StatusCode::CONFLICT, "part_in_flight", format!("Part {part} đang được gửi lại"));
+    }
+    let stored = resend(&st, &session, &report, part, data, &wanted.platforms).await;
+    RETRY_INFLIGHT.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+    let stored = match stored {
+        Ok(parts) => parts,
+        Err(e)    => return e.into_response(),
+    };
+
+    // Fold the part into the stored report; the last one finishes the upload
+    let mut report = report;
+    update_session(&st.sessions, &session_id, |s| {
+        if let Some(latest) = s.discord_result.clone().and_then(|v| serde_json::from_value::<FailureReport>(v).ok()) {
+            report = latest;
+        }
+        report.failed.retain(|f| f.part != part);
+        report.succeeded.extend(stored.iter().cloned());
+        s.discord_result = serde_json::to_value(&report).ok();
+        s.last_activity  = Some(crate::storage::current_datetime_iso());
+    });
+    info!("🔁 Part {part} of {} resent ({})", session.filename,
+        stored.iter().map(|p| p.platform.as_str()).collect::<Vec<_>>().join("+"));
+    if !report.missing(session.file_size).is_empty() {
+        return Json(json!({ "success": true, "part": part, "stored": stored, "report": failure_view(&session, &report) })).into_response();
+    }
+    // Only one request may turn the session into a record
+    if transition_session(&st.sessions, &session_id, &[STATUS_FAILED], STATUS_FINALIZING).is_err() {
+        return Json(json!({ "success": true, "part": part, "stored": stored })).into_response();
+    }
+    let total_parts = report.succeeded.iter().map(|p| p.part).max().unwrap_or(0);
+    let mirror = report.target == UploadTarget::Mirror.as_str();
+    let result = crate::upload::sender_result(report.succeeded, total_parts, session.file_size, mirror);
+    let record = save_uploaded(&st, &session, result);
+    Json(json!({ "success": true, "part": part, "stored": stored, "record": record })).into_response()
+}
+
+async fn resend(
+    st: &AppState, session: &UploadSession, report: &FailureReport, part: u32, data: Bytes, platforms: &[String],
+) -> Result<Vec<PartInfo>, ApiError> {
+    let target = UploadTarget::parse(&report.target).unwrap_or(UploadTarget::Discord);
+    let channel_id = session.channel_id.as_deref().and_then(|c| c.parse::<u64>().ok())
+        .ok_or_else(|| ApiError::new(StatusCode::CONFLICT, "Session không có channel"))?;
+    let folder = if session.folder_id.is_empty() { None } else {
+        st.store.load_folders(&st.cfg.folders_file).into_iter().find(|f| f.id.to_string() == session.folder_id)
+    };
+    let cfg = keyring::config_for(st, session.wrapped_key.as_deref())?;
+    let cfg = if session.zip_level != cfg.zip_compress_level {
+        std::sync::Arc::new(Config { zip_compress_level: session.zip_level, ..(*cfg).clone() })
+    } else {
+        cfg
+    };
+    let resend = crate::upload::Resend {
+        filename:   session.filename.clone(),
+        message:    session.message.clone(),
+        channel_id: serenity::model::id::ChannelId::new(channel_id),
+        http:       std::sync::Arc::clone(&st.http),
+        send_pool:  std::sync::Arc::clone(&st.send_pool),
+        gates:      std::sync::Arc::clone(&st.send_gates),
+        guild_id:   st.guild_id,
+        cfg,
+        target,
+        tg_token:   st.tg_token.clone(),
+        tg_chat:    telegram_chat(st, folder.as_ref(), target).await,
+        diag:       new_diagnostics(&session.session_id, &session.filename),
+    };
+    crate::upload::resend_part(&resend, part, data, platforms).await
+        .map_err(|e| ApiError { status: StatusCode::BAD_GATEWAY, code: Some("part_failed".to_string()),
+                                detail: format!("Gửi lại part {part} lỗi: {e:#}"), report: None })
 }
 
-pub async fn cancel_upload(State(st): State<AppState>, Path(session_id): Path<String>) -> impl IntoResponse {
+pub async fn cancel_upload(
+    State(st): State<AppState>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(e) = check_session_secret(&st, &session_id, &headers) { return e.into_response(); }
+    match transition_session(&st.sessions, &session_id, &[STATUS_UPLOADING], STATUS_CANCELLED) {
+        Ok(_) | Err(TransitionError::NotFound) => {}
+        Err(TransitionError::Conflict { actual }) if actual == STATUS_CANCELLED => {}
+        Err(TransitionError::Conflict { actual }) => return session_conflict(&actual),
+    }
     if let Some(entry) = st.sender_map.lock().await.remove(&session_id) {
         entry.handle.abort();
     }
-    delete_session_record(&st.store, &st.cfg.sessions_file, &session_id);
+    st.part_claims.release_session(&session_id);
+    if let Some(session) = get_session(&st.sessions, &session_id) {
+        drop_unused_channel(&st, &session).await;
+    }
+    Json(json!({ "success": true })).into_response()
+}
+
+/// Delete the channel init_upload created for `session` if nothing was ever
+/// posted in it and neither another live upload nor a stored file (history or
+/// trash) points at it: a channel found by name may be an older file's.
+pub async fn drop_unused_channel(st: &AppState, session: &UploadSession) {
+    if st.cfg.simulate { return; }
+    let Some(ch) = session.channel_id.as_deref() else { return };
+    let Ok(ch_id) = ch.parse::<u64>() else { return };
+    let shared = st.sessions.snapshot().iter()
+        .any(|(sid, s)| *sid != session.session_id && s.channel_id.as_deref() == Some(ch));
+    if shared { return; }
+    let stored = dedup::live_records(st).iter().any(|r| {
+        r.channel_id == ch || r.parts_info.iter().any(|p| p.channel_id.as_deref() == Some(ch))
+    });
+    if stored { return; }
+    match discord_bot::delete_channel_if_empty(&st.http, ch_id).await {
+        Ok(true)  => info!("🧹 Removed empty channel {} of session {}", session.channel_name.as_deref().unwrap_or(ch), session.session_id),
+        Ok(false) => {}
+        Err(e)    => warn!("⚠️ Could not clean up channel {ch}: {e}"),
+    }
+}
+
+// ── Upload queue ───────────────────────────────────────────────────────────────
+
+pub async fn list_queue(State(st): State<AppState>) -> impl IntoResponse {
+    Json(json!({ "items": queue::list(&st) }))
+}
+
+pub async fn remove_queue_item(State(st): State<AppState>, Path(id): Path<String>) -> Response {
+    if queue::remove(&st, &id) {
+        Json(json!({ "success": true })).into_response()
+    } else {
+        err(StatusCode::CONFLICT, "Mục đang chạy hoặc không tồn tại")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OfflineUploadQuery { name: Option<String>, folder_id: Option<String>, message: Option<String> }
+
+/// Upload while Discord is unreachable: the raw body is streamed to disk and
+/// queued, `queued_offline` until the bot is back. Works online too (plain queue).
+pub async fn offline_upload(
+    State(st): State<AppState>,
+    Query(q): Query<OfflineUploadQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let filename = q.name.as_deref().unwrap_or("")
+        .rsplit(['/', '\\']).next().unwrap_or("").trim().to_string();
+    if filename.is_empty() { return err(StatusCode::BAD_REQUEST, "Thiếu tên file (?name=)"); }
+    // Same cap as init_upload: up front when the length is given, else as the bytes arrive
+    let declared = headers.get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+    if let Some(e) = declared.and_then(|n| file_too_large(&st, n)) { return e.into_response(); }
+    let dir = st.store.base_dir().join("offline");
+    let path = dir.join(uuid::Uuid::new_v4().simple().to_string());
+    let written = async {
+        tokio::fs::create_dir_all(&dir).await.map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let mut file = tokio::fs::File::create(&path).await
+            .map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let mut stream = body.into_data_stream();
+        let mut total = 0u64;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| err(StatusCode::BAD_REQUEST, format!("Upload bị ngắt: {e}")))?;
+            total += chunk.len() as u64;
+            if let Some(e) = file_too_large(&st, total) { return Err(e.into_response()); }
+            file.write_all(&chunk).await.map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        }
+        file.flush().await.map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if total == 0 { return Err(err(StatusCode::BAD_REQUEST, "File rỗng")); }
+        Ok(total)
+    }.await;
+    let size = match written {
+        Ok(n)  => n,
+        Err(resp) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return resp;
+        }
+    };
+    let mut item = queue::new_item(
+        path.to_string_lossy().into_owned(), filename, size,
+        q.folder_id.unwrap_or_default(), q.message.unwrap_or_default(),
+    );
+    item.delete_source = true;
+    queue::enqueue(&st, item.clone());
+    let item = queue::list(&st).into_iter().find(|i| i.id == item.id).unwrap_or(item);
+    (StatusCode::ACCEPTED, Json(json!({ "success": true, "item": item }))).into_response()
+}
+
+/// What waits for Discord: held-back operations and parked uploads.
+pub async fn list_pending(State(st): State<AppState>) -> impl IntoResponse {
+    let uploads: Vec<_> = queue::list(&st).into_iter().filter(|i| i.status == queue::QUEUED_OFFLINE).collect();
+    Json(json!({
+        "online":        st.connectivity.is_online(),
+        "offline_since": st.connectivity.offline_since(),
+        "operations":    offline::list(&st),
+        "uploads":       uploads,
+    }))
+}
+
+// ── Shares ─────────────────────────────────────────────────────────────────────
+
+pub async fn create_share(
+    State(st): State<AppState>,
+    Path(file_id): Path<i64>,
+    Json(body): Json<Value>,
+) -> Response {
+    if find_record(&st, file_id).is_none() {
+        return err(StatusCode::NOT_FOUND, "File không tồn tại");
+    }
+    let allowed_ips: Vec<String> = body["allowed_ips"].as_array()
+        .map(|a| a.iter().filter_map(|v| v.as_str()).map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default();
+    if let Some(bad) = allowed_ips.iter().find(|r| !shares::is_valid_rule(r)) {
+        return err(StatusCode::BAD_REQUEST, format!("IP/CIDR không hợp lệ: {bad}"));
+    }
+    let max_downloads = if body["one_time"].as_bool().unwrap_or(false) {
+        Some(1)
+    } else {
+        body["max_downloads"].as_u64().map(|n| n.min(u32::MAX as u64) as u32)
+    };
+    if max_downloads == Some(0) {
+        return err(StatusCode::BAD_REQUEST, "max_downloads phải > 0");
+    }
+    let expires_at = body["expires_in_s"].as_u64()
+        .map(|s| chrono::Utc::now().timestamp() + s as i64);
+
+    let share = Share {
+        token:          shares::new_token(),
+        file_id,
+        created_at:     current_datetime_display(),
+        expires_at,
+        max_downloads,
+        download_count: 0,
+        allowed_ips,
+        ended_at:       None,
+    };
+    if let Err(e) = st.store.update_json(&st.cfg.shares_file, |list: &mut Vec<Share>| list.push(share.clone())) {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    info!("🔗 Share created for file {file_id}: {}", share.token);
+    audit(&st, file_id, "shared", json!({
+        "max_downloads": share.max_downloads,
+        "expires_at":    share.expires_at,
+        "ip_rules":      share.allowed_ips.len(),
+    }));
+    Json(json!({
+        "success": true, "share": share, "url": format!("/p/{}/s/{}", st.profile, share.token),
+        "app_url": deep_link::share_url(&st.profile, &share.token),
+    })).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SharesQuery { file_id: Option<i64>, status: Option<String> }
+
+/// `?status=active|expired|exhausted|orphaned|stale` narrows the list, e.g. to review
+/// what the next GC pass will purge (`purge_at`).
+pub async fn list_shares(State(st): State<AppState>, Query(q): Query<SharesQuery>) -> impl IntoResponse {
+    let now   = chrono::Utc::now().timestamp();
+    let grace = st.cfg.link_grace_s as i64;
+    let list: Vec<Value> = st.store.load_shares(&st.cfg.shares_file)
+        .into_iter()
+        .filter(|s| q.file_id.map(|id| s.file_id == id).unwrap_or(true))
+        .filter_map(|s| {
+            let status = shares::share_status(&s, now, find_record(&st, s.file_id).is_some());
+            if !shares::status_matches(q.status.as_deref(), status) { return None; }
+            let purge_at = shares::purge_after(status, s.expires_at, s.ended_at, grace);
+            let mut v = json!(s);
+            v["status"]   = json!(status);
+            v["purge_at"] = json!(purge_at);
+            Some(v)
+        })
+        .collect();
+    Json(json!({ "shares": list }))
+}
+
+/// Remove dead shares and inboxes past the grace period (`?dry_run=true` only counts).
+pub async fn purge_links(State(st): State<AppState>, Query(q): Query<DryRunQuery>) -> impl IntoResponse {
+    let report = purge_stale_links(&st, q.dry_run.unwrap_or(false)).await;
+    Json(json!({ "dry_run": q.dry_run.unwrap_or(false), "purged": report }))
+}
+
+/// Shared by the GC pass and `POST /api/shares/purge`.
+pub async fn purge_stale_links(st: &AppState, dry_run: bool) -> shares::PurgeReport {
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    st.store.locked(&st.cfg.shares_file, || st.store.locked(&st.cfg.inboxes_file, || {
+        let mut list    = st.store.load_shares(&st.cfg.shares_file);
+        let mut inboxes = st.store.load_inboxes(&st.cfg.inboxes_file);
+        let report = shares::purge(
+            &mut list, &mut inboxes,
+            &|id| find_record(st, id).is_some(),
+            &|fid| folders.iter().any(|f| f.id.to_string() == fid),
+            chrono::Utc::now().timestamp(), st.cfg.link_grace_s as i64,
+        );
+        if !dry_run {
+            let _ = st.store.save_shares(&st.cfg.shares_file, &list);
+            let _ = st.store.save_inboxes(&st.cfg.inboxes_file, &inboxes);
+        }
+        report
+    }))
+}
+
+pub async fn revoke_share(State(st): State<AppState>, Path(token): Path<String>) -> impl IntoResponse {
+    let _ = st.store.update_json(&st.cfg.shares_file, |list: &mut Vec<Share>| list.retain(|s| s.token != token));
+    Json(json!({ "success": true }))
+}
+
+/// Public share download. The counter is bumped and persisted before streaming
+/// starts, so an interrupted or leaked link still consumes its quota.
+pub async fn open_share(
+    State(st): State<AppState>,
+    Path(token): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    let counted = st.store.locked(&st.cfg.shares_file, || -> Result<i64, Response> {
+        let mut list = st.store.load_shares(&st.cfg.shares_file);
+        let share = match list.iter_mut().find(|s| s.token == token) {
+            None    => return Err(err(StatusCode::NOT_FOUND, "Link chia sẻ không tồn tại")),
+            Some(s) => s,
+        };
+        match shares::check_access(share, addr.ip(), chrono::Utc::now().timestamp()) {
+            Ok(()) => {}
+            Err(ShareDenied::Expired)      => return Err(err(StatusCode::GONE, "Link chia sẻ đã hết hạn")),
+            Err(ShareDenied::Exhausted)    => return Err(err(StatusCode::GONE, "Link chia sẻ đã hết lượt tải")),
+            Err(ShareDenied::IpNotAllowed) => return Err(err(StatusCode::FORBIDDEN, "IP không được phép truy cập")),
+        }
+        // An expired file takes no quota from the link
+        if let Err(resp) = find_servable(&st, share.file_id) { return Err(resp); }
+        share.download_count += 1;
+        if share.max_downloads.is_some_and(|m| share.download_count >= m) {
+            share.ended_at = Some(chrono::Utc::now().timestamp());
+        }
+        let file_id = share.file_id;
+        st.store.save_shares(&st.cfg.shares_file, &list).map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        Ok(file_id)
+    });
+    let file_id = match counted {
+        Ok(id)    => id,
+        Err(resp) => return resp,
+    };
+    info!("🔗 Share {token} used by {}", addr.ip());
+    match find_servable(&st, file_id) {
+        Err(resp) => resp,
+        Ok(r)     => {
+            audit(&st, file_id, "downloaded", json!({ "via": "share", "ip": addr.ip().to_string() }));
+            make_stream_response(r, st, false, addr.ip())
+        }
+    }
+}
+
+// ── Inboxes ────────────────────────────────────────────────────────────────────
+
+/// Longest an inbox may stay open.
+const INBOX_MAX_TTL_S: u64 = 30 * 24 * 3600;
+
+pub async fn create_inbox(State(st): State<AppState>, Json(body): Json<Value>) -> Response {
+    let folder_id = match &body["folder_id"] {
+        Value::Null => String::new(),
+        v => v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string()),
+    };
+    if !folder_id.is_empty()
+        && !st.store.load_folders(&st.cfg.folders_file).iter().any(|f| f.id.to_string() == folder_id)
+    {
+        return err(StatusCode::NOT_FOUND, "Folder không tồn tại");
+    }
+    let ttl = body["expires_in_s"].as_u64().unwrap_or(24 * 3600);
+    if ttl == 0 || ttl > INBOX_MAX_TTL_S {
+        return err(StatusCode::BAD_REQUEST, format!("expires_in_s phải trong khoảng 1–{INBOX_MAX_TTL_S}"));
+    }
+    let cap = st.cfg.inbox_max_file_bytes;
+    let max_file_bytes = body["max_file_mb"].as_u64().map(|mb| mb * 1024 * 1024).unwrap_or(cap);
+    if max_file_bytes == 0 || max_file_bytes > cap {
+        return err(StatusCode::BAD_REQUEST, format!("max_file_mb phải trong khoảng 1–{}", cap / 1024 / 1024));
+    }
+    let max_files = body["max_files"].as_u64().map(|n| n.min(u32::MAX as u64) as u32);
+    if max_files == Some(0) {
+        return err(StatusCode::BAD_REQUEST, "max_files phải > 0");
+    }
+    let allowed_types: Vec<String> = body["allowed_types"].as_array()
+        .map(|a| a.iter().filter_map(|v| v.as_str())
+            .map(|t| t.trim().trim_start_matches('.').to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect())
+        .unwrap_or_default();
+
+    let inbox = Inbox {
+        token:         shares::new_token(),
+        folder_id,
+        label:         body["label"].as_str().unwrap_or("").trim().to_string(),
+        created_at:    current_datetime_display(),
+        expires_at:    chrono::Utc::now().timestamp() + ttl as i64,
+        max_file_bytes,
+        allowed_types,
+        max_files,
+        upload_count:  0,
+        ended_at:      None,
+    };
+    if let Err(e) = st.store.update_json(&st.cfg.inboxes_file, |list: &mut Vec<Inbox>| list.push(inbox.clone())) {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    info!("📥 Inbox created: {} (folder {:?})", inbox.token, inbox.folder_id);
+    Json(json!({ "success": true, "inbox": inbox, "url": format!("/i/{}", inbox.token) })).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct InboxesQuery { status: Option<String> }
+
+/// `?status=active|expired|full|orphaned|stale`, as for shares.
+pub async fn list_inboxes(State(st): State<AppState>, Query(q): Query<InboxesQuery>) -> impl IntoResponse {
+    let now     = chrono::Utc::now().timestamp();
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    let list: Vec<Value> = st.store.load_inboxes(&st.cfg.inboxes_file)
+        .into_iter()
+        .filter_map(|i| {
+            let has_folder = i.folder_id.is_empty() || folders.iter().any(|f| f.id.to_string() == i.folder_id);
+            let status = shares::inbox_status(&i, now, has_folder);
+            if !shares::status_matches(q.status.as_deref(), status) { return None; }
+            let purge_at = shares::purge_after(status, Some(i.expires_at), i.ended_at, st.cfg.link_grace_s as i64);
+            let mut v = json!(i);
+            v["status"]   = json!(status);
+            v["purge_at"] = json!(purge_at);
+            Some(v)
+        })
+        .collect();
+    Json(json!({ "inboxes": list }))
+}
+
+pub async fn revoke_inbox(State(st): State<AppState>, Path(token): Path<String>) -> impl IntoResponse {
+    let _ = st.store.update_json(&st.cfg.inboxes_file, |list: &mut Vec<Inbox>| list.retain(|i| i.token != token));
     Json(json!({ "success": true }))
 }
 
+fn inbox_denied(e: InboxDenied) -> Response {
+    match e {
+        InboxDenied::Expired        => err(StatusCode::GONE, "Link inbox đã hết hạn"),
+        InboxDenied::Full           => err(StatusCode::GONE, "Link inbox đã nhận đủ số file"),
+        InboxDenied::TooLarge       => err(StatusCode::PAYLOAD_TOO_LARGE, "File vượt quá dung lượng cho phép"),
+        InboxDenied::TypeNotAllowed => err(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Loại file không được phép"),
+    }
+}
+
+/// Minimal public upload page; it posts back to its own URL.
+pub async fn open_inbox(State(st): State<AppState>, Path(token): Path<String>) -> Response {
+    let Some(inbox) = st.store.load_inboxes(&st.cfg.inboxes_file).into_iter().find(|i| i.token == token) else {
+        return err(StatusCode::NOT_FOUND, "Link inbox không tồn tại");
+    };
+    if chrono::Utc::now().timestamp() >= inbox.expires_at {
+        return inbox_denied(InboxDenied::Expired);
+    }
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], include_str!("../../static/inbox.html")).into_response()
+}
+
+/// Public inbox details for the upload page (no folder names or counters).
+pub async fn inbox_info(State(st): State<AppState>, Path(token): Path<String>) -> Response {
+    let Some(inbox) = st.store.load_inboxes(&st.cfg.inboxes_file).into_iter().find(|i| i.token == token) else {
+        return err(StatusCode::NOT_FOUND, "Link inbox không tồn tại");
+    };
+    Json(json!({
+        "label":         inbox.label,
+        "expires_at":    inbox.expires_at,
+        "max_file_bytes": inbox.max_file_bytes,
+        "allowed_types": inbox.allowed_types,
+        "remaining":     inbox.max_files.map(|m| m.saturating_sub(inbox.upload_count)),
+    })).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct InboxUploadQuery { name: Option<String> }
+
+/// Public upload: the raw body is streamed to a temp file, then queued for the
+/// normal ingest pipeline. A slot is reserved up front and handed back on failure.
+pub async fn inbox_upload(
+    State(st): State<AppState>,
+    Path(token): Path<String>,
+    Query(q): Query<InboxUploadQuery>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    // Keep only the last path component of whatever the client calls the file
+    let filename = q.name.as_deref().unwrap_or("")
+        .rsplit(['/', '\\']).next().unwrap_or("").trim().to_string();
+    if filename.is_empty() { return err(StatusCode::BAD_REQUEST, "Thiếu tên file (?name=)"); }
+    let declared = headers.get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+
+    let reserved = st.store.locked(&st.cfg.inboxes_file, || -> Result<Inbox, Response> {
+        let mut list = st.store.load_inboxes(&st.cfg.inboxes_file);
+        let Some(inbox) = list.iter_mut().find(|i| i.token == token) else {
+            return Err(err(StatusCode::NOT_FOUND, "Link inbox không tồn tại"));
+        };
+        shares::check_inbox(inbox, &filename, declared, chrono::Utc::now().timestamp()).map_err(inbox_denied)?;
+        inbox.upload_count += 1;
+        if inbox.max_files.is_some_and(|m| inbox.upload_count >= m) {
+            inbox.ended_at = Some(chrono::Utc::now().timestamp());
+        }
+        let snapshot = inbox.clone();
+        st.store.save_inboxes(&st.cfg.inboxes_file, &list).map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        Ok(snapshot)
+    });
+    let inbox = match reserved {
+        Ok(i)     => i,
+        Err(resp) => return resp,
+    };
+
+    let dir = st.store.base_dir().join("inbox");
+    let path = dir.join(uuid::Uuid::new_v4().simple().to_string());
+    let written = async {
+        tokio::fs::create_dir_all(&dir).await.map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let mut file = tokio::fs::File::create(&path).await
+            .map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let mut stream = body.into_data_stream();
+        let mut total = 0u64;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| err(StatusCode::BAD_REQUEST, format!("Upload bị ngắt: {e}")))?;
+            total += chunk.len() as u64;
+            if total > inbox.max_file_bytes { return Err(inbox_denied(InboxDenied::TooLarge)); }
+            file.write_all(&chunk).await.map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        }
+        file.flush().await.map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if total == 0 { return Err(err(StatusCode::BAD_REQUEST, "File rỗng")); }
+        Ok(total)
+    }.await;
+
+    let size = match written {
+        Ok(n)  => n,
+        Err(resp) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            let _ = st.store.update_json(&st.cfg.inboxes_file, |list: &mut Vec<Inbox>| {
+                if let Some(i) = list.iter_mut().find(|i| i.token == token) {
+                    i.upload_count = i.upload_count.saturating_sub(1);
+                    i.ended_at = None;
+                }
+            });
+            return resp;
+        }
+    };
+
+    let message = if inbox.label.is_empty() { "📥 Inbox".to_string() } else { format!("📥 Inbox: {}", inbox.label) };
+    let mut item = queue::new_item(path.to_string_lossy().into_owned(), filename, size, inbox.folder_id.clone(), message);
+    item.delete_source = true;
+    info!("📥 Inbox {token}: {} ({:.1}MB) from {}", item.filename, size as f64 / 1024.0 / 1024.0, addr.ip());
+    queue::enqueue(&st, item.clone());
+    (StatusCode::ACCEPTED, Json(json!({ "success": true, "filename": item.filename, "size": size }))).into_response()
+}
+
 // ── Search & Stats ─────────────────────────────────────────────────────────────
 
 #[derive(Deserialize)]
-pub struct SearchQuery { q: Option<String> }
+pub struct SearchQuery { q: Option<String>, meta: Option<String> }
 
 pub async fn search_files(State(st): State<AppState>, Query(q): Query<SearchQuery>) -> impl IntoResponse {
     let q_str = q.q.as_deref().unwrap_or("").trim().to_lowercase();
-    if q_str.is_empty() { return Json(json!({ "files": [] })); }
+    if q_str.is_empty() && q.meta.is_none() { return Json(json!({ "files": [] })); }
     let results: Vec<_> = st.store.load_history(&st.cfg.history_file)
         .into_iter()
         .filter(|f| f.filename.to_lowercase().contains(&q_str))
+        .filter(|f| q.meta.as_deref().is_none_or(|m| meta_matches(f, m)))
         .collect();
     Json(json!({ "files": results }))
 }
@@ -438,63 +3329,319 @@ pub async fn search_files(State(st): State<AppState>, Query(q): Query<SearchQuer
 pub async fn get_stats(State(st): State<AppState>) -> impl IntoResponse {
     let history = st.store.load_history(&st.cfg.history_file);
     let folders = st.store.load_folders(&st.cfg.folders_file);
-    let total_mb: f64 = history.iter().map(|f| f.size_mb).sum();
+    // Physical footprint: what sits on Discord/Telegram (zip overhead included).
+    let usage = UsageSummary::of(&history);
+    let cached = cache::usage(&[st.thumbnail_dir.as_path(), st.part_cache_dir.as_path()]);
     Json(json!({
-        "total_files":   history.len(),
-        "total_folders": folders.len(),
-        "total_mb":      (total_mb * 100.0).round() / 100.0,
+        "total_files":      usage.files,
+        "total_folders":    folders.len(),
+        "total_mb":         (usage.total_mb * 100.0).round() / 100.0,
+        "stored_mb":        (usage.stored_mb() * 100.0).round() / 100.0,
+        "stored_bytes":     usage.stored_bytes,
+        "unmeasured_files": usage.unmeasured,
+        "cache": {
+            "files":    cached.files,
+            "bytes":    cached.bytes,
+            "mb":       (cached.bytes as f64 / 1024.0 / 1024.0 * 100.0).round() / 100.0,
+            "limit_mb": st.cfg.cache_max_bytes / 1024 / 1024,
+        },
     }))
 }
 
+#[derive(Deserialize)]
+pub struct ThroughputQuery { range: Option<String>, bucket: Option<String> }
+
+/// Most buckets one response may hold.
+const MAX_THROUGHPUT_BUCKETS: u64 = 2000;
+
+/// Bucketed upload/download speeds over `?range=` (default 7d, at most
+/// `data.throughput_keep_days`), e.g. `?range=24h&bucket=1h`.
+pub async fn throughput_stats(State(st): State<AppState>, Query(q): Query<ThroughputQuery>) -> Response {
+    let range_s = match q.range.as_deref().map(throughput::parse_span) {
+        None          => 7 * 86400,
+        Some(Some(s)) => s.min(st.cfg.throughput_keep_s),
+        Some(None)    => return err(StatusCode::BAD_REQUEST, "range không hợp lệ (vd: 24h, 7d, 4w)"),
+    };
+    let bucket_s = match q.bucket.as_deref().map(throughput::parse_span) {
+        None          => throughput::default_bucket(range_s),
+        Some(Some(s)) => s,
+        Some(None)    => return err(StatusCode::BAD_REQUEST, "bucket không hợp lệ (vd: 1h, 1d)"),
+    };
+    if range_s / bucket_s > MAX_THROUGHPUT_BUCKETS {
+        return err(StatusCode::BAD_REQUEST, format!("Quá nhiều bucket (tối đa {MAX_THROUGHPUT_BUCKETS}) — tăng bucket hoặc giảm range"));
+    }
+    let samples = st.store.load_jsonl::<throughput::Sample>(&st.cfg.throughput_file);
+    let now = chrono::Utc::now().timestamp();
+    Json(throughput::series(&samples, now, range_s, bucket_s)).into_response()
+}
+
+// ── Export ─────────────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+pub struct ManifestQuery { format: Option<String> }
+
+const MANIFEST_COLUMNS: &[&str] =
+    &["id", "filename", "folder", "size_bytes", "checksum", "uploaded_at", "parts", "platforms"];
+
+/// Rows per streamed body chunk.
+const MANIFEST_BATCH: usize = 500;
+
+/// Flat listing of every file for spreadsheets/catalog tools, streamed in batches.
+pub async fn export_manifest(State(st): State<AppState>, Query(q): Query<ManifestQuery>) -> Response {
+    let csv = match q.format.as_deref().unwrap_or("json") {
+        "csv"  => true,
+        "json" => false,
+        other  => return err(StatusCode::BAD_REQUEST, format!("format không hỗ trợ: {other} (csv|json)")),
+    };
+    let history = st.store.load_history(&st.cfg.history_file);
+    let folders: HashMap<String, String> = st.store.load_folders(&st.cfg.folders_file).into_iter()
+        .map(|f| (f.id.to_string(), f.path)).collect();
+
+    let body = Body::from_stream(async_stream::stream! {
+        let mut buf = if csv { MANIFEST_COLUMNS.join(",") + "\n" } else { "[".to_string() };
+        for (i, rec) in history.iter().enumerate() {
+            let entry = manifest_entry(rec, &folders);
+            if csv {
+                let row: Vec<String> = MANIFEST_COLUMNS.iter().map(|c| csv_field(&entry[*c])).collect();
+                buf.push_str(&row.join(","));
+                buf.push('\n');
+            } else {
+                if i > 0 { buf.push(','); }
+                buf.push_str(&entry.to_string());
+            }
+            if (i + 1) % MANIFEST_BATCH == 0 {
+                yield Ok::<_, std::io::Error>(Bytes::from(std::mem::take(&mut buf)));
+            }
+        }
+        if !csv { buf.push(']'); }
+        yield Ok(Bytes::from(buf));
+    });
+    let (mime, ext) = if csv { ("text/csv; charset=utf-8", "csv") } else { ("application/json", "json") };
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, mime)
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"manifest.{ext}\""))
+        .body(body).unwrap()
+}
+
+/// `folders` maps folder id → path, so a subfolder's files say where they are.
+fn manifest_entry(rec: &FileRecord, folders: &HashMap<String, String>) -> Value {
+    let folder = rec.folder_key()
+        .and_then(|id| folders.get(&id).cloned())
+        .or_else(|| rec.folder_name.clone())
+        .unwrap_or_default();
+    let mut platforms: Vec<&str> = rec.parts_info.iter().map(|p| p.platform.as_str()).collect();
+    platforms.sort_unstable();
+    platforms.dedup();
+    json!({
+        "id":          rec.id,
+        "filename":    rec.filename,
+        "folder":      folder,
+        "size_bytes":  rec.exact_size().unwrap_or((rec.size_mb * 1024.0 * 1024.0).round() as u64),
+        "checksum":    rec.blake3.as_ref().map(|h| format!("blake3:{h}")),
+        "uploaded_at": rec.sent_at,
+        "parts":       rec.parts,
+        "platforms":   platforms,
+    })
+}
+
+/// RFC 4180 field: quote when needed, arrays joined with `+`.
+fn csv_field(v: &Value) -> String {
+    let s = match v {
+        Value::Null      => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(a)  => a.iter().filter_map(|x| x.as_str()).collect::<Vec<_>>().join("+"),
+        other            => other.to_string(),
+    };
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s
+    }
+}
+
+// ── OPDS ───────────────────────────────────────────────────────────────────────
+
+fn opds_response(kind: &'static str, body: String) -> Response {
+    ([(header::CONTENT_TYPE, kind)], body).into_response()
+}
+
+fn has_thumbnail(filename: &str) -> bool {
+    matches!(file_category(filename), "image" | "video")
+}
+
+pub async fn opds_root(State(st): State<AppState>) -> Response {
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    let usage = st.store.folder_usage(&st.cfg.history_file);
+    let counts = |key: &str| usage.get(key).map(|u| u.files).unwrap_or(0);
+    opds_response(opds::NAVIGATION, opds::navigation_feed(&folders, &counts))
+}
+
+pub async fn opds_recent(State(st): State<AppState>) -> Response {
+    let mut files = st.store.load_history(&st.cfg.history_file);
+    files.sort_by_key(|r| std::cmp::Reverse(r.id));
+    files.truncate(opds::RECENT_LIMIT);
+    opds_response(opds::ACQUISITION, opds::acquisition_feed(
+        "urn:discord-drive:recent", "Mới tải lên", "recent", "../", &files, &mime_for, &has_thumbnail,
+    ))
+}
+
+/// `root` lists files outside any folder.
+pub async fn opds_folder(State(st): State<AppState>, Path(folder): Path<String>) -> Response {
+    let (key, title) = if folder == "root" {
+        (String::new(), "Thư mục gốc".to_string())
+    } else {
+        match st.store.load_folders(&st.cfg.folders_file).into_iter().find(|f| f.id.to_string() == folder) {
+            Some(f) => (folder.clone(), f.name),
+            None    => return err(StatusCode::NOT_FOUND, "Folder không tồn tại"),
+        }
+    };
+    let mut files: Vec<FileRecord> = st.store.load_history(&st.cfg.history_file).into_iter()
+        .filter(|r| r.folder_key().unwrap_or_default() == key)
+        .collect();
+    files.sort_by_cached_key(|r| r.filename.to_lowercase());
+    opds_response(opds::ACQUISITION, opds::acquisition_feed(
+        &format!("urn:discord-drive:folder:{folder}"), &title, &folder, "../../", &files, &mime_for, &has_thumbnail,
+    ))
+}
+
 // ── Settings ───────────────────────────────────────────────────────────────────
 
 pub async fn get_settings(State(st): State<AppState>) -> impl IntoResponse {
     let cfg_path = st.base_dir.join("config.json");
-    let env_path = st.base_dir.join("bot.env");
+    let env_path = st.env_path.clone();
     let cfg_data: Value = std::fs::read_to_string(&cfg_path)
         .ok().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or(json!({}));
     let env_data = parse_env(&env_path);
-    Json(json!({ "config": cfg_data, "env": env_data }))
+    // For the file as saved, which may not be running yet
+    let warnings = config::validate(&st.base_dir).limits;
+    Json(json!({ "config": cfg_data, "env": env_data, "warnings": warnings }))
 }
 
+/// Saves are queued through `settings::save`, so concurrent tabs can't interleave
+/// writes. The reply lists what changed and which of it needs a restart.
 pub async fn save_settings(State(st): State<AppState>, Json(body): Json<Value>) -> Response {
-    let mut errors = vec![];
-    if let Some(cfg_data) = body.get("config") {
-        match serde_json::to_string_pretty(cfg_data) {
-            Ok(s) => { let _ = std::fs::write(st.base_dir.join("config.json"), s); }
-            Err(e) => errors.push(format!("config.json: {e}")),
-        }
-    }
-    if let Some(env_map) = body.get("env").and_then(|v| v.as_object()) {
-        let content: String = env_map.iter()
-            .map(|(k, v)| format!("{}={}\n", k, v.as_str().unwrap_or("")))
-            .collect();
-        if let Err(e) = std::fs::write(st.base_dir.join("bot.env"), content) {
-            errors.push(format!("bot.env: {e}"));
+    let env = match body.get("env") {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(map)) => Some(map.clone()),
+        Some(_) => return err(StatusCode::BAD_REQUEST, "env phải là object"),
+    };
+    let req = settings::SaveRequest {
+        base_dir: st.base_dir.clone(),
+        env_path: st.env_path.clone(),
+        config:   body.get("config").filter(|v| !v.is_null()).cloned(),
+        env,
+        logs:     st.logs.clone(),
+    };
+    match settings::save(req).await {
+        Ok(report) => {
+            let message = if report.restart_required.is_empty() { "Đã lưu." } else { "Đã lưu. Restart app để áp dụng." };
+            Json(json!({ "success": true, "message": message, "report": report })).into_response()
         }
+        Err(SaveError::Invalid(e)) => err(StatusCode::BAD_REQUEST, e),
+        Err(SaveError::Io(e))      => err(StatusCode::INTERNAL_SERVER_ERROR, e),
     }
-    if !errors.is_empty() {
-        return err(StatusCode::INTERNAL_SERVER_ERROR, errors.join("; "));
+}
+
+/// Unknown and renamed keys in config.json as it is on disk, values outside their
+/// safe range, and whether it still needs migrating (that happens at the next start).
+pub async fn validate_settings(State(st): State<AppState>) -> Response {
+    Json(config::validate(&st.base_dir)).into_response()
+}
+
+// ── Logs ───────────────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    pub level: Option<String>,
+    pub tail:  Option<usize>,
+}
+
+fn parse_level(level: Option<&str>) -> Result<tracing::Level, ApiError> {
+    match level {
+        None | Some("") => Ok(tracing::Level::TRACE),
+        Some(l) => l.parse().map_err(|_| ApiError::new(StatusCode::BAD_REQUEST,
+            format!("level không hợp lệ: {l} (error|warn|info|debug|trace)"))),
     }
-    Json(json!({ "success": true, "message": "Đã lưu. Restart app để áp dụng." })).into_response()
 }
 
-// ── Helpers ────────────────────────────────────────────────────────────────────
+pub async fn get_logs(State(st): State<AppState>, Query(q): Query<LogsQuery>) -> Response {
+    let min = match parse_level(q.level.as_deref()) { Ok(l) => l, Err(e) => return e.into_response() };
+    let tail = q.tail.unwrap_or(200).min(st.cfg.log_buffer_lines);
+    Json(json!({ "lines": st.logs.tail(min, tail) })).into_response()
+}
 
-fn parse_env(path: &std::path::Path) -> HashMap<String, String> {
-    let mut map = HashMap::new();
-    if let Ok(s) = std::fs::read_to_string(path) {
-        for line in s.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') { continue; }
-            if let Some((k, v)) = line.split_once('=') {
-                map.insert(k.trim().to_string(), v.trim().to_string());
+/// SSE: replays the last `tail` lines, then pushes new ones as they are logged.
+pub async fn stream_logs(State(st): State<AppState>, Query(q): Query<LogsQuery>) -> Response {
+    let min = match parse_level(q.level.as_deref()) { Ok(l) => l, Err(e) => return e.into_response() };
+    let mut rx = st.logs.subscribe();
+    let backlog = st.logs.tail(min, q.tail.unwrap_or(0).min(st.cfg.log_buffer_lines));
+    let stream = async_stream::stream! {
+        for line in backlog {
+            yield Event::default().json_data(&line);
+        }
+        loop {
+            match rx.recv().await {
+                Ok(line) if line.at_least(min) => yield Event::default().json_data(&line),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    yield Ok(Event::default().event("lagged").data(n.to_string()));
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
             }
         }
+    };
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct TraceQuery {
+    /// Present (`?on`, `?on=true`) switches tracing on; absent or `false` switches it off.
+    on:      Option<String>,
+    /// EnvFilter directives to add instead of `logs::TRACE_DIRECTIVES`.
+    filter:  Option<String>,
+    minutes: Option<u64>,
+}
+
+/// Tracing switches itself off again so a forgotten toggle doesn't flood the buffer.
+const TRACE_DEFAULT_MIN: u64 = 15;
+const TRACE_MAX_MIN: u64 = 240;
+
+pub async fn get_trace(State(st): State<AppState>) -> Response {
+    match st.logs.trace() {
+        Some(t) => Json(t.state()).into_response(),
+        None    => err(StatusCode::SERVICE_UNAVAILABLE, "Debug trace không khả dụng"),
+    }
+}
+
+/// Raise the log filter to trace for this app (or `?filter=`) for `?minutes=`, or
+/// restore the startup filter. Lines logged meanwhile carry their span ids.
+pub async fn set_trace(State(st): State<AppState>, Query(q): Query<TraceQuery>) -> Response {
+    let Some(trace) = st.logs.trace() else {
+        return err(StatusCode::SERVICE_UNAVAILABLE, "Debug trace không khả dụng");
+    };
+    let on = match q.on.as_deref() {
+        None | Some("false") | Some("0") => false,
+        Some("") | Some("true") | Some("1") => true,
+        Some(v) => return err(StatusCode::BAD_REQUEST, format!("on không hợp lệ: {v} (true|false)")),
+    };
+    let res = if on {
+        let ttl = std::time::Duration::from_secs(q.minutes.unwrap_or(TRACE_DEFAULT_MIN).clamp(1, TRACE_MAX_MIN) * 60);
+        trace.enable(q.filter.as_deref().filter(|f| !f.trim().is_empty()), ttl)
+    } else {
+        trace.disable()
+    };
+    match res {
+        Ok(state) => {
+            info!("🔬 Debug trace {} ({})", if state.on { "on" } else { "off" }, state.filter);
+            Json(state).into_response()
+        }
+        Err(e) => err(StatusCode::BAD_REQUEST, e),
     }
-    map
 }
 
+// ── Helpers ────────────────────────────────────────────────────────────────────
+
 fn mime_for(filename: &str) -> &'static str {
     let ext = std::path::Path::new(filename).extension()
         .and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
@@ -0,0 +1,40 @@
+/// audit.rs — Append-only log of user-visible file operations.
+use serde_json::Value;
+use tracing::warn;
+
+use crate::storage::{current_datetime_iso, AuditEvent, Store};
+
+/// Record an event. Failures are logged, never propagated — auditing must not break the operation.
+pub fn record(store: &dyn Store, file: &str, file_id: Option<i64>, action: &str, detail: Value) {
+    let ev = AuditEvent {
+        ts:      current_datetime_iso(),
+        action:  action.to_string(),
+        file_id,
+        detail,
+    };
+    if let Err(e) = store.append_jsonl(file, &ev) {
+        warn!("⚠️ audit log write failed: {e}");
+    }
+}
+
+/// The same event for several files, appended in one write.
+pub fn record_many(store: &dyn Store, file: &str, file_ids: &[i64], action: &str, detail: Value) {
+    let ts = current_datetime_iso();
+    let events: Vec<AuditEvent> = file_ids.iter().map(|&id| AuditEvent {
+        ts:      ts.clone(),
+        action:  action.to_string(),
+        file_id: Some(id),
+        detail:  detail.clone(),
+    }).collect();
+    if let Err(e) = store.append_jsonl_many(file, &events) {
+        warn!("⚠️ audit log write failed: {e}");
+    }
+}
+
+/// All events for one file, oldest first.
+pub fn for_file(store: &dyn Store, file: &str, file_id: i64) -> Vec<AuditEvent> {
+    store.load_jsonl::<AuditEvent>(file)
+        .into_iter()
+        .filter(|e| e.file_id == Some(file_id))
+        .collect()
+}
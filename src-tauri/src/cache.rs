@@ -0,0 +1,68 @@
+/// cache.rs — Size-capped on-disk caches (thumbnails, parts) with LRU eviction.
+///
+/// Recency is the file's mtime: cache hits call `touch`, so eviction can go by
+/// mtime without relying on atime, which most mounts don't update.
+use serde::Serialize;
+use std::{path::{Path, PathBuf}, time::SystemTime};
+
+/// Ending of a cache entry still being written; scans leave those alone.
+pub const TMP_SUFFIX: &str = ".tmp";
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CacheUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+struct CachedFile {
+    path:  PathBuf,
+    bytes: u64,
+    used:  SystemTime,
+}
+
+fn scan(dirs: &[&Path]) -> Vec<CachedFile> {
+    dirs.iter()
+        .filter_map(|d| std::fs::read_dir(d).ok())
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_name().to_string_lossy().ends_with(TMP_SUFFIX))
+        .filter_map(|e| {
+            let meta = e.metadata().ok().filter(|m| m.is_file())?;
+            Some(CachedFile {
+                path:  e.path(),
+                bytes: meta.len(),
+                used:  meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            })
+        })
+        .collect()
+}
+
+pub fn usage(dirs: &[&Path]) -> CacheUsage {
+    scan(dirs).iter().fold(CacheUsage::default(), |u, f| CacheUsage { files: u.files + 1, bytes: u.bytes + f.bytes })
+}
+
+/// Mark a cache entry as just used.
+pub fn touch(path: &Path) {
+    if let Ok(f) = std::fs::File::options().append(true).open(path) {
+        let _ = f.set_modified(SystemTime::now());
+    }
+}
+
+/// Delete least-recently-used entries until the caches fit in `limit` bytes.
+/// Returns what was removed.
+pub fn evict_lru(dirs: &[&Path], limit: u64) -> CacheUsage {
+    let mut files = scan(dirs);
+    let mut total: u64 = files.iter().map(|f| f.bytes).sum();
+    let mut evicted = CacheUsage::default();
+    if total <= limit { return evicted; }
+    files.sort_by_key(|f| f.used);
+    for f in files {
+        if total <= limit { break; }
+        if std::fs::remove_file(&f.path).is_ok() {
+            total -= f.bytes;
+            evicted.files += 1;
+            evicted.bytes += f.bytes;
+        }
+    }
+    evicted
+}
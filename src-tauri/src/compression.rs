@@ -0,0 +1,38 @@
+/// compression.rs — Decoding of compressed chunk bodies (`Content-Encoding`).
+use std::io::Read;
+
+/// Encodings accepted on the chunk route, advertised by /api/capabilities.
+pub const CHUNK_ENCODINGS: &[&str] = &["identity", "gzip", "zstd"];
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Unsupported(String),
+    TooLarge,
+    Corrupt(std::io::Error),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported(e) => write!(f, "unsupported encoding: {e}"),
+            Self::TooLarge       => write!(f, "decoded chunk exceeds limit"),
+            Self::Corrupt(e)     => write!(f, "corrupt compressed body: {e}"),
+        }
+    }
+}
+
+/// Decode `data` according to a Content-Encoding value. Output larger than
+/// `limit` is rejected so a tiny compressed body can't balloon in memory.
+pub fn decode(encoding: &str, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+    let encoding = encoding.trim().to_ascii_lowercase();
+    let reader: Box<dyn Read + '_> = match encoding.as_str() {
+        "" | "identity"   => return Ok(data.to_vec()),
+        "gzip" | "x-gzip" => Box::new(flate2::read::GzDecoder::new(data)),
+        "zstd"            => Box::new(zstd::stream::read::Decoder::new(data).map_err(DecodeError::Corrupt)?),
+        other             => return Err(DecodeError::Unsupported(other.to_string())),
+    };
+    let mut out = Vec::with_capacity(data.len().saturating_mul(2).min(limit));
+    reader.take(limit as u64 + 1).read_to_end(&mut out).map_err(DecodeError::Corrupt)?;
+    if out.len() > limit { return Err(DecodeError::TooLarge); }
+    Ok(out)
+}
@@ -1,8 +1,12 @@
 /// config.rs — Discord Drive Config Loader
 /// Mirrors Python config.py: reads config.json, validates, falls back to defaults.
+/// Files from older builds are upgraded through the `schema_version` migration chain first.
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}};
+
+use crate::{crypto::PartKey, discord_bot, media_pool, storage::LifecycleRule};
 
 // ─── Raw JSON shapes (with optional fields for validation) ────────────────────
 
@@ -12,10 +16,20 @@ struct RawUpload {
     parallel_chunks:            Option<usize>,
     discord_safe_ratio:         Option<f64>,
     zip_compress_level:         Option<u32>,
+    zip_password:               Option<String>,
     discord_parallel_sends:     Option<usize>,
+    bundle_parts:               Option<usize>,
     tg_parallel_sends:          Option<usize>,
     discord_send_retries:       Option<u32>,
     discord_retry_base_delay_s: Option<u64>,
+    send_order:                 Option<String>,
+    failure_budget:             Option<u32>,
+    status_reactions:           Option<bool>,
+    max_parts:                  Option<u64>,
+    max_file_gb:                Option<u64>,
+    image_quality:              Option<u8>,
+    image_optimize_max_mb:      Option<u64>,
+    dedup_parts:                Option<bool>,
 }
 
 #[derive(Deserialize, Default, Clone)]
@@ -26,6 +40,10 @@ struct RawDownload {
     part_delay_ms:           Option<u64>,
     stream_buffer_kb:        Option<usize>,
     large_file_threshold_mb: Option<u64>,
+    tg_parallel_ranges:      Option<usize>,
+    parallel_parts:          Option<usize>,
+    prefetch_parts:          Option<usize>,
+    lan_bypass:              Option<bool>,
 }
 
 #[derive(Deserialize, Default, Clone)]
@@ -33,6 +51,7 @@ struct RawRam {
     max_total_upload_mb: Option<u64>,
     session_ttl_minutes: Option<u64>,
     gc_interval_minutes: Option<u64>,
+    session_flush_s:     Option<u64>,
 }
 
 #[derive(Deserialize, Default, Clone)]
@@ -42,18 +61,87 @@ struct RawServer {
     log_level:       Option<String>,
     keep_alive_s:    Option<u64>,
     max_concurrency: Option<usize>,
+    log_buffer_lines: Option<usize>,
+    preview_token_ttl_s: Option<u64>,
+    inbox_max_file_mb: Option<u64>,
+    link_grace_hours: Option<u64>,
+    listen:          Option<String>,
 }
 
 #[derive(Deserialize, Default, Clone)]
 struct RawData {
+    backend:       Option<String>,
     history_file:  Option<String>,
     folders_file:  Option<String>,
     sessions_file: Option<String>,
+    shares_file:   Option<String>,
+    diagnostics_file: Option<String>,
+    audit_file:    Option<String>,
+    queue_file:    Option<String>,
+    inboxes_file:  Option<String>,
+    trash_file:    Option<String>,
+    access_file:   Option<String>,
+    rules_file:    Option<String>,
+    throughput_file: Option<String>,
+    keyring_file:  Option<String>,
+    disk_jobs_file: Option<String>,
+    pending_file:   Option<String>,
+    journal_flush_s: Option<u64>,
+    throughput_keep_days: Option<u64>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct RawCache {
+    max_mb:       Option<u64>,
+    parts_max_mb: Option<u64>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct RawMedia {
+    workers:   Option<usize>,
+    max_queue: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct RawEncryption {
+    enabled: Option<bool>,
+    key:     Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct RawLifecycle {
+    enforce:          Option<bool>,
+    interval_minutes: Option<u64>,
+    #[serde(default)]
+    rules:            Vec<LifecycleRule>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct RawTrash {
+    ttl_days:       Option<u64>,
+    delete_channel: Option<bool>,
 }
 
 #[derive(Deserialize, Default, Clone)]
 struct RawTelegram {
     file_limit_mb: Option<u64>,
+    folder_topics: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct RawSimulate {
+    enabled:        Option<bool>,
+    latency_ms:     Option<u64>,
+    jitter_ms:      Option<u64>,
+    error_rate:     Option<f64>,
+    guild_limit_mb: Option<u64>,
+    dir:            Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct RawProfile {
+    data_dir: Option<String>,
+    env_file: Option<String>,
 }
 
 #[derive(Deserialize, Default, Clone)]
@@ -70,10 +158,66 @@ struct RawConfig {
     data:     RawData,
     #[serde(default)]
     telegram: RawTelegram,
+    #[serde(default)]
+    cache:    RawCache,
+    #[serde(default)]
+    media:    RawMedia,
+    #[serde(default)]
+    encryption: RawEncryption,
+    #[serde(default)]
+    lifecycle: RawLifecycle,
+    #[serde(default)]
+    trash:    RawTrash,
+    #[serde(default)]
+    simulate: RawSimulate,
+    #[serde(default)]
+    profiles: BTreeMap<String, RawProfile>,
+    /// Keep values past the soft upper limits instead of bringing them back down.
+    unsafe_overrides: Option<bool>,
 }
 
 // ─── Validated, exported config ───────────────────────────────────────────────
 
+/// Where the HTTP API listens (`server.listen`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Listen {
+    /// `host:port` ("tcp", the default)
+    Tcp,
+    /// "unix:<path>": a Unix domain socket, owner-only
+    Unix(PathBuf),
+    /// "pipe:<name>": the Windows named pipe `\\.\pipe\<name>`
+    Pipe(String),
+    /// "none": no listener at all; only the app window reaches the API, in-process
+    None,
+}
+
+impl Listen {
+    fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        match raw {
+            "" | "tcp" => return Some(Self::Tcp),
+            "none"     => return Some(Self::None),
+            _ => {}
+        }
+        match raw.split_once(':') {
+            Some(("unix", path)) if !path.is_empty() => Some(Self::Unix(PathBuf::from(path))),
+            Some(("pipe", name)) if !name.is_empty() => Some(Self::Pipe(name.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Which `storage::Store` holds the profile's data (`data.backend`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+    /// JSON files in the data dir behind a write-ahead journal (the default)
+    Json,
+    /// Process memory only; nothing survives a restart
+    Memory,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct Config {
     // Upload
@@ -81,23 +225,40 @@ pub struct Config {
     pub parallel_chunks:        usize,
     pub discord_safe_ratio:     f64,
     pub zip_compress_level:     u32,
+    pub zip_password:           Option<String>,  // None = parts are not encrypted
     pub discord_parallel_sends: usize,
+    pub discord_send_interval_ms: u64,   // gap between Discord sends per bot; 0 = unpaced
+    pub bundle_parts:           usize,   // parts per Discord message for Discord-only uploads (1 = off)
     pub tg_parallel_sends:      usize,
+    pub tg_send_interval_ms:    u64,     // gap between Telegram sends; 0 = unpaced
     pub discord_send_retries:   u32,
     pub discord_retry_base_s:   u64,
+    pub sequential_sends:       bool,    // send_order == "sequential"
+    pub failure_budget:         u32,     // consecutive part failures before a platform is abandoned
+    pub status_reactions:       bool,    // ✅/⚠️ on part messages after upload and verification
+    pub max_parts:              u64,     // uploads needing more parts are refused up front
+    pub max_file_bytes:         u64,     // GB → bytes; 0 = unlimited
+    pub image_quality:          u8,      // JPEG quality for uploads with optimize_images
+    pub image_optimize_max_bytes: u64,   // MB → bytes; larger images are sent as-is
+    pub dedup_parts:            bool,    // reference parts already stored instead of sending them again
 
     // Download
     pub http_timeout_s:          u64,
     pub download_retry:          u32,
     pub download_retry_base_s:   u64,
     pub part_delay_ms:           u64,
-    pub read_buffer_bytes:       usize,  // KB → bytes
+    pub read_buffer_bytes:       usize,  // KB → bytes; starting chunk size, then adaptive
     pub large_file_threshold_mb: u64,
+    pub tg_parallel_ranges:      usize,  // 1 = single-stream
+    pub download_parallel_parts: usize,  // server-side jobs writing to disk; 1 = in order
+    pub download_prefetch_parts: usize,  // parts fetched ahead of the one streaming; 0 = one at a time
+    pub download_lan_bypass:     bool,   // local clients get fully cached files straight from disk
 
     // RAM
     pub max_upload_ram_bytes: u64,       // MB → bytes (0 = unlimited)
     pub session_ttl_s:        u64,       // minutes → seconds
     pub gc_interval_s:        u64,       // minutes → seconds
+    pub session_flush_s:      u64,       // in-memory sessions are written out this often
 
     // Server
     pub host:            String,
@@ -105,14 +266,133 @@ pub struct Config {
     pub log_level:       String,
     pub keep_alive_s:    u64,
     pub max_concurrency: usize,
+    pub log_buffer_lines: usize,         // lines kept for GET /api/logs
+    pub preview_token_ttl_s: u64,        // max lifetime of a media ?token=
+    pub inbox_max_file_bytes: u64,       // per-file cap (and default) for public inboxes
+    pub link_grace_s:    u64,            // dead shares/inboxes stay listed this long before GC purges them
+    pub listen:          Listen,
 
     // Data files
+    pub store_backend: StoreBackend,
     pub history_file:  String,
     pub folders_file:  String,
     pub sessions_file: String,
+    pub shares_file:   String,
+    pub diagnostics_file: String,
+    pub audit_file:    String,
+    pub queue_file:    String,
+    pub inboxes_file:  String,
+    pub trash_file:    String,
+    pub access_file:   String,
+    pub rules_file:    String,
+    pub throughput_file: String,
+    pub keyring_file:  String,
+    pub disk_jobs_file: String,          // resume journals of download-to-disk jobs
+    pub pending_file:   String,          // Discord work held back while offline
+    pub journal_flush_s: u64,            // journaled saves are rewritten as JSON this often
+    pub throughput_keep_s: u64,          // GC drops speed samples older than this
 
     // Telegram
     pub tg_file_limit_bytes: u64,        // MB → bytes
+    pub tg_folder_topics:    bool,       // one forum topic per drive folder
+
+    // Disk caches
+    pub cache_max_bytes: u64,            // per profile, thumbnails (and parts while parts_max_mb is 0); 0 = unlimited
+    pub part_cache_max_bytes: u64,       // parts kept after merges; 0 = only prefetched parts are cached
+
+    // Media workers (thumbnails, image recompression), shared by all profiles
+    pub media_workers:   usize,          // 0 in config = half the CPU cores
+    pub media_max_queue: usize,          // background jobs waiting; beyond this → 503
+
+    // Encryption (AES-256-GCM on each part before zipping)
+    pub encryption_enabled: bool,        // with no valid key, parts are refused rather than sent in clear
+    #[serde(skip)]
+    pub encryption_key:     Option<PartKey>,
+
+    // Lifecycle
+    pub lifecycle_rules:      Vec<LifecycleRule>,  // per-folder rules live in folders.json
+    pub lifecycle_enforce:    bool,      // false = the job only reports what it would do
+    pub lifecycle_interval_s: u64,       // minutes → seconds
+
+    // Trash
+    pub trash_ttl_s:          u64,       // days → seconds; 0 = no trash, deletes are immediate
+    pub trash_delete_channel: bool,      // purging also deletes the file's channel
+
+    // Simulation (`--simulate`): parts go to local files instead of Discord/Telegram
+    pub simulate:              bool,
+    pub sim_latency_ms:        u64,      // per send/fetch, ± sim_jitter_ms
+    pub sim_jitter_ms:         u64,
+    pub sim_error_rate:        f64,      // share of sends/fetches that fail (then retried)
+    pub sim_guild_limit_bytes: u64,      // MB → bytes; stands in for the guild's boost tier
+    pub sim_dir:               PathBuf,  // resolved against base_dir
+
+    // Extra named profiles (the implicit "default" profile is not listed)
+    pub profiles: Vec<ProfileSpec>,
+
+    pub unsafe_overrides: bool,
+    /// Values that were out of range, and what was used instead.
+    pub limit_warnings:   Vec<LimitWarning>,
+}
+
+/// A config value outside its safe range.
+#[derive(Clone, Debug, Serialize)]
+pub struct LimitWarning {
+    pub key:        String,   // dotted config.json path
+    pub value:      Value,    // as written
+    pub min:        Value,
+    pub max:        Value,
+    pub applied:    Value,    // what the running config uses
+    /// Kept past `max` because of `unsafe_overrides`.
+    pub overridden: bool,
+}
+
+impl std::fmt::Display for LimitWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.overridden {
+            write!(f, "config value {} = {} above safe max {} → kept (unsafe_overrides)", self.key, self.value, self.max)
+        } else {
+            write!(f, "config value {} = {} out of range [{},{}] → {}", self.key, self.value, self.min, self.max, self.applied)
+        }
+    }
+}
+
+/// Parallel sends each platform takes without hitting rate limits: Discord allows
+/// 5 messages per 5 s per channel and bot, Telegram about 20 per minute per group.
+pub const DISCORD_SAFE_SENDS: usize = 5;
+pub const TG_SAFE_SENDS:      usize = 5;
+const DISCORD_PACED_INTERVAL_MS: u64 = 1000;
+const TG_PACED_INTERVAL_MS:      u64 = 3000;
+
+/// Sends within the safe parallelism go out as fast as permits allow; past it
+/// they are spaced so the overall rate stays under the platform's limit.
+fn send_interval_ms(parallel: usize, safe: usize, interval_ms: u64) -> u64 {
+    if parallel > safe { interval_ms } else { 0 }
+}
+
+/// `u.client_chunk_mb`, as written in `from_raw`, → `upload.client_chunk_mb`.
+fn key_path(expr: &str) -> String {
+    let expr = expr.strip_prefix("r.").unwrap_or(expr);
+    let (section, key) = expr.split_once('.').unwrap_or(("", expr));
+    let section = match section {
+        "u"   => "upload",
+        "d"   => "download",
+        "m"   => "ram",
+        "s"   => "server",
+        "dt"  => "data",
+        "tg"  => "telegram",
+        "sim" => "simulate",
+        other => other,
+    };
+    format!("{section}.{key}")
+}
+
+/// A separate drive: own guild/tokens (env file) and own data files (data dir).
+/// Paths are relative to the app base dir.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProfileSpec {
+    pub name:     String,
+    pub data_dir: String,
+    pub env_file: String,
 }
 
 impl Config {
@@ -121,13 +401,16 @@ impl Config {
         let raw: RawConfig = if path.exists() {
             match fs::read_to_string(&path)
                 .context("read config.json")
-                .and_then(|s| {
-                    // Strip keys starting with "_" using serde_json value manipulation
-                    let mut val: serde_json::Value = serde_json::from_str(&s)?;
-                    strip_comment_keys(&mut val);
-                    serde_json::from_value(val).map_err(Into::into)
-                }) {
-                Ok(r) => r,
+                .and_then(|s| parse(&s)) {
+                Ok(p) => {
+                    for key in &p.report.unknown {
+                        eprintln!("⚠️  config key {key} unknown → ignored");
+                    }
+                    if let Some(doc) = p.migrated {
+                        write_back(&path, &doc, &p.report);
+                    }
+                    p.raw
+                }
                 Err(e) => {
                     eprintln!("⚠️  config.json parse error: {e} → using defaults");
                     RawConfig::default()
@@ -138,7 +421,12 @@ impl Config {
             RawConfig::default()
         };
 
-        Self::from_raw(raw)
+        let mut cfg = Self::from_raw(raw);
+        for w in &cfg.limit_warnings {
+            eprintln!("⚠️  {w}");
+        }
+        cfg.sim_dir = base_dir.join(&cfg.sim_dir);
+        cfg
     }
 
     fn from_raw(r: RawConfig) -> Self {
@@ -149,47 +437,68 @@ impl Config {
         let dt = &r.data;
         let tg = &r.telegram;
 
-        macro_rules! clamp {
-            ($val:expr, $default:expr, $lo:expr, $hi:expr) => {{
+        let unsafe_overrides = r.unsafe_overrides.unwrap_or(false);
+        let mut warnings: Vec<LimitWarning> = Vec::new();
+
+        // Out of range → the nearest bound, with a warning. Past the upper bound is
+        // kept as written under `unsafe_overrides`; sends then pace themselves.
+        macro_rules! limit {
+            ($val:expr, $default:expr, $lo:expr, $hi:expr, $overridable:expr) => {{
                 let v = $val.unwrap_or($default);
-                let lo = $lo;
-                let hi = $hi;
+                let (lo, hi) = ($lo, $hi);
                 if v < lo || v > hi {
-                    eprintln!("⚠️  config value {} out of range [{lo},{hi}] → default {}", v, $default);
-                    $default
+                    let overridden = $overridable && v > hi;
+                    let applied = if overridden { v } else if v < lo { lo } else { hi };
+                    warnings.push(LimitWarning {
+                        key:        key_path(stringify!($val)),
+                        value:      Value::from(v),
+                        min:        Value::from(lo),
+                        max:        Value::from(hi),
+                        applied:    Value::from(applied),
+                        overridden,
+                    });
+                    applied
                 } else {
                     v
                 }
             }};
         }
-        macro_rules! clamp_opt_hi {
-            ($val:expr, $default:expr, $lo:expr) => {{
-                let v = $val.unwrap_or($default);
-                if v < $lo {
-                    eprintln!("⚠️  config value {} < min {} → default {}", v, $lo, $default);
-                    $default
-                } else {
-                    v
-                }
-            }};
+        macro_rules! clamp {
+            ($val:expr, $default:expr, $lo:expr, $hi:expr) => { limit!($val, $default, $lo, $hi, unsafe_overrides) };
+        }
+        // Bounds set by file formats or platform APIs: no override gets past them
+        macro_rules! clamp_hard {
+            ($val:expr, $default:expr, $lo:expr, $hi:expr) => { limit!($val, $default, $lo, $hi, false) };
         }
 
         let client_chunk_mb = clamp!(u.client_chunk_mb, 4, 1, 50);
         let parallel_chunks = clamp!(u.parallel_chunks, 4, 1, 16);
-        let discord_safe_ratio_raw = u.discord_safe_ratio.unwrap_or(0.85_f64);
-        let discord_safe_ratio = if !(0.5..=0.99).contains(&discord_safe_ratio_raw) { 0.85 } else { discord_safe_ratio_raw };
-        let zip_compress_level = clamp!(u.zip_compress_level, 0, 0, 9);
+        let discord_safe_ratio = clamp_hard!(u.discord_safe_ratio, 0.85_f64, 0.5, 0.99);
+        let zip_compress_level = clamp_hard!(u.zip_compress_level, 0, 0, 9);
         let discord_parallel_sends = clamp!(u.discord_parallel_sends, 3, 1, 5);
         let tg_parallel_sends = clamp!(u.tg_parallel_sends, 3, 1, 5);
         let discord_send_retries = clamp!(u.discord_send_retries, 3, 1, 10);
         let discord_retry_base_s = clamp!(u.discord_retry_base_delay_s, 2, 1, 30);
+        let sequential_sends = match u.send_order.as_deref().unwrap_or("parallel") {
+            "sequential" => true,
+            "parallel"   => false,
+            other => {
+                eprintln!("⚠️  config value send_order '{other}' invalid → default parallel");
+                false
+            }
+        };
+
+        let failure_budget = clamp!(u.failure_budget, 3, 1, 50);
 
         let http_timeout_s = clamp!(d.http_timeout_s, 600, 30, 3600);
         let download_retry = clamp!(d.retry_count, 3, 1, 10);
         let download_retry_base_s = clamp!(d.retry_base_delay_s, 2, 1, 30);
         let part_delay_ms = clamp!(d.part_delay_ms, 150, 0, 5000);
         let stream_buffer_kb = clamp!(d.stream_buffer_kb, 64, 8, 4096);
-        let large_file_threshold_mb = clamp_opt_hi!(d.large_file_threshold_mb, 500, 50);
+        let large_file_threshold_mb = clamp!(d.large_file_threshold_mb, 500, 50, u64::MAX);
+        let tg_parallel_ranges = clamp!(d.tg_parallel_ranges, 4, 1, 16);
+        let download_parallel_parts = clamp!(d.parallel_parts, 4, 1, 16);
+        let download_prefetch_parts = clamp!(d.prefetch_parts, 3, 0, 16);
 
         let max_total_upload_mb = m.max_total_upload_mb.unwrap_or(512);
         let session_ttl_minutes = clamp!(m.session_ttl_minutes, 60, 5, 1440);
@@ -200,17 +509,39 @@ impl Config {
             log_level_raw
         } else { "info".to_string() };
 
-        let tg_file_limit_mb = clamp!(tg.file_limit_mb, 50, 10, 4000);
+        let tg_file_limit_mb = clamp_hard!(tg.file_limit_mb, 50, 10, 4000);
+
+        let encryption_enabled = r.encryption.enabled.unwrap_or(false);
+        let encryption_key = r.encryption.key.as_deref().filter(|k| !k.trim().is_empty()).and_then(|k| {
+            let key = PartKey::from_hex(k);
+            if key.is_none() { eprintln!("⚠️  encryption.key must be 64 hex characters → ignored"); }
+            key
+        });
+
+        let sim = &r.simulate;
+        let sim_error_rate = clamp_hard!(sim.error_rate, 0.0, 0.0, 0.9);
 
         Config {
             client_chunk_bytes:       client_chunk_mb * 1024 * 1024,
             parallel_chunks,
             discord_safe_ratio,
             zip_compress_level,
+            zip_password:           u.zip_password.clone().filter(|p| !p.is_empty()),
             discord_parallel_sends,
+            discord_send_interval_ms: send_interval_ms(discord_parallel_sends, DISCORD_SAFE_SENDS, DISCORD_PACED_INTERVAL_MS),
+            bundle_parts:           clamp_hard!(u.bundle_parts, 1, 1, discord_bot::MAX_ATTACHMENTS),
             tg_parallel_sends,
+            tg_send_interval_ms:    send_interval_ms(tg_parallel_sends, TG_SAFE_SENDS, TG_PACED_INTERVAL_MS),
             discord_send_retries,
             discord_retry_base_s,
+            sequential_sends,
+            failure_budget,
+            status_reactions:       u.status_reactions.unwrap_or(false),
+            max_parts:              clamp!(u.max_parts, 5000, 10, 100_000),
+            max_file_bytes:         clamp!(u.max_file_gb, 0, 0, 100_000) * 1024 * 1024 * 1024,
+            image_quality:          clamp!(u.image_quality, 82, 30, 95),
+            image_optimize_max_bytes: clamp!(u.image_optimize_max_mb, 64, 1, 512) * 1024 * 1024,
+            dedup_parts:            u.dedup_parts.unwrap_or(true),
 
             http_timeout_s,
             download_retry,
@@ -218,42 +549,341 @@ impl Config {
             part_delay_ms,
             read_buffer_bytes:       stream_buffer_kb * 1024,
             large_file_threshold_mb,
+            tg_parallel_ranges,
+            download_parallel_parts,
+            download_prefetch_parts,
+            download_lan_bypass:     d.lan_bypass.unwrap_or(true),
 
             max_upload_ram_bytes: max_total_upload_mb * 1024 * 1024,
             session_ttl_s:        session_ttl_minutes * 60,
             gc_interval_s:        gc_interval_minutes * 60,
+            session_flush_s:      clamp!(m.session_flush_s, 5, 1, 300),
 
             host:            s.host.clone().unwrap_or_else(|| "0.0.0.0".to_string()),
             port:            s.port.unwrap_or(8000),
             log_level,
             keep_alive_s:    clamp!(s.keep_alive_s, 600, 10, 3600),
             max_concurrency: clamp!(s.max_concurrency, 5, 1, 100),
-
+            log_buffer_lines: clamp!(s.log_buffer_lines, 2000, 100, 50000),
+            preview_token_ttl_s: clamp!(s.preview_token_ttl_s, 300, 30, 3600),
+            inbox_max_file_bytes: clamp!(s.inbox_max_file_mb, 2048, 1, 102400) * 1024 * 1024,
+            link_grace_s:    clamp!(s.link_grace_hours, 24, 0, 8760) * 3600,
+            listen:          match s.listen.as_deref().map(Listen::parse) {
+                None          => Listen::Tcp,
+                Some(Some(l)) => l,
+                Some(None)    => {
+                    eprintln!("⚠️  server.listen invalid (tcp | unix:<path> | pipe:<name> | none) → tcp");
+                    Listen::Tcp
+                }
+            },
+
+            store_backend: match dt.backend.as_deref().unwrap_or("json") {
+                "json"   => StoreBackend::Json,
+                "memory" => StoreBackend::Memory,
+                other => {
+                    eprintln!("⚠️  config value data.backend '{other}' invalid → default json");
+                    StoreBackend::Json
+                }
+            },
             history_file:  dt.history_file.clone().unwrap_or_else(|| "file_history.json".to_string()),
             folders_file:  dt.folders_file.clone().unwrap_or_else(|| "folders.json".to_string()),
             sessions_file: dt.sessions_file.clone().unwrap_or_else(|| "upload_sessions.json".to_string()),
+            shares_file:   dt.shares_file.clone().unwrap_or_else(|| "shares.json".to_string()),
+            diagnostics_file: dt.diagnostics_file.clone().unwrap_or_else(|| "upload_diagnostics.json".to_string()),
+            audit_file:    dt.audit_file.clone().unwrap_or_else(|| "audit_log.jsonl".to_string()),
+            queue_file:    dt.queue_file.clone().unwrap_or_else(|| "upload_queue.json".to_string()),
+            inboxes_file:  dt.inboxes_file.clone().unwrap_or_else(|| "inboxes.json".to_string()),
+            trash_file:    dt.trash_file.clone().unwrap_or_else(|| "trash.json".to_string()),
+            access_file:   dt.access_file.clone().unwrap_or_else(|| "file_access.json".to_string()),
+            rules_file:    dt.rules_file.clone().unwrap_or_else(|| "folder_rules.json".to_string()),
+            throughput_file: dt.throughput_file.clone().unwrap_or_else(|| "throughput.jsonl".to_string()),
+            keyring_file:  dt.keyring_file.clone().unwrap_or_else(|| "keyring.json".to_string()),
+            disk_jobs_file: dt.disk_jobs_file.clone().unwrap_or_else(|| "download_jobs.json".to_string()),
+            pending_file:   dt.pending_file.clone().unwrap_or_else(|| "pending_ops.json".to_string()),
+            journal_flush_s: clamp!(dt.journal_flush_s, 10, 1, 3600),
+            throughput_keep_s: clamp!(dt.throughput_keep_days, 90, 1, 3650) * 86400,
 
             tg_file_limit_bytes: tg_file_limit_mb * 1024 * 1024,
+            tg_folder_topics:    tg.folder_topics.unwrap_or(false),
+
+            cache_max_bytes: clamp!(r.cache.max_mb, 512, 0, 102400) * 1024 * 1024,
+            part_cache_max_bytes: clamp!(r.cache.parts_max_mb, 256, 0, 102400) * 1024 * 1024,
+
+            media_workers:   match clamp!(r.media.workers, 0, 0, 64) {
+                0 => media_pool::default_workers(),
+                n => n,
+            },
+            media_max_queue: clamp!(r.media.max_queue, media_pool::DEFAULT_MAX_QUEUE, 1, 10000),
+
+            encryption_enabled,
+            encryption_key,
+
+            lifecycle_rules:      r.lifecycle.rules.clone(),
+            lifecycle_enforce:    r.lifecycle.enforce.unwrap_or(false),
+            lifecycle_interval_s: clamp!(r.lifecycle.interval_minutes, 60, 5, 10080) * 60,
+
+            trash_ttl_s:          clamp!(r.trash.ttl_days, 30, 0, 3650) * 86400,
+            trash_delete_channel: r.trash.delete_channel.unwrap_or(false),
+
+            simulate:              sim.enabled.unwrap_or(false),
+            sim_latency_ms:        clamp!(sim.latency_ms, 200, 0, 60_000),
+            sim_jitter_ms:         clamp!(sim.jitter_ms, 100, 0, 60_000),
+            sim_error_rate,
+            sim_guild_limit_bytes: clamp!(sim.guild_limit_mb, 10, 1, 500) * 1024 * 1024,
+            sim_dir:               PathBuf::from(sim.dir.clone().unwrap_or_else(|| "simulated_parts".to_string())),
+
+            profiles: r.profiles.iter()
+                .filter(|(name, _)| {
+                    let ok = is_valid_profile_name(name) && name.as_str() != "default";
+                    if !ok { eprintln!("⚠️  profile name '{name}' invalid → ignored"); }
+                    ok
+                })
+                .map(|(name, p)| ProfileSpec {
+                    name:     name.clone(),
+                    data_dir: p.data_dir.clone().unwrap_or_else(|| format!("profiles/{name}")),
+                    env_file: p.env_file.clone().unwrap_or_else(|| format!("profiles/{name}/bot.env")),
+                })
+                .collect(),
+
+            unsafe_overrides,
+            // Last, after every clamp! above has had its say
+            limit_warnings: warnings,
         }
     }
 
+    /// Largest chunk body accepted: client_chunk_mb + 20% headroom, at least 50MB.
+    /// Also caps the decoded size of compressed chunks.
+    pub fn chunk_body_limit(&self) -> usize {
+        (((self.client_chunk_bytes as f64) * 1.2) as usize).max(50 * 1024 * 1024)
+    }
+
+    /// JSON stores besides history (the audit and throughput logs are append-only JSONL and not cached).
+    pub fn data_files(&self) -> Vec<&str> {
+        vec![&self.folders_file, &self.sessions_file, &self.shares_file, &self.diagnostics_file,
+             &self.queue_file, &self.inboxes_file, &self.trash_file, &self.access_file,
+             &self.rules_file, &self.keyring_file, &self.disk_jobs_file, &self.pending_file]
+    }
+
     pub fn print_summary(&self) {
         println!("{}", "─".repeat(60));
         println!("⚙️  Discord Drive Config (Rust + Tauri)");
         let chunk_mb = self.client_chunk_bytes / 1024 / 1024;
         println!("   Upload  : chunk={chunk_mb}MB  parallel_chunks={}  safe_ratio={}", self.parallel_chunks, self.discord_safe_ratio);
-        println!("   Discord : parallel_sends={}  zip_level={}  retries={}", self.discord_parallel_sends, self.zip_compress_level, self.discord_send_retries);
+        let order = if self.sequential_sends { "sequential" } else { "parallel" };
+        println!("   Discord : parallel_sends={}  zip_level={}  retries={}  order={order}  failure_budget={}  bundle={}  dedup={}", self.discord_parallel_sends, self.zip_compress_level, self.discord_send_retries, self.failure_budget, self.bundle_parts, self.dedup_parts);
+        if self.zip_password.is_some() { println!("   ZIP     : AES-256 password enabled"); }
+        if self.encryption_enabled {
+            let state = if self.encryption_key.is_some() { "on" } else { "ON but key invalid → uploads fail" };
+            println!("   Encrypt : AES-256-GCM {state}");
+        }
+        if self.discord_send_interval_ms > 0 {
+            println!("   Pacing  : Discord sends {}ms apart per bot (parallel_sends > {DISCORD_SAFE_SENDS})", self.discord_send_interval_ms);
+        }
         let tg_limit_mb = self.tg_file_limit_bytes / 1024 / 1024;
-        println!("   Telegram: parallel_sends={}  file_limit={tg_limit_mb}MB", self.tg_parallel_sends);
-        println!("   Download: timeout={}s  retry={}  large>={}MB", self.http_timeout_s, self.download_retry, self.large_file_threshold_mb);
+        println!("   Telegram: parallel_sends={}  file_limit={tg_limit_mb}MB  ranges={}  folder_topics={}", self.tg_parallel_sends, self.tg_parallel_ranges, self.tg_folder_topics);
+        if self.tg_send_interval_ms > 0 {
+            println!("   Pacing  : Telegram sends {}ms apart (parallel_sends > {TG_SAFE_SENDS})", self.tg_send_interval_ms);
+        }
+        println!("   Download: timeout={}s  retry={}  large>={}MB  prefetch={}  to_disk_parallel={}  lan_bypass={}", self.http_timeout_s, self.download_retry, self.large_file_threshold_mb, self.download_prefetch_parts, self.download_parallel_parts, self.download_lan_bypass);
         let ram_limit_mb = self.max_upload_ram_bytes / 1024 / 1024;
         let ram_label = if self.max_upload_ram_bytes == 0 { "unlimited".to_string() } else { format!("{ram_limit_mb}MB") };
-        println!("   RAM     : max_upload={ram_label}  ttl={}min  gc={}min", self.session_ttl_s / 60, self.gc_interval_s / 60);
-        println!("   Server  : {}:{}  log={}  concurrency={}", self.host, self.port, self.log_level, self.max_concurrency);
+        println!("   RAM     : max_upload={ram_label}  ttl={}min  gc={}min  flush={}s", self.session_ttl_s / 60, self.gc_interval_s / 60, self.session_flush_s);
+        let cache_label = if self.cache_max_bytes == 0 { "unlimited".to_string() } else { format!("{}MB", self.cache_max_bytes / 1024 / 1024) };
+        println!("   Cache   : max={cache_label} (thumbnails, evicted LRU every gc)  parts={}MB", self.part_cache_max_bytes / 1024 / 1024);
+        println!("   Media   : workers={}  max_queue={}", self.media_workers, self.media_max_queue);
+        if !self.lifecycle_rules.is_empty() {
+            let mode = if self.lifecycle_enforce { "enforce" } else { "report only" };
+            println!("   Lifecycle: {} rule(s), every {}min, {mode}", self.lifecycle_rules.len(), self.lifecycle_interval_s / 60);
+        }
+        match self.trash_ttl_s {
+            0   => println!("   Trash   : off (deletes are immediate)"),
+            ttl => println!("   Trash   : purge after {}d  delete_channel={}", ttl / 86400, self.trash_delete_channel),
+        }
+        let listen = match &self.listen {
+            Listen::Tcp     => format!("{}:{}", self.host, self.port),
+            Listen::Unix(p) => format!("unix:{}", p.display()),
+            Listen::Pipe(n) => format!("pipe:{n}"),
+            Listen::None    => "none (app window only)".to_string(),
+        };
+        println!("   Server  : {listen}  log={}  concurrency={}", self.log_level, self.max_concurrency);
+        if self.store_backend == StoreBackend::Memory { println!("   Store   : memory (nothing is saved)"); }
+        if self.simulate {
+            println!("   Simulate: ON → {}  latency={}±{}ms  errors={:.0}%  guild_limit={}MB",
+                self.sim_dir.display(), self.sim_latency_ms, self.sim_jitter_ms,
+                self.sim_error_rate * 100.0, self.sim_guild_limit_bytes / 1024 / 1024);
+        }
+        if !self.profiles.is_empty() {
+            let names: Vec<&str> = self.profiles.iter().map(|p| p.name.as_str()).collect();
+            println!("   Profiles: default, {}", names.join(", "));
+        }
+        if self.unsafe_overrides {
+            let n = self.limit_warnings.iter().filter(|w| w.overridden).count();
+            println!("   Limits  : unsafe_overrides ON ({n} value(s) past the safe max)");
+        }
         println!("{}", "─".repeat(60));
     }
 }
 
+/// Profile names end up in URLs (`/p/<name>/api/...`) and paths.
+pub fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 32
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// ─── Schema versions ──────────────────────────────────────────────────────────
+
+/// `schema_version` this build writes. A config.json without one is version 0.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// One step of the chain: upgrades a file at version `to - 1` to `to`.
+struct Migration {
+    to:      u32,
+    /// Dotted (old, new) key paths; the old key's "_" doc moves along.
+    renames: &'static [(&'static str, &'static str)],
+}
+
+/// Renaming or moving a key: add a step here and bump SCHEMA_VERSION, so files
+/// written for older builds keep their value instead of falling back to the default.
+const MIGRATIONS: &[Migration] = &[
+    // 0 → 1: introduces schema_version itself
+    Migration { to: 1, renames: &[] },
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Renamed {
+    pub from: &'static str,
+    pub to:   &'static str,
+}
+
+/// What `GET /api/settings/validation` reports about config.json.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SchemaReport {
+    pub schema_version:  u32,            // as found in the file
+    pub current_version: u32,
+    /// The file needs (or, at startup, got) migrating to `current_version`.
+    pub migrated:        bool,
+    /// Written by a newer build: keys it knows may show up as unknown here.
+    pub newer:           bool,
+    /// Keys this build doesn't read: typos, or settings that no longer exist.
+    pub unknown:         Vec<String>,
+    /// Old key names present in the file, with the key each one became.
+    pub deprecated:      Vec<Renamed>,
+    /// Values outside their safe range, and what a restart would use instead.
+    pub limits:          Vec<LimitWarning>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error:           Option<String>,
+}
+
+struct Parsed {
+    raw:      RawConfig,
+    /// The upgraded document, "_" docs included, when a migration step ran.
+    migrated: Option<Value>,
+    report:   SchemaReport,
+}
+
+fn parse(text: &str) -> anyhow::Result<Parsed> {
+    let mut doc: Value = serde_json::from_str(text)?;
+    let found = doc.get("schema_version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    let mut report = SchemaReport {
+        schema_version:  found,
+        current_version: SCHEMA_VERSION,
+        newer:           found > SCHEMA_VERSION,
+        ..Default::default()
+    };
+    for m in MIGRATIONS.iter().filter(|m| m.to > found) {
+        for &(from, to) in m.renames {
+            if rename_key(&mut doc, from, to) {
+                report.deprecated.push(Renamed { from, to });
+            }
+        }
+        report.migrated = true;
+    }
+    let migrated = match doc.as_object_mut() {
+        Some(map) if report.migrated => {
+            map.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+            Some(doc.clone())
+        }
+        _ => None,
+    };
+
+    // Strip keys starting with "_" using serde_json value manipulation
+    strip_comment_keys(&mut doc);
+    if let Some(map) = doc.as_object_mut() { map.remove("schema_version"); }
+    let raw = serde_ignored::deserialize(doc, |path| report.unknown.push(path.to_string()))?;
+    Ok(Parsed { raw, migrated, report })
+}
+
+/// Move `from` (and its "_" doc) to `to`. Returns whether `from` was there; an
+/// existing `to` wins over it.
+fn rename_key(doc: &mut Value, from: &str, to: &str) -> bool {
+    let Some(value) = take_key(doc, from) else { return false };
+    let doc_key = |path: &str| match path.rsplit_once('.') {
+        Some((parent, key)) => format!("{parent}._{key}"),
+        None                => format!("_{path}"),
+    };
+    let note = take_key(doc, &doc_key(from));
+    put_key(doc, to, value);
+    if let Some(note) = note { put_key(doc, &doc_key(to), note); }
+    true
+}
+
+fn take_key(doc: &mut Value, path: &str) -> Option<Value> {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((parent, key)) => (parent.split('.').try_fold(&mut *doc, |v, k| v.get_mut(k))?, key),
+        None                => (doc, path),
+    };
+    parent.as_object_mut()?.remove(key)
+}
+
+fn put_key(doc: &mut Value, path: &str, value: Value) {
+    let mut node = doc;
+    let mut keys = path.split('.').peekable();
+    while let Some(key) = keys.next() {
+        let Some(map) = node.as_object_mut() else { return };
+        if keys.peek().is_none() {
+            map.entry(key.to_string()).or_insert(value);
+            return;
+        }
+        node = map.entry(key.to_string()).or_insert_with(|| Value::Object(Default::default()));
+    }
+}
+
+/// Save the migrated config.json, keeping the original as config.json.v<N>.bak.
+fn write_back(path: &Path, doc: &Value, report: &SchemaReport) {
+    let backup = path.with_extension(format!("json.v{}.bak", report.schema_version));
+    let written = fs::copy(path, &backup)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(serde_json::to_string_pretty(doc)?))
+        .and_then(|s| Ok(fs::write(path, s + "\n")?));
+    match written {
+        Ok(()) => {
+            eprintln!("ℹ️  config.json migrated v{} → v{SCHEMA_VERSION} (old file: {})", report.schema_version, backup.display());
+            for r in &report.deprecated {
+                eprintln!("ℹ️  config key {} → {}", r.from, r.to);
+            }
+        }
+        Err(e) => eprintln!("⚠️  config.json migrated in memory only, write failed: {e}"),
+    }
+}
+
+/// Check config.json as it is on disk now, without changing it.
+pub fn validate(base_dir: &Path) -> SchemaReport {
+    let failed = |e: String| SchemaReport { current_version: SCHEMA_VERSION, error: Some(e), ..Default::default() };
+    match fs::read_to_string(base_dir.join("config.json")) {
+        Ok(s)  => check_text(&s).unwrap_or_else(|e| failed(e.to_string())),
+        Err(e) => failed(format!("config.json: {e}")),
+    }
+}
+
+/// The report for config.json contents that may not be on disk yet; `Err` if
+/// they don't parse into the config's shape at all.
+pub fn check_text(text: &str) -> anyhow::Result<SchemaReport> {
+    parse(text).map(|p| SchemaReport {
+        limits: Config::from_raw(p.raw).limit_warnings,
+        ..p.report
+    })
+}
+
 fn strip_comment_keys(val: &mut serde_json::Value) {
     if let serde_json::Value::Object(map) = val {
         let keys_to_remove: Vec<String> = map.keys()
@@ -268,3 +898,72 @@ fn strip_comment_keys(val: &mut serde_json::Value) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upload(u: RawUpload, unsafe_overrides: bool) -> Config {
+        Config::from_raw(RawConfig { upload: u, unsafe_overrides: Some(unsafe_overrides), ..Default::default() })
+    }
+
+    fn warning<'a>(cfg: &'a Config, key: &str) -> Option<&'a LimitWarning> {
+        cfg.limit_warnings.iter().find(|w| w.key == key)
+    }
+
+    #[test]
+    fn key_path_expands_section_aliases() {
+        assert_eq!(key_path("u.client_chunk_mb"), "upload.client_chunk_mb");
+        assert_eq!(key_path("dt.throughput_keep_days"), "data.throughput_keep_days");
+        assert_eq!(key_path("tg.parallel_sends"), "telegram.parallel_sends");
+        assert_eq!(key_path("sim.dir"), "simulate.dir");
+        assert_eq!(key_path("r.cache.max_mb"), "cache.max_mb");
+    }
+
+    #[test]
+    fn in_range_values_are_kept_without_warning() {
+        let cfg = upload(RawUpload { client_chunk_mb: Some(8), zip_compress_level: Some(6), ..Default::default() }, false);
+        assert_eq!(cfg.client_chunk_bytes, 8 * 1024 * 1024);
+        assert_eq!(cfg.zip_compress_level, 6);
+        assert!(warning(&cfg, "upload.client_chunk_mb").is_none());
+        assert!(warning(&cfg, "upload.zip_compress_level").is_none());
+    }
+
+    #[test]
+    fn out_of_range_values_clamp_to_the_nearest_bound() {
+        let cfg = upload(RawUpload { client_chunk_mb: Some(0), parallel_chunks: Some(64), ..Default::default() }, false);
+        assert_eq!(cfg.client_chunk_bytes, 1024 * 1024);
+        assert_eq!(cfg.parallel_chunks, 16);
+
+        let low = warning(&cfg, "upload.client_chunk_mb").unwrap();
+        assert_eq!(low.value, Value::from(0u64));
+        assert_eq!(low.applied, Value::from(1u64));
+        assert!(!low.overridden);
+
+        let high = warning(&cfg, "upload.parallel_chunks").unwrap();
+        assert_eq!(high.applied, Value::from(16usize));
+        assert!(!high.overridden);
+    }
+
+    #[test]
+    fn unsafe_overrides_keep_soft_limits_only() {
+        let cfg = upload(RawUpload {
+            parallel_chunks:    Some(64),
+            client_chunk_mb:    Some(0),
+            zip_compress_level: Some(12),
+            discord_safe_ratio: Some(1.5),
+            ..Default::default()
+        }, true);
+        assert_eq!(cfg.parallel_chunks, 64);
+        assert!(warning(&cfg, "upload.parallel_chunks").unwrap().overridden);
+
+        // Below the minimum is never kept
+        assert_eq!(cfg.client_chunk_bytes, 1024 * 1024);
+        assert!(!warning(&cfg, "upload.client_chunk_mb").unwrap().overridden);
+
+        assert_eq!(cfg.zip_compress_level, 9);
+        assert!(!warning(&cfg, "upload.zip_compress_level").unwrap().overridden);
+        assert_eq!(cfg.discord_safe_ratio, 0.99);
+        assert!(!warning(&cfg, "upload.discord_safe_ratio").unwrap().overridden);
+    }
+}
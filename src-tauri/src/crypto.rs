@@ -0,0 +1,111 @@
+/// crypto.rs — Optional AES-256-GCM encryption of part bytes (config `encryption`).
+///
+/// A part is sealed before it is zipped, so Discord/Telegram only ever hold
+/// ciphertext: a random 96-bit nonce followed by the ciphertext and its tag.
+/// `PartInfo::encrypted` says which parts need opening, so files uploaded before
+/// encryption was turned on still download as they are. The key is either the
+/// static `encryption.key` or, with the keyring unlocked, a per-file data key
+/// (see keyring.rs); both reach the sender as `Config::encryption_key`.
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+use crate::config::Config;
+
+const NONCE_LEN: usize = 12;
+
+/// A 256-bit AES key (`encryption.key`, a file's data key or the keyring's master
+/// key); never printed or serialized.
+#[derive(Clone)]
+pub struct PartKey([u8; 32]);
+
+impl PartKey {
+    /// 64 hex characters, e.g. the output of `openssl rand -hex 32`.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        from_hex(s.trim())?.try_into().ok().map(Self)
+    }
+
+    pub fn generate() -> Self {
+        Self(Aes256Gcm::generate_key(&mut OsRng).into())
+    }
+
+    /// PBKDF2-HMAC-SHA256 of a passphrase. Slow on purpose: call off the runtime.
+    pub fn derive(passphrase: &str, salt: &[u8], rounds: u32) -> Self {
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, rounds, &mut key);
+        Self(key)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0))
+    }
+}
+
+impl std::fmt::Debug for PartKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PartKey(…)")
+    }
+}
+
+/// `key` sealed under `kek`, as hex for storing in JSON.
+pub fn wrap(kek: &PartKey, key: &PartKey) -> Result<String> {
+    Ok(to_hex(&encrypt(kek, &key.0)?))
+}
+
+pub fn unwrap(kek: &PartKey, wrapped: &str) -> Result<PartKey> {
+    let sealed = from_hex(wrapped).ok_or_else(|| anyhow!("wrapped key is not hex"))?;
+    let key: [u8; 32] = decrypt(kek, &sealed)?.try_into().map_err(|_| anyhow!("wrapped key has the wrong length"))?;
+    Ok(PartKey(key))
+}
+
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut out = [0u8; N];
+    OsRng.fill_bytes(&mut out);
+    out
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() { return None; }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+pub fn encrypt(key: &PartKey, plain: &[u8]) -> Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let sealed = key.cipher().encrypt(&nonce, plain).map_err(|_| anyhow!("AES-GCM encrypt failed"))?;
+    let mut out = Vec::with_capacity(NONCE_LEN + sealed.len());
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+pub fn decrypt(key: &PartKey, data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < NONCE_LEN { anyhow::bail!("encrypted part too short ({} bytes)", data.len()); }
+    let (nonce, sealed) = data.split_at(NONCE_LEN);
+    key.cipher().decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| anyhow!("decrypt part failed (wrong encryption.key, or the part is corrupt)"))
+}
+
+/// The bytes to zip and send for a part, and whether they are encrypted.
+/// Runs on a blocking thread, like zipping.
+pub async fn seal(cfg: &Config, data: Bytes) -> Result<(Bytes, bool)> {
+    if !cfg.encryption_enabled { return Ok((data, false)); }
+    let key = cfg.encryption_key.clone()
+        .ok_or_else(|| anyhow!("encryption.enabled is set but encryption.key is not 64 hex characters"))?;
+    let sealed = tokio::task::spawn_blocking(move || encrypt(&key, &data)).await??;
+    Ok((Bytes::from(sealed), true))
+}
+
+/// Undo `seal` on a downloaded, unzipped part.
+pub fn open(cfg: &Config, data: Vec<u8>, encrypted: bool) -> Result<Vec<u8>> {
+    if !encrypted { return Ok(data); }
+    let key = cfg.encryption_key.as_ref()
+        .ok_or_else(|| anyhow!("Part is encrypted but encryption.key is not set"))?;
+    decrypt(key, &data)
+}
@@ -0,0 +1,270 @@
+/// dedup.rs — Content-addressed parts. A fresh part whose BLAKE3 matches one
+/// already stored on the same platform points at that message instead of being
+/// sent again, so uploading the same ISO twice costs no extra storage.
+///
+/// From then on records share messages, counted by reference: deleting a record
+/// only removes the messages (and the channel) that no other record, in history
+/// or in the trash, still points at, nor an upload in progress (`Claims`).
+///
+/// Only plaintext parts take part: an encrypted part's stored bytes depend on
+/// the key it was sealed with, and a recompress wants fresh bytes on purpose.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::{download, state::AppState, storage::{FileRecord, PartInfo}};
+
+/// A stored message: platform, channel ("" on Telegram) and message id. Bundled
+/// parts share one, so this is what deleting works with.
+pub type MessageKey = (String, String, i64);
+
+pub fn message_key(p: &PartInfo) -> MessageKey {
+    (p.platform.clone(), p.channel_id.clone().unwrap_or_default(), p.message_id)
+}
+
+/// Stored plaintext parts by (BLAKE3, platform), for one upload to look up its
+/// parts in. Parts the upload stores itself are added as they finish, so a file
+/// repeating itself (zeroed regions of a disk image) is only stored once too.
+///
+/// The index is a snapshot taken when the upload starts; every hit is claimed
+/// through `Claims`, which refuses messages deleted since.
+pub struct PartIndex {
+    parts:   Mutex<HashMap<(String, String), PartInfo>>,
+    session: String,
+    claims:  Arc<Claims>,
+}
+
+impl PartIndex {
+    pub fn build(records: &[FileRecord], session: &str, claims: &Arc<Claims>) -> Self {
+        claims.lock().indexes += 1;
+        let index = Self { parts: Mutex::default(), session: session.to_string(), claims: Arc::clone(claims) };
+        for p in records.iter().filter(|r| r.wrapped_key.is_none()).flat_map(|r| &r.parts_info) {
+            index.learn(p);
+        }
+        index
+    }
+
+    pub fn learn(&self, p: &PartInfo) {
+        let Some(hash) = p.blake3.clone() else { return };
+        if p.encrypted || p.raw_bytes == 0 { return; }
+        self.parts.lock().unwrap_or_else(|e| e.into_inner())
+            .entry((hash, p.platform.clone())).or_insert_with(|| p.clone());
+    }
+
+    /// The stored copy of a part with `hash` on `platform`, renumbered as `part`.
+    /// None when there is none or it is being (or has been) deleted.
+    pub fn find(&self, hash: &str, raw_bytes: u64, platform: &str, part: u32) -> Option<PartInfo> {
+        let stored = self.parts.lock().unwrap_or_else(|e| e.into_inner())
+            .get(&(hash.to_string(), platform.to_string()))
+            .filter(|p| p.raw_bytes == raw_bytes)
+            .cloned()?;
+        self.claims.claim(&self.session, message_key(&stored)).then(|| PartInfo { part, ..stored })
+    }
+}
+
+impl Drop for PartIndex {
+    fn drop(&mut self) {
+        let mut claims = self.claims.lock();
+        claims.indexes -= 1;
+        // No snapshot left that could still point at them
+        if claims.indexes == 0 { claims.deleted.clear(); }
+    }
+}
+
+/// Where reuse and deletion of stored messages meet, under one lock: a delete
+/// leaves the messages an upload in progress has picked up (its record isn't
+/// saved yet, so `live_records` can't see it), and an upload doesn't pick up a
+/// message that is being deleted or was deleted after its index was built.
+#[derive(Default)]
+pub struct Claims(Mutex<ClaimState>);
+
+#[derive(Default)]
+struct ClaimState {
+    /// Messages each upload session references, until its record is saved.
+    sessions: HashMap<String, HashSet<MessageKey>>,
+    /// Messages a delete is working on.
+    deleting: HashSet<MessageKey>,
+    /// Messages deleted while some `PartIndex` was alive.
+    deleted:  HashSet<MessageKey>,
+    indexes:  usize,
+}
+
+impl Claims {
+    fn lock(&self) -> std::sync::MutexGuard<'_, ClaimState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn claim(&self, session: &str, key: MessageKey) -> bool {
+        let mut state = self.lock();
+        if state.deleting.contains(&key) || state.deleted.contains(&key) { return false; }
+        state.sessions.entry(session.to_string()).or_default().insert(key);
+        true
+    }
+
+    /// The session's record is saved (or the session is gone): its messages are
+    /// now protected by `live_records`, or no longer needed.
+    pub fn release_session(&self, session: &str) {
+        self.lock().sessions.remove(session);
+    }
+
+    /// Drop the claims of sessions `keep` says are over.
+    pub fn retain_sessions(&self, keep: impl Fn(&str) -> bool) {
+        self.lock().sessions.retain(|s, _| keep(s));
+    }
+
+    /// What deleting `record` may remove when `others` stay: `release`, minus the
+    /// messages an upload has claimed. Those returned are marked as being deleted
+    /// until `end_delete`.
+    pub fn begin_delete<'a>(&self, record: &FileRecord, others: impl IntoIterator<Item = &'a FileRecord>) -> Release {
+        let mut state = self.lock();
+        let mut release = release(record, others);
+        let claimed: HashSet<&MessageKey> = state.sessions.values().flatten().collect();
+        let before = release.parts.len();
+        release.parts.retain(|p| !claimed.contains(&message_key(p)));
+        let kept = before - release.parts.len();
+        release.shared += kept;
+        // The channel holds what was kept, so it stays too
+        release.channel_used |= kept > 0;
+        let keys: Vec<MessageKey> = release.parts.iter().map(message_key).collect();
+        state.deleting.extend(keys);
+        release
+    }
+
+    pub fn end_delete(&self, parts: &[PartInfo]) {
+        let mut state = self.lock();
+        for key in parts.iter().map(message_key) {
+            state.deleting.remove(&key);
+            if state.indexes > 0 { state.deleted.insert(key); }
+        }
+    }
+}
+
+/// Every record that may point at stored parts: history and the trash.
+pub fn live_records(st: &AppState) -> Vec<FileRecord> {
+    let mut records = st.store.load_history(&st.cfg.history_file);
+    records.extend(st.store.load_trash(&st.cfg.trash_file).into_iter().map(|t| t.record));
+    records
+}
+
+/// What deleting `record` may remove when `others` (every record that stays) do.
+pub struct Release {
+    /// `record`'s parts whose message no other record points at.
+    pub parts:        Vec<PartInfo>,
+    /// Parts left in place for other records.
+    pub shared:       usize,
+    /// Some other record keeps parts in `record`'s channel.
+    pub channel_used: bool,
+}
+
+pub fn release<'a>(record: &FileRecord, others: impl IntoIterator<Item = &'a FileRecord>) -> Release {
+    // Nothing an import points at is ours to delete, its channel included
+    if record.imported {
+        return Release { parts: vec![], shared: 0, channel_used: !record.channel_id.is_empty() };
+    }
+    let mut held: HashSet<MessageKey> = HashSet::new();
+    let mut channel_used = false;
+    for other in others {
+        for p in download::normalize_parts(other) {
+            channel_used |= p.channel_id.as_deref() == Some(record.channel_id.as_str());
+            held.insert(message_key(&p));
+        }
+    }
+    let (shared, parts): (Vec<PartInfo>, Vec<PartInfo>) = download::normalize_parts(record).into_iter()
+        .partition(|p| held.contains(&message_key(p)));
+    Release { parts, shared: shared.len(), channel_used: channel_used && !record.channel_id.is_empty() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(part: u32, message_id: i64, hash: &str) -> PartInfo {
+        PartInfo {
+            part, message_id,
+            platform:   "discord".to_string(),
+            channel_id: Some("10".to_string()),
+            blake3:     Some(hash.to_string()),
+            raw_bytes:  100,
+            ..Default::default()
+        }
+    }
+
+    fn record(id: i64, parts: Vec<PartInfo>) -> FileRecord {
+        FileRecord { id, channel_id: "10".to_string(), parts_info: parts, ..Default::default() }
+    }
+
+    fn no_others<'a>() -> [&'a FileRecord; 0] { [] }
+
+    fn message_ids(parts: &[PartInfo]) -> Vec<i64> {
+        parts.iter().map(|p| p.message_id).collect()
+    }
+
+    #[test]
+    fn release_keeps_messages_another_record_points_at() {
+        let a = record(1, vec![part(1, 101, "h1"), part(2, 102, "h2")]);
+        let b = record(2, vec![part(1, 102, "h2")]);
+        let r = release(&a, [&b]);
+        assert_eq!(message_ids(&r.parts), vec![101]);
+        assert_eq!(r.shared, 1);
+        assert!(r.channel_used);
+    }
+
+    #[test]
+    fn release_frees_everything_nothing_else_uses() {
+        let a = record(1, vec![part(1, 101, "h1"), part(2, 102, "h2")]);
+        let other = FileRecord { channel_id: "20".to_string(), ..record(2, vec![]) };
+        let r = release(&a, [&other]);
+        assert_eq!(message_ids(&r.parts), vec![101, 102]);
+        assert_eq!(r.shared, 0);
+        assert!(!r.channel_used);
+    }
+
+    #[test]
+    fn imported_record_releases_nothing() {
+        let a = FileRecord { imported: true, ..record(1, vec![part(1, 101, "h1")]) };
+        let r = release(&a, no_others());
+        assert!(r.parts.is_empty());
+        assert!(r.channel_used);
+    }
+
+    #[test]
+    fn delete_leaves_messages_an_upload_claimed() {
+        let claims = Arc::new(Claims::default());
+        let a = record(1, vec![part(1, 101, "h1"), part(2, 102, "h2")]);
+        let index = PartIndex::build(std::slice::from_ref(&a), "upload", &claims);
+        assert_eq!(index.find("h2", 100, "discord", 1).map(|p| p.message_id), Some(102));
+
+        let r = claims.begin_delete(&a, no_others());
+        assert_eq!(message_ids(&r.parts), vec![101]);
+        assert_eq!(r.shared, 1);
+        assert!(r.channel_used);
+        claims.end_delete(&r.parts);
+
+        // Saved or gone, the upload no longer holds it
+        claims.release_session("upload");
+        let r = claims.begin_delete(&a, no_others());
+        assert_eq!(message_ids(&r.parts), vec![101, 102]);
+    }
+
+    #[test]
+    fn upload_does_not_claim_deleted_messages() {
+        let claims = Arc::new(Claims::default());
+        let a = record(1, vec![part(1, 101, "h1")]);
+        let index = PartIndex::build(std::slice::from_ref(&a), "upload", &claims);
+
+        let r = claims.begin_delete(&a, no_others());
+        assert!(index.find("h1", 100, "discord", 1).is_none());
+        claims.end_delete(&r.parts);
+        // The index still lists it, but the message is gone
+        assert!(index.find("h1", 100, "discord", 1).is_none());
+    }
+
+    #[test]
+    fn part_index_matches_hash_size_and_platform() {
+        let claims = Arc::new(Claims::default());
+        let a = record(1, vec![part(1, 101, "h1")]);
+        let index = PartIndex::build(std::slice::from_ref(&a), "upload", &claims);
+        assert_eq!(index.find("h1", 100, "discord", 7).map(|p| p.part), Some(7));
+        assert!(index.find("h1", 99, "discord", 1).is_none());
+        assert!(index.find("h1", 100, "telegram", 1).is_none());
+    }
+}
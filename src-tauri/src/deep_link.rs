@@ -0,0 +1,170 @@
+/// deep_link.rs — `discorddrive://` links, so a share or manifest link clicked
+/// elsewhere on the desktop opens the app on that file.
+///
+/// - `discorddrive://file/<id>` opens the file's preview.
+/// - `discorddrive://p/<profile>/s/<token>` opens the file behind one of that
+///   profile's share links (without counting as a download). The older
+///   `discorddrive://s/<token>` form is looked up in the active profile.
+/// - `discorddrive://import?path=<file.ddrive>` imports a manifest from disk, once
+///   the user has said yes in a native dialog: any web page can fire such a link.
+///
+/// Links are resolved here against their profile (switching to it) or the active
+/// one, and queued; a `deep-link` event tells the page to take them
+/// (`take_deep_links`). A link that launched the app arrives before the page has
+/// loaded, which takes the queue once it has.
+use serde::Serialize;
+use std::{path::PathBuf, sync::Mutex};
+use tracing::{info, warn};
+
+use crate::{profiles::SharedRegistry, state::AppState};
+
+pub const SCHEME: &str = "discorddrive";
+
+/// Manifests are a few KB per part; anything this large isn't one.
+const MAX_MANIFEST_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Link {
+    File(i64),
+    Share { profile: Option<String>, token: String },
+    Import(PathBuf),
+}
+
+/// What the window should do.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    OpenFile { file_id: i64 },
+    Import   { filename: String, manifest: String },
+    Error    { url: String, detail: String },
+}
+
+pub fn share_url(profile: &str, token: &str) -> String {
+    format!("{SCHEME}://p/{profile}/s/{token}")
+}
+
+pub fn parse(url: &tauri::Url) -> Result<Link, String> {
+    if url.scheme() != SCHEME {
+        return Err(format!("Không phải link {SCHEME}://"));
+    }
+    let arg = url.path().trim_matches('/');
+    match url.host_str().unwrap_or("") {
+        "file" => arg.parse().map(Link::File).map_err(|_| format!("Id file không hợp lệ: {arg}")),
+        "s" if !arg.is_empty() && !arg.contains('/') => Ok(Link::Share { profile: None, token: arg.to_string() }),
+        "p" => match arg.split('/').collect::<Vec<_>>().as_slice() {
+            [profile, "s", token] if !profile.is_empty() && !token.is_empty() =>
+                Ok(Link::Share { profile: Some(profile.to_string()), token: token.to_string() }),
+            _ => Err(format!("Link chia sẻ không hợp lệ: {arg}")),
+        },
+        "import" => url.query_pairs().find(|(k, _)| k == "path")
+            .map(|(_, v)| Link::Import(PathBuf::from(v.into_owned())))
+            .ok_or_else(|| "Link import thiếu ?path=".to_string()),
+        other => Err(format!("Link không được hỗ trợ: {other}")),
+    }
+}
+
+/// Turn a link into the window's action, checking it against `st`.
+pub async fn resolve(st: &AppState, link: Link) -> Result<Action, String> {
+    match link {
+        Link::File(id) => open_file(st, id),
+        Link::Share { token, .. } => {
+            let file_id = st.store.load_shares(&st.cfg.shares_file).into_iter()
+                .find(|s| s.token == token).map(|s| s.file_id)
+                .ok_or_else(|| "Link chia sẻ không thuộc drive này".to_string())?;
+            open_file(st, file_id)
+        }
+        Link::Import(path) => {
+            let is_manifest = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("ddrive"));
+            let len = tokio::fs::metadata(&path).await.map_err(|e| format!("{}: {e}", path.display()))?.len();
+            if !is_manifest || len > MAX_MANIFEST_BYTES {
+                return Err(format!("Không phải file .ddrive: {}", path.display()));
+            }
+            let manifest = tokio::fs::read_to_string(&path).await.map_err(|e| format!("{}: {e}", path.display()))?;
+            let filename = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            Ok(Action::Import { filename, manifest })
+        }
+    }
+}
+
+fn open_file(st: &AppState, file_id: i64) -> Result<Action, String> {
+    st.store.find_record(&st.cfg.history_file, file_id)
+        .map(|r| Action::OpenFile { file_id: r.id })
+        .ok_or_else(|| "File không tồn tại".to_string())
+}
+
+/// Actions the page hasn't taken yet.
+#[derive(Default)]
+pub struct Pending(Mutex<Vec<Action>>);
+
+impl Pending {
+    pub fn push(&self, action: Action) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(action);
+    }
+
+    pub fn take(&self) -> Vec<Action> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Ask before importing `filename`. False when the user declines or closes the dialog.
+async fn confirm_import(app: &tauri::AppHandle, filename: &str) -> bool {
+    use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    app.dialog()
+        .message(format!("Một link muốn import manifest \"{filename}\" vào drive.\nChỉ đồng ý nếu bạn vừa tự mở link này."))
+        .title("Import manifest?")
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::OkCancel)
+        .show(move |ok| { let _ = tx.send(ok); });
+    rx.await.unwrap_or(false)
+}
+
+/// Resolve `urls` against their profiles, queue the actions, bring the window
+/// forward and tell the page.
+pub async fn dispatch(app: tauri::AppHandle, reg: SharedRegistry, urls: Vec<tauri::Url>) {
+    use tauri::{Emitter, Manager};
+    let active = reg.active();
+    let pending = app.state::<Pending>();
+    if let Some(win) = app.get_webview_window("main") {
+        let _ = win.unminimize();
+        let _ = win.set_focus();
+    }
+    for url in urls {
+        let res = match parse(&url) {
+            Ok(link) => {
+                let profile = match &link {
+                    Link::Share { profile: Some(p), .. } => p.clone(),
+                    _ => active.clone(),
+                };
+                match reg.get(&profile) {
+                    // The page talks to the active profile, so a link into another one switches to it
+                    Some(_) if profile != reg.active() && reg.set_active(&profile).is_err() =>
+                        Err(format!("Không chuyển được sang profile {profile}")),
+                    Some(entry) => resolve(&entry.state, link).await,
+                    None        => Err(format!("Profile không tồn tại: {profile}")),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(Action::Import { filename, .. }) if !confirm_import(&app, &filename).await => {
+                info!("🔗 Deep link {url}: import declined");
+            }
+            Ok(action) => {
+                info!("🔗 Deep link {url}");
+                pending.push(action);
+            }
+            Err(detail) => {
+                warn!("⚠️ Deep link {url}: {detail}");
+                pending.push(Action::Error { url: url.to_string(), detail });
+            }
+        }
+    }
+    let _ = app.emit("deep-link", ());
+}
+
+/// Queued actions, for the page to run.
+#[tauri::command]
+pub fn take_deep_links(pending: tauri::State<'_, Pending>) -> Vec<Action> {
+    pending.take()
+}
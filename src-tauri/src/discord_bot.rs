@@ -1,36 +1,170 @@
 /// discord_bot.rs — Discord bot using Serenity.
 use anyhow::{anyhow, Context as AnyhowContext, Result};
+use serde::Serialize;
 use serenity::{
     async_trait,
     http::Http,
+    builder::{
+        CreateCommand, CreateCommandOption, CreateEmbed,
+        CreateInteractionResponse, CreateInteractionResponseMessage, EditChannel, GetMessages,
+    },
     model::{
-        channel::GuildChannel,
+        application::{CommandDataOptionValue, CommandInteraction, CommandOptionType, Interaction},
+        channel::{Attachment, ChannelType, GuildChannel, Message, ReactionType},
+        event::ResumedEvent,
         gateway::Ready,
         id::{ChannelId, GuildId},
     },
+    gateway::{ConnectionStage, ShardStageUpdateEvent},
     prelude::*,
 };
-use std::sync::Arc;
+use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
 use tokio::sync::{mpsc, Mutex};
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+use crate::{
+    folder_tree,
+    offline::{self, Connectivity},
+    queue,
+    state::AppState,
+    storage::{Folder, Store, UsageSummary},
+};
+
+/// The profile's AppState, set right after the bot becomes ready.
+pub type AppSlot = Arc<std::sync::OnceLock<AppState>>;
+
+/// REST clients for every bot in the guild. The primary (gateway) bot keeps channel
+/// management; part uploads rotate across all of them so each bot's rate limit
+/// buckets are spent separately.
+pub struct HttpPool {
+    bots: Vec<Arc<Http>>,
+    next: AtomicUsize,
+}
+
+impl HttpPool {
+    pub fn new(primary: Arc<Http>, extra: Vec<Arc<Http>>) -> Self {
+        let mut bots = vec![primary];
+        bots.extend(extra);
+        Self { bots, next: AtomicUsize::new(0) }
+    }
 
-use crate::storage::JsonStore;
+    pub fn primary(&self) -> &Arc<Http> { &self.bots[0] }
+
+    /// Every bot, primary first.
+    pub fn bots(&self) -> &[Arc<Http>] { &self.bots }
+
+    /// Next bot in round-robin order.
+    pub fn next(&self) -> Arc<Http> {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.bots.len();
+        Arc::clone(&self.bots[i])
+    }
+
+    pub fn len(&self) -> usize { self.bots.len() }
+
+    pub fn is_empty(&self) -> bool { self.bots.is_empty() }
+}
+
+/// `DISCORD_EXTRA_TOKENS`: comma- or whitespace-separated bot tokens.
+pub fn parse_extra_tokens(raw: &str) -> Vec<String> {
+    raw.split(|c: char| c == ',' || c.is_whitespace())
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Build REST clients for the extra tokens, keeping only bots that can see the guild.
+pub async fn connect_extra_bots(tokens: &[String], guild_id: GuildId) -> Vec<Arc<Http>> {
+    let mut bots = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        let http = Arc::new(Http::new(token));
+        match guild_id.to_partial_guild(&http).await {
+            Ok(_)  => bots.push(http),
+            Err(e) => warn!("⚠️ Extra bot #{} skipped (not in guild or bad token): {e}", i + 1),
+        }
+    }
+    bots
+}
 
 pub struct Handler {
     pub guild_id:      GuildId,
     pub history_file:  String,
     pub folders_file:  String,
-    pub store:         Arc<JsonStore>,
+    pub store:         Arc<dyn Store>,
     pub ready_tx:      Mutex<Option<mpsc::Sender<()>>>,
+    pub app:           AppSlot,
+    /// When the gateway last dropped; reported by the next reconcile.
+    pub down_since:    std::sync::Mutex<Option<std::time::Instant>>,
+    /// Shared with the AppState: work needing Discord waits while this is offline.
+    pub connectivity:  Arc<Connectivity>,
 }
 
 #[async_trait]
 impl EventHandler for Handler {
-    async fn ready(&self, _ctx: serenity::prelude::Context, ready: Ready) {
+    async fn ready(&self, ctx: serenity::prelude::Context, ready: Ready) {
         info!("✅ Bot online: {}", ready.user.name);
+        if let Err(e) = self.guild_id.set_commands(&ctx.http, vec![drive_command()]).await {
+            warn!("⚠️  Could not register /drive slash command: {e}");
+        }
         if let Some(tx) = self.ready_tx.lock().await.take() {
             let _ = tx.send(()).await;
         }
+        // A new session (first start or after an invalidated one) saw no events for the gap
+        self.reconcile(&ctx.http, "ready").await;
+        self.back_online();
+    }
+
+    /// `!store [folder]` (or a mention) with attachments → upload them into the drive.
+    async fn message(&self, ctx: serenity::prelude::Context, msg: Message) {
+        if msg.author.bot || msg.guild_id != Some(self.guild_id) || msg.attachments.is_empty() {
+            return;
+        }
+        let content = msg.content.trim();
+        let folder_arg = match content.strip_prefix("!store") {
+            Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => rest.trim().to_string(),
+            _ if msg.mentions_me(&ctx).await.unwrap_or(false) => String::new(),
+            _ => return,
+        };
+        let Some(st) = self.app.get().cloned() else {
+            let _ = msg.reply(&ctx.http, "⏳ Drive đang khởi động, thử lại sau").await;
+            return;
+        };
+        let folder_id = if folder_arg.is_empty() { String::new() } else {
+            match find_folder(&self.store.load_folders(&self.folders_file), &folder_arg) {
+                Some(f) => f.id.to_string(),
+                None => {
+                    let _ = msg.reply(&ctx.http, format!("❌ Không tìm thấy folder: {folder_arg}")).await;
+                    return;
+                }
+            }
+        };
+        // Ingest can take minutes for big files; don't block the gateway event loop
+        tokio::spawn(async move {
+            for att in &msg.attachments {
+                let text = match ingest_attachment(&st, att, &folder_id).await {
+                    Ok(rec) => format!("✅ Đã lưu `{}` ({:.2} MB){}", rec.filename, rec.size_mb,
+                        rec.jump_url.map(|u| format!(" → {u}")).unwrap_or_default()),
+                    Err(e) => {
+                        warn!("⚠️  Discord ingest of {} failed: {e}", att.filename);
+                        format!("❌ Không lưu được `{}`: {e}", att.filename)
+                    }
+                };
+                let _ = msg.reply(&ctx.http, text).await;
+            }
+        });
+    }
+
+    async fn interaction_create(&self, ctx: serenity::prelude::Context, interaction: Interaction) {
+        let Interaction::Command(cmd) = interaction else { return };
+        if cmd.data.name != "drive" { return; }
+        let embed = match self.drive_embed(&ctx.http, &cmd).await {
+            Ok(e)  => e,
+            Err(e) => CreateEmbed::new().title("❌ Lỗi").description(e.to_string()),
+        };
+        let reply = CreateInteractionResponseMessage::new().embed(embed).ephemeral(true);
+        if let Err(e) = cmd.create_response(&ctx.http, CreateInteractionResponse::Message(reply)).await {
+            warn!("⚠️  /drive response failed: {e}");
+        }
     }
 
     async fn channel_delete(
@@ -39,13 +173,7 @@ impl EventHandler for Handler {
         channel: GuildChannel,
         _messages: Option<Vec<serenity::model::channel::Message>>,
     ) {
-        let mut history = self.store.load_history(&self.history_file);
-        let before = history.len();
-        history.retain(|f| f.channel_id != channel.id.get().to_string());
-        if history.len() < before {
-            if let Err(e) = self.store.save_history(&self.history_file, &history) {
-                error!("Failed to save history after channel delete: {e}");
-            }
+        if self.forget_channels(&HashSet::from([channel.id.get().to_string()])) > 0 {
             info!("🗑️ Channel #{} deleted → removed from history", channel.name);
         }
     }
@@ -55,25 +183,138 @@ impl EventHandler for Handler {
         _ctx: serenity::prelude::Context,
         category: GuildChannel,
     ) {
-        let mut folders = self.store.load_folders(&self.folders_file);
-        let before = folders.len();
-        folders.retain(|f| f.discord_category_id != category.id.get() as i64);
-        if folders.len() < before {
-            if let Err(e) = self.store.save_folders(&self.folders_file, &folders) {
-                error!("Failed to save folders after category delete: {e}");
-            }
+        let (removed, detached) = self.forget_categories(&HashSet::from([category.id.get() as i64]));
+        if removed > 0 {
             info!("🗑️ Category {} deleted → removed from folders", category.name);
         }
+        if detached > 0 {
+            info!("🗑️ Overflow category {} deleted → detached from its folder", category.name);
+        }
+    }
+
+    /// Categories arrive as channel updates; a folder's category renamed by hand
+    /// in Discord is resynced right away.
+    async fn channel_update(&self, ctx: serenity::prelude::Context, _old: Option<GuildChannel>, new: GuildChannel) {
+        if new.kind != ChannelType::Category { return; }
+        let id = new.id.get() as i64;
+        let folders = self.store.load_folders(&self.folders_file);
+        let Some(folder) = folders.iter().find(|f| f.category_ids().contains(&id)) else { return };
+        match resync_folder(&ctx.http, self.guild_id, &*self.store, &self.folders_file, &self.history_file, folder.id).await {
+            Ok(Some(r)) if r.changed() => info!("🔄 Category {} updated → {}", new.name, r.summary()),
+            Ok(_)  => {}
+            Err(e) => warn!("⚠️ Resync of folder {} after category update failed: {e}", folder.name),
+        }
+    }
+
+    /// The gateway replays missed events on resume, but not across a new session.
+    async fn resume(&self, ctx: serenity::prelude::Context, _: ResumedEvent) {
+        self.reconcile(&ctx.http, "resume").await;
+        self.back_online();
+    }
+
+    async fn shard_stage_update(&self, _ctx: serenity::prelude::Context, event: ShardStageUpdateEvent) {
+        if event.new != ConnectionStage::Connected && event.old == ConnectionStage::Connected {
+            warn!("⚠️ Discord gateway disconnected ({:?})", event.new);
+            *self.down_since.lock().unwrap_or_else(|e| e.into_inner()) = Some(std::time::Instant::now());
+            if self.connectivity.went_offline() {
+                if let Some(st) = self.app.get() { offline::on_disconnect(st); }
+            }
+        }
+    }
+}
+
+impl Handler {
+    /// Run what waited for the gateway, once per outage.
+    fn back_online(&self) {
+        if !self.connectivity.came_online() { return; }
+        if let Some(st) = self.app.get().cloned() {
+            tokio::spawn(async move { offline::on_reconnect(&st).await });
+        }
+    }
+
+    /// Drop history records living in `channel_ids`. Returns how many were removed.
+    fn forget_channels(&self, channel_ids: &HashSet<String>) -> usize {
+        self.store.locked(&self.history_file, || {
+            let mut history = self.store.load_history(&self.history_file);
+            let before = history.len();
+            history.retain(|f| !channel_ids.contains(&f.channel_id));
+            let removed = before - history.len();
+            if removed > 0 {
+                if let Err(e) = self.store.save_history(&self.history_file, &history) {
+                    error!("Failed to save history after channel delete: {e}");
+                }
+            }
+            removed
+        })
+    }
+
+    /// Drop folders whose primary category is in `category_ids` and detach matching
+    /// overflow categories. Returns (folders removed, overflow categories detached).
+    fn forget_categories(&self, category_ids: &HashSet<i64>) -> (usize, usize) {
+        self.store.locked(&self.folders_file, || {
+            let mut folders = self.store.load_folders(&self.folders_file);
+            let before = folders.len();
+            folders.retain(|f| !category_ids.contains(&f.discord_category_id));
+            let removed = before - folders.len();
+            let mut detached = 0;
+            for f in &mut folders {
+                let n = f.overflow_category_ids.len();
+                f.overflow_category_ids.retain(|id| !category_ids.contains(id));
+                detached += n - f.overflow_category_ids.len();
+            }
+            if removed + detached > 0 {
+                if let Err(e) = self.store.save_folders(&self.folders_file, &folders) {
+                    error!("Failed to save folders after category delete: {e}");
+                }
+            }
+            (removed, detached)
+        })
+    }
+
+    /// Apply the channel/category delete cleanup for anything that vanished while
+    /// the gateway was away (or the app was closed).
+    async fn reconcile(&self, http: &Http, why: &str) {
+        let gap = self.down_since.lock().unwrap_or_else(|e| e.into_inner()).take()
+            .map(|t| format!(" after {}s offline", t.elapsed().as_secs()))
+            .unwrap_or_default();
+        // Snapshot before listing the guild: anything created after the listing isn't judged
+        let history = self.store.load_history(&self.history_file);
+        let folders = self.store.load_folders(&self.folders_file);
+        let channels = match self.guild_id.channels(http).await {
+            Ok(c) if !c.is_empty() => c,
+            Ok(_)  => { warn!("⚠️ Reconcile ({why}) skipped: guild listed no channels"); return; }
+            Err(e) => { warn!("⚠️ Reconcile ({why}) skipped: {e}"); return; }
+        };
+        let live: HashSet<i64> = channels.keys().map(|id| id.get() as i64).collect();
+        // Imports live in the guild they were exported from, not this one
+        let gone_channels: HashSet<String> = history.iter()
+            .filter(|r| !r.imported)
+            .filter(|r| r.channel_id.parse::<i64>().is_ok_and(|id| !live.contains(&id)))
+            .map(|r| r.channel_id.clone())
+            .collect();
+        let gone_categories: HashSet<i64> = folders.iter()
+            .flat_map(|f| f.category_ids())
+            .filter(|id| !live.contains(id))
+            .collect();
+        let records = if gone_channels.is_empty() { 0 } else { self.forget_channels(&gone_channels) };
+        let (removed, detached) = if gone_categories.is_empty() { (0, 0) } else { self.forget_categories(&gone_categories) };
+        if records + removed + detached > 0 {
+            info!("🔄 Reconciled with Discord ({why}{gap}): {records} record(s), {removed} folder(s), {detached} overflow category(ies) removed");
+        } else {
+            info!("🔄 Reconciled with Discord ({why}{gap}): in sync");
+        }
     }
 }
 
+/// Discord channel/category name for a file. Non-ASCII is transliterated
+/// ("Báo cáo" → "bao-cao") rather than dropped; the record keeps the original name.
 pub fn sanitize_name(name: &str) -> String {
     use std::path::Path;
     let stem = Path::new(name)
         .file_stem()
         .and_then(|s| s.to_str())
         .unwrap_or(name);
-    let lower = stem.to_lowercase();
+    let lower = unidecode::unidecode(stem).to_lowercase();
     let filtered: String = lower.chars()
         .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == ' ')
         .collect();
@@ -93,6 +334,27 @@ pub fn sanitize_name(name: &str) -> String {
     if trimmed.is_empty() { "file".to_string() } else { trimmed.chars().take(100).collect() }
 }
 
+/// Overflow category `n` of a folder whose primary category is `base`, kept within
+/// Discord's 100 characters.
+pub fn overflow_name(base: &str, n: usize) -> String {
+    let suffix = format!("-{n}");
+    let base: String = sanitize_name(base).chars().take(100 - suffix.len()).collect();
+    format!("{base}{suffix}")
+}
+
+/// A new category, whatever else is called the same. Subfolders use this: their
+/// path-derived names can match another folder's category.
+pub async fn create_category(http: &Arc<Http>, guild_id: GuildId, name: &str) -> Result<GuildChannel> {
+    let safe = sanitize_name(name);
+    let cat = guild_id.create_channel(
+        http,
+        serenity::builder::CreateChannel::new(&safe)
+            .kind(serenity::model::channel::ChannelType::Category),
+    ).await.context("create category")?;
+    info!("📁 Created category: {safe}");
+    Ok(cat)
+}
+
 pub async fn get_or_create_category(
     http:     &Arc<Http>,
     guild_id: GuildId,
@@ -118,32 +380,165 @@ pub async fn get_or_create_category(
     Ok(cat)
 }
 
+// ── Message ingestion ──────────────────────────────────────────────────────────
+
+/// Stream an attachment to a temp file, then upload it like a queued local file.
+async fn ingest_attachment(st: &AppState, att: &Attachment, folder_id: &str) -> Result<crate::storage::FileRecord> {
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let dir = st.store.base_dir().join("discord_inbox");
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(format!("{}-{}", att.id, sanitize_name(&att.filename)));
+    let res = async {
+        let resp = reqwest::get(&att.url).await?.error_for_status()?;
+        let mut file = tokio::fs::File::create(&path).await?;
+        let mut body = resp.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await?;
+        queue::ingest_local_file(st, &path, &att.filename, folder_id, "", None, None).await
+            .map_err(|e| anyhow!("{e}"))
+    }.await;
+    let _ = tokio::fs::remove_file(&path).await;
+    res
+}
+
+// ── Slash commands ─────────────────────────────────────────────────────────────
+
+/// Discord's cap on channels (categories included) per guild.
+pub const GUILD_CHANNEL_LIMIT: usize = 500;
+
+fn drive_command() -> CreateCommand {
+    CreateCommand::new("drive")
+        .description("Discord Drive")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand, "quota", "Tổng dung lượng và số kênh còn trống"))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand, "usage", "Dung lượng của một folder")
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::String, "folder", "Tên hoặc ID folder").required(true)))
+}
+
+impl Handler {
+    async fn drive_embed(&self, http: &Http, cmd: &CommandInteraction) -> Result<CreateEmbed> {
+        let sub = cmd.data.options.first().ok_or_else(|| anyhow!("Thiếu lệnh con"))?;
+        let history = self.store.load_history(&self.history_file);
+        let folders = self.store.load_folders(&self.folders_file);
+        let channels = self.guild_id.channels(http).await.context("fetch channels")?;
+        match sub.name.as_str() {
+            "quota" => {
+                let usage = UsageSummary::of(&history);
+                Ok(usage_fields(CreateEmbed::new().title("📊 Discord Drive — quota"), &usage)
+                    .field("Folder", folders.len().to_string(), true)
+                    .field("Kênh server", format!("{} / {GUILD_CHANNEL_LIMIT}", channels.len()), true))
+            }
+            "usage" => {
+                let query = match &sub.value {
+                    CommandDataOptionValue::SubCommand(opts) => opts.iter()
+                        .find(|o| o.name == "folder")
+                        .and_then(|o| o.value.as_str())
+                        .unwrap_or(""),
+                    _ => "",
+                };
+                let folder = find_folder(&folders, query)
+                    .ok_or_else(|| anyhow!("Không tìm thấy folder: {query}"))?;
+                let id = folder.id.to_string();
+                let usage = UsageSummary::of(history.iter().filter(|f| f.in_folder(&id)));
+                let cats: Vec<ChannelId> = folder.category_ids().into_iter()
+                    .map(|c| ChannelId::new(c as u64)).collect();
+                let used = channels.values().filter(|c| c.parent_id.is_some_and(|p| cats.contains(&p))).count();
+                Ok(usage_fields(CreateEmbed::new().title(format!("📁 {}", folder.name)), &usage)
+                    .field("Kênh", format!("{used} / {}", cats.len() * CATEGORY_CHANNEL_LIMIT), true)
+                    .field("Category", cats.len().to_string(), true))
+            }
+            other => Err(anyhow!("Lệnh không hỗ trợ: {other}")),
+        }
+    }
+}
+
+/// Match by exact id first, then case-insensitive name, then path ("Work/photos").
+fn find_folder<'a>(folders: &'a [Folder], query: &str) -> Option<&'a Folder> {
+    let q = query.trim();
+    folders.iter().find(|f| f.id.to_string() == q)
+        .or_else(|| folders.iter().find(|f| f.name.eq_ignore_ascii_case(q)))
+        .or_else(|| folders.iter().find(|f| f.path.eq_ignore_ascii_case(q)))
+}
+
+fn usage_fields(embed: CreateEmbed, u: &UsageSummary) -> CreateEmbed {
+    let mut stored = format!("{:.2} MB", u.stored_mb());
+    if u.unmeasured > 0 { stored.push_str(&format!(" ({} file ước tính)", u.unmeasured)); }
+    embed
+        .field("File", u.files.to_string(), true)
+        .field("Dung lượng", format!("{:.2} MB", u.total_mb), true)
+        .field("Lưu trữ thực tế", stored, true)
+}
+
+/// Discord refuses to put more than 50 channels in one category.
+pub const CATEGORY_CHANNEL_LIMIT: usize = 50;
+
+/// The categories backing one drive folder, plus the folder name used for overflow categories.
+pub struct CategoryTarget<'a> {
+    pub folder_name:  &'a str,
+    pub category_ids: &'a [ChannelId],
+}
+
+/// Find or create the text channel for `file_name`.
+/// When every category of the target folder is full, a new `<folder>-N` category is
+/// created and returned as the second tuple item so the caller can record it on the Folder.
 pub async fn get_or_create_channel(
-    http:        &Arc<Http>,
-    guild_id:    GuildId,
-    file_name:   &str,
-    category_id: Option<ChannelId>,
-) -> Result<GuildChannel> {
+    http:      &Arc<Http>,
+    guild_id:  GuildId,
+    file_name: &str,
+    target:    Option<CategoryTarget<'_>>,
+) -> Result<(GuildChannel, Option<ChannelId>)> {
     let safe = sanitize_name(file_name);
     let guild = guild_id.to_partial_guild(http).await
         .context("fetch guild")?;
     let channels = guild.channels(http).await.context("fetch channels")?;
+    let category_ids = target.as_ref().map(|t| t.category_ids).unwrap_or(&[]);
     for (_, ch) in &channels {
         if ch.kind == serenity::model::channel::ChannelType::Text
             && ch.name.to_lowercase() == safe
-            && (category_id.is_none() || ch.parent_id == category_id)
+            && (category_ids.is_empty() || ch.parent_id.is_some_and(|p| category_ids.contains(&p)))
         {
-            return Ok(ch.clone());
+            return Ok((ch.clone(), None));
         }
     }
+
+    let mut new_category = None;
+    let parent = match &target {
+        None => None,
+        Some(t) => {
+            let free = t.category_ids.iter().copied().find(|cat| {
+                channels.values().filter(|c| c.parent_id == Some(*cat)).count() < CATEGORY_CHANNEL_LIMIT
+            });
+            match free {
+                Some(cat) => Some(cat),
+                None => {
+                    let name = overflow_name(t.folder_name, t.category_ids.len() + 1);
+                    let cat = guild.create_channel(
+                        http,
+                        serenity::builder::CreateChannel::new(&name)
+                            .kind(serenity::model::channel::ChannelType::Category),
+                    ).await.context("create overflow category")?;
+                    info!("📁 Category full → created overflow category: {name}");
+                    new_category = Some(cat.id);
+                    Some(cat.id)
+                }
+            }
+        }
+    };
+
     let mut builder = serenity::builder::CreateChannel::new(&safe)
         .kind(serenity::model::channel::ChannelType::Text);
-    if let Some(cat_id) = category_id {
+    if let Some(cat_id) = parent {
         builder = builder.category(cat_id);
     }
     let ch = guild.create_channel(http, builder).await.context("create channel")?;
     info!("📄 Created channel: {safe}");
-    Ok(ch)
+    Ok((ch, new_category))
 }
 
 pub async fn delete_channel(http: &Arc<Http>, channel_id: u64) -> Result<()> {
@@ -151,6 +546,20 @@ pub async fn delete_channel(http: &Arc<Http>, channel_id: u64) -> Result<()> {
     Ok(())
 }
 
+/// Delete a channel only while it holds no messages. Returns whether it was deleted.
+pub async fn delete_channel_if_empty(http: &Arc<Http>, channel_id: u64) -> Result<bool> {
+    let ch = ChannelId::new(channel_id);
+    let newest = ch.messages(http, GetMessages::new().limit(1)).await.context("fetch messages")?;
+    if !newest.is_empty() { return Ok(false); }
+    ch.delete(http).await.context("delete channel")?;
+    Ok(true)
+}
+
+pub async fn delete_message(http: &Arc<Http>, channel_id: u64, message_id: u64) -> Result<()> {
+    ChannelId::new(channel_id).delete_message(http, message_id).await.context("delete message")?;
+    Ok(())
+}
+
 pub async fn delete_category(http: &Arc<Http>, guild_id: GuildId, category_id: u64) -> Result<()> {
     let guild = guild_id.to_partial_guild(http).await.context("fetch guild")?;
     let channels = guild.channels(http).await.context("fetch channels")?;
@@ -162,6 +571,133 @@ pub async fn delete_category(http: &Arc<Http>, guild_id: GuildId, category_id: u
     Ok(())
 }
 
+/// Give a category `name` (sanitized) unless it already has it. Returns whether it changed.
+pub async fn rename_category(http: &Http, category_id: i64, name: &str) -> Result<bool> {
+    let expected = sanitize_name(name);
+    let cat = ChannelId::new(category_id as u64);
+    let current = cat.to_channel(http).await.context("fetch category")?
+        .guild().map(|c| c.name).unwrap_or_default();
+    if current.to_lowercase() == expected { return Ok(false); }
+    cat.edit(http, EditChannel::new().name(&expected)).await
+        .with_context(|| format!("rename category {current}"))?;
+    Ok(true)
+}
+
+// ── Folder resync ──────────────────────────────────────────────────────────────
+
+/// What `resync_folder` changed.
+#[derive(Debug, Default, Serialize)]
+pub struct FolderResync {
+    pub folder_id:        i64,
+    pub name:             String,
+    /// Previous folder name when it was taken over from the renamed category.
+    pub renamed_from:     Option<String>,
+    /// A subfolder's category renamed back after its path, which the tree decides.
+    pub category_renamed: bool,
+    /// Overflow categories renamed back to `<folder>-N`.
+    pub overflow_renamed: usize,
+    /// File channels moved back under one of the folder's categories.
+    pub reparented:       usize,
+    /// Orphaned channels left in place because every category is full.
+    pub unplaced:         usize,
+}
+
+impl FolderResync {
+    pub fn changed(&self) -> bool {
+        self.renamed_from.is_some() || self.category_renamed || self.overflow_renamed + self.reparented > 0
+    }
+
+    pub fn summary(&self) -> String {
+        let mut parts = vec![];
+        if let Some(old) = &self.renamed_from { parts.push(format!("folder \"{old}\" → \"{}\"", self.name)); }
+        if self.category_renamed { parts.push("category renamed back after the folder path".to_string()); }
+        if self.overflow_renamed > 0 { parts.push(format!("{} overflow category(ies) renamed", self.overflow_renamed)); }
+        if self.reparented > 0 { parts.push(format!("{} channel(s) re-parented", self.reparented)); }
+        if self.unplaced > 0 { parts.push(format!("{} channel(s) left orphaned (categories full)", self.unplaced)); }
+        parts.join(", ")
+    }
+}
+
+/// Bring a folder back in line with its Discord categories: take over the primary
+/// category's name when it was renamed by hand (a subfolder's is renamed back to
+/// its path instead), rename overflow categories to `<folder>-N` to match, and move the folder's file channels that ended up outside
+/// its categories back under one with room. Returns None for an unknown folder.
+/// Deleted categories/channels are left to the delete events and reconcile.
+pub async fn resync_folder(
+    http:         &Http,
+    guild_id:     GuildId,
+    store:        &dyn Store,
+    folders_file: &str,
+    history_file: &str,
+    folder_id:    i64,
+) -> Result<Option<FolderResync>> {
+    let channels = guild_id.channels(http).await.context("fetch channels")?;
+    let mut folders = store.load_folders(folders_file);
+    let Some(folder) = folders.iter_mut().find(|f| f.id == folder_id) else { return Ok(None) };
+    let primary = channels.get(&ChannelId::new(folder.discord_category_id as u64))
+        .ok_or_else(|| anyhow!("Category của folder {} không còn trên Discord", folder.name))?;
+
+    let mut report = FolderResync { folder_id, ..Default::default() };
+    // Category names are sanitized, so only a different sanitized form means a rename
+    let expected = sanitize_name(&folder.category_name());
+    if primary.name.to_lowercase() != expected {
+        if folder.parent_id.is_some() {
+            primary.id.edit(http, EditChannel::new().name(&expected)).await
+                .with_context(|| format!("rename category {}", primary.name))?;
+            report.category_renamed = true;
+        } else {
+            report.renamed_from = Some(std::mem::replace(&mut folder.name, primary.name.clone()));
+        }
+    }
+    report.name = folder.name.clone();
+
+    let base = folder.category_name();
+    for (i, id) in folder.overflow_category_ids.iter().enumerate() {
+        let expected = overflow_name(&base, i + 2);
+        let Some(cat) = channels.get(&ChannelId::new(*id as u64)) else { continue };
+        if cat.name.to_lowercase() == expected { continue; }
+        cat.id.edit(http, EditChannel::new().name(&expected)).await
+            .with_context(|| format!("rename overflow category {}", cat.name))?;
+        report.overflow_renamed += 1;
+    }
+
+    let category_ids: Vec<ChannelId> = folder.category_ids().into_iter()
+        .map(|c| ChannelId::new(c as u64)).collect();
+    let mut used: HashMap<ChannelId, usize> = category_ids.iter().map(|cat| {
+        (*cat, channels.values().filter(|c| c.parent_id == Some(*cat)).count())
+    }).collect();
+    let key = folder_id.to_string();
+    let orphans: HashSet<ChannelId> = store.load_history(history_file).iter()
+        .filter(|r| r.in_folder(&key))
+        .filter_map(|r| r.channel_id.parse::<u64>().ok().filter(|&id| id != 0))
+        .map(ChannelId::new)
+        .filter(|id| channels.get(id).is_some_and(|c| {
+            c.kind == ChannelType::Text && !c.parent_id.is_some_and(|p| category_ids.contains(&p))
+        }))
+        .collect();
+    for channel in orphans {
+        let Some(cat) = category_ids.iter().copied().find(|c| used[c] < CATEGORY_CHANNEL_LIMIT) else {
+            report.unplaced += 1;
+            continue;
+        };
+        channel.edit(http, EditChannel::new().category(cat)).await
+            .with_context(|| format!("move channel {channel} into category"))?;
+        *used.get_mut(&cat).unwrap() += 1;
+        report.reparented += 1;
+    }
+
+    if report.renamed_from.is_some() {
+        // Only the name: the folder may have gained categories while we were talking to Discord
+        store.locked(folders_file, || {
+            let mut folders = store.load_folders(folders_file);
+            if let Some(f) = folders.iter_mut().find(|f| f.id == folder_id) { f.name = report.name.clone(); }
+            folder_tree::refresh_paths(&mut folders);
+            store.save_folders(folders_file, &folders)
+        })?;
+    }
+    Ok(Some(report))
+}
+
 pub async fn send_part(
     http:       &Arc<Http>,
     channel_id: ChannelId,
@@ -169,24 +705,91 @@ pub async fn send_part(
     zip_name:   String,
     content:    String,
 ) -> Result<(i64, String)> {
-    let attachment = serenity::builder::CreateAttachment::bytes(zip_bytes, &zip_name);
+    send_bundle(http, channel_id, vec![(zip_bytes, zip_name)], content).await
+}
+
+/// Most attachments Discord accepts on one message.
+pub const MAX_ATTACHMENTS: usize = 10;
+
+/// Post several zipped parts as the attachments of one message, in order.
+pub async fn send_bundle(
+    http:       &Arc<Http>,
+    channel_id: ChannelId,
+    files:      Vec<(Vec<u8>, String)>,
+    content:    String,
+) -> Result<(i64, String)> {
+    let attachments = files.into_iter().map(|(bytes, name)| serenity::builder::CreateAttachment::bytes(bytes, name));
     let builder = serenity::builder::CreateMessage::new()
         .content(&content)
-        .add_file(attachment);
+        .add_files(attachments);
     let msg = channel_id.send_message(http, builder).await
         .context("send Discord message")?;
     Ok((msg.id.get() as i64, msg.link()))
 }
 
+/// Rename a file channel; the name is sanitized the same way as at creation.
+pub async fn rename_channel(http: &Arc<Http>, channel_id: u64, file_name: &str) -> Result<String> {
+    let safe = sanitize_name(file_name);
+    ChannelId::new(channel_id)
+        .edit(http, serenity::builder::EditChannel::new().name(&safe)).await
+        .context("rename channel")?;
+    Ok(safe)
+}
+
+/// Swap the file name in a part's caption (`✂️ \`name\` — Phần N`), keeping any user message.
+/// Returns false when the caption doesn't mention `old_name`.
+pub async fn retitle_part(
+    http:       &Arc<Http>,
+    channel_id: u64,
+    message_id: u64,
+    old_name:   &str,
+    new_name:   &str,
+) -> Result<bool> {
+    let channel = ChannelId::new(channel_id);
+    let msg = channel.message(http, message_id).await.context("fetch message")?;
+    let old = format!("`{old_name}`");
+    if !msg.content.contains(&old) { return Ok(false); }
+    let content = msg.content.replacen(&old, &format!("`{new_name}`"), 1);
+    channel.edit_message(http, message_id, serenity::builder::EditMessage::new().content(content)).await
+        .context("edit caption")?;
+    Ok(true)
+}
+
+/// Reactions the bot leaves on part messages after a health check.
+pub const MARK_OK:       &str = "✅";
+pub const MARK_DEGRADED: &str = "⚠️";
+
+/// Leave exactly one of the status marks on a part message, removing the other.
+pub async fn set_status_reaction(http: &Arc<Http>, channel_id: u64, message_id: u64, healthy: bool) -> Result<()> {
+    let (add, remove) = if healthy { (MARK_OK, MARK_DEGRADED) } else { (MARK_DEGRADED, MARK_OK) };
+    let channel = ChannelId::new(channel_id);
+    // Removing a reaction we never left is a no-op on Discord's side
+    let _ = channel.delete_reaction(http, message_id, None, ReactionType::Unicode(remove.to_string())).await;
+    channel.create_reaction(http, message_id, ReactionType::Unicode(add.to_string())).await
+        .context("add reaction")?;
+    Ok(())
+}
+
+/// Size of the part's attachment (`index` within a bundle), failing if the message or attachment is gone.
+pub async fn attachment_size(http: &Arc<Http>, channel_id: u64, message_id: u64, index: usize) -> Result<u64> {
+    let msg = ChannelId::new(channel_id)
+        .message(http, message_id).await
+        .context("fetch message")?;
+    let att = msg.attachments.get(index)
+        .ok_or_else(|| anyhow!("No attachment #{index} on message {message_id}"))?;
+    Ok(att.size as u64)
+}
+
 pub async fn fetch_attachment_url(
     http:       &Arc<Http>,
     channel_id: u64,
     message_id: u64,
+    index:      usize,
 ) -> Result<String> {
     let msg = ChannelId::new(channel_id)
         .message(http, message_id).await
         .context("fetch message")?;
-    let att = msg.attachments.into_iter().next()
-        .ok_or_else(|| anyhow!("No attachment on message {message_id}"))?;
+    let att = msg.attachments.into_iter().nth(index)
+        .ok_or_else(|| anyhow!("No attachment #{index} on message {message_id}"))?;
     Ok(att.url)
 }
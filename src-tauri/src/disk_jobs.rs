@@ -0,0 +1,211 @@
+/// disk_jobs.rs — Merges into a local path that run in the background, so large
+/// files don't have to go through the browser's download pipeline.
+///
+/// The `download_to_disk` IPC command starts one with the path from the save dialog
+/// and answers with its id straight away; the window polls `GET /api/download/jobs`
+/// for bytes written. Finished jobs stay listed for `KEEP_FINISHED_MS` so a late
+/// poll still sees how they ended. The merge goes to `<path>.part`, renamed over
+/// `path` once complete, so a failed job never leaves a truncated target.
+///
+/// Running jobs keep a journal in `disk_jobs_file`: the parts already written and
+/// where. If the app stops mid-merge, the next start resumes each job from its
+/// missing parts, after re-hashing the ones the journal says are there. A failed
+/// job keeps both too, so saving the file to the same path again (or the next
+/// start) carries on; only a part that doesn't match its record starts it over.
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::{
+    audit,
+    download::{OnPartWritten, WrittenPart},
+    extract,
+    lifecycle,
+    state::AppState,
+    storage::{current_timestamp_ms, FileRecord},
+};
+
+const KEEP_FINISHED_MS: i64 = 60 * 60 * 1000;
+
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_DONE:    &str = "done";
+pub const STATUS_FAILED:  &str = "failed";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskJob {
+    pub id:          String,
+    pub file_id:     i64,
+    pub filename:    String,
+    pub path:        String,
+    pub status:      &'static str,
+    pub done_bytes:  u64,
+    /// From the record; an estimate from `size_mb` for legacy records.
+    pub total_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error:       Option<String>,
+    pub started_at:  i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<i64>,
+}
+
+struct Entry {
+    job:     DiskJob,
+    written: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+pub struct DiskJobs {
+    jobs: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl DiskJobs {
+    /// Register a job; the merge adds to the returned counter as it writes.
+    pub fn start(&self, record: &FileRecord, path: &std::path::Path) -> (String, Arc<AtomicU64>) {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let written = self.register(&id, record, path);
+        (id, written)
+    }
+
+    fn register(&self, id: &str, record: &FileRecord, path: &std::path::Path) -> Arc<AtomicU64> {
+        let written = Arc::new(AtomicU64::new(0));
+        let total_bytes = record.exact_size().unwrap_or((record.size_mb * 1024.0 * 1024.0) as u64);
+        let job = DiskJob {
+            id:          id.to_string(),
+            file_id:     record.id,
+            filename:    record.filename.clone(),
+            path:        path.display().to_string(),
+            status:      STATUS_RUNNING,
+            done_bytes:  0,
+            total_bytes,
+            error:       None,
+            started_at:  current_timestamp_ms(),
+            finished_at: None,
+        };
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).insert(id.to_string(), Entry { job, written: Arc::clone(&written) });
+        written
+    }
+
+    fn is_running(&self, id: &str) -> bool {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).get(id).is_some_and(|e| e.job.status == STATUS_RUNNING)
+    }
+
+    pub fn finish(&self, id: &str, outcome: Result<(), String>) {
+        if let Some(e) = self.jobs.lock().unwrap_or_else(|e| e.into_inner()).get_mut(id) {
+            e.job.finished_at = Some(current_timestamp_ms());
+            match outcome {
+                Ok(()) => {
+                    e.job.status = STATUS_DONE;
+                    // Legacy records only had an estimate
+                    e.job.total_bytes = e.written.load(Ordering::Relaxed);
+                }
+                Err(msg) => {
+                    e.job.status = STATUS_FAILED;
+                    e.job.error = Some(msg);
+                }
+            }
+        }
+    }
+
+    /// Every job, oldest first, dropping those finished longer than `KEEP_FINISHED_MS` ago.
+    pub fn list(&self) -> Vec<DiskJob> {
+        let cutoff = current_timestamp_ms() - KEEP_FINISHED_MS;
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.retain(|_, e| !e.job.finished_at.is_some_and(|t| t <= cutoff));
+        let mut out: Vec<DiskJob> = jobs.values().map(|e| DiskJob {
+            done_bytes: e.written.load(Ordering::Relaxed),
+            ..e.job.clone()
+        }).collect();
+        out.sort_by_key(|j| j.started_at);
+        out
+    }
+}
+
+/// A running job as `disk_jobs_file` keeps it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    pub id:         String,
+    pub file_id:    i64,
+    pub path:       PathBuf,
+    pub started_at: i64,
+    pub written:    Vec<WrittenPart>,
+}
+
+fn with_journals<R>(st: &AppState, f: impl FnOnce(&mut Vec<Journal>) -> R) -> Option<R> {
+    st.store.update_json(&st.cfg.disk_jobs_file, f)
+        .map_err(|e| warn!("⚠️ Download journal not saved: {e}"))
+        .ok()
+}
+
+/// Start merging `record` into `path` in the background. Returns the job id.
+pub fn start(st: &AppState, record: FileRecord, path: PathBuf) -> String {
+    let (id, written) = st.disk_jobs.start(&record, &path);
+    info!("💾 Saving {} ({}) to {} [job {id}]", record.filename, record.id, path.display());
+    let journal = Journal { id: id.clone(), file_id: record.id, path: path.clone(), started_at: current_timestamp_ms(), written: vec![] };
+    // A failed job for the same file and path left its parts: carry on from them
+    let done = with_journals(st, |all| {
+        let earlier = all.iter().position(|j| j.file_id == record.id && j.path == path && !st.disk_jobs.is_running(&j.id));
+        let done = earlier.map(|at| all.remove(at).written).unwrap_or_default();
+        all.push(Journal { written: done.clone(), ..journal });
+        done
+    }).unwrap_or_default();
+    run(st.clone(), record, path, id.clone(), written, done);
+    id
+}
+
+/// Merge `record` into `path` as job `id`, journaling each part. `done` are parts
+/// an earlier run wrote.
+fn run(st: AppState, record: FileRecord, path: PathBuf, id: String, written: Arc<AtomicU64>, done: Vec<WrittenPart>) {
+    let on_part: OnPartWritten = {
+        let (st, id) = (st.clone(), id.clone());
+        Arc::new(move |w| {
+            with_journals(&st, |all| {
+                if let Some(j) = all.iter_mut().find(|j| j.id == id) {
+                    j.written.retain(|p| p.part != w.part);
+                    j.written.push(w);
+                }
+            });
+        })
+    };
+    tokio::spawn(async move {
+        let file_id = record.id;
+        let res = extract::resume_to_file(&st, record, &path, written, &done, Some(on_part)).await;
+        match &res {
+            Ok(()) => {
+                lifecycle::record_access(&*st.store, &st.cfg, file_id);
+                audit::record(&*st.store, &st.cfg.audit_file, Some(file_id), "downloaded",
+                    json!({ "via": "disk", "path": path.display().to_string() }));
+                info!("💾 Saved {} [job {id}]", path.display());
+            }
+            Err(e) => warn!("⚠️ Saving {} failed [job {id}]: {}", path.display(), e.detail),
+        }
+        let mismatch = res.as_ref().is_err_and(|e| e.code.as_deref() == Some(extract::PART_MISMATCH));
+        if mismatch {
+            let _ = tokio::fs::remove_file(extract::partial_path(&path)).await;
+        }
+        if res.is_ok() || mismatch {
+            with_journals(&st, |all| all.retain(|j| j.id != id));
+        }
+        st.disk_jobs.finish(&id, res.map_err(|e| e.detail));
+    });
+}
+
+/// Pick up the jobs a previous run of the app left unfinished. A job whose file
+/// is gone from the drive, or whose folder is, is dropped.
+pub fn resume_all(st: &AppState) {
+    let journals: Vec<Journal> = st.store.load_json(&st.cfg.disk_jobs_file);
+    if journals.is_empty() { return; }
+    info!("💾 [{}] Resuming {} download-to-disk job(s)", st.profile, journals.len());
+    for j in journals {
+        let record = st.store.find_record(&st.cfg.history_file, j.file_id);
+        let Some(record) = record.filter(|_| j.path.parent().is_some_and(|p| p.is_dir())) else {
+            warn!("⚠️ Dropping download job {}: file or folder no longer there", j.id);
+            with_journals(st, |all| all.retain(|x| x.id != j.id));
+            continue;
+        };
+        let written = st.disk_jobs.register(&j.id, &record, &j.path);
+        run(st.clone(), record, j.path, j.id, written, j.written);
+    }
+}
@@ -0,0 +1,134 @@
+/// disposition.rs — Content-Disposition header values for downloads.
+///
+/// `filename*=UTF-8''…` (RFC 5987/6266) carries the exact name. `filename=` carries
+/// an ASCII transliteration for older webviews/browsers that ignore the extended
+/// form or mangle raw UTF-8 in headers: "Báo cáo Đà Nẵng.pdf" → "Bao cao Da Nang.pdf".
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// RFC 5987 attr-char: everything else in `filename*` is percent-encoded.
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!').remove(b'#').remove(b'$').remove(b'&').remove(b'+').remove(b'-')
+    .remove(b'.').remove(b'^').remove(b'_').remove(b'`').remove(b'|').remove(b'~');
+
+/// Longest ASCII fallback; the extension is kept when the stem is cut.
+pub const MAX_FALLBACK: usize = 150;
+/// Used when nothing of the stem survives transliteration (e.g. only emoji).
+const EMPTY_STEM: &str = "download";
+/// Extensions longer than this are treated as part of the stem.
+const MAX_EXT: usize = 10;
+
+/// `kind` is "attachment" or "inline".
+pub fn header_value(kind: &str, filename: &str) -> String {
+    let encoded = utf8_percent_encode(filename, ATTR_CHAR);
+    format!("{kind}; filename=\"{}\"; filename*=UTF-8''{encoded}", ascii_fallback(filename))
+}
+
+/// Printable-ASCII stand-in for `filename`, safe inside a quoted `filename=`.
+pub fn ascii_fallback(filename: &str) -> String {
+    let (stem, ext) = match filename.rsplit_once('.') {
+        Some((s, e)) if !s.is_empty() && !e.is_empty() && e.chars().count() <= MAX_EXT => (s, Some(e)),
+        _ => (filename, None),
+    };
+    let ext = ext.map(ascii_part).filter(|e| !e.is_empty());
+    let budget = MAX_FALLBACK - ext.as_ref().map_or(0, |e| e.len() + 1);
+    let mut stem = ascii_part(stem);
+    if stem.len() > budget {
+        stem.truncate(budget);
+        stem.truncate(stem.trim_end().len());
+    }
+    if stem.is_empty() { stem = EMPTY_STEM.to_string(); }
+    match ext {
+        Some(ext) => format!("{stem}.{ext}"),
+        None      => stem,
+    }
+}
+
+/// Transliterate, replace what can't appear in a quoted header value (quotes,
+/// backslashes, controls) and `%` (some clients percent-decode `filename=`),
+/// then collapse the runs of spaces/underscores this leaves.
+fn ascii_part(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in unidecode::unidecode(s).chars() {
+        let c = match c {
+            ' ' => ' ',
+            '"' | '\\' | '%' => '_',
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        };
+        let prev = out.chars().last();
+        if (c == ' ' || c == '_') && prev == Some(c) { continue; }
+        out.push(c);
+    }
+    out.trim_matches(|c| c == ' ' || c == '_').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_ascii_is_unchanged() {
+        assert_eq!(ascii_fallback("report-2024_final.pdf"), "report-2024_final.pdf");
+        assert_eq!(
+            header_value("attachment", "report.pdf"),
+            "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf",
+        );
+    }
+
+    #[test]
+    fn vietnamese_is_transliterated() {
+        assert_eq!(ascii_fallback("Báo cáo tháng 10 Đà Nẵng.pdf"), "Bao cao thang 10 Da Nang.pdf");
+        assert_eq!(ascii_fallback("Nguyễn Thị Ánh Tuyết.docx"), "Nguyen Thi Anh Tuyet.docx");
+        assert_eq!(ascii_fallback("ẢNH CƯỚI.JPG"), "ANH CUOI.JPG");
+    }
+
+    #[test]
+    fn extended_form_keeps_exact_utf8() {
+        let v = header_value("inline", "Đà Nẵng.mp4");
+        assert!(v.starts_with("inline; filename=\"Da Nang.mp4\"; "));
+        assert!(v.ends_with("filename*=UTF-8''%C4%90%C3%A0%20N%E1%BA%B5ng.mp4"));
+    }
+
+    #[test]
+    fn emoji_only_stem_falls_back() {
+        assert_eq!(ascii_fallback("🎉🎂.zip"), "download.zip");
+        assert_eq!(ascii_fallback("🎉"), "download");
+        let v = header_value("attachment", "🎉.zip");
+        assert!(v.contains("filename*=UTF-8''%F0%9F%8E%89.zip"));
+    }
+
+    #[test]
+    fn emoji_between_words_collapses() {
+        assert_eq!(ascii_fallback("party 🎉 time.txt"), "party time.txt");
+        assert_eq!(ascii_fallback("a🎉🎉b.txt"), "ab.txt");
+    }
+
+    #[test]
+    fn header_breaking_characters_are_replaced() {
+        assert_eq!(ascii_fallback("say \"hi\"\\now.txt"), "say _hi_now.txt");
+        assert_eq!(ascii_fallback("100% done.txt"), "100_ done.txt");
+        assert_eq!(ascii_fallback("line\r\nbreak.txt"), "line_break.txt");
+        let v = header_value("attachment", "a\"b.txt");
+        assert!(v.contains("filename*=UTF-8''a%22b.txt"));
+    }
+
+    #[test]
+    fn long_names_are_cut_but_keep_extension() {
+        let name = format!("{}.tar", "Dữ liệu ".repeat(40));
+        let fallback = ascii_fallback(&name);
+        assert!(fallback.len() <= MAX_FALLBACK, "{} > {MAX_FALLBACK}", fallback.len());
+        assert!(fallback.starts_with("Du lieu Du lieu"));
+        assert!(fallback.ends_with(".tar"));
+        assert!(!fallback.contains(" .tar"));
+        // The exact name is never truncated
+        assert!(header_value("attachment", &name).matches("D%E1%BB%AF").count() == 40);
+    }
+
+    #[test]
+    fn odd_extensions() {
+        assert_eq!(ascii_fallback(".bashrc"), ".bashrc");
+        assert_eq!(ascii_fallback("archive.tar.gz"), "archive.tar.gz");
+        assert_eq!(ascii_fallback("noext."), "noext.");
+        assert_eq!(ascii_fallback("ảnh.ẢNH"), "anh.ANH");
+    }
+}
@@ -1,15 +1,23 @@
 /// download.rs — Download and merge file parts from Discord / Telegram.
 use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
 use serenity::http::Http;
-use std::sync::Arc;
-use tokio::time::{sleep, Duration};
-use tracing::info;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex};
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{info, info_span, warn, Instrument};
 
 use crate::{
+    cache,
     config::Config,
+    crypto,
     discord_bot,
-    storage::{FileRecord, PartInfo},
+    progress,
+    simulate,
+    storage::{current_timestamp_ms, FileRecord, PartInfo},
     telegram,
     zip_utils::unzip_or_raw,
 };
@@ -27,9 +35,60 @@ pub fn normalize_parts(record: &FileRecord) -> Vec<PartInfo> {
         channel_id: Some(record.channel_id.clone()),
         file_id:    None,
         jump_url:   None,
+        ..Default::default()
     }).collect()
 }
 
+/// Parts in order, each with every stored copy (more than one for mirrored uploads).
+pub fn part_copies(record: &FileRecord) -> Vec<Vec<PartInfo>> {
+    let mut groups: Vec<Vec<PartInfo>> = vec![];
+    let mut parts = normalize_parts(record);
+    parts.sort_by_key(|p| p.part);
+    for p in parts {
+        match groups.last_mut() {
+            Some(g) if g[0].part == p.part => g.push(p),
+            _ => groups.push(vec![p]),
+        }
+    }
+    groups
+}
+
+/// Fetch a part from the first copy that works.
+async fn fetch_any(
+    copies:    &[PartInfo],
+    http:      &Arc<Http>,
+    cfg:       &Config,
+    tg_client: &reqwest::Client,
+    tg_token:  &str,
+) -> Result<(Vec<u8>, &'static str)> {
+    let mut last_err = None;
+    for (i, info) in copies.iter().enumerate() {
+        match fetch_part(info, http, cfg, tg_client, tg_token).await {
+            Ok(data) => return Ok((data, if info.platform == "telegram" { "telegram" } else { "discord" })),
+            Err(e) => {
+                if i + 1 < copies.len() {
+                    warn!("  ⚠️ Part {} ({}) failed: {e} → trying mirror copy", info.part, info.platform);
+                }
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("part has no copies")))
+}
+
+/// Strong validator for a record's content: the file's BLAKE3 when it was recorded,
+/// so it survives parts being re-sent; otherwise it changes whenever they are replaced.
+pub fn etag(record: &FileRecord) -> String {
+    if let Some(hash) = record.blake3.as_deref().filter(|h| h.len() >= 32) {
+        return format!("\"b3-{}\"", &hash[..32]);
+    }
+    let mut key = format!("{}:{}", record.id, record.size_bytes);
+    for p in &record.parts_info {
+        key.push_str(&format!("|{}:{}:{}:{}", p.part, p.platform, p.message_id, p.file_id.as_deref().unwrap_or("")));
+    }
+    format!("\"{:x}\"", md5::compute(key.as_bytes()))
+}
+
 /// Download one part (Discord or Telegram) and unzip it.
 pub async fn fetch_part(
     info:       &PartInfo,
@@ -38,7 +97,9 @@ pub async fn fetch_part(
     tg_client:  &reqwest::Client,
     tg_token:   &str,
 ) -> Result<Vec<u8>> {
-    let raw = if info.platform == "telegram" {
+    let raw = if cfg.simulate {
+        simulate::fetch_part(cfg, info).await?
+    } else if info.platform == "telegram" {
         let file_id = info.file_id.as_deref()
             .ok_or_else(|| anyhow!("Telegram part {} has no file_id", info.part))?;
         telegram::download_part(tg_client, cfg, tg_token, file_id).await?
@@ -50,10 +111,11 @@ pub async fn fetch_part(
             .context("parse channel_id")?;
         let msg_id: u64 = info.message_id as u64;
 
-        let url = discord_bot::fetch_attachment_url(http, channel_id, msg_id).await?;
+        let url = discord_bot::fetch_attachment_url(http, channel_id, msg_id, info.attachment_index()).await?;
         download_url(cfg, &url).await?
     };
-    unzip_or_raw(raw)
+    let data = unzip_or_raw(raw, cfg.zip_password.as_deref())?;
+    crypto::open(cfg, data, info.encrypted)
 }
 
 async fn download_url(cfg: &Config, url: &str) -> Result<Vec<u8>> {
@@ -84,6 +146,369 @@ async fn download_url(cfg: &Config, url: &str) -> Result<Vec<u8>> {
     Err(last_err.unwrap_or_else(|| anyhow!("Download failed")))
 }
 
+// ── Part cache ─────────────────────────────────────────────────────────────────
+//
+// Parts fetched ahead of a click (`POST /api/files/:id/prefetch`) are kept unzipped
+// as `<file_id>_<tag>_<part>.part`. The tag covers the content (etag) and the part
+// layout, so entries retire once a file is re-split (recompress, import) even
+// though its BLAKE3 stays.
+//
+// Streaming merges (previews, thumbnails, /api/merge) also keep the parts they
+// fetch, so watching a video twice reads it from disk the second time. Parts of
+// encrypted files are never kept this way. The directory has one LRU budget
+// (`part_cache_budget`), applied as each part is kept and on every GC pass.
+//
+// Parts of a pinned file (`POST /api/files/:id/pin`) live in `pinned/` under the
+// cache instead, which eviction never looks into, so the file stays readable
+// without Discord however much else gets cached. Encrypted files can't be pinned.
+
+/// Subdirectory of the part cache holding pinned files' parts.
+pub const PINNED_DIR: &str = "pinned";
+
+fn cached_part_name(record: &FileRecord, part: u32) -> String {
+    let mut key = etag(record);
+    for p in &record.parts_info {
+        key.push_str(&format!("|{}:{}:{}:{}", p.part, p.platform, p.message_id, p.raw_bytes));
+    }
+    format!("{}_{}_{part}.part", record.id, &format!("{:x}", md5::compute(key.as_bytes()))[..12])
+}
+
+pub fn cached_part_path(dir: &Path, record: &FileRecord, part: u32) -> PathBuf {
+    let name = cached_part_name(record, part);
+    if record.pinned { dir.join(PINNED_DIR).join(name) } else { dir.join(name) }
+}
+
+/// Remove every cached part of a file, pinned or not.
+pub fn forget_cached_parts(dir: &Path, file_id: i64) {
+    let prefix = format!("{file_id}_");
+    for d in [dir.to_path_buf(), dir.join(PINNED_DIR)] {
+        for entry in std::fs::read_dir(d).into_iter().flatten().flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// A part from the cache, or fetched and then cached: always for a pinned file,
+/// else under `part_cache_max_bytes`.
+async fn fetch_cached(
+    copies:    &[PartInfo],
+    cached:    &Path,
+    pinned:    bool,
+    http:      &Arc<Http>,
+    cfg:       &Config,
+    tg_client: &reqwest::Client,
+    tg_token:  &str,
+) -> Result<(Vec<u8>, &'static str)> {
+    if let Ok(data) = tokio::fs::read(cached).await {
+        cache::touch(cached);
+        return Ok((data, "cache"));
+    }
+    let fetched = fetch_any(copies, http, cfg, tg_client, tg_token).await?;
+    let limit = cfg.part_cache_max_bytes;
+    let encrypted = copies.iter().any(|c| c.encrypted);
+    if pinned && !encrypted {
+        if let Err(e) = write_part(cached, &fetched.0).await {
+            warn!("  ⚠️ Could not keep pinned part {}: {e}", copies[0].part);
+        }
+    } else if !pinned && limit > 0 && (fetched.0.len() as u64) <= limit && !encrypted {
+        if let Err(e) = keep_part(cached, &fetched.0, limit).await {
+            warn!("  ⚠️ Could not cache part {}: {e}", copies[0].part);
+        }
+    }
+    Ok(fetched)
+}
+
+/// The part cache's LRU budget: `cache.parts_max_mb`, or `cache.max_mb` when
+/// merges keep nothing and only prefetched parts are there. 0 = unlimited.
+pub fn part_cache_budget(cfg: &Config) -> u64 {
+    if cfg.part_cache_max_bytes > 0 { cfg.part_cache_max_bytes } else { cfg.cache_max_bytes }
+}
+
+/// Write aside and rename, so a merge never reads a half-written part. The temp
+/// name is unique: two merges of the same file may keep the same part at once.
+async fn write_part(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = path.with_file_name(format!("{name}.{}{}", uuid::Uuid::new_v4().simple(), cache::TMP_SUFFIX));
+    if let Err(e) = tokio::fs::write(&tmp, data).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e.into());
+    }
+    if let Err(e) = tokio::fs::rename(&tmp, path).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+async fn keep_part(path: &Path, data: &[u8], limit: u64) -> Result<()> {
+    write_part(path, data).await?;
+    if limit == 0 { return Ok(()); }
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let evicted = tokio::task::spawn_blocking(move || cache::evict_lru(&[dir.as_path()], limit)).await?;
+    if evicted.files > 0 {
+        info!("  🧹 Part cache: evicted {} part(s), {:.1}MB", evicted.files, evicted.bytes as f64 / 1024.0 / 1024.0);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrefetchedPart {
+    pub part:   u32,
+    pub bytes:  u64,
+    pub source: &'static str,   // "cache" when it was already there
+}
+
+/// Fetch the first `count` parts of `record` into the part cache.
+pub async fn prefetch_parts(
+    record:    &FileRecord,
+    count:     usize,
+    http:      &Arc<Http>,
+    cfg:       &Config,
+    tg_token:  &str,
+    dir:       &Path,
+) -> Result<Vec<PrefetchedPart>> {
+    let tg_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(cfg.http_timeout_s))
+        .build()?;
+    let mut done = vec![];
+    for copies in part_copies(record).iter().take(count) {
+        let part = copies[0].part;
+        let path = cached_part_path(dir, record, part);
+        if let Ok(meta) = tokio::fs::metadata(&path).await {
+            cache::touch(&path);
+            done.push(PrefetchedPart { part, bytes: meta.len(), source: "cache" });
+            continue;
+        }
+        let (data, platform) = fetch_any(copies, http, cfg, &tg_client, tg_token).await?;
+        keep_part(&path, &data, part_cache_budget(cfg)).await.context("write part cache")?;
+        done.push(PrefetchedPart { part, bytes: data.len() as u64, source: platform });
+    }
+    Ok(done)
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PinnedParts {
+    pub parts:   usize,
+    pub bytes:   u64,
+    pub moved:   usize,   // already in the LRU cache, moved over
+    pub fetched: usize,
+}
+
+/// Bring every part of `record` (already marked pinned) into `pinned/`: parts in
+/// the LRU cache are moved over, the rest fetched. Copies kept for the file's
+/// earlier parts (another etag) are dropped.
+pub async fn pin_parts(
+    record:    &FileRecord,
+    http:      &Arc<Http>,
+    cfg:       &Config,
+    tg_token:  &str,
+    dir:       &Path,
+) -> Result<PinnedParts> {
+    if record.is_encrypted() { return Err(anyhow!("encrypted files are not pinned")); }
+    let tg_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(cfg.http_timeout_s))
+        .build()?;
+    let pinned_dir = dir.join(PINNED_DIR);
+    tokio::fs::create_dir_all(&pinned_dir).await.context("create pinned cache")?;
+    let mut keep: HashSet<String> = HashSet::new();
+    let mut done = PinnedParts::default();
+    for copies in part_copies(record) {
+        let part = copies[0].part;
+        let name = cached_part_name(record, part);
+        let path = pinned_dir.join(&name);
+        keep.insert(name.clone());
+        done.parts += 1;
+        if let Ok(meta) = tokio::fs::metadata(&path).await {
+            done.bytes += meta.len();
+            continue;
+        }
+        if tokio::fs::rename(dir.join(&name), &path).await.is_ok() {
+            done.bytes += tokio::fs::metadata(&path).await.map(|m| m.len()).unwrap_or(0);
+            done.moved += 1;
+            continue;
+        }
+        let (data, platform) = fetch_any(&copies, http, cfg, &tg_client, tg_token).await?;
+        write_part(&path, &data).await.context("write pinned part")?;
+        info!("  📌 Part {part} ({platform}) — {:.1}MB", data.len() as f64 / 1024.0 / 1024.0);
+        done.bytes += data.len() as u64;
+        done.fetched += 1;
+    }
+    let prefix = format!("{}_", record.id);
+    for entry in std::fs::read_dir(&pinned_dir).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(&prefix) && !keep.contains(&name) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+    Ok(done)
+}
+
+/// Hand a file's pinned parts back to the LRU cache, which then evicts down to
+/// `part_cache_budget` (`limit`) as usual. Returns how many were pinned.
+pub fn unpin_parts(dir: &Path, file_id: i64, limit: u64) -> usize {
+    let prefix = format!("{file_id}_");
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir.join(PINNED_DIR)).into_iter().flatten().flatten() {
+        let name = entry.file_name();
+        if !name.to_string_lossy().starts_with(&prefix) { continue; }
+        count += 1;
+        if std::fs::rename(entry.path(), dir.join(&name)).is_err() {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+    if limit > 0 && count > 0 {
+        cache::evict_lru(&[dir], limit);
+    }
+    count
+}
+
+/// Drop pinned parts no pinned record still uses (file unpinned behind our back,
+/// deleted, or its parts replaced). Returns how many went.
+pub fn sweep_pinned(dir: &Path, records: &[FileRecord]) -> usize {
+    let keep: HashSet<String> = records.iter().filter(|r| r.pinned)
+        .flat_map(|r| part_copies(r).into_iter().map(move |c| cached_part_name(r, c[0].part)))
+        .collect();
+    let mut removed = 0;
+    for entry in std::fs::read_dir(dir.join(PINNED_DIR)).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(cache::TMP_SUFFIX) { continue; }
+        if !keep.contains(&name) && std::fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+// ── Adaptive buffering ─────────────────────────────────────────────────────────
+//
+// The merge task tunes its chunk size and queue depth to the consumer: sends that
+// have to wait mean the client is the bottleneck, so both shrink and less of the
+// part sits in the channel; a run of sends that never wait grows them so fast
+// clients get big writes. `read_buffer_bytes` is only the starting chunk size.
+
+const MIN_CHUNK:   usize = 16 * 1024;
+const MAX_CHUNK:   usize = 4 * 1024 * 1024;
+const MIN_DEPTH:   usize = 2;
+const MAX_DEPTH:   usize = 64;
+const START_DEPTH: usize = 16;
+/// Waiting longer than this for queue room counts as a stall.
+const STALL:       Duration = Duration::from_millis(20);
+/// Consecutive quick sends before growing.
+const GROW_AFTER:  u32 = 8;
+/// Queue room is rechecked this often while the consumer is behind.
+const POLL:        Duration = Duration::from_millis(5);
+/// Finished downloads kept for diagnostics.
+const MAX_RECENT:  usize = 20;
+
+/// Live buffer state of one merge, as shown in download diagnostics.
+#[derive(Debug, Clone, Serialize)]
+pub struct BufferStats {
+    pub file_id:     i64,
+    pub filename:    String,
+    pub started_at:  i64,    // ms
+    pub finished_at: Option<i64>,
+    pub parts_done:  usize,
+    pub parts_total: usize,
+    pub bytes_sent:  u64,
+    pub chunks:      u64,
+    pub chunk_bytes: usize,  // current chunk size
+    pub depth:       usize,  // current queue depth (chunks)
+    pub queued:      usize,  // chunks waiting for the client
+    pub stalls:      u64,    // sends that waited longer than STALL
+    pub stall_ms:    u64,    // total time spent waiting on the client
+    pub outcome:     Option<String>,  // "done" | "cancelled" | error
+}
+
+/// Process-wide (all profiles) registry of merges in flight and recently finished.
+static ACTIVE: Mutex<BTreeMap<u64, BufferStats>> = Mutex::new(BTreeMap::new());
+static RECENT: Mutex<VecDeque<BufferStats>> = Mutex::new(VecDeque::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+pub fn active_downloads() -> Vec<BufferStats> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+}
+
+pub fn recent_downloads() -> Vec<BufferStats> {
+    RECENT.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+}
+
+/// Registry entry for one merge; moves it to RECENT when the task ends.
+struct Tracked {
+    key:     u64,
+    outcome: &'static str,
+}
+
+impl Tracked {
+    fn start(record: &FileRecord, parts_total: usize, chunk_bytes: usize) -> Self {
+        let key = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).insert(key, BufferStats {
+            file_id: record.id, filename: record.filename.clone(), started_at: current_timestamp_ms(),
+            finished_at: None, parts_done: 0, parts_total, bytes_sent: 0, chunks: 0,
+            chunk_bytes, depth: START_DEPTH, queued: 0, stalls: 0, stall_ms: 0, outcome: None,
+        });
+        Self { key, outcome: "cancelled" }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut BufferStats)) {
+        if let Some(s) = ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&self.key) { f(s); }
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        let Some(mut s) = ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.key) else { return };
+        s.finished_at = Some(current_timestamp_ms());
+        s.outcome.get_or_insert_with(|| self.outcome.to_string());
+        let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+        recent.push_front(s);
+        recent.truncate(MAX_RECENT);
+    }
+}
+
+/// Chunk size and queue depth for one merge, adjusted after every send.
+struct Pacer {
+    chunk:  usize,
+    depth:  usize,
+    streak: u32,
+}
+
+impl Pacer {
+    fn new(start_chunk: usize) -> Self {
+        Self { chunk: start_chunk.clamp(MIN_CHUNK, MAX_CHUNK), depth: START_DEPTH, streak: 0 }
+    }
+
+    fn observe(&mut self, waited: Duration) {
+        if waited >= STALL {
+            self.chunk  = (self.chunk / 2).max(MIN_CHUNK);
+            self.depth  = (self.depth / 2).max(MIN_DEPTH);
+            self.streak = 0;
+        } else {
+            self.streak += 1;
+            if self.streak >= GROW_AFTER {
+                self.chunk  = (self.chunk * 2).min(MAX_CHUNK);
+                self.depth  = (self.depth + 2).min(MAX_DEPTH);
+                self.streak = 0;
+            }
+        }
+    }
+}
+
+/// Send one chunk once fewer than `depth` are queued. `None` means the receiver is gone.
+async fn paced_send(tx: &tokio::sync::mpsc::Sender<Result<Bytes>>, depth: usize, chunk: Bytes) -> Option<Duration> {
+    let started = Instant::now();
+    while tx.max_capacity() - tx.capacity() >= depth {
+        if tx.is_closed() { return None; }
+        sleep(POLL).await;
+    }
+    tx.send(Ok(chunk)).await.ok()?;
+    Some(started.elapsed())
+}
+
 /// Merge all parts into a single byte stream.
 /// Returns an async generator-style channel receiver for streaming.
 pub async fn merge_to_channel(
@@ -91,42 +516,315 @@ pub async fn merge_to_channel(
     http:      Arc<Http>,
     cfg:       Arc<Config>,
     tg_token:  String,
+    cache_dir: PathBuf,
 ) -> tokio::sync::mpsc::Receiver<Result<Bytes>> {
-    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    merge_from(record, http, cfg, tg_token, cache_dir, 0).await.0
+}
+
+/// The first part holding byte `from` and that part's offset; (0, 0) when part
+/// sizes aren't recorded and the merge has to start at the beginning.
+pub fn seek(parts: &[Vec<PartInfo>], from: u64) -> (usize, u64) {
+    let Some(offsets) = part_offsets(parts) else { return (0, 0) };
+    let i = offsets.partition_point(|&o| o <= from).saturating_sub(1);
+    (i, offsets.get(i).copied().unwrap_or(0))
+}
+
+/// `merge_to_channel` for output starting around byte `from` (a Range request):
+/// parts wholly before it are never fetched. Also returns the offset of the first
+/// byte sent, which is at or before `from`.
+pub async fn merge_from(
+    record:    FileRecord,
+    http:      Arc<Http>,
+    cfg:       Arc<Config>,
+    tg_token:  String,
+    cache_dir: PathBuf,
+    from:      u64,
+) -> (tokio::sync::mpsc::Receiver<Result<Bytes>>, u64) {
+    let (tx, rx) = tokio::sync::mpsc::channel(MAX_DEPTH);
+    let span = info_span!("merge", file_id = record.id);
+    let parts = part_copies(&record);
+    let (first, skipped) = seek(&parts, from);
     tokio::spawn(async move {
         let tg_client = reqwest::Client::builder()
             .timeout(Duration::from_secs(cfg.http_timeout_s))
             .build()
             .unwrap();
 
-        let parts = normalize_parts(&record);
         let total = parts.len();
+        let mut pacer   = Pacer::new(cfg.read_buffer_bytes);
+        let mut tracked = Tracked::start(&record, total, pacer.chunk);
+        let job = progress::Job::start(progress::Kind::Download,
+            record.exact_size().unwrap_or((record.size_mb * 1024.0 * 1024.0) as u64).saturating_sub(skipped));
+        if first > 0 {
+            info!("  ⏩ Seek to byte {from}: starting at part {}/{total}", first + 1);
+        }
 
-        for (i, part_info) in parts.into_iter().enumerate() {
-            match fetch_part(&part_info, &http, &cfg, &tg_client, &tg_token).await {
-                Ok(data) => {
-                    info!("  ✅ Part {}/{} ({}) — {:.1}MB", i+1, total, part_info.platform,
+        // Up to `download.prefetch_parts` parts past the one streaming are fetched in
+        // their own tasks (aborted with the JoinSet when the client goes away) and
+        // held in `ready` until their turn.
+        let window = cfg.download_prefetch_parts + 1;
+        let mut inflight = tokio::task::JoinSet::new();
+        let mut ready: BTreeMap<usize, Result<(Vec<u8>, &'static str)>> = BTreeMap::new();
+        let mut next = first;
+        let spawn_fetch = |inflight: &mut tokio::task::JoinSet<_>, k: usize| {
+            let copies = parts[k].clone();
+            let (cached, pinned) = (cached_part_path(&cache_dir, &record, copies[0].part), record.pinned);
+            let (http, cfg, tg_client, tg_token) = (Arc::clone(&http), Arc::clone(&cfg), tg_client.clone(), tg_token.clone());
+            inflight.spawn(async move {
+                (k, fetch_cached(&copies, &cached, pinned, &http, &cfg, &tg_client, &tg_token).await)
+            }.in_current_span());
+        };
+
+        for (i, copies) in parts.iter().enumerate().skip(first) {
+            while next < total && next < i + window {
+                spawn_fetch(&mut inflight, next);
+                next += 1;
+            }
+            let fetched = loop {
+                if let Some(r) = ready.remove(&i) { break r; }
+                match inflight.join_next().await {
+                    Some(Ok((k, r))) => { ready.insert(k, r); }
+                    Some(Err(e))     => break Err(anyhow!("part fetch task failed: {e}")),
+                    None             => break Err(anyhow!("part {} was never fetched", copies[0].part)),
+                }
+            };
+            match fetched {
+                Ok((data, platform)) => {
+                    info!("  ✅ Part {}/{} ({platform}) — {:.1}MB", i+1, total,
                         data.len() as f64 / 1024.0 / 1024.0);
-                    // Stream in read_buffer_bytes chunks
-                    let buf_size = cfg.read_buffer_bytes;
                     let mut offset = 0;
                     while offset < data.len() {
-                        let end = (offset + buf_size).min(data.len());
-                        if tx.send(Ok(Bytes::copy_from_slice(&data[offset..end]))).await.is_err() {
-                            return;
-                        }
+                        let end = (offset + pacer.chunk).min(data.len());
+                        let Some(waited) = paced_send(&tx, pacer.depth,
+                            Bytes::copy_from_slice(&data[offset..end])).await else { return };
+                        pacer.observe(waited);
+                        job.add((end - offset) as u64);
+                        let queued = tx.max_capacity() - tx.capacity();
+                        tracked.update(|s| {
+                            s.bytes_sent  += (end - offset) as u64;
+                            s.chunks      += 1;
+                            s.chunk_bytes  = pacer.chunk;
+                            s.depth        = pacer.depth;
+                            s.queued       = queued;
+                            if waited >= STALL {
+                                s.stalls   += 1;
+                                s.stall_ms += waited.as_millis() as u64;
+                            }
+                        });
                         offset = end;
                     }
+                    tracked.update(|s| s.parts_done = i + 1);
                     if cfg.part_delay_ms > 0 {
                         sleep(Duration::from_millis(cfg.part_delay_ms)).await;
                     }
                 }
                 Err(e) => {
+                    tracked.update(|s| s.outcome = Some(e.to_string()));
                     let _ = tx.send(Err(e)).await;
                     return;
                 }
             }
         }
-    });
-    rx
+        tracked.outcome = "done";
+    }.instrument(span));
+    (rx, skipped)
+}
+
+/// `merge_from` read straight off the part cache, for a client on this machine or
+/// LAN: no Discord/Telegram and no `part_delay_ms`. None unless every part from
+/// the one holding `from` on is cached.
+pub fn merge_cached(
+    record:    &FileRecord,
+    cache_dir: &Path,
+    from:      u64,
+    chunk:     usize,
+) -> Option<(tokio::sync::mpsc::Receiver<Result<Bytes>>, u64)> {
+    use tokio::io::AsyncReadExt;
+    let parts = part_copies(record);
+    let (first, skipped) = seek(&parts, from);
+    let paths: Vec<PathBuf> = parts.iter().skip(first)
+        .map(|copies| cached_part_path(cache_dir, record, copies[0].part))
+        .collect();
+    if paths.is_empty() || !paths.iter().all(|p| p.is_file()) { return None; }
+    let (tx, rx) = tokio::sync::mpsc::channel(START_DEPTH);
+    let span = info_span!("merge", file_id = record.id);
+    tokio::spawn(async move {
+        info!("  🏠 Local client: {} part(s) straight from the cache", paths.len());
+        for path in paths {
+            let mut file = match tokio::fs::File::open(&path).await {
+                Ok(f)  => f,
+                Err(e) => { let _ = tx.send(Err(anyhow!("cached part {}: {e}", path.display()))).await; return; }
+            };
+            cache::touch(&path);
+            loop {
+                let mut buf = vec![0u8; chunk];
+                let n = match file.read(&mut buf).await {
+                    Ok(n)  => n,
+                    Err(e) => { let _ = tx.send(Err(anyhow!("cached part {}: {e}", path.display()))).await; return; }
+                };
+                if n == 0 { break; }
+                buf.truncate(n);
+                if tx.send(Ok(Bytes::from(buf))).await.is_err() { return; }
+            }
+        }
+    }.instrument(span));
+    Some((rx, skipped))
+}
+
+// ── Merge to disk ──────────────────────────────────────────────────────────────
+//
+// Server-side jobs that write a file (extract, recompress) don't need the bytes in
+// order: with every part's `raw_bytes` known, each part's offset is too, so up to
+// `download.parallel_parts` parts are fetched at once and written where they belong.
+
+/// Where each part starts in the merged file, or None if any part predates
+/// `raw_bytes` or its copies disagree on it.
+pub fn part_offsets(parts: &[Vec<PartInfo>]) -> Option<Vec<u64>> {
+    let mut offset = 0u64;
+    parts.iter().map(|copies| {
+        let size = copies[0].raw_bytes;
+        if size == 0 || copies.iter().any(|c| c.raw_bytes != size) { return None; }
+        let start = offset;
+        offset += size;
+        Some(start)
+    }).collect()
+}
+
+/// A part already in a partly merged file, so a later run can check it and go on
+/// from the parts still missing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrittenPart {
+    pub part:   u32,
+    pub offset: u64,
+    pub len:    u64,
+    pub blake3: String,   // of the bytes written, so parts without a recorded hash can be checked too
+}
+
+/// A fetched part that doesn't match its record: retrying can't help.
+#[derive(Debug)]
+pub struct PartMismatch { pub part: u32, pub got: u64, pub expected: u64 }
+
+impl std::fmt::Display for PartMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "part {} is {} bytes, record says {}", self.part, self.got, self.expected)
+    }
+}
+
+impl std::error::Error for PartMismatch {}
+
+/// Told about each part as it lands in the file.
+pub type OnPartWritten = Arc<dyn Fn(WrittenPart) + Send + Sync>;
+
+/// Fetch `record`'s parts in parallel into `file`, each at its offset, adding to
+/// `written` as parts land. Parts in `skip` are already there and left as they
+/// are. Needs `part_offsets`; returns the bytes written.
+#[allow(clippy::too_many_arguments)]
+pub async fn merge_to_file(
+    record:    &FileRecord,
+    http:      Arc<Http>,
+    cfg:       Arc<Config>,
+    tg_token:  String,
+    cache_dir: &Path,
+    file:      std::fs::File,
+    written:   Arc<AtomicU64>,
+    skip:      &HashSet<u32>,
+    on_part:   Option<OnPartWritten>,
+) -> Result<u64> {
+    let parts = part_copies(record);
+    let offsets = part_offsets(&parts).ok_or_else(|| anyhow!("some parts have no recorded size"))?;
+    let total: u64 = parts.iter().map(|c| c[0].raw_bytes).sum();
+    file.set_len(total).context("size output file")?;
+    let file = Arc::new(file);
+    let tg_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(cfg.http_timeout_s))
+        .build()?;
+    let todo: Vec<(Vec<PartInfo>, u64)> = parts.into_iter().zip(offsets)
+        .filter(|(copies, _)| !skip.contains(&copies[0].part))
+        .collect();
+    let job = Arc::new(progress::Job::start(progress::Kind::Download, todo.iter().map(|(c, _)| c[0].raw_bytes).sum()));
+    let count = todo.len();
+    let width = cfg.download_parallel_parts;
+
+    // Every future owns what it uses, so the stream stays Send for spawned jobs
+    futures::stream::iter(todo)
+        .map(|(copies, offset)| {
+            let cached = cached_part_path(cache_dir, record, copies[0].part);
+            let (file, job, written, on_part) = (Arc::clone(&file), Arc::clone(&job), Arc::clone(&written), on_part.clone());
+            let (http, cfg, tg_client, tg_token) = (Arc::clone(&http), Arc::clone(&cfg), tg_client.clone(), tg_token.clone());
+            async move {
+                let (data, platform) = match tokio::fs::read(&cached).await {
+                    Ok(data) => { cache::touch(&cached); (data, "cache") }
+                    Err(_)   => fetch_any(&copies, &http, &cfg, &tg_client, &tg_token).await?,
+                };
+                let expected = copies[0].raw_bytes;
+                if data.len() as u64 != expected {
+                    return Err(PartMismatch { part: copies[0].part, got: data.len() as u64, expected }.into());
+                }
+                let track = on_part.is_some();
+                let hash = tokio::task::spawn_blocking(move || {
+                    write_at(&file, &data, offset)?;
+                    Ok::<_, std::io::Error>(track.then(|| blake3::hash(&data).to_hex().to_string()))
+                }).await?.context("write part")?;
+                if let (Some(on_part), Some(blake3)) = (on_part, hash) {
+                    on_part(WrittenPart { part: copies[0].part, offset, len: expected, blake3 });
+                }
+                job.add(expected);
+                written.fetch_add(expected, Ordering::Relaxed);
+                info!("  ✅ Part {}/{count} ({platform}) — {:.1}MB at {offset}", copies[0].part,
+                    expected as f64 / 1024.0 / 1024.0);
+                Ok(expected)
+            }
+        })
+        .buffer_unordered(width)
+        .try_fold(0u64, |sum, n| async move { Ok(sum + n) })
+        .await
+}
+
+/// The parts of `done` that are still in `file` as written: at the offset the
+/// record puts them, the right length, and with the same BLAKE3.
+pub fn check_written(record: &FileRecord, file: &std::fs::File, done: &[WrittenPart]) -> Vec<WrittenPart> {
+    let parts = part_copies(record);
+    let Some(offsets) = part_offsets(&parts) else { return vec![] };
+    let layout: BTreeMap<u32, (u64, u64)> = parts.iter().zip(offsets)
+        .map(|(copies, offset)| (copies[0].part, (offset, copies[0].raw_bytes)))
+        .collect();
+    done.iter().filter(|w| {
+        if layout.get(&w.part) != Some(&(w.offset, w.len)) { return false; }
+        let mut buf = vec![0u8; w.len as usize];
+        read_at(file, &mut buf, w.offset).is_ok() && blake3::hash(&buf).to_hex().as_str() == w.blake3
+    }).cloned().collect()
+}
+
+#[cfg(unix)]
+fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &std::fs::File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => { let rest = buf; buf = &mut rest[n..]; offset += n as u64; }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_at(file: &std::fs::File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_at(file: &std::fs::File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::WriteZero.into()),
+            n => { buf = &buf[n..]; offset += n as u64; }
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,211 @@
+/// extract.rs — Expand an uploaded ZIP into one record per entry.
+///
+/// The archive is streamed from Discord/Telegram to a temp file (ZIP needs seeking),
+/// entries are unpacked next to it, and each one goes through the same ingest path
+/// as queued local files, a few at a time.
+use axum::http::StatusCode;
+use futures::StreamExt;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::{api::ApiError, download, keyring, queue, state::AppState, storage::FileRecord, throughput};
+
+/// Upper bound for `concurrency`; each ingest already runs its own parallel sends.
+pub const MAX_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Serialize)]
+pub struct Extracted {
+    pub name:    String,
+    pub file_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExtractFailure {
+    pub name:  String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ExtractReport {
+    pub extracted: Vec<Extracted>,
+    pub failed:    Vec<ExtractFailure>,
+    /// Directories and empty entries, which have nothing to upload.
+    pub skipped:   Vec<String>,
+}
+
+struct Entry {
+    name: String,
+    path: PathBuf,
+}
+
+pub async fn extract_all(
+    st:          &AppState,
+    record:      FileRecord,
+    folder_id:   &str,
+    concurrency: usize,
+) -> Result<ExtractReport, ApiError> {
+    let work = st.store.base_dir().join("extract").join(uuid::Uuid::new_v4().simple().to_string());
+    let res = run(st, record, folder_id, concurrency, &work).await;
+    let _ = tokio::fs::remove_dir_all(&work).await;
+    res
+}
+
+async fn run(
+    st:          &AppState,
+    record:      FileRecord,
+    folder_id:   &str,
+    concurrency: usize,
+    work:        &Path,
+) -> Result<ExtractReport, ApiError> {
+    let io_err = |e: std::io::Error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    tokio::fs::create_dir_all(work).await.map_err(io_err)?;
+
+    let archive = work.join("archive.zip");
+    info!("📦 Extracting {} ({})", record.filename, record.id);
+    download_to_file(st, record, &archive, Arc::default()).await?;
+
+    let (entries, mut report) = {
+        let work = work.to_path_buf();
+        tokio::task::spawn_blocking(move || unpack(&archive, &work)).await
+            .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??
+    };
+    info!("📦 {} entries unpacked, uploading {concurrency} at a time", entries.len());
+
+    let results: Vec<_> = futures::stream::iter(entries)
+        .map(|entry| async move {
+            let res = queue::ingest_local_file(st, &entry.path, &entry.name, folder_id, "", None, None).await;
+            let _ = tokio::fs::remove_file(&entry.path).await;
+            (entry.name, res)
+        })
+        .buffer_unordered(concurrency.clamp(1, MAX_CONCURRENCY))
+        .collect().await;
+
+    for (name, res) in results {
+        match res {
+            Ok(rec) => report.extracted.push(Extracted { name, file_id: rec.id }),
+            Err(e)  => {
+                warn!("⚠️ Extracted entry {name} failed: {e}");
+                report.failed.push(ExtractFailure { name, error: e.to_string() });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Where a merge into `path` writes until it is complete: `<path>.part`.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Merge a stored file into `path` under its read lock, adding to `written` as it
+/// goes. Parts are fetched in parallel and written at their offsets when the
+/// record knows every part's size. The merge goes to `partial_path` and replaces
+/// `path` only once it is complete.
+pub async fn download_to_file(st: &AppState, record: FileRecord, path: &Path, written: Arc<AtomicU64>) -> Result<(), ApiError> {
+    resume_to_file(st, record, path, written, &[], None).await
+}
+
+/// Error code of a merge that fetched a part unlike its record.
+pub const PART_MISMATCH: &str = "part_mismatch";
+
+/// `download_to_file` into a `path` an earlier run left half done (in its
+/// `partial_path`): the parts in `done` that still check out against their BLAKE3
+/// are kept, the rest fetched.
+/// Records without part sizes can't be merged out of order and start over.
+pub async fn resume_to_file(
+    st:      &AppState,
+    record:  FileRecord,
+    path:    &Path,
+    written: Arc<AtomicU64>,
+    done:    &[download::WrittenPart],
+    on_part: Option<download::OnPartWritten>,
+) -> Result<(), ApiError> {
+    let io_err = |e: std::io::Error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let _lock = st.file_locks.read(record.id).await;
+    let cfg = keyring::config_for(st, record.wrapped_key.as_deref())?;
+    let started = std::time::Instant::now();
+    let partial = partial_path(path);
+    if download::part_offsets(&download::part_copies(&record)).is_some() {
+        // Kept as is: a resumed run checks what is already there
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(done.is_empty())
+            .open(&partial).map_err(io_err)?;
+        let (file, kept) = if done.is_empty() {
+            (file, vec![])
+        } else {
+            let (record, done) = (record.clone(), done.to_vec());
+            tokio::task::spawn_blocking(move || {
+                let kept = download::check_written(&record, &file, &done);
+                (file, kept)
+            }).await.map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        };
+        if !done.is_empty() {
+            info!("  ↩️ Resuming {}: {}/{} written part(s) check out", record.filename, kept.len(), done.len());
+        }
+        written.fetch_add(kept.iter().map(|w| w.len).sum(), Ordering::Relaxed);
+        let skip = kept.iter().map(|w| w.part).collect();
+        let fetched = download::merge_to_file(
+            &record, Arc::clone(&st.http), cfg, st.tg_token.clone(), &st.part_cache_dir, file, written, &skip, on_part,
+        ).await.map_err(|e| ApiError {
+            code: e.is::<download::PartMismatch>().then(|| PART_MISMATCH.to_string()),
+            ..ApiError::new(StatusCode::BAD_GATEWAY, format!("Tải file lỗi: {e}"))
+        })?;
+        throughput::record(&*st.store, &st.cfg.throughput_file, throughput::Direction::Download, fetched, started.elapsed());
+        return tokio::fs::rename(&partial, path).await.map_err(io_err);
+    }
+    let mut file = tokio::fs::File::create(&partial).await.map_err(io_err)?;
+    let mut total = 0u64;
+    let mut rx = download::merge_to_channel(
+        record, Arc::clone(&st.http), cfg, st.tg_token.clone(),
+        st.part_cache_dir.clone(),
+    ).await;
+    while let Some(chunk) = rx.recv().await {
+        let chunk = chunk.map_err(|e| ApiError::new(StatusCode::BAD_GATEWAY, format!("Tải file lỗi: {e}")))?;
+        file.write_all(&chunk).await.map_err(io_err)?;
+        total += chunk.len() as u64;
+        written.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+    }
+    file.flush().await.map_err(io_err)?;
+    drop(file);
+    throughput::record(&*st.store, &st.cfg.throughput_file, throughput::Direction::Download, total, started.elapsed());
+    tokio::fs::rename(&partial, path).await.map_err(io_err)
+}
+
+/// Write every file entry to `work/<index>`. Entry names keep only their last
+/// path component, since records have no nested folders.
+fn unpack(archive: &Path, work: &Path) -> Result<(Vec<Entry>, ExtractReport), ApiError> {
+    let file = std::fs::File::open(archive)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut zip = zip::ZipArchive::new(file)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, format!("Không phải file ZIP hợp lệ: {e}")))?;
+
+    let mut entries = vec![];
+    let mut report = ExtractReport::default();
+    for i in 0..zip.len() {
+        let listed = zip.name_for_index(i).unwrap_or("?").to_string();
+        let mut zf = match zip.by_index(i) {
+            Ok(zf) => zf,
+            Err(e) => {
+                report.failed.push(ExtractFailure { name: listed, error: e.to_string() });
+                continue;
+            }
+        };
+        let name = zf.enclosed_name()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()));
+        let Some(name) = name.filter(|_| zf.is_file() && zf.size() > 0) else {
+            report.skipped.push(listed);
+            continue;
+        };
+        let path = work.join(i.to_string());
+        let copied = std::fs::File::create(&path).and_then(|mut out| std::io::copy(&mut zf, &mut out));
+        match copied {
+            Ok(_)  => entries.push(Entry { name, path }),
+            Err(e) => report.failed.push(ExtractFailure { name: listed, error: e.to_string() }),
+        }
+    }
+    Ok((entries, report))
+}
@@ -0,0 +1,64 @@
+/// file_locks.rs — Per-file read/write locks so a delete can't pull parts out from
+/// under a download that is still merging them.
+///
+/// Downloads (merge, preview, shares, thumbnails, extract) hold a read guard for as
+/// long as they fetch parts; deletes take the write guard. Entries are created on
+/// demand and dropped with their last guard, so the map only holds files in use.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+type Gate = Arc<RwLock<()>>;
+
+#[derive(Default)]
+pub struct FileLocks {
+    gates: Mutex<HashMap<i64, Gate>>,
+}
+
+#[allow(dead_code)] // guards are only held for their Drop
+enum Held {
+    Read(OwnedRwLockReadGuard<()>),
+    Write(OwnedRwLockWriteGuard<()>),
+}
+
+/// Releases its lock on drop and forgets the gate once nobody else holds or waits on it.
+pub struct FileGuard {
+    locks: Arc<FileLocks>,
+    id:    i64,
+    held:  Option<Held>,
+}
+
+impl Drop for FileGuard {
+    fn drop(&mut self) {
+        self.held.take();
+        let mut gates = self.locks.gates.lock().unwrap_or_else(|e| e.into_inner());
+        // Only the map's own reference left: no guards, no waiters
+        if gates.get(&self.id).is_some_and(|g| Arc::strong_count(g) == 1) {
+            gates.remove(&self.id);
+        }
+    }
+}
+
+impl FileLocks {
+    fn gate(&self, id: i64) -> Gate {
+        Arc::clone(self.gates.lock().unwrap_or_else(|e| e.into_inner()).entry(id).or_default())
+    }
+
+    /// Shared lock for reading the file's parts; waits for a running delete.
+    pub async fn read(self: &Arc<Self>, id: i64) -> FileGuard {
+        let held = Held::Read(self.gate(id).read_owned().await);
+        FileGuard { locks: Arc::clone(self), id, held: Some(held) }
+    }
+
+    /// Exclusive lock, waiting for in-flight downloads to finish.
+    pub async fn write(self: &Arc<Self>, id: i64) -> FileGuard {
+        let held = Held::Write(self.gate(id).write_owned().await);
+        FileGuard { locks: Arc::clone(self), id, held: Some(held) }
+    }
+
+    /// Exclusive lock, or `None` right away if the file is being read.
+    pub fn try_write(self: &Arc<Self>, id: i64) -> Option<FileGuard> {
+        let held = Held::Write(self.gate(id).try_write_owned().ok()?);
+        Some(FileGuard { locks: Arc::clone(self), id, held: Some(held) })
+    }
+}
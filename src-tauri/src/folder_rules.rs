@@ -0,0 +1,35 @@
+/// folder_rules.rs — Filename patterns that pick a folder for uploads without one.
+///
+/// Patterns are case-insensitive globs over the whole filename (`*` any run,
+/// `?` one character): `*.pdf`, `IMG_*.jpg`. Rules are tried in order and the
+/// first match wins.
+use crate::storage::{Folder, FolderRule};
+
+/// Longest pattern accepted by `POST /api/rules`.
+pub const MAX_PATTERN_LEN: usize = 200;
+
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let n: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    // Last `*` seen and the name position it is currently covering up to
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        match p.get(pi) {
+            Some('*') => { star = Some((pi, ni)); pi += 1; }
+            Some(&c) if c == '?' || c == n[ni] => { pi += 1; ni += 1; }
+            _ => match star {
+                Some((sp, sn)) => { pi = sp + 1; ni = sn + 1; star = Some((sp, sn + 1)); }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// First rule matching `filename` whose folder still exists.
+pub fn resolve<'a>(rules: &'a [FolderRule], folders: &[Folder], filename: &str) -> Option<&'a FolderRule> {
+    rules.iter().find(|r| {
+        glob_match(&r.pattern, filename) && folders.iter().any(|f| f.id.to_string() == r.folder_id)
+    })
+}
@@ -0,0 +1,69 @@
+/// folder_tree.rs — Folders nest through `parent_id`; Discord categories don't.
+/// They stay one flat list, a subfolder's category named after its whole path
+/// ("work-photos-2024"), and the tree itself lives in `folders_file` alone.
+///
+/// Each folder keeps its `path` ("Work/photos/2024") materialized: it is rewritten
+/// for the whole subtree whenever a folder is created, moved, renamed from
+/// Discord or deleted (its children move up a level).
+use std::collections::{HashMap, HashSet};
+
+use crate::storage::Folder;
+
+/// Most folders above any one folder.
+pub const MAX_DEPTH: usize = 16;
+
+/// Ids from `id` up to its top-level folder. Stops at a missing parent or a loop,
+/// so a damaged tree still gives every folder a place.
+fn ancestry(by_id: &HashMap<i64, &Folder>, id: i64) -> Vec<i64> {
+    let mut chain = vec![];
+    let mut at = Some(id);
+    while let Some(id) = at {
+        if chain.contains(&id) { break; }
+        let Some(f) = by_id.get(&id) else { break };
+        chain.push(id);
+        at = f.parent_id;
+    }
+    chain
+}
+
+/// Recompute every `path` from the parent links. Returns whether any changed.
+pub fn refresh_paths(folders: &mut [Folder]) -> bool {
+    let paths: Vec<String> = {
+        let by_id: HashMap<i64, &Folder> = folders.iter().map(|f| (f.id, f)).collect();
+        folders.iter().map(|f| {
+            let chain = ancestry(&by_id, f.id);
+            chain.iter().rev().map(|id| by_id[id].name.as_str()).collect::<Vec<_>>().join("/")
+        }).collect()
+    };
+    let mut changed = false;
+    for (f, path) in folders.iter_mut().zip(paths) {
+        if f.path != path {
+            f.path = path;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// How many folders sit above `id`.
+pub fn depth(folders: &[Folder], id: i64) -> usize {
+    let by_id: HashMap<i64, &Folder> = folders.iter().map(|f| (f.id, f)).collect();
+    ancestry(&by_id, id).len().saturating_sub(1)
+}
+
+/// `id` and every folder below it.
+pub fn subtree(folders: &[Folder], id: i64) -> HashSet<i64> {
+    let mut ids = HashSet::from([id]);
+    loop {
+        let before = ids.len();
+        for f in folders {
+            if f.parent_id.is_some_and(|p| ids.contains(&p)) { ids.insert(f.id); }
+        }
+        if ids.len() == before { return ids; }
+    }
+}
+
+/// The folder named `name` directly under `parent` (None = top level).
+pub fn child<'a>(folders: &'a [Folder], parent: Option<i64>, name: &str) -> Option<&'a Folder> {
+    folders.iter().find(|f| f.parent_id == parent && f.name == name)
+}
@@ -0,0 +1,220 @@
+/// health.rs — One scored snapshot of drive health for the dashboard widget.
+///
+/// Each check reports a status, a count, the points it costs and up to
+/// `MAX_ITEMS` items with a drill-down `link` (an API path, or a Discord URL for
+/// channels). The score starts at 100 and loses each check's penalty, which is
+/// capped per check so one noisy problem can't hide the others.
+use serde::Serialize;
+use serde_json::{json, Value};
+use serenity::model::{channel::ChannelType, id::ChannelId};
+use std::{collections::{HashMap, HashSet}, time::Duration};
+
+use crate::{
+    cache,
+    discord_bot::{CATEGORY_CHANNEL_LIMIT, GUILD_CHANNEL_LIMIT},
+    state::AppState,
+    storage::{current_datetime_iso, AuditEvent},
+    telegram,
+    upload::{STATUS_DONE, STATUS_FINALIZING},
+};
+
+/// Items listed per check; `count` still covers all of them.
+const MAX_ITEMS: usize = 20;
+/// Share of a channel limit (or of the cache cap) from which it is reported.
+const NEAR_FULL: f64 = 0.9;
+/// Sessions idle for this share of the TTL are reported before GC drops them.
+const STALE_TTL_SHARE: u64 = 2;
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Ok,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub id:      &'static str,
+    pub status:  Level,
+    pub count:   usize,
+    pub penalty: u32,
+    pub summary: String,
+    pub items:   Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub score:      u32,
+    pub status:     Level,
+    pub checked_at: String,
+    pub checks:     Vec<Check>,
+}
+
+impl Check {
+    /// `per_item` points per problem up to `cap`; any problem is reported at `level`.
+    fn new(id: &'static str, level: Level, items: Vec<Value>, per_item: u32, cap: u32, summary: String) -> Self {
+        let count = items.len();
+        Self {
+            id,
+            status:  if count == 0 { Level::Ok } else { level },
+            count,
+            penalty: (count as u32).saturating_mul(per_item).min(cap),
+            summary,
+            items:   items.into_iter().take(MAX_ITEMS).collect(),
+        }
+    }
+}
+
+pub async fn summary(st: &AppState) -> Summary {
+    let history = st.store.load_history(&st.cfg.history_file);
+    let live: HashSet<i64> = history.iter().map(|r| r.id).collect();
+
+    let mut checks = vec![degraded_files(st, &live), failed_verifications(st), stale_sessions(st)];
+    checks.extend(discord_checks(st, &history).await);
+    checks.push(telegram_check(st).await);
+    checks.push(cache_check(st));
+
+    let score = 100u32.saturating_sub(checks.iter().map(|c| c.penalty).sum());
+    let worst = checks.iter().map(|c| c.status).max().unwrap_or(Level::Ok);
+    let status = if score < 50 { Level::Error } else { worst };
+    Summary { score, status, checked_at: current_datetime_iso(), checks }
+}
+
+/// Files whose latest verification (POST /api/files/:id/verify) found bad parts.
+fn degraded_files(st: &AppState, live: &HashSet<i64>) -> Check {
+    let mut latest: HashMap<i64, AuditEvent> = HashMap::new();
+    for ev in st.store.load_jsonl::<AuditEvent>(&st.cfg.audit_file) {
+        if ev.action != "verified" { continue; }
+        if let Some(id) = ev.file_id.filter(|id| live.contains(id)) { latest.insert(id, ev); }
+    }
+    let mut items: Vec<Value> = latest.into_iter()
+        .filter(|(_, ev)| ev.detail["healthy"] == json!(false))
+        .map(|(id, ev)| json!({
+            "file_id":     id,
+            "degraded":    ev.detail["degraded"],
+            "verified_at": ev.ts,
+            "link":        format!("/api/files/{id}/activity"),
+        }))
+        .collect();
+    items.sort_by_key(|v| v["file_id"].as_i64());
+    let summary = format!("{} file có phần bị lỗi ở lần kiểm tra gần nhất", items.len());
+    Check::new("degraded_files", Level::Error, items, 10, 40, summary)
+}
+
+/// Queue items whose parts didn't byte-match their source (`after_verify`).
+fn failed_verifications(st: &AppState) -> Check {
+    let items: Vec<Value> = st.store.load_queue(&st.cfg.queue_file).into_iter()
+        .filter(|q| q.verified == Some(false))
+        .map(|q| json!({ "queue_id": q.id, "filename": q.filename, "record_id": q.record_id, "link": "/api/queue" }))
+        .collect();
+    let summary = format!("{} upload từ hàng đợi không khớp với file gốc", items.len());
+    Check::new("failed_verifications", Level::Warn, items, 5, 20, summary)
+}
+
+/// Unfinished sessions idle for half the TTL: abandoned uploads still holding RAM and a channel.
+fn stale_sessions(st: &AppState) -> Check {
+    let now = chrono::Utc::now().timestamp();
+    let min_idle = st.cfg.session_ttl_s / STALE_TTL_SHARE;
+    let mut items: Vec<Value> = st.sessions.snapshot().into_values()
+        .filter(|s| s.status != STATUS_DONE && s.status != STATUS_FINALIZING)
+        .filter_map(|s| {
+            let idle = s.idle_secs(now).filter(|&i| i >= min_idle)?;
+            Some(json!({
+                "session_id": s.session_id, "filename": s.filename, "status": s.status, "idle_s": idle,
+                "link": format!("/api/upload/session/{}", s.session_id),
+            }))
+        })
+        .collect();
+    items.sort_by_key(|v| std::cmp::Reverse(v["idle_s"].as_u64()));
+    let summary = format!("{} phiên upload bỏ dở quá {} phút", items.len(), min_idle / 60);
+    Check::new("stale_sessions", Level::Warn, items, 2, 10, summary)
+}
+
+/// Reachability, folders/guild close to Discord's channel limits, and channels in the
+/// drive's categories that no file record points to.
+async fn discord_checks(st: &AppState, history: &[crate::storage::FileRecord]) -> Vec<Check> {
+    if st.cfg.simulate {
+        return vec![Check::new("discord", Level::Ok, vec![], 0, 0, "Chế độ mô phỏng".to_string())];
+    }
+    let started = std::time::Instant::now();
+    let channels = match tokio::time::timeout(PING_TIMEOUT, st.guild_id.channels(&st.http)).await {
+        Ok(Ok(c))  => c,
+        Ok(Err(e)) => return vec![unreachable("discord", e.to_string(), 40)],
+        Err(_)     => return vec![unreachable("discord", "timeout".to_string(), 40)],
+    };
+    let reach = Check::new("discord", Level::Ok, vec![], 0, 0,
+        format!("Kết nối được ({} ms)", started.elapsed().as_millis()));
+
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    let used_in = |cat: i64| channels.values().filter(|c| c.parent_id == Some(ChannelId::new(cat as u64))).count();
+    let mut quota = vec![];
+    let guild_used = channels.len();
+    if guild_used as f64 >= GUILD_CHANNEL_LIMIT as f64 * NEAR_FULL {
+        quota.push(json!({ "scope": "guild", "used": guild_used, "limit": GUILD_CHANNEL_LIMIT, "link": "/api/stats" }));
+    }
+    for f in &folders {
+        // Only the newest category fills up; older ones are full by design
+        let Some(&last) = f.category_ids().last() else { continue };
+        let used = used_in(last);
+        if used as f64 >= CATEGORY_CHANNEL_LIMIT as f64 * NEAR_FULL {
+            quota.push(json!({
+                "scope": "folder", "folder_id": f.id, "name": f.name, "used": used, "limit": CATEGORY_CHANNEL_LIMIT,
+                "link": "/api/folders?include=stats",
+            }));
+        }
+    }
+    let quota_summary = format!("{} folder/server sắp đầy giới hạn kênh Discord", quota.len());
+    // Filling the guild blocks every upload; a full folder category just opens an overflow one
+    let guild_full = quota.iter().any(|q| q["scope"] == "guild");
+    let quota = Check::new("near_quota", if guild_full { Level::Error } else { Level::Warn }, quota,
+        if guild_full { 25 } else { 5 }, 25, quota_summary);
+
+    let drive_categories: HashSet<ChannelId> = folders.iter().flat_map(|f| f.category_ids())
+        .map(|c| ChannelId::new(c as u64)).collect();
+    let mut referenced: HashSet<String> = history.iter().map(|r| r.channel_id.clone()).collect();
+    referenced.extend(st.sessions.snapshot().into_values().filter_map(|s| s.channel_id));
+    let mut orphans: Vec<Value> = channels.values()
+        .filter(|c| c.kind == ChannelType::Text && c.parent_id.is_some_and(|p| drive_categories.contains(&p)))
+        .filter(|c| !referenced.contains(&c.id.get().to_string()))
+        .map(|c| json!({
+            "channel_id": c.id.get().to_string(), "name": c.name,
+            "link": format!("https://discord.com/channels/{}/{}", st.guild_id.get(), c.id.get()),
+        }))
+        .collect();
+    orphans.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    let orphan_summary = format!("{} kênh trong folder không thuộc file nào", orphans.len());
+    vec![reach, quota, Check::new("orphan_channels", Level::Warn, orphans, 1, 10, orphan_summary)]
+}
+
+async fn telegram_check(st: &AppState) -> Check {
+    if !st.tg_enabled || st.cfg.simulate {
+        let why = if st.cfg.simulate { "Chế độ mô phỏng" } else { "Chưa cấu hình" };
+        return Check::new("telegram", Level::Ok, vec![], 0, 0, why.to_string());
+    }
+    let started = std::time::Instant::now();
+    match telegram::ping(&reqwest::Client::new(), &st.tg_token, PING_TIMEOUT).await {
+        Ok(()) => Check::new("telegram", Level::Ok, vec![], 0, 0,
+            format!("Kết nối được ({} ms)", started.elapsed().as_millis())),
+        Err(e) => unreachable("telegram", e.to_string(), 20),
+    }
+}
+
+fn cache_check(st: &AppState) -> Check {
+    let usage = cache::usage(&[st.thumbnail_dir.as_path(), st.part_cache_dir.as_path()]);
+    let max = st.cfg.cache_max_bytes;
+    let mb = |b: u64| (b as f64 / 1024.0 / 1024.0 * 10.0).round() / 10.0;
+    let full = max > 0 && usage.bytes as f64 >= max as f64 * NEAR_FULL;
+    let items = if full { vec![json!({ "bytes": usage.bytes, "max_bytes": max, "link": "/api/settings" })] } else { vec![] };
+    let limit = if max == 0 { "không giới hạn".to_string() } else { format!("{} MB", mb(max)) };
+    let mut check = Check::new("cache", Level::Warn, items, 5, 5,
+        format!("{} file, {} MB / {limit}", usage.files, mb(usage.bytes)));
+    check.count = usage.files;
+    check
+}
+
+fn unreachable(id: &'static str, error: String, penalty: u32) -> Check {
+    Check::new(id, Level::Error, vec![json!({ "error": error })], penalty, penalty,
+        "Không kết nối được".to_string())
+}
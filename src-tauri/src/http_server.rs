@@ -0,0 +1,208 @@
+/// http_server.rs — Accept loop for the Axum router with HTTP/1.1 keep-alive and
+/// HTTP/2 (h2c prior knowledge, or h2 when a TLS proxy sits in front).
+///
+/// `axum::serve` hides hyper's connection settings, so connections are driven here
+/// directly. `server.keep_alive_s` bounds how long an idle connection waits for its
+/// next request; chunk uploads reuse the same sockets instead of reconnecting.
+///
+/// With `server.listen` set to a Unix socket or named pipe no TCP port is opened;
+/// with "none" the window reaches the router in-process through `handle_local`.
+use std::{net::{IpAddr, SocketAddr}, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::connect_info::{IntoMakeServiceWithConnectInfo, MockConnectInfo},
+    http::{header, HeaderValue, Method, Request, Response, StatusCode},
+    Router,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use tokio::net::TcpListener;
+use tower::Service;
+use tracing::{debug, warn};
+
+/// HTTP/2 PING interval bounds; dead peers are dropped after one unanswered ping.
+const H2_PING_MIN: Duration = Duration::from_secs(10);
+const H2_PING_MAX: Duration = Duration::from_secs(60);
+const H2_PING_TIMEOUT: Duration = Duration::from_secs(20);
+/// Concurrent streams per HTTP/2 connection (parallel chunk PUTs share one socket).
+const H2_MAX_STREAMS: u32 = 128;
+/// Largest response body `handle_local` hands to the window in one piece.
+const LOCAL_BODY_MAX: u64 = 32 * 1024 * 1024;
+
+fn builder(keep_alive: Duration) -> auto::Builder<TokioExecutor> {
+    let mut b = auto::Builder::new(TokioExecutor::new());
+    // The read-head timer also runs while an HTTP/1.1 connection sits idle between
+    // requests, so it doubles as the keep-alive idle timeout.
+    b.http1()
+        .timer(TokioTimer::new())
+        .keep_alive(true)
+        .header_read_timeout(keep_alive);
+    b.http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(H2_MAX_STREAMS)
+        .keep_alive_interval((keep_alive / 4).clamp(H2_PING_MIN, H2_PING_MAX))
+        .keep_alive_timeout(H2_PING_TIMEOUT);
+    b
+}
+
+/// Serve `router` on `listener` until the process exits. Handlers still see the
+/// peer via `ConnectInfo<SocketAddr>`.
+pub async fn serve(listener: TcpListener, router: Router, keep_alive: Duration) {
+    let builder = builder(keep_alive);
+    let mut make_service: IntoMakeServiceWithConnectInfo<Router, SocketAddr> =
+        router.into_make_service_with_connect_info::<SocketAddr>();
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // EMFILE and friends: back off instead of spinning
+                warn!("⚠️ accept failed: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let svc = match make_service.call(peer).await {
+            Ok(svc) => svc,
+            Err(never) => match never {},
+        };
+        let builder = builder.clone();
+        tokio::spawn(async move {
+            let io = TokioIo::new(stream);
+            if let Err(e) = builder.serve_connection_with_upgrades(io, TowerToHyperService::new(svc)).await {
+                debug!("connection {peer} closed: {e}");
+            }
+        });
+    }
+}
+
+/// Handlers that log or rate-limit by `ConnectInfo<SocketAddr>` see loopback when
+/// the request did not arrive over TCP.
+pub fn local_router(router: Router) -> Router {
+    router.layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))))
+}
+
+/// A peer on this machine or the local network: loopback, RFC 1918, link-local
+/// or IPv6 unique-local. `local_router` peers count, being loopback.
+pub fn is_local_peer(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_private() || v4.is_link_local(),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_local_peer(IpAddr::V4(v4)),
+            None => {
+                let head = v6.segments()[0];
+                v6.is_loopback() || head & 0xfe00 == 0xfc00 || head & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/// Serve `router` (from `local_router`) on a Unix domain socket at `path`. A stale
+/// socket file is replaced and the new one is readable by the owner only.
+#[cfg(unix)]
+pub async fn serve_unix(path: &std::path::Path, router: Router, keep_alive: Duration) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let _ = std::fs::remove_file(path);
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    let builder = builder(keep_alive);
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("⚠️ accept failed: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let (builder, svc) = (builder.clone(), router.clone());
+        tokio::spawn(async move {
+            if let Err(e) = builder.serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(svc)).await {
+                debug!("unix connection closed: {e}");
+            }
+        });
+    }
+}
+
+/// Serve `router` (from `local_router`) on the named pipe `\\.\pipe\<name>`.
+/// Remote (SMB) clients are rejected; a pipe already owned by another process is an error.
+#[cfg(windows)]
+pub async fn serve_pipe(name: &str, router: Router, keep_alive: Duration) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    let path = format!(r"\\.\pipe\{name}");
+    let builder = builder(keep_alive);
+    let mut server = ServerOptions::new().first_pipe_instance(true).create(&path)?;
+    loop {
+        server.connect().await?;
+        // Open the next instance before handing this one off so clients never see "busy"
+        let conn = std::mem::replace(&mut server, ServerOptions::new().create(&path)?);
+        let (builder, svc) = (builder.clone(), router.clone());
+        tokio::spawn(async move {
+            if let Err(e) = builder.serve_connection_with_upgrades(TokioIo::new(conn), TowerToHyperService::new(svc)).await {
+                debug!("pipe connection closed: {e}");
+            }
+        });
+    }
+}
+
+/// Run one request from the window's `drive://` scheme through `router` (from
+/// `local_router`). Tauri wants the response as one buffer, so bodies are capped at
+/// `LOCAL_BODY_MAX`: a larger GET that the route serves by range is answered with
+/// its first `LOCAL_BODY_MAX` bytes from where it was asked (206), and media
+/// elements ask for the rest; anything else that large gets a 413. Endpoints that
+/// never finish their body (event streams) do not work over this path.
+pub async fn handle_local(router: Router, req: Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let retry = (req.method() == Method::GET).then(|| (req.uri().clone(), req.headers().clone()));
+    let res = call(router.clone(), req.map(Body::from)).await;
+    let too_big = res.headers().get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+        .is_some_and(|n| n > LOCAL_BODY_MAX);
+    if !too_big { return buffer(res).await; }
+    let ranged = res.headers().get(header::ACCEPT_RANGES).is_some_and(|v| v == "bytes");
+    let Some((uri, headers)) = retry.filter(|_| ranged) else { return too_large() };
+    let start = headers.get(header::RANGE)
+        .and_then(|v| v.to_str().ok()?.strip_prefix("bytes=")?.split('-').next()?.parse::<u64>().ok())
+        .unwrap_or(0);
+    let mut req = Request::new(Body::empty());
+    *req.uri_mut() = uri;
+    *req.headers_mut() = headers;
+    let window = format!("bytes={start}-{}", start + LOCAL_BODY_MAX - 1);
+    req.headers_mut().insert(header::RANGE, HeaderValue::from_str(&window).expect("ascii range"));
+    buffer(call(router, req).await).await
+}
+
+async fn call(mut router: Router, req: Request<Body>) -> Response<Body> {
+    match router.call(req).await {
+        Ok(res) => res,
+        Err(never) => match never {},
+    }
+}
+
+async fn buffer(res: Response<Body>) -> Response<Vec<u8>> {
+    let (parts, body) = res.into_parts();
+    match axum::body::to_bytes(body, LOCAL_BODY_MAX as usize).await {
+        Ok(bytes) => Response::from_parts(parts, bytes.to_vec()),
+        Err(e) => {
+            warn!("⚠️ local request body failed: {e}");
+            let mut res = Response::new(e.to_string().into_bytes());
+            *res.status_mut() = StatusCode::BAD_GATEWAY;
+            res
+        }
+    }
+}
+
+fn too_large() -> Response<Vec<u8>> {
+    let mut res = Response::new(format!(
+        "Response larger than {}MB cannot go through drive:// — serve it over HTTP (server.listen)",
+        LOCAL_BODY_MAX / 1024 / 1024,
+    ).into_bytes());
+    *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    res
+}
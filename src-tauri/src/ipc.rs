@@ -0,0 +1,126 @@
+/// ipc.rs — Tauri commands for the core file operations, so the desktop window can
+/// list, upload, download and delete without a round trip through the local HTTP
+/// server. Like un-prefixed `/api/...` routes they act on the active profile.
+///
+/// From the frontend: `window.__TAURI__.core.invoke('list_files', { folderId })`.
+/// Commands that take a local path (`upload_local_file`, `download_to_disk`,
+/// `enqueue_upload`) are
+/// only granted to bundled pages (capabilities/default.json), never to the window
+/// while it is served over HTTP or `drive://` (capabilities/remote.json).
+use serde_json::{json, Value};
+use tauri::State;
+
+use crate::{
+    api, disk_jobs, profiles::SharedRegistry, queue, state::AppState,
+    storage::{FileRecord, QueueItem, SourceAction},
+    trash,
+};
+
+fn active(reg: &SharedRegistry) -> Result<AppState, String> {
+    reg.get(&reg.active()).map(|p| p.state.clone()).ok_or_else(|| "Không có profile nào đang hoạt động".to_string())
+}
+
+/// Same as `GET /api/files?folder_id=`.
+#[tauri::command]
+pub async fn list_files(reg: State<'_, SharedRegistry>, folder_id: Option<String>) -> Result<Vec<FileRecord>, String> {
+    let st = active(&reg)?;
+    Ok(api::files_in(&st, folder_id.as_deref().unwrap_or("")))
+}
+
+/// Upload a file from local disk, waiting until it is stored.
+#[tauri::command]
+pub async fn upload_local_file(
+    reg:       State<'_, SharedRegistry>,
+    path:      String,
+    folder_id: Option<String>,
+    message:   Option<String>,
+) -> Result<FileRecord, String> {
+    let st = active(&reg)?;
+    let path = std::path::PathBuf::from(path);
+    let filename = path.file_name().map(|n| n.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("Không phải đường dẫn file: {}", path.display()))?;
+    queue::ingest_local_file(
+        &st, &path, &filename, folder_id.as_deref().unwrap_or(""), message.as_deref().unwrap_or(""), None, None,
+    ).await.map_err(|e| e.to_string())
+}
+
+/// Queue a file from local disk for background upload; listed on `GET /api/queue`.
+/// `after_verify` deletes or moves the source once the upload byte-matches it.
+/// Only here, not on the HTTP router: anything able to reach the port could
+/// upload, delete or move any file the app can read with it.
+#[tauri::command]
+pub async fn enqueue_upload(
+    reg:          State<'_, SharedRegistry>,
+    path:         String,
+    filename:     Option<String>,
+    folder_id:    Option<String>,
+    message:      Option<String>,
+    after_verify: Option<SourceAction>,
+) -> Result<QueueItem, String> {
+    let st = active(&reg)?;
+    let path = std::path::PathBuf::from(path.trim());
+    if !path.is_absolute() {
+        return Err("path phải là đường dẫn tuyệt đối".to_string());
+    }
+    let meta = std::fs::metadata(&path).ok().filter(|m| m.is_file())
+        .ok_or_else(|| format!("Không đọc được file: {}", path.display()))?;
+    if meta.len() == 0 { return Err("File rỗng".to_string()); }
+    if let Some(SourceAction::Move { to }) = &after_verify {
+        let to = std::path::Path::new(to);
+        if !to.is_absolute() || !to.is_dir() {
+            return Err(format!("Thư mục đích không tồn tại: {}", to.display()));
+        }
+    }
+    let filename = match filename.filter(|n| !n.trim().is_empty()) {
+        Some(n) => n,
+        None => path.file_name().map(|n| n.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("Không phải đường dẫn file: {}", path.display()))?,
+    };
+    let mut item = queue::new_item(
+        path.to_string_lossy().into_owned(), filename, meta.len(),
+        folder_id.unwrap_or_default(), message.unwrap_or_default(),
+    );
+    item.after_verify = after_verify;
+    queue::enqueue(&st, item.clone());
+    Ok(item)
+}
+
+/// Merge a file into `path`, picked with the save dialog, in the background.
+/// Returns the job id; progress is on `GET /api/download/jobs`. Only here, not on
+/// the HTTP router: anything able to reach the port could write anywhere with it.
+#[tauri::command]
+pub async fn download_to_disk(reg: State<'_, SharedRegistry>, file_id: i64, path: String) -> Result<String, String> {
+    let st = active(&reg)?;
+    let record = st.store.find_record(&st.cfg.history_file, file_id)
+        .ok_or_else(|| "File không tồn tại".to_string())?;
+    if record.is_expired() { return Err("File đã hết hạn".to_string()); }
+    let path = std::path::PathBuf::from(path.trim());
+    if !path.is_absolute() {
+        return Err("path phải là đường dẫn tuyệt đối".to_string());
+    }
+    if !path.parent().is_some_and(|p| p.is_dir()) {
+        return Err(format!("Thư mục không tồn tại: {}", path.display()));
+    }
+    if path.is_dir() {
+        return Err(format!("{} là thư mục", path.display()));
+    }
+    Ok(disk_jobs::start(&st, record, path))
+}
+
+/// Same as `DELETE /api/files/:id?wait=true`: to the trash unless it is off or
+/// `permanent`, else deleted with the Telegram cleanup report returned.
+#[tauri::command]
+pub async fn delete_file(
+    reg:            State<'_, SharedRegistry>,
+    file_id:        i64,
+    delete_channel: Option<bool>,
+    permanent:      Option<bool>,
+) -> Result<Value, String> {
+    let st = active(&reg)?;
+    let delete_channel = delete_channel.unwrap_or(false);
+    if st.cfg.trash_ttl_s > 0 && !permanent.unwrap_or(false) {
+        let entry = trash::trash_file(&st, file_id, delete_channel).map_err(|e| e.to_string())?;
+        return Ok(trash::trashed_reply(&st, &entry));
+    }
+    api::remove_file(&st, file_id, delete_channel, true).await.map_err(|e| e.to_string())
+}
@@ -0,0 +1,215 @@
+/// keyring.rs — Per-file data keys wrapped by a master passphrase.
+///
+/// The passphrase never touches disk: PBKDF2 turns it into a master key, and the
+/// keyring file only keeps the salt plus a known value sealed under that key, to
+/// tell a wrong passphrase from a right one. Each encrypted upload gets a fresh
+/// random data key; the record stores it wrapped (`FileRecord::wrapped_key`), so
+/// one leaked part key exposes one file. The master key lives in memory from
+/// POST /api/keys/unlock until /api/keys/lock or a restart.
+///
+/// Wrong passphrases back off: each one doubles the wait before the next try.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{sync::{Arc, Mutex, RwLock}, time::{Duration, Instant}};
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    crypto::{self, PartKey},
+    state::AppState,
+    storage::{current_datetime_iso, Store},
+};
+
+/// PBKDF2-HMAC-SHA256 rounds for new keyrings (OWASP's 2023 figure).
+const ROUNDS: u32 = 600_000;
+const MIN_PASSPHRASE: usize = 8;
+/// Sealed under the master key as the passphrase check.
+const CHECK: &[u8] = b"discord-drive keyring v1";
+/// Longest wait after repeated wrong passphrases.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyringFile {
+    version:    u32,
+    rounds:     u32,
+    salt:       String,   // hex
+    check:      String,   // hex; CHECK sealed under the master key
+    created_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub initialized: bool,
+    pub unlocked:    bool,
+}
+
+#[derive(Debug)]
+pub enum UnlockError {
+    TooShort,
+    Wrong,
+    /// Too many wrong passphrases; try again after this long.
+    Throttled(Duration),
+    /// No keyring yet, but records hold keys wrapped by a previous one.
+    Orphaned(usize),
+    Other(anyhow::Error),
+}
+
+/// Wrong passphrases in a row and when the next try is allowed.
+#[derive(Default)]
+struct Failures {
+    count:     u32,
+    not_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub enum KeyError {
+    /// The record has a wrapped key but nobody unlocked the keyring yet.
+    Locked,
+    Invalid(anyhow::Error),
+}
+
+impl std::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::Locked     => f.write_str("keyring is locked"),
+            KeyError::Invalid(e) => write!(f, "cannot unwrap file key: {e}"),
+        }
+    }
+}
+
+pub struct Keyring {
+    store:    Arc<dyn Store>,
+    file:     String,
+    /// Where records with a `wrapped_key` may be.
+    records:  [String; 2],
+    master:   RwLock<Option<PartKey>>,
+    /// Held for a whole unlock, so two first unlocks can't each create a keyring.
+    unlock:   Mutex<()>,
+    failures: Mutex<Failures>,
+}
+
+impl Keyring {
+    pub fn new(store: Arc<dyn Store>, file: &str, history_file: &str, trash_file: &str) -> Self {
+        Self {
+            store, file: file.to_string(),
+            records:  [history_file.to_string(), trash_file.to_string()],
+            master:   RwLock::new(None),
+            unlock:   Mutex::new(()),
+            failures: Mutex::default(),
+        }
+    }
+
+    /// None when there is no keyring yet. A keyring file that can't be read is an
+    /// error: treating it as missing would create a new one over it.
+    fn load(&self) -> Result<Option<KeyringFile>> {
+        let doc = self.store.load_doc(&self.file);
+        if doc.is_null() {
+            // The JSON backend reads an unparseable file as missing
+            let on_disk = self.store.base_dir().join(&self.file);
+            if on_disk.exists() { return Err(anyhow!("{} exists but cannot be read", on_disk.display())); }
+            return Ok(None);
+        }
+        serde_json::from_value(doc).map_err(|e| anyhow!("{}: {e}", self.file))
+    }
+
+    /// Records (history and trash) holding a key wrapped by some keyring.
+    fn wrapped_records(&self) -> usize {
+        let history = self.store.load_history(&self.records[0]).into_iter().filter(|r| r.wrapped_key.is_some()).count();
+        let trashed = self.store.load_trash(&self.records[1]).into_iter().filter(|t| t.record.wrapped_key.is_some()).count();
+        history + trashed
+    }
+
+    fn master(&self) -> Option<PartKey> {
+        self.master.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn status(&self) -> Status {
+        Status { initialized: !matches!(self.load(), Ok(None)), unlocked: self.master().is_some() }
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.master().is_some()
+    }
+
+    /// Derive the master key and keep it in memory. The first unlock creates the
+    /// keyring with this passphrase, unless records still hold keys wrapped by a
+    /// lost one. Returns whether it was created.
+    /// Runs PBKDF2: call it on a blocking thread.
+    pub fn unlock(&self, passphrase: &str) -> Result<bool, UnlockError> {
+        // Tries run one at a time, so parallel guesses wait out the backoff too
+        let _guard = self.unlock.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(until) = self.failures.lock().unwrap_or_else(|e| e.into_inner()).not_until {
+            let now = Instant::now();
+            if until > now { return Err(UnlockError::Throttled(until - now)); }
+        }
+        let (key, created) = match self.load().map_err(UnlockError::Other)? {
+            Some(kf) => {
+                let salt = crypto::from_hex(&kf.salt).ok_or_else(|| UnlockError::Other(anyhow::anyhow!("keyring salt is not hex")))?;
+                let key = PartKey::derive(passphrase, &salt, kf.rounds);
+                let check = crypto::from_hex(&kf.check).ok_or_else(|| UnlockError::Other(anyhow::anyhow!("keyring check is not hex")))?;
+                match crypto::decrypt(&key, &check) {
+                    Ok(v) if v == CHECK => (key, false),
+                    _ => {
+                        self.failed();
+                        return Err(UnlockError::Wrong);
+                    }
+                }
+            }
+            None => {
+                if passphrase.chars().count() < MIN_PASSPHRASE { return Err(UnlockError::TooShort); }
+                let orphaned = self.wrapped_records();
+                if orphaned > 0 { return Err(UnlockError::Orphaned(orphaned)); }
+                let salt = crypto::random_bytes::<16>();
+                let key = PartKey::derive(passphrase, &salt, ROUNDS);
+                let kf = KeyringFile {
+                    version:    1,
+                    rounds:     ROUNDS,
+                    salt:       crypto::to_hex(&salt),
+                    check:      crypto::to_hex(&crypto::encrypt(&key, CHECK).map_err(UnlockError::Other)?),
+                    created_at: current_datetime_iso(),
+                };
+                self.store.save_json(&self.file, &Some(kf)).map_err(UnlockError::Other)?;
+                info!("🔑 Keyring created");
+                (key, true)
+            }
+        };
+        *self.master.write().unwrap_or_else(|e| e.into_inner()) = Some(key);
+        *self.failures.lock().unwrap_or_else(|e| e.into_inner()) = Failures::default();
+        Ok(created)
+    }
+
+    /// Count a wrong passphrase: 1s before the next try, doubling up to `MAX_BACKOFF`.
+    fn failed(&self) {
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        failures.count += 1;
+        let wait = Duration::from_secs(1 << failures.count.min(16).saturating_sub(1)).min(MAX_BACKOFF);
+        failures.not_until = Some(Instant::now() + wait);
+        warn!("⚠️ Wrong keyring passphrase ({} in a row), next try in {}s", failures.count, wait.as_secs());
+    }
+
+    pub fn lock(&self) {
+        *self.master.write().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// A fresh data key for one file and its wrapped form for the record.
+    /// None while locked.
+    pub fn new_file_key(&self) -> Option<Result<(PartKey, String)>> {
+        let master = self.master()?;
+        let key = PartKey::generate();
+        Some(crypto::wrap(&master, &key).map(|wrapped| (key, wrapped)))
+    }
+
+    pub fn unwrap(&self, wrapped: &str) -> Result<PartKey, KeyError> {
+        let master = self.master().ok_or(KeyError::Locked)?;
+        crypto::unwrap(&master, wrapped).map_err(KeyError::Invalid)
+    }
+}
+
+/// The config to read or resend parts with: it carries the file's own data key
+/// when `wrapped` is set (a record's or session's `wrapped_key`), like the
+/// per-upload `zip_compress_level` override.
+pub fn config_for(st: &AppState, wrapped: Option<&str>) -> Result<Arc<Config>, KeyError> {
+    let Some(wrapped) = wrapped else { return Ok(Arc::clone(&st.cfg)) };
+    let key = st.keyring.unwrap(wrapped)?;
+    Ok(Arc::new(Config { encryption_key: Some(key), ..(*st.cfg).clone() }))
+}
@@ -1,9 +1,46 @@
 pub mod api;
+pub mod audit;
+pub mod cache;
+pub mod compression;
 pub mod config;
+pub mod crypto;
+pub mod dedup;
+pub mod deep_link;
 pub mod discord_bot;
+pub mod disk_jobs;
+pub mod disposition;
 pub mod download;
+pub mod extract;
+pub mod file_locks;
+pub mod folder_rules;
+pub mod folder_tree;
+pub mod health;
+pub mod http_server;
+pub mod ipc;
+pub mod keyring;
+pub mod lifecycle;
+pub mod logs;
+pub mod manifest;
+pub mod media_pool;
+pub mod offline;
+pub mod opds;
+pub mod optimize;
+pub mod permissions;
+pub mod phash;
+pub mod profiles;
+pub mod progress;
+pub mod preview_tokens;
+pub mod queue;
+pub mod sessions;
+pub mod settings;
+pub mod shares;
+pub mod simulate;
 pub mod state;
 pub mod storage;
 pub mod telegram;
+pub mod throughput;
+pub mod thumbnails;
+pub mod trash;
 pub mod upload;
+pub mod verify;
 pub mod zip_utils;
@@ -0,0 +1,321 @@
+/// lifecycle.rs — Retention rules: move old files to the trash, recompress cold ones.
+///
+/// Rules come from config.json (`lifecycle.rules`) and from each folder's own list;
+/// a folder's rules are tried before the global ones and the first match wins.
+/// A file's own `expires_at` (set via the metadata batch) comes before any rule.
+/// The lifecycle job evaluates them every `interval_minutes`. Unless `enforce` is
+/// on it only logs what it would do — the same plan `GET /api/lifecycle` returns.
+use axum::http::StatusCode;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::{
+    api::{self, ApiError},
+    audit,
+    config::Config,
+    dedup,
+    discord_bot,
+    extract,
+    queue,
+    simulate,
+    state::AppState,
+    storage::{current_timestamp_ms, FileRecord, Folder, LifecycleAction, LifecycleRule, PartInfo, Store, TrashEntry},
+    upload::UploadTarget,
+};
+
+/// Level used by `recompress` rules that don't set one.
+pub const DEFAULT_RECOMPRESS_LEVEL: u32 = 9;
+
+/// Access times are only rewritten when older than this, so streaming doesn't
+/// turn into a write per range request.
+const ACCESS_RESOLUTION_S: i64 = 3600;
+const DAY_MS: i64 = 86_400_000;
+/// `Planned::rule` for files trashed because their `expires_at` passed.
+pub const EXPIRY_RULE: &str = "expires_at";
+
+static ACCESS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Remember that a file was downloaded or previewed.
+pub fn record_access(store: &dyn Store, cfg: &Config, file_id: i64) {
+    let now = chrono::Utc::now().timestamp();
+    let key = file_id.to_string();
+    let _guard = ACCESS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut access = store.load_access(&cfg.access_file);
+    if access.get(&key).is_some_and(|&t| now - t < ACCESS_RESOLUTION_S) { return; }
+    access.insert(key, now);
+    let _ = store.save_access(&cfg.access_file, &access);
+}
+
+/// Why a rule can't be used, in the API's language.
+pub fn validate(rule: &LifecycleRule) -> Result<(), String> {
+    if rule.name.trim().is_empty() {
+        return Err("Rule cần có name".to_string());
+    }
+    if rule.older_than_days.is_none() && rule.unaccessed_days.is_none() {
+        return Err(format!("Rule '{}' cần older_than_days hoặc unaccessed_days", rule.name));
+    }
+    if rule.zip_level.is_some_and(|l| l > 9) {
+        return Err(format!("Rule '{}': zip_level phải trong khoảng 0–9", rule.name));
+    }
+    Ok(())
+}
+
+/// One file the rules would act on.
+#[derive(Debug, Clone, Serialize)]
+pub struct Planned {
+    pub file_id:   i64,
+    pub filename:  String,
+    pub folder_id: String,            // "" = root
+    pub rule:      String,
+    pub action:    LifecycleAction,
+    pub zip_level: Option<u32>,       // recompress target
+    pub age_days:  i64,
+    pub idle_days: i64,               // since last download/preview
+    pub bytes:     u64,               // current footprint
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub file_id: i64,
+    pub rule:    String,
+    pub error:   String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+    pub trashed:      Vec<i64>,
+    pub recompressed: Vec<i64>,
+    pub failed:       Vec<Failure>,
+    pub saved_bytes:  i64,            // footprint change from recompression
+}
+
+/// Rules in evaluation order with the folder id each is limited to.
+fn scoped_rules<'a>(global: &'a [LifecycleRule], folders: &'a [Folder]) -> Vec<(Option<String>, &'a LifecycleRule)> {
+    let mut out = vec![];
+    for f in folders {
+        out.extend(f.lifecycle.iter().map(|r| (Some(f.id.to_string()), r)));
+    }
+    for r in global {
+        let scope = match r.folder.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(key) => match folders.iter().find(|f| f.id.to_string() == key || f.name.eq_ignore_ascii_case(key) || f.path.eq_ignore_ascii_case(key)) {
+                Some(f) => Some(f.id.to_string()),
+                None    => { warn!("⚠️ Lifecycle rule '{}': folder '{key}' not found → skipped", r.name); continue; }
+            },
+        };
+        out.push((scope, r));
+    }
+    out.retain(|(_, r)| match validate(r) {
+        Ok(())  => true,
+        Err(e)  => { warn!("⚠️ Lifecycle: {e} → skipped"); false }
+    });
+    out
+}
+
+/// What the rules would do right now.
+pub fn plan(st: &AppState) -> Vec<Planned> {
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    let rules   = scoped_rules(&st.cfg.lifecycle_rules, &folders);
+    let history = st.store.load_history(&st.cfg.history_file);
+    if rules.is_empty() && history.iter().all(|r| r.expires_at.is_none()) { return vec![]; }
+    let access  = st.store.load_access(&st.cfg.access_file);
+    let now     = current_timestamp_ms();
+
+    history.iter().filter_map(|rec| {
+        let folder_id = rec.folder_key().unwrap_or_default();
+        let age_days  = (now - rec.id) / DAY_MS;
+        let last_used = access.get(&rec.id.to_string()).map(|&s| s * 1000).unwrap_or(rec.id);
+        let idle_days = (now - last_used) / DAY_MS;
+        if rec.expires_at.is_some_and(|t| t * 1000 <= now) {
+            return Some(Planned {
+                file_id: rec.id, filename: rec.filename.clone(), folder_id,
+                rule: EXPIRY_RULE.to_string(), action: LifecycleAction::Trash, zip_level: None,
+                age_days, idle_days, bytes: rec.footprint_bytes(),
+            });
+        }
+        rules.iter().find_map(|(scope, rule)| {
+            if scope.as_ref().is_some_and(|s| *s != folder_id) { return None; }
+            if rule.older_than_days.is_some_and(|d| age_days < d as i64) { return None; }
+            if rule.unaccessed_days.is_some_and(|d| idle_days < d as i64) { return None; }
+            let zip_level = (rule.action == LifecycleAction::Recompress)
+                .then(|| rule.zip_level.unwrap_or(DEFAULT_RECOMPRESS_LEVEL));
+            // Already written at (or above) the target level: let a later rule have it
+            if zip_level.is_some_and(|l| rec.zip_level.is_some_and(|cur| cur >= l)) { return None; }
+            Some(Planned {
+                file_id: rec.id, filename: rec.filename.clone(), folder_id: folder_id.clone(),
+                rule: rule.name.clone(), action: rule.action, zip_level,
+                age_days, idle_days, bytes: rec.footprint_bytes(),
+            })
+        })
+    }).collect()
+}
+
+/// Carry out a plan. Files being downloaded are reported as failed and retried next run.
+pub async fn enforce(st: &AppState, planned: Vec<Planned>) -> RunReport {
+    let mut report = RunReport::default();
+    let (trash, recompress): (Vec<_>, Vec<_>) = planned.into_iter()
+        .partition(|p| p.action == LifecycleAction::Trash);
+
+    if !trash.is_empty() { move_to_trash(st, trash, &mut report); }
+
+    for p in recompress {
+        let Some(record) = st.store.find_record(&st.cfg.history_file, p.file_id) else { continue };
+        let level = p.zip_level.unwrap_or(DEFAULT_RECOMPRESS_LEVEL);
+        match recompress_file(st, record, level).await {
+            Ok(saved) => { report.recompressed.push(p.file_id); report.saved_bytes += saved; }
+            Err(e)    => {
+                warn!("⚠️ Lifecycle: recompress {} failed: {e}", p.filename);
+                report.failed.push(Failure { file_id: p.file_id, rule: p.rule, error: e.to_string() });
+            }
+        }
+    }
+    report
+}
+
+fn move_to_trash(st: &AppState, planned: Vec<Planned>, report: &mut RunReport) {
+    let mut guards = vec![];
+    let mut reasons: HashMap<i64, String> = HashMap::new();
+    for p in planned {
+        match st.file_locks.try_write(p.file_id) {
+            Some(g) => { guards.push(g); reasons.insert(p.file_id, p.rule); }
+            None    => report.failed.push(Failure {
+                file_id: p.file_id, rule: p.rule, error: "file đang được tải xuống".to_string(),
+            }),
+        }
+    }
+    let now = chrono::Utc::now().timestamp();
+    let committed = st.store.locked(&st.cfg.history_file, || st.store.locked(&st.cfg.trash_file, || {
+        let mut history = st.store.load_history(&st.cfg.history_file);
+        let mut trash   = st.store.load_trash(&st.cfg.trash_file);
+        history.retain(|rec| match reasons.get(&rec.id) {
+            None       => true,
+            Some(rule) => {
+                trash.push(TrashEntry {
+                    record: rec.clone(), trashed_at: now, reason: format!("lifecycle:{rule}"), delete_channel: false,
+                });
+                false
+            }
+        });
+        // One commit: a crash can neither lose a record nor leave it in both lists
+        let mut tx = st.store.transaction();
+        tx.save_history(&st.cfg.history_file, &history);
+        tx.save_json(&st.cfg.trash_file, &trash).and_then(|()| tx.commit())
+    }));
+    if let Err(e) = committed {
+        warn!("⚠️ Lifecycle trash commit failed: {e}");
+    }
+    for (id, rule) in reasons {
+        audit::record(&*st.store, &st.cfg.audit_file, Some(id), "trashed", json!({ "rule": rule }));
+        report.trashed.push(id);
+    }
+}
+
+/// Re-upload `record` at `level` to the platforms it was on, point the record at
+/// the new parts and delete the old ones, with their channel once it is empty. Returns the footprint saved (negative if the file grew).
+async fn recompress_file(st: &AppState, record: FileRecord, level: u32) -> Result<i64, ApiError> {
+    let work = st.store.base_dir().join("lifecycle").join(uuid::Uuid::new_v4().simple().to_string());
+    let copy = async {
+        tokio::fs::create_dir_all(&work).await
+            .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let path = work.join("data");
+        extract::download_to_file(st, record.clone(), &path, Default::default()).await?;
+        let folder_id = record.folder_key().unwrap_or_default();
+        let platform = UploadTarget::of_record(&record);
+        queue::ingest_local_file(st, &path, &record.filename, &folder_id, "", Some(level), Some(platform)).await
+    }.await;
+    let _ = tokio::fs::remove_dir_all(&work).await;
+    let fresh = copy?;
+
+    let swapped = {
+        let _lock = st.file_locks.write(record.id).await;
+        st.store.update_history(&st.cfg.history_file, |history| {
+            history.retain(|r| r.id != fresh.id);
+            let rec = history.iter_mut().find(|r| r.id == record.id)?;
+            let old = rec.clone();
+            rec.channel_id   = fresh.channel_id.clone();
+            rec.channel_name = fresh.channel_name.clone();
+            rec.method       = fresh.method.clone();
+            rec.method_key   = fresh.method_key.clone();
+            rec.parts        = fresh.parts;
+            rec.parts_info   = fresh.parts_info.clone();
+            rec.message_ids  = fresh.message_ids.clone();
+            rec.jump_url     = fresh.jump_url.clone();
+            rec.stored_bytes = fresh.stored_bytes;
+            rec.size_bytes   = fresh.size_bytes;
+            rec.zip_level    = fresh.zip_level;
+            rec.wrapped_key  = fresh.wrapped_key.clone();
+            Some(old)
+        }).ok().flatten()
+    };
+    let Some(old) = swapped else {
+        // Deleted while we were copying it: the copy goes too
+        drop_parts(st, &fresh).await;
+        return Err(ApiError::new(StatusCode::CONFLICT, "File đã bị xoá trong lúc nén lại"));
+    };
+    drop_parts(st, &old).await;
+
+    let saved = old.footprint_bytes() as i64 - fresh.footprint_bytes() as i64;
+    audit::record(&*st.store, &st.cfg.audit_file, Some(record.id), "recompressed", json!({
+        "zip_level": level, "stored_before": old.footprint_bytes(), "stored_after": fresh.footprint_bytes(),
+    }));
+    info!("♻️ Recompressed {} at level {level}: {:+.1}MB", record.filename, -(saved as f64) / 1024.0 / 1024.0);
+    Ok(saved)
+}
+
+/// Delete a record's messages on Discord and Telegram (best effort), once it is
+/// out of history, and its channel when no other record has parts there and
+/// nothing is left in it.
+/// Messages another record references are left alone.
+async fn drop_parts(st: &AppState, record: &FileRecord) {
+    let release = st.part_claims.begin_delete(record, &dedup::live_records(st));
+    delete_released(st, record, &release.parts).await;
+    st.part_claims.end_delete(&release.parts);
+    if st.cfg.simulate || release.channel_used { return; }
+    if let Ok(ch) = record.channel_id.parse::<u64>() {
+        if let Err(e) = discord_bot::delete_channel_if_empty(&st.http, ch).await {
+            warn!("⚠️ Could not delete the channel of {}: {e}", record.filename);
+        }
+    }
+}
+
+async fn delete_released(st: &AppState, record: &FileRecord, parts: &[PartInfo]) {
+    if st.cfg.simulate {
+        for p in parts {
+            if let Err(e) = simulate::delete_part(&st.cfg, p).await {
+                warn!("⚠️ Could not delete part {} of {}: {e}", p.part, record.filename);
+            }
+        }
+        return;
+    }
+    // Bundled parts share a message; delete it once
+    let mut seen = std::collections::HashSet::new();
+    for p in parts.iter().filter(|p| p.platform != "telegram" && seen.insert(p.message_id)) {
+        let Some(ch) = p.channel_id.as_deref().and_then(|c| c.parse::<u64>().ok()) else { continue };
+        if let Err(e) = discord_bot::delete_message(&st.http, ch, p.message_id as u64).await {
+            warn!("⚠️ Could not delete part {} of {}: {e}", p.part, record.filename);
+        }
+    }
+    if parts.iter().any(|p| p.platform == "telegram") {
+        api::delete_telegram_parts(st, &FileRecord { parts_info: parts.to_vec(), ..record.clone() }).await;
+    }
+}
+
+/// The lifecycle job for one profile.
+pub async fn run_task(st: AppState) {
+    loop {
+        sleep(Duration::from_secs(st.cfg.lifecycle_interval_s)).await;
+        let _running = st.lifecycle_lock.lock().await;
+        let planned = plan(&st);
+        if planned.is_empty() { continue; }
+        if !st.cfg.lifecycle_enforce {
+            info!("♻️ Lifecycle (report only): {} file(s) match — see GET /api/lifecycle", planned.len());
+            continue;
+        }
+        let report = enforce(&st, planned).await;
+        info!("♻️ Lifecycle: {} trashed, {} recompressed, {} failed",
+            report.trashed.len(), report.recompressed.len(), report.failed.len());
+    }
+}
@@ -0,0 +1,236 @@
+/// logs.rs — In-memory ring buffer of recent log lines for the UI.
+///
+/// The release build has no console (windows_subsystem = "windows"), so a tracing
+/// layer keeps the last N events here and the API serves them as JSON or SSE.
+///
+/// The filter sits behind a reload layer: `TraceSwitch` raises it to trace for this
+/// crate at runtime, and lines carry the spans they were logged in (`upload{session=…}`,
+/// `merge{file_id=…}`, `request{…}`), so one upload can be followed without RUST_LOG.
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tracing::{field::{Field, Visit}, span, Event, Level, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, reload, EnvFilter, Layer, Registry};
+
+pub const DEFAULT_CAPACITY: usize = 2000;
+
+/// Added to the startup filter while tracing is on.
+pub const TRACE_DIRECTIVES: &str = "discord_drive_lib=trace,discord_drive=trace,tower_http=debug";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    pub ts:      String,
+    pub level:   String,
+    pub target:  String,
+    pub message: String,
+    /// Enclosing spans, outermost first: `upload{session=…}:part{n=3}`.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub spans:   String,
+}
+
+impl LogLine {
+    /// True if this line is at least as severe as `min` (ERROR is the most severe).
+    pub fn at_least(&self, min: Level) -> bool {
+        self.level.parse::<Level>().map(|l| l <= min).unwrap_or(true)
+    }
+}
+
+pub struct LogBuffer {
+    lines:    Mutex<VecDeque<LogLine>>,
+    capacity: Mutex<usize>,
+    tx:       broadcast::Sender<LogLine>,
+    trace:    OnceLock<Arc<TraceSwitch>>,
+}
+
+pub type SharedLogs = Arc<LogBuffer>;
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> SharedLogs {
+        let (tx, _) = broadcast::channel(256);
+        Arc::new(Self {
+            lines:    Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: Mutex::new(capacity),
+            tx,
+            trace:    OnceLock::new(),
+        })
+    }
+
+    /// Config is read after logging starts, so the size is adjusted afterwards.
+    pub fn set_capacity(&self, capacity: usize) {
+        *self.capacity.lock().unwrap_or_else(|e| e.into_inner()) = capacity;
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        while lines.len() > capacity { lines.pop_front(); }
+    }
+
+    fn push(&self, line: LogLine) {
+        let cap = *self.capacity.lock().unwrap_or_else(|e| e.into_inner());
+        {
+            let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+            while lines.len() >= cap.max(1) { lines.pop_front(); }
+            lines.push_back(line.clone());
+        }
+        let _ = self.tx.send(line);
+    }
+
+    /// Last `tail` lines at or above `min` severity, oldest first.
+    pub fn tail(&self, min: Level, tail: usize) -> Vec<LogLine> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out: Vec<LogLine> = lines.iter().rev()
+            .filter(|l| l.at_least(min))
+            .take(tail)
+            .cloned()
+            .collect();
+        out.reverse();
+        out
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogLine> { self.tx.subscribe() }
+
+    /// Set once by main after the subscriber is installed.
+    pub fn attach_trace(&self, switch: TraceSwitch) {
+        let _ = self.trace.set(Arc::new(switch));
+    }
+
+    pub fn trace(&self) -> Option<&Arc<TraceSwitch>> { self.trace.get() }
+}
+
+// ── Debug tracing ──────────────────────────────────────────────────────────────
+
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceState {
+    pub on:     bool,
+    pub filter: String,
+    /// When tracing switches itself off again (RFC 3339).
+    pub until:  Option<String>,
+}
+
+/// Swaps the live EnvFilter between the startup one and a verbose one.
+pub struct TraceSwitch {
+    handle: FilterHandle,
+    base:   String,
+    state:  Mutex<TraceState>,
+    epoch:  AtomicU64,       // bumped on every change so stale auto-off timers do nothing
+}
+
+impl TraceSwitch {
+    pub fn new(handle: FilterHandle, base: String) -> Self {
+        let state = TraceState { on: false, filter: base.clone(), until: None };
+        Self { handle, base, state: Mutex::new(state), epoch: AtomicU64::new(0) }
+    }
+
+    pub fn state(&self) -> TraceState {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Add `directives` (default `TRACE_DIRECTIVES`) to the startup filter for `ttl`.
+    pub fn enable(self: &Arc<Self>, directives: Option<&str>, ttl: Duration) -> Result<TraceState, String> {
+        let filter = format!("{},{}", self.base, directives.unwrap_or(TRACE_DIRECTIVES));
+        let parsed = EnvFilter::try_new(&filter).map_err(|e| format!("filter không hợp lệ: {e}"))?;
+        self.handle.reload(parsed).map_err(|e| e.to_string())?;
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst) + 1;
+        let until = chrono::Utc::now() + chrono::Duration::from_std(ttl).unwrap_or_default();
+        let state = TraceState { on: true, filter, until: Some(until.to_rfc3339()) };
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = state.clone();
+        let me = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            if me.epoch.load(Ordering::SeqCst) == epoch {
+                let _ = me.disable();
+            }
+        });
+        Ok(state)
+    }
+
+    /// Back to the startup filter.
+    pub fn disable(&self) -> Result<TraceState, String> {
+        let parsed = EnvFilter::try_new(&self.base).map_err(|e| e.to_string())?;
+        self.handle.reload(parsed).map_err(|e| e.to_string())?;
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        let state = TraceState { on: false, filter: self.base.clone(), until: None };
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = state.clone();
+        Ok(state)
+    }
+}
+
+/// tracing layer feeding a LogBuffer.
+pub struct RingLayer {
+    buf: SharedLogs,
+}
+
+impl RingLayer {
+    pub fn new(buf: SharedLogs) -> Self { Self { buf } }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields:  String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={value}", field.name());
+        }
+    }
+}
+
+/// Formatted fields of a span, kept in its extensions.
+struct SpanFields(String);
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for RingLayer {
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut v = MessageVisitor::default();
+        attrs.record(&mut v);
+        span.extensions_mut().insert(SpanFields(v.fields));
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut v = MessageVisitor::default();
+        values.record(&mut v);
+        let mut ext = span.extensions_mut();
+        if let Some(fields) = ext.get_mut::<SpanFields>() {
+            fields.0.push_str(&v.fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut v = MessageVisitor::default();
+        event.record(&mut v);
+        let meta = event.metadata();
+        let mut spans = String::new();
+        for span in ctx.event_scope(event).into_iter().flat_map(|scope| scope.from_root()) {
+            if !spans.is_empty() { spans.push(':'); }
+            spans.push_str(span.name());
+            if let Some(SpanFields(f)) = span.extensions().get::<SpanFields>() {
+                if !f.is_empty() { let _ = write!(spans, "{{{}}}", f.trim_start()); }
+            }
+        }
+        self.buf.push(LogLine {
+            ts:      chrono::Utc::now().to_rfc3339(),
+            level:   meta.level().to_string(),
+            target:  meta.target().to_string(),
+            message: v.message + &v.fields,
+            spans,
+        });
+    }
+}
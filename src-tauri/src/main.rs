@@ -1,36 +1,65 @@
 // main.rs — Discord Drive Tauri entry point.
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{collections::{BTreeMap, HashMap}, path::PathBuf, sync::Arc, time::Duration};
 
 use axum::{
     extract::DefaultBodyLimit,
     http::{header, StatusCode},
-    routing::{delete, get, post},
+    routing::{any, delete, get, patch, post, put},
     Router,
 };
 use serenity::{model::id::GuildId, prelude::*};
-use tokio::{sync::{mpsc, Mutex}, time::sleep};
-use tower_http::{cors::{Any, CorsLayer}, services::ServeDir};
-use tracing::info;
+use tokio::{sync::{mpsc, Mutex, Notify}, time::sleep};
+use tower_http::{cors::{Any, CorsLayer}, services::ServeDir, trace::TraceLayer};
+use tracing::{info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use discord_drive_lib::{
     api,
-    config::Config,
-    discord_bot::Handler,
+    cache,
+    config::{Config, Listen, StoreBackend},
+    deep_link,
+    discord_bot::{connect_extra_bots, parse_extra_tokens, AppSlot, Handler, HttpPool},
+    disk_jobs,
+    download,
+    http_server,
+    ipc,
+    keyring::Keyring,
+    lifecycle,
+    logs::{self, LogBuffer, RingLayer, SharedLogs, TraceSwitch},
+    manifest,
+    media_pool,
+    offline::Connectivity,
+    permissions,
+    preview_tokens::PreviewSigner,
+    profiles::{self, ProfileEntry, ProfileRegistry, SharedRegistry},
+    progress,
+    queue,
+    sessions::{self, SessionTable},
+    simulate,
     state::AppState,
-    storage::JsonStore,
-    upload::new_sender_map,
+    storage::{JsonStore, MemoryStore, Store},
+    throughput,
+    trash,
+    upload::{self, new_sender_map, SendGates},
 };
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "info".into()),
-        )
+    // fmt to the console (debug builds) + a ring buffer the UI can read via /api/logs
+    // The filter is reloadable so /api/debug/trace can raise it at runtime
+    let log_buffer = LogBuffer::new(logs::DEFAULT_CAPACITY);
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "info".into());
+    let base_filter = env_filter.to_string();
+    let (filter, filter_handle) = tracing_subscriber::reload::Layer::new(env_filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(RingLayer::new(Arc::clone(&log_buffer)))
         .init();
+    log_buffer.attach_trace(TraceSwitch::new(filter_handle, base_filter));
 
     let base_dir = if let Ok(manifest) = std::env::var("CARGO_MANIFEST_DIR") {
         PathBuf::from(&manifest)
@@ -52,97 +81,495 @@ async fn main() {
         dotenvy::dotenv().ok();
     }
 
-    let discord_token = std::env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN not set in bot.env");
-    let guild_id_raw: u64 = std::env::var("DISCORD_GUILD_ID")
-        .expect("DISCORD_GUILD_ID not set")
-        .parse()
-        .expect("DISCORD_GUILD_ID must be a number");
-    let guild_id = GuildId::new(guild_id_raw);
-
-    let tg_token   = std::env::var("TELEGRAM_TOKEN").unwrap_or_default();
-    let tg_chat_id = std::env::var("TELEGRAM_CHAT_ID").unwrap_or_default();
-    let tg_enabled = !tg_token.is_empty() && !tg_chat_id.is_empty();
-
-    if tg_enabled {
-        info!("✅ Telegram enabled — dual-platform upload active");
-    } else {
-        info!("ℹ️  Telegram not configured — Discord only");
-    }
-
-    let cfg = Arc::new(Config::load(&base_dir));
+    let mut cfg = Config::load(&base_dir);
+    if std::env::args().any(|a| a == "--simulate") { cfg.simulate = true; }
+    let cfg = Arc::new(cfg);
     cfg.print_summary();
+    log_buffer.set_capacity(cfg.log_buffer_lines);
+    media_pool::init(cfg.media_workers, cfg.media_max_queue);
 
     // ── FIX: chunk upload limit = client_chunk_mb * parallel_chunks + 20% headroom ──
     // Use 500MB hard cap; individual route overrides the global 2MB Axum default.
-    let chunk_body_limit = ((cfg.client_chunk_bytes as f64) * 1.2) as usize;
-    let chunk_body_limit = chunk_body_limit.max(50 * 1024 * 1024); // minimum 50MB
+    let chunk_body_limit = cfg.chunk_body_limit();
     info!("📦 Chunk body limit: {:.0}MB", chunk_body_limit as f64 / 1024.0 / 1024.0);
 
-    let thumbnail_dir = base_dir.join("thumbnails_cache");
+    // ── Profiles ───────────────────────────────────────────────────────────────
+    // The default profile reads the process env (bot.env above) and keeps the
+    // original data layout in base_dir; it must start or the app exits.
+    let default_env = ProfileEnv::from_process_env();
+    let default_state = match start_profile(
+        profiles::DEFAULT_PROFILE, default_env, &cfg, &base_dir, base_dir.clone(), env_path,
+        Arc::clone(&log_buffer),
+    ).await {
+        Ok(st) => st,
+        Err(e) => {
+            eprintln!("❌ {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut states = vec![default_state];
+    for spec in &cfg.profiles {
+        let env_path = base_dir.join(&spec.env_file);
+        let started = match ProfileEnv::from_file(&env_path) {
+            Ok(env) => start_profile(
+                &spec.name, env, &cfg, &base_dir, base_dir.join(&spec.data_dir), env_path,
+                Arc::clone(&log_buffer),
+            ).await,
+            Err(e) => Err(e),
+        };
+        match started {
+            Ok(st) => states.push(st),
+            Err(e) => eprintln!("⚠️  Profile '{}' disabled: {e}", spec.name),
+        }
+    }
+
+    let mut entries = BTreeMap::new();
+    for st in states {
+        let router = api_router(chunk_body_limit).with_state(st.clone());
+        entries.insert(st.profile.clone(), ProfileEntry { state: st, router });
+    }
+    let registry: SharedRegistry = Arc::new(ProfileRegistry::new(base_dir.clone(), entries));
+
+    // ── Axum router ────────────────────────────────────────────────────────────
+    let cors = CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any);
+    let static_dir = base_dir.join("static");
+    let static_dir_root = static_dir.clone();
+
+    let mut router = Router::new();
+    for name in registry.names() {
+        let entry = registry.get(&name).expect("registered profile");
+        router = router.nest(&format!("/p/{name}"), entry.router.clone());
+    }
+    let router = router
+        .merge(Router::new()
+            .route("/api/profiles", get(profiles::list_profiles).post(profiles::switch_profile))
+            .route("/api/*rest",    any(profiles::forward_to_active))
+            .route("/s/*rest",      any(profiles::forward_to_active))
+            .route("/i/*rest",      any(profiles::forward_to_active))
+            .route("/opds",         any(profiles::forward_to_active))
+            .route("/opds/*rest",   any(profiles::forward_to_active))
+            .with_state(Arc::clone(&registry)))
+        .route("/", get(|| async move {
+            let path = static_dir_root.join("index.html");
+            match tokio::fs::read(&path).await {
+                Ok(bytes) => axum::response::Response::builder()
+                    .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+                    .body(axum::body::Body::from(bytes))
+                    .unwrap(),
+                Err(_) => axum::response::Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(axum::body::Body::from("index.html not found"))
+                    .unwrap(),
+            }
+        }))
+        .nest_service("/static", ServeDir::new(&static_dir))
+        .fallback_service(ServeDir::new(&static_dir).append_index_html_on_directories(true))
+        // Debug-level, so only recorded while /api/debug/trace is on
+        .layer(TraceLayer::new_for_http().make_span_with(|req: &axum::http::Request<_>| {
+            tracing::debug_span!("request", method = %req.method(), path = %req.uri().path())
+        }))
+        .layer(cors);
+
+    let keep_alive = Duration::from_secs(cfg.keep_alive_s);
+    // The window's `drive://` scheme always routes in-process; without a TCP
+    // listener it is the only way in.
+    let local_router = http_server::local_router(router.clone());
+    match cfg.listen.clone() {
+        Listen::Tcp => {
+            let addr = format!("{}:{}", cfg.host, cfg.port);
+            let listener = tokio::net::TcpListener::bind(&addr)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to bind {addr}: {e}"));
+            info!("🌐 HTTP server listening on http://{addr} (HTTP/1.1 + h2c, keep-alive {}s)", cfg.keep_alive_s);
+            tokio::spawn(async move {
+                http_server::serve(listener, router, keep_alive).await;
+            });
+        }
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            info!("🌐 HTTP server listening on unix:{} (no TCP port)", path.display());
+            let router = local_router.clone();
+            tokio::spawn(async move {
+                if let Err(e) = http_server::serve_unix(&path, router, keep_alive).await {
+                    warn!("⚠️ unix socket {} failed: {e}", path.display());
+                }
+            });
+        }
+        #[cfg(windows)]
+        Listen::Pipe(name) => {
+            info!("🌐 HTTP server listening on \\\\.\\pipe\\{name} (no TCP port)");
+            let router = local_router.clone();
+            tokio::spawn(async move {
+                if let Err(e) = http_server::serve_pipe(&name, router, keep_alive).await {
+                    warn!("⚠️ named pipe {name} failed: {e}");
+                }
+            });
+        }
+        Listen::None => info!("🔒 No HTTP listener — the API is reachable from the app window only"),
+        #[allow(unreachable_patterns)]
+        other => warn!("⚠️ server.listen = {other:?} is not supported on this OS — no HTTP listener"),
+    }
+
+    // Upload queue workers and download-to-disk jobs: bots are ready at this point,
+    // so work a previous run left unfinished resumes now
+    for st in registry.states() {
+        queue::spawn_worker(st.clone());
+        disk_jobs::resume_all(st);
+    }
+
+    // GC task (one per profile)
+    for st in registry.states() {
+        tokio::spawn(gc_task(st.clone()));
+        tokio::spawn(journal_task(st.clone()));
+        tokio::spawn(sessions::flush_task(Arc::clone(&st.sessions), Duration::from_secs(st.cfg.session_flush_s)));
+        tokio::spawn(lifecycle::run_task(st.clone()));
+        tokio::spawn(permissions::startup_check(st.clone()));
+    }
+
+    // ── Tauri window ───────────────────────────────────────────────────────────
+    // Custom schemes are served as http://<scheme>.localhost on Windows
+    let window_url = match cfg.listen {
+        Listen::Tcp => format!("http://127.0.0.1:{}", cfg.port),
+        _ if cfg!(windows) => "http://drive.localhost/".to_string(),
+        _ => "drive://localhost/".to_string(),
+    };
+    let in_process = cfg.listen != Listen::Tcp;
+    info!("🖥️  Opening window → {window_url}");
+
+    let link_registry = Arc::clone(&registry);
+    let exit_registry = Arc::clone(&registry);
+    tauri::Builder::default()
+        // Must come first: a second launch (how Windows/Linux deliver a clicked
+        // discorddrive:// link) hands its link to this instance and exits
+        .plugin(tauri_plugin_single_instance::init(|_app, _argv, _cwd| {}))
+        .plugin(tauri_plugin_deep_link::init())
+        // Save dialog for download-to-disk (the download_to_disk command)
+        .plugin(tauri_plugin_dialog::init())
+        .manage(Arc::clone(&registry))
+        .manage(deep_link::Pending::default())
+        .invoke_handler(tauri::generate_handler![
+            ipc::list_files,
+            ipc::upload_local_file,
+            ipc::download_to_disk,
+            ipc::enqueue_upload,
+            ipc::delete_file,
+            deep_link::take_deep_links,
+        ])
+        .register_asynchronous_uri_scheme_protocol("drive", move |_ctx, request, responder| {
+            let router = local_router.clone();
+            tokio::spawn(async move {
+                responder.respond(http_server::handle_local(router, request).await);
+            });
+        })
+        .setup(move |app| {
+            use tauri::Manager;
+            // tauri.conf.json points the window at the TCP port; switch it over
+            if in_process {
+                if let (Some(win), Ok(url)) = (app.get_webview_window("main"), tauri::Url::parse(&window_url)) {
+                    let _ = win.navigate(url);
+                }
+            }
+            tokio::spawn(taskbar_progress(app.handle().clone()));
+
+            use tauri_plugin_deep_link::DeepLinkExt;
+            // Installed bundles register the scheme; this covers dev builds and portable copies
+            #[cfg(any(windows, target_os = "linux"))]
+            if let Err(e) = app.deep_link().register_all() {
+                warn!("⚠️ Could not register {}:// links: {e}", deep_link::SCHEME);
+            }
+            if let Ok(Some(urls)) = app.deep_link().get_current() {
+                tokio::spawn(deep_link::dispatch(app.handle().clone(), Arc::clone(&link_registry), urls));
+            }
+            let handle = app.handle().clone();
+            app.deep_link().on_open_url(move |event| {
+                tokio::spawn(deep_link::dispatch(handle.clone(), Arc::clone(&link_registry), event.urls()));
+            });
+            Ok(())
+        })
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(move |_app, event| {
+            // Sessions and journaled saves reach their JSON files before the process goes
+            if let tauri::RunEvent::Exit = event {
+                for st in exit_registry.states() {
+                    if let Err(e) = st.sessions.flush() { warn!("⚠️ [{}] Session flush on exit failed: {e}", st.profile); }
+                    if let Err(e) = st.store.flush_journal() { warn!("⚠️ [{}] Journal flush on exit failed: {e}", st.profile); }
+                }
+            }
+        });
+}
+
+/// Mirror aggregate transfer progress onto the window: taskbar/dock progress bar,
+/// a percentage in the title, and a `transfer-progress` event for listeners.
+async fn taskbar_progress(app: tauri::AppHandle) {
+    use tauri::{window::{ProgressBarState, ProgressBarStatus}, Emitter, Manager};
+    let mut last = progress::Snapshot::default();
+    loop {
+        sleep(Duration::from_millis(500)).await;
+        let snap = progress::snapshot();
+        if snap == last { continue; }
+        let _ = app.emit("transfer-progress", &snap);
+        if let Some(win) = app.get_webview_window("main") {
+            let (bar, title) = match snap.percent {
+                Some(p) => (
+                    ProgressBarState { status: Some(ProgressBarStatus::Normal), progress: Some(p as u64) },
+                    format!("Discord Drive — {p}% (↑{} ↓{})", snap.uploads, snap.downloads),
+                ),
+                None => (
+                    ProgressBarState { status: Some(ProgressBarStatus::None), progress: None },
+                    "Discord Drive".to_string(),
+                ),
+            };
+            let _ = win.set_progress_bar(bar);
+            let _ = win.set_title(&title);
+        }
+        last = snap;
+    }
+}
+
+/// Discord/Telegram credentials of one profile.
+struct ProfileEnv {
+    discord_token: String,
+    extra_tokens:  Vec<String>,
+    guild_id:      String,
+    tg_token:      String,
+    tg_chat_id:    String,
+    tg_topic_id:   String,
+    manifest_key:  String,
+}
+
+impl ProfileEnv {
+    fn from_process_env() -> Self {
+        Self {
+            discord_token: std::env::var("DISCORD_TOKEN").unwrap_or_default(),
+            extra_tokens:  parse_extra_tokens(&std::env::var("DISCORD_EXTRA_TOKENS").unwrap_or_default()),
+            guild_id:      std::env::var("DISCORD_GUILD_ID").unwrap_or_default(),
+            tg_token:      std::env::var("TELEGRAM_TOKEN").unwrap_or_default(),
+            tg_chat_id:    std::env::var("TELEGRAM_CHAT_ID").unwrap_or_default(),
+            tg_topic_id:   std::env::var("TELEGRAM_TOPIC_ID").unwrap_or_default(),
+            manifest_key:  std::env::var("MANIFEST_KEY").unwrap_or_default(),
+        }
+    }
+
+    /// Read an env file without touching the process environment.
+    fn from_file(path: &std::path::Path) -> anyhow::Result<Self> {
+        let mut map = HashMap::new();
+        for item in dotenvy::from_path_iter(path)
+            .map_err(|e| anyhow::anyhow!("read {}: {e}", path.display()))?
+        {
+            let (k, v) = item?;
+            map.insert(k, v);
+        }
+        let mut get = |k: &str| map.remove(k).unwrap_or_default();
+        Ok(Self {
+            discord_token: get("DISCORD_TOKEN"),
+            extra_tokens:  parse_extra_tokens(&get("DISCORD_EXTRA_TOKENS")),
+            guild_id:      get("DISCORD_GUILD_ID"),
+            tg_token:      get("TELEGRAM_TOKEN"),
+            tg_chat_id:    get("TELEGRAM_CHAT_ID"),
+            tg_topic_id:   get("TELEGRAM_TOPIC_ID"),
+            manifest_key:  get("MANIFEST_KEY"),
+        })
+    }
+}
+
+/// Start one profile's Discord bot and build its AppState.
+async fn start_profile(
+    name:     &str,
+    env:      ProfileEnv,
+    cfg:      &Arc<Config>,
+    base_dir: &std::path::Path,
+    data_dir: PathBuf,
+    env_path: PathBuf,
+    logs:     SharedLogs,
+) -> anyhow::Result<AppState> {
+    let guild_id = if cfg.simulate {
+        simulate::guild_id()
+    } else {
+        if env.discord_token.is_empty() {
+            anyhow::bail!("DISCORD_TOKEN not set in {}", env_path.display());
+        }
+        let guild_id_raw: u64 = env.guild_id.parse()
+            .map_err(|_| anyhow::anyhow!("DISCORD_GUILD_ID must be a number ({})", env_path.display()))?;
+        GuildId::new(guild_id_raw)
+    };
+
+    // The simulator stands in for Telegram too, so dual/mirror uploads can be tried
+    let tg_enabled = cfg.simulate || (!env.tg_token.is_empty() && !env.tg_chat_id.is_empty());
+    let tg_topic_id = match env.tg_topic_id.trim() {
+        "" => None,
+        raw => Some(raw.parse::<i64>()
+            .map_err(|_| anyhow::anyhow!("TELEGRAM_TOPIC_ID must be a number ({})", env_path.display()))?),
+    };
+    if tg_enabled {
+        info!("✅ [{name}] Telegram enabled — dual-platform upload active");
+    } else {
+        info!("ℹ️  [{name}] Telegram not configured — Discord only");
+    }
+
+    std::fs::create_dir_all(&data_dir).ok();
+    let thumbnail_dir = data_dir.join("thumbnails_cache");
     std::fs::create_dir_all(&thumbnail_dir).ok();
+    let part_cache_dir = data_dir.join("parts_cache");
+    std::fs::create_dir_all(&part_cache_dir).ok();
+    let manifest_key = Arc::new(manifest::load_key(&env.manifest_key, &data_dir)?);
 
-    let store = Arc::new(JsonStore::new(base_dir.clone()));
+    let store: Arc<dyn Store> = match cfg.store_backend {
+        StoreBackend::Json   => Arc::new(JsonStore::new(data_dir.clone())),
+        StoreBackend::Memory => {
+            warn!("⚠️ [{name}] data.backend = memory: nothing is saved, all data is lost on exit");
+            Arc::new(MemoryStore::new(data_dir.clone()))
+        }
+    };
+    // Saves that were journaled but not yet rewritten when the app last stopped
+    match store.replay_journal() {
+        Ok(r) if r.entries > 0 => info!("📓 [{name}] Replayed {} journal entries into {} file(s)", r.entries, r.files),
+        Ok(_)  => {}
+        Err(e) => warn!("⚠️ [{name}] Journal replay failed, kept for next start: {e}"),
+    }
+
+    // Parse and index every store while the bot connects; awaited before serving
+    let warmup = tokio::task::spawn_blocking({
+        let store = Arc::clone(&store);
+        let cfg   = Arc::clone(cfg);
+        move || store.warm_up(&cfg.history_file, &cfg.data_files())
+    });
 
     // ── Discord bot ────────────────────────────────────────────────────────────
-    info!("🤖 Starting Discord bot...");
+    // Filled in once the AppState exists so message commands can use the upload pipeline
+    let app_slot: AppSlot = Arc::default();
+    let connectivity: Arc<Connectivity> = Arc::default();
+    let (http, send_pool) = if cfg.simulate {
+        info!("🧪 [{name}] Simulation mode — no bot, parts are written to {}", cfg.sim_dir.display());
+        let http = Arc::new(serenity::http::Http::new(""));
+        (Arc::clone(&http), Arc::new(HttpPool::new(http, vec![])))
+    } else {
+        start_bot(name, &env, cfg, guild_id, &store, &app_slot, &connectivity).await?
+    };
+
+    match warmup.await {
+        Ok(w)  => info!("🔥 [{name}] Stores warmed up: {} records, {} files in {}ms", w.records, w.files, w.ms),
+        Err(e) => anyhow::bail!("store warm-up failed: {e}"),
+    }
+
+    let sessions = Arc::new(SessionTable::load(Arc::clone(&store), &cfg.sessions_file));
+    let keyring = Arc::new(Keyring::new(Arc::clone(&store), &cfg.keyring_file, &cfg.history_file, &cfg.trash_file));
+    let state = AppState {
+        cfg:          Arc::clone(cfg),
+        store,
+        sessions,
+        http,
+        send_gates:   Arc::new(SendGates::new(cfg, send_pool.len())),
+        send_pool,
+        guild_id,
+        tg_enabled,
+        tg_token:     env.tg_token,
+        tg_chat_id:   env.tg_chat_id,
+        tg_topic_id,
+        sender_map:   new_sender_map(),
+        base_dir:     base_dir.to_path_buf(),
+        thumbnail_dir,
+        part_cache_dir,
+        lifecycle_lock: Arc::default(),
+        thumb_inflight: Arc::default(),
+        file_locks:   Arc::default(),
+        disk_jobs:    Arc::default(),
+        profile:      name.to_string(),
+        env_path,
+        queue_notify: Arc::new(Notify::new()),
+        logs,
+        preview_signer: Arc::new(PreviewSigner::new()),
+        manifest_key,
+        keyring,
+        connectivity,
+        part_claims:  Arc::default(),
+    };
+    let _ = app_slot.set(state.clone());
+    Ok(state)
+}
+
+/// Log the profile's bot (and extra bots) in and wait until it is ready.
+async fn start_bot(
+    name:     &str,
+    env:      &ProfileEnv,
+    cfg:      &Config,
+    guild_id: GuildId,
+    store:    &Arc<dyn Store>,
+    app_slot: &AppSlot,
+    connectivity: &Arc<Connectivity>,
+) -> anyhow::Result<(Arc<serenity::http::Http>, Arc<HttpPool>)> {
+    info!("🤖 [{name}] Starting Discord bot...");
     let (ready_tx, mut ready_rx) = mpsc::channel::<()>(1);
 
     let handler = Handler {
         guild_id,
         history_file: cfg.history_file.clone(),
         folders_file: cfg.folders_file.clone(),
-        store:        Arc::clone(&store),
+        store:        Arc::clone(store),
         ready_tx:     Mutex::new(Some(ready_tx)),
+        app:          Arc::clone(app_slot),
+        down_since:   Default::default(),
+        connectivity: Arc::clone(connectivity),
     };
 
     let intents = GatewayIntents::GUILDS | GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
-    let mut client = Client::builder(&discord_token, intents)
+    let mut client = Client::builder(&env.discord_token, intents)
         .event_handler(handler)
         .await
-        .expect("Failed to create Discord client");
+        .map_err(|e| anyhow::anyhow!("Failed to create Discord client: {e}"))?;
 
     let http = Arc::clone(&client.http);
 
+    let profile = name.to_string();
     tokio::spawn(async move {
         if let Err(e) = client.start().await {
-            eprintln!("❌ Discord client error: {e}");
+            eprintln!("❌ [{profile}] Discord client error: {e}");
         }
     });
 
     match tokio::time::timeout(Duration::from_secs(30), ready_rx.recv()).await {
-        Ok(Some(())) => info!("✅ Discord bot ready"),
-        _ => {
-            eprintln!("❌ Discord bot did not become ready within 30s. Check DISCORD_TOKEN.");
-            std::process::exit(1);
-        }
+        Ok(Some(())) => info!("✅ [{name}] Discord bot ready"),
+        _ => anyhow::bail!("Discord bot did not become ready within 30s. Check DISCORD_TOKEN."),
     }
+    let extra_bots = connect_extra_bots(&env.extra_tokens, guild_id).await;
+    if !extra_bots.is_empty() {
+        info!("🤖 [{name}] {} extra bot(s) sharing part uploads", extra_bots.len());
+    }
+    Ok((Arc::clone(&http), Arc::new(HttpPool::new(http, extra_bots))))
+}
 
-    // ── AppState ───────────────────────────────────────────────────────────────
-    let app_state = AppState {
-        cfg:          Arc::clone(&cfg),
-        store:        Arc::clone(&store),
-        http:         Arc::clone(&http),
-        guild_id,
-        tg_enabled,
-        tg_token:     tg_token.clone(),
-        tg_chat_id:   tg_chat_id.clone(),
-        sender_map:   new_sender_map(),
-        base_dir:     base_dir.clone(),
-        thumbnail_dir: thumbnail_dir.clone(),
-    };
-
-    // ── Axum router ────────────────────────────────────────────────────────────
-    let cors = CorsLayer::new().allow_origin(Any).allow_methods(Any).allow_headers(Any);
-    let static_dir = base_dir.join("static");
-    let static_dir_root = static_dir.clone();
-
-    let router = Router::new()
+/// All per-profile routes. Mounted at `/p/<name>` and reached un-prefixed via the active profile.
+fn api_router(chunk_body_limit: usize) -> Router<AppState> {
+    Router::new()
         .route("/api/health",                 get(api::health))
+        .route("/api/health/summary",         get(api::health_summary))
+        .route("/api/capabilities",           get(api::capabilities))
         .route("/api/folders",                get(api::get_folders).post(api::create_folder))
-        .route("/api/folders/:id",            delete(api::delete_folder))
+        .route("/api/folders/:id",            delete(api::delete_folder).patch(api::move_folder))
+        .route("/api/folders/:id/lifecycle",  put(api::set_folder_lifecycle))
+        .route("/api/folders/:id/resync",     post(api::resync_folder))
+        .route("/api/rules",                  get(api::list_rules).post(api::create_rule))
+        .route("/api/rules/:id",              delete(api::delete_rule))
+        .route("/api/lifecycle",              get(api::get_lifecycle))
+        .route("/api/lifecycle/run",          post(api::run_lifecycle))
         .route("/api/files",                  get(api::get_files))
+        .route("/api/files/metadata-batch",   post(api::metadata_batch))
         .route("/api/files/:id",              delete(api::delete_file).patch(api::rename_file))
         .route("/api/files/:id/move",         post(api::move_file))
+        .route("/api/files/:id/verify",       post(api::verify_file))
+        .route("/api/verify/:id",             post(api::verify_file_hash))
+        .route("/api/files/:id/activity",     get(api::file_activity))
+        .route("/api/files/:id/parts",        get(api::file_parts))
+        .route("/api/files/:id/extract-all",  post(api::extract_all))
+        .route("/api/files/:id/preview-token", post(api::create_preview_token))
+        .route("/api/files/:id/prefetch",     post(api::prefetch_file))
+        .route("/api/files/:id/pin",          post(api::pin_file).delete(api::unpin_file))
+        .route("/api/trash",                  get(api::list_trash))
+        .route("/api/trash/:id",              delete(api::purge_trash))
+        .route("/api/trash/:id/restore",      post(api::restore_trash))
+        .route("/api/files/:id/manifest",     get(api::export_file_manifest))
+        .route("/api/files/:id/similar",      get(api::similar_files))
         .route("/api/merge/:id",              get(api::merge_file))
         .route("/api/preview/:id",            get(api::preview_file))
         .route("/api/thumbnail/:id",          get(api::thumbnail))
@@ -153,75 +580,118 @@ async fn main() {
                 .layer(DefaultBodyLimit::max(chunk_body_limit)))
         // ──────────────────────────────────────────────────────────────────────
         .route("/api/upload/session/:sid",    get(api::get_upload_session).delete(api::cancel_upload))
+        .route("/api/upload/session/:sid/diagnostics", get(api::get_upload_diagnostics))
+        .route("/api/upload/session/:sid/keepalive",   patch(api::keepalive_upload))
+        .route("/api/download/jobs",          get(api::list_download_jobs))
+        .route("/api/downloads/diagnostics",  get(api::get_download_diagnostics))
+        .route("/api/diagnostics/permissions", get(api::permission_diagnostics))
+        .route("/api/keys",                   get(api::keys_status))
+        .route("/api/keys/unlock",            post(api::unlock_keys))
+        .route("/api/keys/lock",              post(api::lock_keys))
+        .route("/api/progress",               get(api::get_progress))
         .route("/api/upload/complete/:sid",   post(api::complete_upload))
+        // A resent part is a whole part (up to the Telegram file limit), not a chunk
+        .route("/api/upload/retry/:sid",
+            post(api::retry_upload)
+                .layer(DefaultBodyLimit::disable()))
+        // Spooled to disk whole while Discord is away
+        .route("/api/upload/offline",
+            put(api::offline_upload)
+                .layer(DefaultBodyLimit::disable()))
+        .route("/api/pending",                get(api::list_pending))
         .route("/api/search",                 get(api::search_files))
+        .route("/api/metadata",               get(api::metadata_values))
         .route("/api/stats",                  get(api::get_stats))
+        .route("/api/stats/throughput",       get(api::throughput_stats))
+        .route("/api/export/manifest",        get(api::export_manifest))
+        .route("/api/import/manifest",        post(api::import_manifest))
         .route("/api/settings",               get(api::get_settings).post(api::save_settings))
-        .route("/", get(|| async move {
-            let path = static_dir_root.join("index.html");
-            match tokio::fs::read(&path).await {
-                Ok(bytes) => axum::response::Response::builder()
-                    .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-                    .body(axum::body::Body::from(bytes))
-                    .unwrap(),
-                Err(_) => axum::response::Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(axum::body::Body::from("index.html not found"))
-                    .unwrap(),
-            }
-        }))
-        .nest_service("/static", ServeDir::new(&static_dir))
-        .fallback_service(ServeDir::new(&static_dir).append_index_html_on_directories(true))
-        .with_state(app_state.clone())
-        .layer(cors);
-
-    let addr = format!("{}:{}", cfg.host, cfg.port);
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .unwrap_or_else(|e| panic!("Failed to bind {addr}: {e}"));
-    info!("🌐 HTTP server listening on http://{addr}");
-
-    tokio::spawn(async move {
-        axum::serve(listener, router).await.expect("axum server error");
-    });
+        .route("/api/settings/validation",    get(api::validate_settings))
+        .route("/api/logs",                   get(api::get_logs))
+        .route("/api/logs/stream",            get(api::stream_logs))
+        .route("/api/debug/trace",            get(api::get_trace).post(api::set_trace))
+        .route("/api/queue",                  get(api::list_queue))
+        .route("/api/queue/:id",              delete(api::remove_queue_item))
+        .route("/api/files/:id/share",        post(api::create_share))
+        .route("/api/shares",                 get(api::list_shares))
+        .route("/api/shares/purge",           post(api::purge_links))
+        .route("/api/shares/:token",          delete(api::revoke_share))
+        .route("/s/:token",                   get(api::open_share))
+        .route("/api/inbox",                  get(api::list_inboxes).post(api::create_inbox))
+        .route("/api/inbox/:token",           delete(api::revoke_inbox))
+        .route("/i/:token",
+            get(api::open_inbox)
+                .post(api::inbox_upload)
+                .layer(DefaultBodyLimit::disable()))
+        .route("/i/:token/info",              get(api::inbox_info))
+        .route("/opds",                       get(api::opds_root))
+        .route("/opds/recent",                get(api::opds_recent))
+        .route("/opds/folders/:folder",       get(api::opds_folder))
+}
 
-    // GC task
-    {
-        let store2 = Arc::clone(&store);
-        let cfg2   = Arc::clone(&cfg);
-        tokio::spawn(async move { gc_task(store2, cfg2).await; });
+/// Rewrite journaled saves as JSON snapshots; anything newer is still in the journal.
+async fn journal_task(st: AppState) {
+    loop {
+        sleep(Duration::from_secs(st.cfg.journal_flush_s)).await;
+        let store = Arc::clone(&st.store);
+        if let Ok(Err(e)) = tokio::task::spawn_blocking(move || store.flush_journal()).await {
+            warn!("⚠️ Journal flush failed: {e}");
+        }
     }
-
-    // ── Tauri window ───────────────────────────────────────────────────────────
-    info!("🖥️  Opening window → http://127.0.0.1:{}", cfg.port);
-
-    tauri::Builder::default()
-        .setup(|_app| Ok(()))
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
 }
 
-async fn gc_task(store: Arc<JsonStore>, cfg: Arc<Config>) {
+async fn gc_task(st: AppState) {
+    let (store, cfg) = (Arc::clone(&st.store), Arc::clone(&st.cfg));
     loop {
         sleep(Duration::from_secs(cfg.gc_interval_s)).await;
-        let sessions = store.load_sessions(&cfg.sessions_file);
-        let now      = chrono::Utc::now().timestamp() as u64;
+        let sessions = st.sessions.snapshot();
+        let now      = chrono::Utc::now().timestamp();
         let mut expired: Vec<String> = vec![];
         for (sid, session) in &sessions {
-            if let Ok(created) = chrono::DateTime::parse_from_rfc3339(&session.created_at) {
-                let age = now.saturating_sub(created.timestamp() as u64);
-                if age > cfg.session_ttl_s && session.status == "uploading" {
+            // TTL counts inactivity, so long slow uploads survive as long as chunks keep coming.
+            // `finalizing` sessions are owned by an in-flight complete_upload.
+            if let Some(idle) = session.idle_secs(now) {
+                if idle > cfg.session_ttl_s && session.status != upload::STATUS_FINALIZING {
                     expired.push(sid.clone());
                 }
             }
         }
-        if !expired.is_empty() {
-            let mut sessions = sessions;
-            for sid in &expired {
-                sessions.remove(sid);
-                info!("🧹 GC: session {sid} expired → removed");
+        for sid in &expired {
+            // An init that never got going shouldn't leave its channel behind
+            api::drop_unused_channel(&st, &sessions[sid]).await;
+            upload::delete_session_record(&st.sessions, sid);
+            info!("🧹 GC: session {sid} idle past TTL → removed");
+        }
+        // Parts claimed by uploads that ended without a record hold nothing anymore
+        st.part_claims.retain_sessions(|sid| st.sessions.get(sid).is_some_and(|s| {
+            s.status != upload::STATUS_DONE && s.status != upload::STATUS_CANCELLED
+        }));
+        // Thumbnails and parts each have their own budget, so neither evicts by the other's
+        for (dir, limit) in [(&st.thumbnail_dir, cfg.cache_max_bytes), (&st.part_cache_dir, download::part_cache_budget(&cfg))] {
+            if limit == 0 { continue; }
+            let evicted = cache::evict_lru(&[dir.as_path()], limit);
+            if evicted.files > 0 {
+                info!("🧹 GC: evicted {} cached file(s), {:.1}MB", evicted.files, evicted.bytes as f64 / 1024.0 / 1024.0);
             }
-            let _ = store.save_sessions(&cfg.sessions_file, &sessions);
+        }
+        let history = store.load_history(&cfg.history_file);
+        let stale = download::sweep_pinned(&st.part_cache_dir, &history);
+        if stale > 0 {
+            info!("🧹 GC: dropped {stale} pinned part(s) no longer in use");
+        }
+        let purged = trash::purge_expired(&st).await;
+        if purged > 0 {
+            info!("🧹 GC: purged {purged} file(s) from the trash");
+        }
+        // Dead share/inbox links; preview tokens are stateless and just stop verifying
+        let purged = api::purge_stale_links(&st, false).await;
+        if purged.shares + purged.inboxes > 0 {
+            info!("🧹 GC: purged {} share link(s), {} inbox(es)", purged.shares, purged.inboxes);
+        }
+        match throughput::prune(&*store, &cfg.throughput_file, cfg.throughput_keep_s) {
+            Ok(0)  => {}
+            Ok(n)  => info!("🧹 GC: dropped {n} old throughput sample(s)"),
+            Err(e) => warn!("⚠️ GC: throughput prune failed: {e}"),
         }
     }
 }
@@ -0,0 +1,269 @@
+/// manifest.rs — Portable `.ddrive` manifests for cross-instance sharing.
+///
+/// A manifest carries what another instance (or the CLI) needs to fetch a file
+/// straight from Discord/Telegram: the part list with message/file ids, where the
+/// parts live, optional MD5s and how the parts are wrapped. It is signed with
+/// HMAC-SHA256 over the document as received, minus its `signature` and written
+/// in canonical form (sorted keys, no whitespace), keyed by `MANIFEST_KEY` from
+/// bot.env; instances that share the key accept each other's manifests. Fields
+/// this build doesn't know are signed too, so nothing can be slipped past it.
+/// Without the env var a random key is kept in `<data_dir>/manifest.key`.
+///
+/// Importing turns a manifest back into a `FileRecord` that points at the same
+/// messages; nothing is re-uploaded, so the parts must stay reachable from here.
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{collections::HashMap, path::Path};
+
+use crate::storage::{current_datetime_display, current_datetime_iso, current_timestamp_ms, FileRecord, PartInfo};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const FORMAT: &str = "ddrive";
+pub const VERSION: u32 = 1;
+/// Extension (and MIME subtype) of exported manifests.
+pub const EXTENSION: &str = "ddrive";
+const KEY_FILE: &str = "manifest.key";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format:      String,
+    pub version:     u32,
+    pub exported_at: String,
+    pub file:        ManifestFile,
+    pub source:      Source,
+    pub encryption:  Encryption,
+    pub parts:       Vec<ManifestPart>,
+    /// Hex HMAC-SHA256; left out of the signed body.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature:   String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub filename:   String,
+    pub size_bytes: u64,
+    /// `size_bytes` was estimated from the size in MB (legacy records); importers
+    /// don't record it as exact.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub size_estimated: bool,
+    pub method_key: String,
+    pub zip_level:  Option<u32>,
+    /// Of the whole original file; only with `?checksums=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5:        Option<String>,
+}
+
+/// Where the parts were posted. An importer needs a bot in this guild (or the
+/// same Telegram bot token) to fetch them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Source {
+    pub guild_id:   String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tg_chat_id: Option<String>,
+}
+
+/// Discord parts are always single-entry ZIPs; `aes256` means the entry is
+/// password protected and the importer needs the same `upload.zip_password`.
+/// `wrapped_key` is the file's data key sealed by the exporter's keyring: the
+/// importer needs a keyring unlocked with the same passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Encryption {
+    pub container:   String,
+    pub aes256:      bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestPart {
+    #[serde(flatten)]
+    pub info: PartInfo,
+    /// Of the unwrapped part bytes; only with `?checksums=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5:  Option<String>,
+}
+
+impl Manifest {
+    /// Unsigned manifest for `record`. `md5s` maps part number → MD5 of its bytes.
+    pub fn of(
+        record:     &FileRecord,
+        guild_id:   &str,
+        tg_chat_id: Option<&str>,
+        aes256:     bool,
+        md5s:       &HashMap<u32, String>,
+        file_md5:   Option<String>,
+    ) -> Self {
+        let mut parts: Vec<ManifestPart> = crate::download::normalize_parts(record).into_iter()
+            .map(|info| ManifestPart { md5: md5s.get(&info.part).cloned(), info })
+            .collect();
+        parts.sort_by_key(|p| p.info.part);
+        Self {
+            format:      FORMAT.to_string(),
+            version:     VERSION,
+            exported_at: current_datetime_iso(),
+            file: ManifestFile {
+                filename:   record.filename.clone(),
+                size_bytes: record.exact_size().unwrap_or((record.size_mb * 1024.0 * 1024.0).round() as u64),
+                size_estimated: record.exact_size().is_none(),
+                method_key: record.method_key.clone(),
+                zip_level:  record.zip_level,
+                md5:        file_md5,
+            },
+            source: Source {
+                guild_id:   guild_id.to_string(),
+                tg_chat_id: tg_chat_id.filter(|_| parts.iter().any(|p| p.info.platform == "telegram")).map(str::to_string),
+            },
+            encryption: Encryption { container: "zip".to_string(), aes256, wrapped_key: record.wrapped_key.clone() },
+            parts,
+            signature: String::new(),
+        }
+    }
+
+    pub fn sign(mut self, key: &[u8]) -> Self {
+        self.signature = String::new();
+        let body = serde_json::to_value(&self).expect("manifest serializes");
+        self.signature = mac(&body, key).finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect();
+        self
+    }
+
+    /// Check the signature of a manifest as received: `raw` is the document the
+    /// manifest was parsed from.
+    pub fn verify(raw: &[u8], key: &[u8]) -> bool {
+        let Ok(Value::Object(mut body)) = serde_json::from_slice::<Value>(raw) else { return false };
+        let Some(sig) = body.remove("signature").and_then(|s| s.as_str().and_then(decode_hex)) else { return false };
+        mac(&Value::Object(body), key).verify_slice(&sig).is_ok()
+    }
+
+    /// Reject documents that are not manifests this build understands.
+    pub fn check_format(&self) -> Result<(), String> {
+        if self.format != FORMAT {
+            return Err(format!("Không phải manifest .{EXTENSION} (format: {})", self.format));
+        }
+        if self.version == 0 || self.version > VERSION {
+            return Err(format!("Manifest phiên bản {} chưa được hỗ trợ (tối đa {VERSION})", self.version));
+        }
+        if self.parts.is_empty() {
+            return Err("Manifest không có part nào".to_string());
+        }
+        Ok(())
+    }
+
+    /// What this instance lacks to fetch the parts, in words for the user. Empty
+    /// when the parts were posted with the same guild/bot and settings.
+    pub fn requirements(
+        &self, guild_id: &str, tg_token_set: bool, zip_password_set: bool, encryption_key_set: bool, keyring_unlocked: bool,
+    ) -> Vec<String> {
+        let mut out = vec![];
+        let discord = self.parts.iter().any(|p| p.info.platform != "telegram");
+        let telegram = self.parts.iter().any(|p| p.info.platform == "telegram");
+        if discord && self.source.guild_id != guild_id {
+            out.push(format!("Part Discord nằm ở server {} — bot phải được mời vào server đó và đọc được các channel", self.source.guild_id));
+        }
+        if telegram && !tg_token_set {
+            out.push("Part Telegram cần TELEGRAM_TOKEN của chính bot đã upload (file_id gắn với bot)".to_string());
+        }
+        if self.encryption.aes256 && !zip_password_set {
+            out.push("Part được mã hoá AES-256 — cần đặt cùng upload.zip_password".to_string());
+        }
+        let sealed = self.parts.iter().any(|p| p.info.encrypted);
+        if sealed && self.encryption.wrapped_key.is_some() && !keyring_unlocked {
+            out.push("Part được mã hoá bằng khoá riêng của file — cần mở khoá keyring với cùng passphrase (POST /api/keys/unlock)".to_string());
+        } else if sealed && self.encryption.wrapped_key.is_none() && !encryption_key_set {
+            out.push("Part được mã hoá AES-256-GCM — cần đặt cùng encryption.key".to_string());
+        }
+        out
+    }
+
+    /// A history record pointing at the manifest's parts.
+    pub fn to_record(&self, folder_id: Option<String>, folder_name: Option<String>) -> FileRecord {
+        let parts_info: Vec<PartInfo> = self.parts.iter().map(|p| p.info.clone()).collect();
+        let mut numbers: Vec<u32> = parts_info.iter().map(|p| p.part).collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        let first = parts_info.iter().find(|p| p.platform != "telegram");
+        FileRecord {
+            id:           current_timestamp_ms(),
+            filename:     self.file.filename.clone(),
+            size_mb:      (self.file.size_bytes as f64 / 1024.0 / 1024.0 * 100.0).round() / 100.0,
+            channel_id:   first.and_then(|p| p.channel_id.clone()).unwrap_or_default(),
+            channel_name: String::new(),
+            folder_id:    folder_id.map(serde_json::Value::String),
+            folder_name,
+            status:       "sent".to_string(),
+            method:       format!("Import manifest ({} phần)", numbers.len()),
+            method_key:   self.file.method_key.clone(),
+            parts:        numbers.len() as u32,
+            message_ids:  parts_info.iter().filter(|p| p.platform != "telegram").map(|p| p.message_id).collect(),
+            jump_url:     first.and_then(|p| p.jump_url.clone()),
+            sent_at:      current_datetime_display(),
+            stored_bytes: parts_info.iter().map(|p| p.stored_bytes).sum(),
+            size_bytes:   if self.file.size_estimated { 0 } else { self.file.size_bytes },
+            zip_level:    self.file.zip_level,
+            parts_info,
+            wrapped_key:  self.encryption.wrapped_key.clone(),
+            imported:     true,
+            ..Default::default()
+        }
+    }
+}
+
+/// HMAC of `body` (without `signature`) in canonical form.
+fn mac(body: &Value, key: &[u8]) -> HmacSha256 {
+    let mut text = String::new();
+    canonical(body, &mut text);
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(text.as_bytes());
+    mac
+}
+
+/// Compact JSON with object keys sorted, whatever order the map keeps them in.
+fn canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, k) in keys.into_iter().enumerate() {
+                if i > 0 { out.push(','); }
+                out.push_str(&Value::String(k.clone()).to_string());
+                out.push(':');
+                canonical(&map[k], out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, v) in items.iter().enumerate() {
+                if i > 0 { out.push(','); }
+                canonical(v, out);
+            }
+            out.push(']');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+/// `MANIFEST_KEY` when set, else the profile's persistent random key.
+pub fn load_key(env_key: &str, data_dir: &Path) -> Result<Vec<u8>> {
+    if !env_key.trim().is_empty() {
+        return Ok(env_key.trim().as_bytes().to_vec());
+    }
+    let path = data_dir.join(KEY_FILE);
+    if let Ok(existing) = std::fs::read_to_string(&path) {
+        if !existing.trim().is_empty() { return Ok(existing.trim().as_bytes().to_vec()); }
+    }
+    let key = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    std::fs::write(&path, &key).with_context(|| format!("write {}", path.display()))?;
+    Ok(key.into_bytes())
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes().chunks(2).map(|pair| {
+        if pair.len() != 2 { return None; }
+        u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()
+    }).collect()
+}
@@ -0,0 +1,138 @@
+/// media_pool.rs — Bounded worker pool for CPU-heavy media work (thumbnails, image recompression).
+///
+/// Opening a gallery of new videos used to start one decode per tile at once and
+/// peg every core. Each job now takes one of `media.workers` slots: `run` waits in
+/// line, while `enqueue` moves the job to the background so an HTTP handler can
+/// answer 202 with a retry hint instead of holding the connection open.
+/// Process-wide, like progress.rs: every profile shares the same CPU.
+use serde::Serialize;
+use std::{collections::HashSet, future::Future, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Mutex, OnceLock}, time::Instant};
+use tokio::sync::Semaphore;
+
+pub const DEFAULT_MAX_QUEUE: usize = 256;
+/// Retry hints stay within this, so a long queue doesn't park the client for minutes.
+const MAX_RETRY_AFTER_S: u64 = 30;
+/// Assumed job time until the first one finishes.
+const INITIAL_AVG_MS: u64 = 500;
+
+static POOL: OnceLock<MediaPool> = OnceLock::new();
+
+pub struct MediaPool {
+    slots:     Semaphore,
+    workers:   usize,
+    max_queue: usize,
+    waiting:   AtomicUsize,          // jobs (inline or queued) waiting for a slot
+    queued:    Mutex<HashSet<String>>, // keys of background jobs not finished yet
+    avg_ms:    AtomicU64,            // moving average job time, for retry hints
+}
+
+/// A background job was accepted; poll again after `retry_after_s`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Queued {
+    pub position:      usize,
+    pub retry_after_s: u64,
+}
+
+/// The queue already holds `media.max_queue` jobs.
+#[derive(Debug, Clone, Copy)]
+pub struct QueueFull {
+    pub retry_after_s: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub workers: usize,
+    pub busy:    usize,
+    pub waiting: usize,
+    pub avg_ms:  u64,
+}
+
+/// Set up the pool from config; call once before serving.
+pub fn init(workers: usize, max_queue: usize) {
+    let _ = POOL.set(MediaPool::new(workers, max_queue));
+}
+
+pub fn pool() -> &'static MediaPool {
+    POOL.get_or_init(|| MediaPool::new(default_workers(), DEFAULT_MAX_QUEUE))
+}
+
+/// Half the cores: leaves room for uploads, downloads and the UI.
+pub fn default_workers() -> usize {
+    std::thread::available_parallelism().map(|n| n.get() / 2).unwrap_or(1).max(1)
+}
+
+impl MediaPool {
+    fn new(workers: usize, max_queue: usize) -> Self {
+        let workers = workers.max(1);
+        Self {
+            slots:     Semaphore::new(workers),
+            workers,
+            max_queue,
+            waiting:   AtomicUsize::new(0),
+            queued:    Mutex::new(HashSet::new()),
+            avg_ms:    AtomicU64::new(INITIAL_AVG_MS),
+        }
+    }
+
+    /// Every slot is taken, so a new job would have to wait.
+    pub fn is_busy(&self) -> bool {
+        self.slots.available_permits() == 0
+    }
+
+    pub fn is_queued(&self, key: &str) -> bool {
+        self.queued.lock().unwrap_or_else(|e| e.into_inner()).contains(key)
+    }
+
+    /// Run `job` once a slot is free. CPU-bound parts inside it still belong on
+    /// `spawn_blocking`; the slot only bounds how many run at once.
+    pub async fn run<F: Future>(&self, job: F) -> F::Output {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let slot = self.slots.acquire().await.expect("media pool is never closed");
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let out = job.await;
+        self.record(started.elapsed().as_millis() as u64);
+        drop(slot);
+        out
+    }
+
+    /// Run `job` in the background under `key`. A key that is already queued isn't
+    /// added twice; the caller just gets the current hint.
+    pub fn enqueue(&'static self, key: String, job: impl Future<Output = ()> + Send + 'static) -> Result<Queued, QueueFull> {
+        let mut queued = self.queued.lock().unwrap_or_else(|e| e.into_inner());
+        let waiting = self.waiting.load(Ordering::Relaxed);
+        if !queued.contains(&key) {
+            if waiting >= self.max_queue {
+                return Err(QueueFull { retry_after_s: self.retry_after_s(waiting) });
+            }
+            queued.insert(key.clone());
+            tokio::spawn(async move {
+                self.run(job).await;
+                self.queued.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+            });
+        }
+        let position = waiting + 1;
+        Ok(Queued { position, retry_after_s: self.retry_after_s(position) })
+    }
+
+    /// Seconds until a job with `ahead` jobs in front of it is likely done.
+    pub fn retry_after_s(&self, ahead: usize) -> u64 {
+        let rounds = (ahead / self.workers + 1) as u64;
+        (rounds * self.avg_ms.load(Ordering::Relaxed)).div_ceil(1000).clamp(1, MAX_RETRY_AFTER_S)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            workers: self.workers,
+            busy:    self.workers - self.slots.available_permits(),
+            waiting: self.waiting.load(Ordering::Relaxed),
+            avg_ms:  self.avg_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record(&self, ms: u64) {
+        // Racy read-modify-write is fine for a hint
+        let avg = self.avg_ms.load(Ordering::Relaxed);
+        self.avg_ms.store((avg * 7 + ms) / 8, Ordering::Relaxed);
+    }
+}
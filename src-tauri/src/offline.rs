@@ -0,0 +1,143 @@
+/// offline.rs — Working while the Discord gateway is away.
+///
+/// Browsing only reads the local stores and keeps working as is. What needs
+/// Discord is held back instead of failing:
+/// - queued uploads wait as `queued_offline` (`PUT /api/upload/offline` spools a
+///   browser upload to disk for this);
+/// - deleting for good takes the record out of the drive at once and keeps its
+///   parts' deletion in `pending_file`, as does renaming a file's channel.
+///
+/// Moving files only touches local records and needs nothing held back. When
+/// the gateway is back (`ready` or `resume`) the held-back work runs, oldest
+/// first; `GET /api/pending` lists it meanwhile.
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    api,
+    audit,
+    dedup,
+    queue,
+    state::AppState,
+    storage::FileRecord,
+};
+
+/// Whether the profile's gateway is connected; the simulator never goes offline.
+#[derive(Default)]
+pub struct Connectivity {
+    offline_since: Mutex<Option<i64>>,   // unix seconds
+}
+
+impl Connectivity {
+    pub fn is_online(&self) -> bool {
+        self.offline_since.lock().unwrap_or_else(|e| e.into_inner()).is_none()
+    }
+
+    pub fn offline_since(&self) -> Option<i64> {
+        *self.offline_since.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns false if it already was offline.
+    pub fn went_offline(&self) -> bool {
+        let mut since = self.offline_since.lock().unwrap_or_else(|e| e.into_inner());
+        let changed = since.is_none();
+        since.get_or_insert_with(|| chrono::Utc::now().timestamp());
+        changed
+    }
+
+    /// Returns false if it already was online.
+    pub fn came_online(&self) -> bool {
+        self.offline_since.lock().unwrap_or_else(|e| e.into_inner()).take().is_some()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    /// The record is already gone from the drive; its parts (and channel) are not.
+    Delete { record: FileRecord, delete_channel: bool },
+    RenameChannel { file_id: i64, from: String, to: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingOp {
+    pub id:        String,
+    #[serde(flatten)]
+    pub op:        Op,
+    pub queued_at: i64,
+}
+
+pub fn list(st: &AppState) -> Vec<PendingOp> {
+    st.store.load_json(&st.cfg.pending_file)
+}
+
+fn push(st: &AppState, op: Op) -> PendingOp {
+    let pending = PendingOp {
+        id:        uuid::Uuid::new_v4().simple().to_string()[..12].to_string(),
+        op,
+        queued_at: chrono::Utc::now().timestamp(),
+    };
+    if let Err(e) = st.store.update_json(&st.cfg.pending_file, |ops: &mut Vec<PendingOp>| ops.push(pending.clone())) {
+        warn!("⚠️ Pending operation not saved: {e}");
+    }
+    pending
+}
+
+/// Hold back deleting `record`'s parts, which the caller has taken out of history
+/// or the trash.
+pub fn defer_delete(st: &AppState, record: FileRecord, delete_channel: bool) -> PendingOp {
+    info!("📴 Offline: deleting the parts of {} ({}) once Discord is back", record.filename, record.id);
+    push(st, Op::Delete { record, delete_channel })
+}
+
+pub fn defer_rename(st: &AppState, file_id: i64, from: &str, to: &str) -> PendingOp {
+    info!("📴 Offline: renaming the channel of {file_id} once Discord is back");
+    push(st, Op::RenameChannel { file_id, from: from.to_string(), to: to.to_string() })
+}
+
+/// The gateway dropped: queued uploads wait for it.
+pub fn on_disconnect(st: &AppState) {
+    let held = queue::hold_for_offline(st);
+    warn!("📴 [{}] Discord offline: {held} queued upload(s) held, deletes and channel renames will wait", st.profile);
+}
+
+/// The gateway is back: release held uploads and run pending operations.
+pub async fn on_reconnect(st: &AppState) {
+    let released = queue::release_offline(st);
+    let ops = list(st);
+    if released + ops.len() > 0 {
+        info!("📶 [{}] Discord back: {released} upload(s) released, {} pending operation(s) to run", st.profile, ops.len());
+    }
+    for pending in ops {
+        if !st.connectivity.is_online() {
+            warn!("📴 Offline again: pending operations left for the next reconnect");
+            return;
+        }
+        run(st, &pending).await;
+        let _ = st.store.update_json(&st.cfg.pending_file, |ops: &mut Vec<PendingOp>| ops.retain(|o| o.id != pending.id));
+    }
+}
+
+async fn run(st: &AppState, pending: &PendingOp) {
+    match &pending.op {
+        Op::Delete { record, delete_channel } => {
+            let _lock = st.file_locks.write(record.id).await;
+            let others = dedup::live_records(st);
+            let (telegram, shared) = api::drop_stored(st, record.clone(), &others, *delete_channel).await;
+            audit::record(&*st.store, &st.cfg.audit_file, Some(record.id), "parts_deleted", json!({
+                "pending":        pending.id,
+                "delete_channel": delete_channel,
+                "telegram":       telegram,
+                "shared":         shared,
+            }));
+            info!("🗑️ Pending delete of {} ({}) done", record.filename, record.id);
+        }
+        Op::RenameChannel { file_id, from, to } => {
+            let discord = api::rename_on_discord(st, *file_id, from, to).await;
+            audit::record(&*st.store, &st.cfg.audit_file, Some(*file_id), "channel_renamed",
+                json!({ "pending": pending.id, "discord": discord }));
+        }
+    }
+}
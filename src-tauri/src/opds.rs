@@ -0,0 +1,100 @@
+/// opds.rs — OPDS 1.2 catalog feeds for e-readers and media apps on the LAN.
+///
+/// `/opds` is a navigation feed with one section per folder (plus root and recent);
+/// each section is an acquisition feed whose entries point at the ranged
+/// `/api/merge/:id` download. Links are relative, so the same feeds work under
+/// `/p/<profile>/opds` too.
+use chrono::TimeZone;
+use std::fmt::Write as _;
+
+use crate::storage::{FileRecord, Folder};
+
+pub const NAVIGATION: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+pub const ACQUISITION: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+
+/// Entries in the "recent" section.
+pub const RECENT_LIMIT: usize = 50;
+
+fn esc(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Record ids are upload timestamps in ms.
+fn updated(id: i64) -> String {
+    chrono::Utc.timestamp_millis_opt(id).single()
+        .unwrap_or_else(chrono::Utc::now)
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+fn open_feed(out: &mut String, id: &str, title: &str, self_href: &str, kind: &str, start: &str) {
+    let _ = write!(out, concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:opds=\"http://opds-spec.org/2010/catalog\">\n",
+        "  <id>{}</id>\n  <title>{}</title>\n  <updated>{}</updated>\n",
+        "  <author><name>Discord Drive</name></author>\n",
+        "  <link rel=\"self\" href=\"{}\" type=\"{}\"/>\n",
+        "  <link rel=\"start\" href=\"{}\" type=\"{}\"/>\n",
+    ), esc(id), esc(title), now(), esc(self_href), kind, esc(start), NAVIGATION);
+}
+
+fn nav_entry(out: &mut String, id: &str, title: &str, summary: &str, href: &str) {
+    let _ = write!(out, concat!(
+        "  <entry>\n    <id>{}</id>\n    <title>{}</title>\n    <updated>{}</updated>\n",
+        "    <content type=\"text\">{}</content>\n",
+        "    <link rel=\"subsection\" href=\"{}\" type=\"{}\"/>\n  </entry>\n",
+    ), esc(id), esc(title), now(), esc(summary), esc(href), ACQUISITION);
+}
+
+/// Root catalog at `opds`: recent, root files, then one section per folder.
+/// `counts` maps folder id ("" = root) to its number of files.
+pub fn navigation_feed(folders: &[Folder], counts: &dyn Fn(&str) -> usize) -> String {
+    let mut out = String::new();
+    open_feed(&mut out, "urn:discord-drive:catalog", "Discord Drive", "opds", NAVIGATION, "opds");
+    nav_entry(&mut out, "urn:discord-drive:recent", "Mới tải lên",
+        &format!("{RECENT_LIMIT} file mới nhất"), "opds/recent");
+    nav_entry(&mut out, "urn:discord-drive:folder:root", "Thư mục gốc",
+        &format!("{} file", counts("")), "opds/folders/root");
+    for f in folders {
+        nav_entry(&mut out, &format!("urn:discord-drive:folder:{}", f.id), &f.name,
+            &format!("{} file", counts(&f.id.to_string())), &format!("opds/folders/{}", f.id));
+    }
+    out.push_str("</feed>\n");
+    out
+}
+
+/// Acquisition feed for one section. `base` is the path from the feed back to the
+/// profile root (e.g. `../../` for `opds/folders/<id>`); `mime`/`has_thumb` come from
+/// the API's file-type helpers.
+pub fn acquisition_feed(
+    id:        &str,
+    title:     &str,
+    self_href: &str,
+    base:      &str,
+    files:     &[FileRecord],
+    mime:      &dyn Fn(&str) -> &'static str,
+    has_thumb: &dyn Fn(&str) -> bool,
+) -> String {
+    let mut out = String::new();
+    open_feed(&mut out, id, title, self_href, ACQUISITION, &format!("{base}opds"));
+    for r in files {
+        let size = r.exact_size().map(|b| format!(" length=\"{b}\"")).unwrap_or_default();
+        let _ = write!(out, concat!(
+            "  <entry>\n    <id>urn:discord-drive:file:{}</id>\n    <title>{}</title>\n",
+            "    <updated>{}</updated>\n    <content type=\"text\">{:.1} MB · {}</content>\n",
+            "    <link rel=\"http://opds-spec.org/acquisition\" href=\"{}api/merge/{}\" type=\"{}\"{}/>\n",
+        ), r.id, esc(&r.filename), updated(r.id), r.size_mb, esc(&r.sent_at),
+           base, r.id, mime(&r.filename), size);
+        if has_thumb(&r.filename) {
+            let _ = writeln!(out,
+                "    <link rel=\"http://opds-spec.org/image/thumbnail\" href=\"{base}api/thumbnail/{}\" type=\"image/jpeg\"/>",
+                r.id);
+        }
+        out.push_str("  </entry>\n");
+    }
+    out.push_str("</feed>\n");
+    out
+}
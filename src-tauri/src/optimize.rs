@@ -0,0 +1,103 @@
+/// optimize.rs — Optional recompression of photos before they are split.
+///
+/// With `optimize_images` on an upload, a JPEG or PNG is held in memory until
+/// every chunk is in, then re-encoded: JPEGs at `upload.image_quality`, PNGs
+/// losslessly at the highest zlib level. EXIF (date, GPS, orientation) and the ICC
+/// profile are carried over. The result is only used when it saves at least
+/// `MIN_SAVING`; otherwise the original bytes go out untouched. Decoding is held
+/// to `limits()`, so a small file claiming huge dimensions is skipped, not inflated.
+use image::{
+    codecs::{
+        jpeg::{JpegDecoder, JpegEncoder},
+        png::{CompressionType, FilterType, PngDecoder, PngEncoder},
+    },
+    DynamicImage, ImageDecoder, ImageEncoder, Limits,
+};
+use std::io::Cursor;
+
+use crate::storage::ImageOptimization;
+
+/// Smaller savings are not worth a lossy pass.
+const MIN_SAVING: f64 = 0.05;
+
+/// Widest or tallest image re-encoded, and the most memory a decode may take.
+const MAX_SIDE:  u32 = 16_384;
+const MAX_ALLOC: u64 = 512 * 1024 * 1024;
+
+fn limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width  = Some(MAX_SIDE);
+    limits.max_image_height = Some(MAX_SIDE);
+    limits.max_alloc        = Some(MAX_ALLOC);
+    limits
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Jpeg,
+    Png,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self { Kind::Jpeg => "jpeg", Kind::Png => "png" }
+    }
+}
+
+pub fn kind_of(filename: &str) -> Option<Kind> {
+    let ext = std::path::Path::new(filename).extension()
+        .and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" => Some(Kind::Jpeg),
+        "png"          => Some(Kind::Png),
+        _              => None,
+    }
+}
+
+/// Re-encoded bytes and their summary, or None when the image can't be decoded
+/// within `limits()`, is animated, or doesn't shrink by `MIN_SAVING`.
+pub fn optimize(kind: Kind, data: &[u8], jpeg_quality: u8) -> Option<(Vec<u8>, ImageOptimization)> {
+    let out = match kind {
+        Kind::Jpeg => reencode_jpeg(data, jpeg_quality),
+        Kind::Png  => reencode_png(data),
+    }.ok()?;
+    if (out.len() as f64) > data.len() as f64 * (1.0 - MIN_SAVING) { return None; }
+    let summary = ImageOptimization {
+        format:          kind.as_str().to_string(),
+        original_bytes:  data.len() as u64,
+        optimized_bytes: out.len() as u64,
+        quality:         (kind == Kind::Jpeg).then_some(jpeg_quality),
+    };
+    Some((out, summary))
+}
+
+fn reencode_jpeg(data: &[u8], quality: u8) -> anyhow::Result<Vec<u8>> {
+    let mut decoder = JpegDecoder::new(Cursor::new(data))?;
+    decoder.set_limits(limits())?;
+    let exif = decoder.exif_metadata()?;
+    let icc  = decoder.icc_profile()?;
+    let img  = DynamicImage::from_decoder(decoder)?;
+    let mut out = vec![];
+    let mut encoder = JpegEncoder::new_with_quality(&mut out, quality);
+    if let Some(exif) = exif { encoder.set_exif_metadata(exif)?; }
+    if let Some(icc) = icc { encoder.set_icc_profile(icc)?; }
+    img.write_with_encoder(encoder)?;
+    Ok(out)
+}
+
+fn reencode_png(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    // APNG frames after the first would be lost; acTL comes before the image data
+    let head = data.windows(4).position(|w| w == b"IDAT").unwrap_or(data.len());
+    if data[..head].windows(4).any(|w| w == b"acTL") { anyhow::bail!("animated PNG"); }
+    let mut decoder = PngDecoder::new(Cursor::new(data))?;
+    decoder.set_limits(limits())?;
+    let exif = decoder.exif_metadata()?;
+    let icc  = decoder.icc_profile()?;
+    let img  = DynamicImage::from_decoder(decoder)?;
+    let mut out = vec![];
+    let mut encoder = PngEncoder::new_with_quality(&mut out, CompressionType::Best, FilterType::Adaptive);
+    if let Some(exif) = exif { encoder.set_exif_metadata(exif)?; }
+    if let Some(icc) = icc { encoder.set_icc_profile(icc)?; }
+    img.write_with_encoder(encoder)?;
+    Ok(out)
+}
@@ -0,0 +1,150 @@
+/// permissions.rs — Pre-flight check of the bots' Discord permissions.
+///
+/// A bot missing Manage Channels or Attach Files only fails once an upload is
+/// underway, with a bare 403. This computes each bot's effective permissions in
+/// the guild and in every folder category (where channel overwrites can take them
+/// away again) and names the ones that are missing. Run at startup and on demand
+/// through GET /api/diagnostics/permissions.
+use serde::Serialize;
+use serenity::{
+    http::Http,
+    model::{channel::ChannelType, id::ChannelId, permissions::Permissions},
+};
+use std::{collections::HashSet, sync::Arc};
+use tracing::{info, warn};
+
+use crate::{state::AppState, storage::current_datetime_iso};
+
+/// What the primary bot needs: it manages channels as well as sending parts.
+const PRIMARY: &[(Permissions, &str, &str)] = &[
+    (Permissions::VIEW_CHANNEL,         "View Channels",        "thấy kênh của folder"),
+    (Permissions::MANAGE_CHANNELS,      "Manage Channels",      "tạo/đổi tên/xoá category và kênh"),
+    (Permissions::SEND_MESSAGES,        "Send Messages",        "gửi các part"),
+    (Permissions::ATTACH_FILES,         "Attach Files",         "đính kèm file part"),
+    (Permissions::READ_MESSAGE_HISTORY, "Read Message History", "tải part về"),
+];
+
+/// Only needed with `upload.status_reactions`.
+const REACTIONS: (Permissions, &str, &str) = (Permissions::ADD_REACTIONS, "Add Reactions", "đánh dấu ✅/⚠️ lên part");
+
+/// Extra bots only send parts.
+const EXTRA: &[(Permissions, &str, &str)] = &[
+    (Permissions::VIEW_CHANNEL,  "View Channels", "thấy kênh của folder"),
+    (Permissions::SEND_MESSAGES, "Send Messages", "gửi các part"),
+    (Permissions::ATTACH_FILES,  "Attach Files",  "đính kèm file part"),
+];
+
+#[derive(Debug, Serialize)]
+pub struct Missing {
+    pub permission: &'static str,
+    pub needed_for: &'static str,
+}
+
+/// The guild itself, or one folder category.
+#[derive(Debug, Serialize)]
+pub struct Scope {
+    pub scope:   &'static str,           // "guild" | "category"
+    pub id:      String,
+    pub name:    String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder:  Option<String>,
+    pub missing: Vec<Missing>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BotReport {
+    pub bot:     usize,                  // 0 = primary, then DISCORD_EXTRA_TOKENS in order
+    pub user:    Option<String>,
+    pub ok:      bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error:   Option<String>,         // the check itself failed (not in guild, bad token...)
+    pub scopes:  Vec<Scope>,             // only the ones with something missing
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub ok:         bool,
+    pub simulated:  bool,
+    pub checked_at: String,
+    pub bots:       Vec<BotReport>,
+}
+
+pub async fn check(st: &AppState) -> Report {
+    let mut report = Report { ok: true, simulated: st.cfg.simulate, checked_at: current_datetime_iso(), bots: vec![] };
+    if st.cfg.simulate { return report; }
+
+    let mut primary: Vec<(Permissions, &str, &str)> = PRIMARY.to_vec();
+    if st.cfg.status_reactions { primary.push(REACTIONS); }
+    let categories: Vec<(i64, String)> = st.store.load_folders(&st.cfg.folders_file).iter()
+        .flat_map(|f| f.category_ids().into_iter().map(|c| (c, f.name.clone())))
+        .collect();
+    for (i, http) in st.send_pool.bots().iter().enumerate() {
+        let required = if i == 0 { primary.as_slice() } else { EXTRA };
+        report.bots.push(check_bot(http, st.guild_id, &categories, required, i).await);
+    }
+    report.ok = report.bots.iter().all(|b| b.ok);
+    report
+}
+
+async fn check_bot(
+    http:       &Arc<Http>,
+    guild_id:   serenity::model::id::GuildId,
+    categories: &[(i64, String)],
+    required:   &[(Permissions, &'static str, &'static str)],
+    index:      usize,
+) -> BotReport {
+    let mut bot = BotReport { bot: index, user: None, ok: false, error: None, scopes: vec![] };
+    let fetched = async {
+        let guild = guild_id.to_partial_guild(http).await?;
+        let member = guild_id.current_user_member(http).await?;
+        let channels = guild_id.channels(http).await?;
+        Ok::<_, serenity::Error>((guild, member, channels))
+    }.await;
+    let (guild, member, channels) = match fetched {
+        Ok(f)  => f,
+        Err(e) => { bot.error = Some(e.to_string()); return bot; }
+    };
+    bot.user = Some(member.user.name.clone());
+    let missing = |have: Permissions| -> Vec<Missing> {
+        required.iter().filter(|(p, _, _)| !have.contains(*p))
+            .map(|&(_, permission, needed_for)| Missing { permission, needed_for })
+            .collect()
+    };
+
+    let in_guild = missing(guild.member_permissions(&member));
+    if !in_guild.is_empty() {
+        bot.scopes.push(Scope { scope: "guild", id: guild_id.get().to_string(), name: guild.name.clone(), folder: None, missing: in_guild });
+    }
+    let mut seen = HashSet::new();
+    for (id, folder) in categories {
+        if !seen.insert(*id) { continue; }
+        // A category deleted on Discord is recreated on the next upload; nothing to check yet
+        let Some(cat) = channels.get(&ChannelId::new(*id as u64)).filter(|c| c.kind == ChannelType::Category) else { continue };
+        let in_cat = missing(guild.user_permissions_in(cat, &member));
+        if !in_cat.is_empty() {
+            bot.scopes.push(Scope { scope: "category", id: id.to_string(), name: cat.name.clone(), folder: Some(folder.clone()), missing: in_cat });
+        }
+    }
+    bot.ok = bot.scopes.is_empty();
+    bot
+}
+
+/// Log the startup check, one line per scope that lacks something.
+pub async fn startup_check(st: AppState) {
+    let report = check(&st).await;
+    if report.simulated { return; }
+    if report.ok {
+        info!("🔐 [{}] Discord permissions OK ({} bot(s))", st.profile, report.bots.len());
+        return;
+    }
+    for bot in report.bots.iter().filter(|b| !b.ok) {
+        let who = bot.user.clone().unwrap_or_else(|| format!("bot #{}", bot.bot));
+        if let Some(e) = &bot.error {
+            warn!("🔐 [{}] Could not check permissions of {who}: {e}", st.profile);
+        }
+        for s in &bot.scopes {
+            let names: Vec<&str> = s.missing.iter().map(|m| m.permission).collect();
+            warn!("🔐 [{}] {who} is missing in {} {}: {}", st.profile, s.scope, s.name, names.join(", "));
+        }
+    }
+}
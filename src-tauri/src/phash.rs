@@ -0,0 +1,58 @@
+/// phash.rs — Perceptual hashes for finding the same photo under another name.
+///
+/// The classic DCT pHash: shrink to 32×32 greyscale, take the lowest 8×8
+/// frequencies (minus the DC term), and set a bit for each one above their median.
+/// Re-encoding, resizing and small edits flip only a few bits, so the Hamming
+/// distance between two hashes says how alike the pictures look.
+///
+/// Hashes are taken from the 256 px thumbnail rather than the original, so one
+/// can be backfilled from a cached thumbnail without fetching the file again.
+use image::{imageops::FilterType, DynamicImage};
+
+/// Distance at or under which two images count as similar by default.
+pub const DEFAULT_MAX_DISTANCE: u32 = 10;
+
+const SIZE: usize = 32;
+const LOW: usize = 8;
+
+pub fn hash(img: &DynamicImage) -> u64 {
+    let grey = img.resize_exact(SIZE as u32, SIZE as u32, FilterType::Triangle).to_luma8();
+    let px: Vec<f64> = grey.pixels().map(|p| p.0[0] as f64).collect();
+
+    let cos: Vec<f64> = (0..LOW * SIZE).map(|i| {
+        let (u, x) = (i / SIZE, i % SIZE);
+        (std::f64::consts::PI * (2 * x + 1) as f64 * u as f64 / (2 * SIZE) as f64).cos()
+    }).collect();
+    let mut coeffs = Vec::with_capacity(LOW * LOW);
+    for u in 0..LOW {
+        for v in 0..LOW {
+            let mut sum = 0.0;
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    sum += px[y * SIZE + x] * cos[u * SIZE + y] * cos[v * SIZE + x];
+                }
+            }
+            coeffs.push(sum);
+        }
+    }
+
+    // The DC term is overall brightness; leave it out of the median
+    let mut sorted = coeffs[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    coeffs.iter().enumerate().skip(1)
+        .filter(|(_, &c)| c > median)
+        .fold(0u64, |bits, (i, _)| bits | 1 << i)
+}
+
+pub fn to_hex(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+pub fn from_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
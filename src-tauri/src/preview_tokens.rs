@@ -0,0 +1,67 @@
+/// preview_tokens.rs — Short-lived signed `?token=` for media tags.
+///
+/// `<img>`/`<video>` can't send headers, so preview/thumbnail/merge also accept a
+/// token bound to one file id and an expiry: `<file_id>.<expires_unix>.<hmac hex>`.
+/// The key is random per process, so a restart invalidates outstanding tokens.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    WrongFile,
+    Expired,
+    BadSignature,
+}
+
+pub struct PreviewSigner {
+    key: Vec<u8>,
+}
+
+impl Default for PreviewSigner {
+    fn default() -> Self { Self::new() }
+}
+
+impl PreviewSigner {
+    pub fn new() -> Self {
+        let mut key = Vec::with_capacity(32);
+        key.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+        key.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+        Self { key }
+    }
+
+    fn mac(&self, file_id: i64, expires_at: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(format!("{file_id}.{expires_at}").as_bytes());
+        mac
+    }
+
+    pub fn mint(&self, file_id: i64, expires_at: i64) -> String {
+        let sig: String = self.mac(file_id, expires_at).finalize().into_bytes()
+            .iter().map(|b| format!("{b:02x}")).collect();
+        format!("{file_id}.{expires_at}.{sig}")
+    }
+
+    pub fn verify(&self, token: &str, file_id: i64, now: i64) -> Result<(), TokenError> {
+        let mut it = token.splitn(3, '.');
+        let (Some(id), Some(exp), Some(sig)) = (it.next(), it.next(), it.next()) else {
+            return Err(TokenError::Malformed);
+        };
+        let id:  i64 = id.parse().map_err(|_| TokenError::Malformed)?;
+        let exp: i64 = exp.parse().map_err(|_| TokenError::Malformed)?;
+        let sig = decode_hex(sig).ok_or(TokenError::Malformed)?;
+        self.mac(id, exp).verify_slice(&sig).map_err(|_| TokenError::BadSignature)?;
+        if id != file_id { return Err(TokenError::WrongFile); }
+        if now >= exp { return Err(TokenError::Expired); }
+        Ok(())
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes().chunks(2).map(|pair| {
+        if pair.len() != 2 { return None; }
+        u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()
+    }).collect()
+}
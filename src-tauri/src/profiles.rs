@@ -0,0 +1,107 @@
+/// profiles.rs — Multiple independent drives ("profiles") served by one app.
+///
+/// Every profile has its own AppState (bot, guild, data files) and its API is
+/// mounted at `/p/<name>/api/...`. Un-prefixed `/api/...` requests are forwarded
+/// to whichever profile is currently active.
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, path::PathBuf, sync::{Arc, RwLock}};
+use tower::Service;
+use tracing::info;
+
+use crate::state::AppState;
+
+pub const DEFAULT_PROFILE: &str = "default";
+const ACTIVE_FILE: &str = "active_profile.json";
+
+#[derive(Serialize, Deserialize, Default)]
+struct ActiveProfile { active: String }
+
+pub struct ProfileEntry {
+    pub state:  AppState,
+    pub router: Router,
+}
+
+pub struct ProfileRegistry {
+    base_dir: PathBuf,
+    active:   RwLock<String>,
+    profiles: BTreeMap<String, ProfileEntry>,
+}
+
+pub type SharedRegistry = Arc<ProfileRegistry>;
+
+impl ProfileRegistry {
+    /// Restores the last active profile, falling back to `default` if it no longer exists.
+    pub fn new(base_dir: PathBuf, profiles: BTreeMap<String, ProfileEntry>) -> Self {
+        let saved: ActiveProfile = std::fs::read_to_string(base_dir.join(ACTIVE_FILE)).ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        let active = if profiles.contains_key(&saved.active) { saved.active } else { DEFAULT_PROFILE.to_string() };
+        info!("👤 Active profile: {active}");
+        Self { base_dir, active: RwLock::new(active), profiles }
+    }
+
+    pub fn active(&self) -> String {
+        self.active.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn names(&self) -> Vec<String> { self.profiles.keys().cloned().collect() }
+
+    pub fn get(&self, name: &str) -> Option<&ProfileEntry> { self.profiles.get(name) }
+
+    pub fn states(&self) -> impl Iterator<Item = &AppState> { self.profiles.values().map(|p| &p.state) }
+
+    pub fn set_active(&self, name: &str) -> anyhow::Result<()> {
+        *self.active.write().unwrap_or_else(|e| e.into_inner()) = name.to_string();
+        let body = serde_json::to_string_pretty(&ActiveProfile { active: name.to_string() })?;
+        std::fs::write(self.base_dir.join(ACTIVE_FILE), body)?;
+        Ok(())
+    }
+}
+
+fn err(status: StatusCode, msg: impl Into<String>) -> Response {
+    (status, Json(json!({ "detail": msg.into() }))).into_response()
+}
+
+// ── Handlers ───────────────────────────────────────────────────────────────────
+
+pub async fn list_profiles(State(reg): State<SharedRegistry>) -> impl IntoResponse {
+    let profiles: Vec<Value> = reg.profiles.iter().map(|(name, p)| json!({
+        "name":     name,
+        "guild_id": p.state.guild_id.get().to_string(),
+        "telegram": p.state.tg_enabled,
+        "prefix":   format!("/p/{name}"),
+    })).collect();
+    Json(json!({ "active": reg.active(), "profiles": profiles }))
+}
+
+pub async fn switch_profile(State(reg): State<SharedRegistry>, Json(body): Json<Value>) -> Response {
+    let name = body["active"].as_str().unwrap_or("").trim().to_string();
+    if reg.get(&name).is_none() {
+        return err(StatusCode::NOT_FOUND, format!("Profile không tồn tại: {name}"));
+    }
+    if let Err(e) = reg.set_active(&name) {
+        return err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    info!("👤 Switched active profile → {name}");
+    Json(json!({ "success": true, "active": name })).into_response()
+}
+
+/// Route an un-prefixed request to the active profile's router.
+pub async fn forward_to_active(State(reg): State<SharedRegistry>, req: Request) -> Response {
+    let active = reg.active();
+    match reg.get(&active) {
+        // Router is always ready, so calling without poll_ready is fine
+        Some(p) => match p.router.clone().call(req).await {
+            Ok(resp) => resp,
+            Err(never) => match never {},
+        },
+        None => err(StatusCode::SERVICE_UNAVAILABLE, "Không có profile nào đang hoạt động"),
+    }
+}
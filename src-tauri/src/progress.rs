@@ -0,0 +1,76 @@
+/// progress.rs — Aggregate transfer progress for the desktop shell.
+///
+/// Sender tasks (bytes handed to Discord/Telegram) and merge tasks (bytes streamed
+/// to a client) each hold a `Job`. The Tauri side polls `snapshot()` to drive the
+/// taskbar/dock progress bar and window title; `/api/progress` serves the same data.
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{atomic::{AtomicU64, Ordering}, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Upload,
+    Download,
+}
+
+struct Entry {
+    kind:  Kind,
+    done:  u64,
+    total: u64,
+}
+
+/// Process-wide: the window shows every profile's transfers together.
+static JOBS: Mutex<BTreeMap<u64, Entry>> = Mutex::new(BTreeMap::new());
+static NEXT_KEY: AtomicU64 = AtomicU64::new(1);
+
+/// A running transfer; it stops counting when dropped.
+pub struct Job {
+    key: u64,
+}
+
+impl Job {
+    pub fn start(kind: Kind, total: u64) -> Self {
+        let key = NEXT_KEY.fetch_add(1, Ordering::Relaxed);
+        JOBS.lock().unwrap_or_else(|e| e.into_inner()).insert(key, Entry { kind, done: 0, total });
+        Self { key }
+    }
+
+    pub fn add(&self, bytes: u64) {
+        if let Some(e) = JOBS.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&self.key) {
+            e.done = (e.done + bytes).min(e.total);
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        JOBS.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.key);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    pub uploads:     usize,
+    pub downloads:   usize,
+    pub done_bytes:  u64,
+    pub total_bytes: u64,
+    /// None while nothing is transferring.
+    pub percent:     Option<u8>,
+}
+
+pub fn snapshot() -> Snapshot {
+    let jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut s = Snapshot::default();
+    for e in jobs.values() {
+        match e.kind {
+            Kind::Upload   => s.uploads += 1,
+            Kind::Download => s.downloads += 1,
+        }
+        s.done_bytes  += e.done;
+        s.total_bytes += e.total;
+    }
+    if !jobs.is_empty() {
+        s.percent = Some((s.done_bytes * 100).checked_div(s.total_bytes).unwrap_or(0) as u8);
+    }
+    s
+}
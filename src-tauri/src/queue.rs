@@ -0,0 +1,288 @@
+/// queue.rs — Persistent server-side upload queue.
+///
+/// Items reference a file on local disk and are ingested one at a time through the
+/// normal session/sender pipeline. The queue lives in `queue_file`, so pending work
+/// survives a restart; the worker starts once the profile's Discord bot is ready.
+/// While the gateway is away items wait as `queued_offline` (offline.rs).
+///
+/// An item with `after_verify` is stored as `verifying` before its source is
+/// checked and released, and only then `done`: one still `verifying` after a
+/// restart is checked again rather than left with its source untouched.
+use axum::http::StatusCode;
+use bytes::Bytes;
+use tokio::{io::AsyncReadExt, task::JoinHandle};
+use tracing::{info, warn};
+
+use crate::{
+    api::{feed_chunk, finalize_session, start_session, ApiError, NewUpload},
+    state::AppState,
+    storage::{current_datetime_iso, FileRecord, QueueItem, SourceAction},
+    upload::{transition_session, UploadTarget, STATUS_CANCELLED, STATUS_UPLOADING},
+    verify,
+};
+
+pub const QUEUED:         &str = "queued";
+pub const QUEUED_OFFLINE: &str = "queued_offline";
+pub const RUNNING:        &str = "running";
+pub const VERIFYING:      &str = "verifying";
+pub const DONE:           &str = "done";
+pub const FAILED:         &str = "failed";
+
+/// Finished items kept for display before being trimmed.
+const MAX_FINISHED: usize = 100;
+
+static QUEUE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+fn with_queue<R>(st: &AppState, f: impl FnOnce(&mut Vec<QueueItem>) -> R) -> R {
+    let _guard = QUEUE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut items = st.store.load_queue(&st.cfg.queue_file);
+    let out = f(&mut items);
+    if let Err(e) = st.store.save_queue(&st.cfg.queue_file, &items) {
+        eprintln!("Failed to save upload queue: {e}");
+    }
+    out
+}
+
+pub fn list(st: &AppState) -> Vec<QueueItem> {
+    st.store.load_queue(&st.cfg.queue_file)
+}
+
+pub fn enqueue(st: &AppState, mut item: QueueItem) {
+    if !st.connectivity.is_online() && item.status == QUEUED {
+        item.status = QUEUED_OFFLINE.to_string();
+    }
+    info!("🗂️ Queued {} ({}, {})", item.filename, item.id, item.status);
+    with_queue(st, |items| items.push(item));
+    st.queue_notify.notify_one();
+}
+
+/// Park queued items until Discord is back. Returns how many.
+pub fn hold_for_offline(st: &AppState) -> usize {
+    with_queue(st, |items| {
+        let mut held = 0;
+        for i in items.iter_mut().filter(|i| i.status == QUEUED) {
+            i.status = QUEUED_OFFLINE.to_string();
+            held += 1;
+        }
+        held
+    })
+}
+
+/// Hand parked items back to the worker. Returns how many.
+pub fn release_offline(st: &AppState) -> usize {
+    let released = with_queue(st, |items| {
+        let mut released = 0;
+        for i in items.iter_mut().filter(|i| i.status == QUEUED_OFFLINE) {
+            i.status = QUEUED.to_string();
+            released += 1;
+        }
+        released
+    });
+    if released > 0 { st.queue_notify.notify_one(); }
+    released
+}
+
+/// Remove an item that isn't being worked on. Returns false if it is running,
+/// verifying or unknown.
+pub fn remove(st: &AppState, id: &str) -> bool {
+    let removed = with_queue(st, |items| {
+        let pos = items.iter().position(|i| i.id == id && i.status != RUNNING && i.status != VERIFYING)?;
+        Some(items.remove(pos))
+    });
+    if let Some(item) = removed.as_ref().filter(|i| i.delete_source) {
+        let _ = std::fs::remove_file(&item.source_path);
+    }
+    removed.is_some()
+}
+
+fn take_next(st: &AppState) -> Option<QueueItem> {
+    with_queue(st, |items| {
+        let item = items.iter_mut().find(|i| i.status == QUEUED)?;
+        item.status = RUNNING.to_string();
+        Some(item.clone())
+    })
+}
+
+/// Items whose source is still to be checked (`after_verify`) stay `verifying`.
+fn finish(st: &AppState, id: &str, res: &Result<FileRecord, ApiError>) {
+    // Failed because Discord went away: try again once it is back
+    let retry = !st.connectivity.is_online();
+    with_queue(st, |items| {
+        if let Some(item) = items.iter_mut().find(|i| i.id == id) {
+            match res {
+                Ok(rec) => {
+                    item.status = if item.after_verify.is_some() { VERIFYING } else { DONE }.to_string();
+                    item.record_id = Some(rec.id);
+                    item.error = None;
+                }
+                Err(e) if retry => { item.status = QUEUED_OFFLINE.to_string(); item.error = Some(e.to_string()); }
+                Err(e)  => { item.status = FAILED.to_string(); item.error = Some(e.to_string()); }
+            }
+        }
+        let finished = items.iter().filter(|i| i.status == DONE || i.status == FAILED).count();
+        let mut drop_n = finished.saturating_sub(MAX_FINISHED);
+        items.retain(|i| {
+            if drop_n > 0 && (i.status == DONE || i.status == FAILED) { drop_n -= 1; false } else { true }
+        });
+    });
+}
+
+fn take_verifying(st: &AppState) -> Option<QueueItem> {
+    list(st).into_iter().find(|i| i.status == VERIFYING)
+}
+
+/// Check `item`'s upload against its source and release the source, then mark it done.
+async fn settle(st: &AppState, item: &QueueItem) {
+    let record = item.record_id.and_then(|id| st.store.find_record(&st.cfg.history_file, id));
+    let outcome = match (record, &item.after_verify) {
+        (Some(record), Some(action)) => verify_then_release(st, &record, &item.source_path, action).await,
+        (None, _) => Err("File đã bị xoá trước khi kiểm tra — giữ file nguồn".to_string()),
+        (_, None) => Ok(()),
+    };
+    if let Err(ref e) = outcome {
+        warn!("⚠️ Queued upload {} not released: {e}", item.id);
+    }
+    with_queue(st, |items| {
+        if let Some(i) = items.iter_mut().find(|i| i.id == item.id) {
+            i.status   = DONE.to_string();
+            i.verified = Some(outcome.is_ok());
+            i.error    = outcome.err();
+        }
+    });
+}
+
+/// Start the queue worker for one profile. Items left `running` by a previous
+/// process were interrupted mid-ingest and are started over; items left
+/// `verifying` are checked again once Discord is reachable.
+pub fn spawn_worker(st: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let resumed = with_queue(&st, |items| {
+            for i in items.iter_mut().filter(|i| i.status == RUNNING) {
+                i.status = QUEUED.to_string();
+            }
+            items.iter().filter(|i| i.status == QUEUED).count()
+        });
+        if resumed > 0 {
+            info!("🗂️ [{}] Resuming {resumed} queued upload(s)", st.profile);
+        }
+        loop {
+            if st.connectivity.is_online() {
+                if let Some(item) = take_verifying(&st) {
+                    settle(&st, &item).await;
+                    continue;
+                }
+            }
+            let next = if st.connectivity.is_online() { take_next(&st) } else { None };
+            let Some(item) = next else {
+                st.queue_notify.notified().await;
+                continue;
+            };
+            info!("🗂️ Ingesting queued file {} ({})", item.filename, item.id);
+            let res = ingest_local_file(
+                &st, std::path::Path::new(&item.source_path),
+                &item.filename, &item.folder_id, &item.message, None, None,
+            ).await;
+            if let Err(ref e) = res {
+                warn!("⚠️ Queued upload {} failed: {e}", item.id);
+            } else if item.delete_source {
+                let _ = tokio::fs::remove_file(&item.source_path).await;
+            }
+            finish(&st, &item.id, &res);
+        }
+    })
+}
+
+/// Check the uploaded copies against the source, then apply `action` to it. On any
+/// failure the source stays where it is and the reason is returned.
+async fn verify_then_release(st: &AppState, record: &FileRecord, source: &str, action: &SourceAction) -> Result<(), String> {
+    let source = std::path::Path::new(source);
+    let report = verify::verify_source(st, record, source).await
+        .map_err(|e| format!("Không kiểm tra được: {e} — giữ file nguồn"))?;
+    if !report.healthy {
+        return Err(format!("Part {:?} không khớp file nguồn — giữ file nguồn", report.degraded));
+    }
+    match action {
+        SourceAction::Delete => {
+            tokio::fs::remove_file(source).await.map_err(|e| format!("Không xoá được file nguồn: {e}"))?;
+            info!("🗑️ {} verified → source deleted", record.filename);
+        }
+        SourceAction::Move { to } => {
+            let name = source.file_name().ok_or("Đường dẫn nguồn không có tên file")?;
+            let dest = std::path::Path::new(to).join(name);
+            if tokio::fs::try_exists(&dest).await.unwrap_or(true) {
+                return Err(format!("{} đã tồn tại — giữ file nguồn", dest.display()));
+            }
+            // rename fails across filesystems; copy then remove instead
+            if tokio::fs::rename(source, &dest).await.is_err() {
+                tokio::fs::copy(source, &dest).await.map_err(|e| format!("Không chuyển được file nguồn: {e}"))?;
+                tokio::fs::remove_file(source).await.map_err(|e| format!("Đã chép nhưng không xoá được file nguồn: {e}"))?;
+            }
+            info!("📦 {} verified → source moved to {}", record.filename, dest.display());
+        }
+    }
+    Ok(())
+}
+
+/// Upload a file from local disk through the regular session + sender pipeline.
+/// `zip_level` overrides `upload.zip_compress_level` for this file's parts, and
+/// `platform` the default upload target.
+pub async fn ingest_local_file(
+    st:        &AppState,
+    path:      &std::path::Path,
+    filename:  &str,
+    folder_id: &str,
+    message:   &str,
+    zip_level: Option<u32>,
+    platform:  Option<UploadTarget>,
+) -> Result<FileRecord, ApiError> {
+    let io_err = |e: std::io::Error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{}: {e}", path.display()));
+    let file_size = tokio::fs::metadata(path).await.map_err(io_err)?.len();
+    if file_size == 0 {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "File rỗng"));
+    }
+    let chunk = st.cfg.client_chunk_bytes;
+    let total_chunks = file_size.div_ceil(chunk) as usize;
+
+    let session_id = start_session(st, NewUpload {
+        filename:  filename.to_string(),
+        file_size,
+        total_chunks,
+        folder_id: folder_id.to_string(),
+        message:   message.to_string(),
+        platform:  platform.unwrap_or(UploadTarget::default_for(st.tg_enabled)),
+        zip_level,
+        pre_split: false,
+        optimize:  false,
+        metadata:  Default::default(),
+        defer_channel: false,
+    }).await?;
+
+    let fed = async {
+        let mut file = tokio::fs::File::open(path).await.map_err(io_err)?;
+        for idx in 0..total_chunks {
+            let mut buf = vec![0u8; chunk.min(file_size - idx as u64 * chunk) as usize];
+            file.read_exact(&mut buf).await.map_err(io_err)?;
+            feed_chunk(st, &session_id, idx, Bytes::from(buf), true).await?;
+        }
+        Ok::<_, ApiError>(())
+    }.await;
+
+    if let Err(e) = fed {
+        let _ = transition_session(&st.sessions, &session_id, &[STATUS_UPLOADING], STATUS_CANCELLED);
+        if let Some(entry) = st.sender_map.lock().await.remove(&session_id) {
+            entry.handle.abort();
+        }
+        return Err(e);
+    }
+    finalize_session(st, &session_id).await
+}
+
+pub fn new_item(source_path: String, filename: String, file_size: u64, folder_id: String, message: String) -> QueueItem {
+    QueueItem {
+        id: uuid::Uuid::new_v4().simple().to_string()[..12].to_string(),
+        source_path, filename, file_size, folder_id, message,
+        status: QUEUED.to_string(),
+        created_at: current_datetime_iso(),
+        ..Default::default()
+    }
+}
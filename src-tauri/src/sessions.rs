@@ -0,0 +1,82 @@
+/// sessions.rs — Upload sessions held in memory and written out in the background.
+///
+/// Every received chunk updates its session, and re-reading plus rewriting the
+/// sessions file for each one throttled parallel uploads. While the app runs this
+/// table is the source of truth: changes only set a dirty flag and `flush_task`
+/// writes a snapshot every `session_flush_s`. The helpers in upload.rs flush at
+/// once for the state changes that must survive a crash (create, transitions, delete).
+use anyhow::Result;
+use std::{collections::HashMap, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, MutexGuard}, time::Duration};
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::storage::{Store, Transaction, UploadSession};
+
+pub type SessionMap = HashMap<String, UploadSession>;
+
+pub struct SessionTable {
+    store:    Arc<dyn Store>,
+    file:     String,
+    sessions: Mutex<SessionMap>,
+    dirty:    AtomicBool,
+}
+
+impl SessionTable {
+    pub fn load(store: Arc<dyn Store>, file: &str) -> Self {
+        let sessions = store.load_sessions(file);
+        Self { store, file: file.to_string(), sessions: Mutex::new(sessions), dirty: AtomicBool::new(false) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SessionMap> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self, id: &str) -> Option<UploadSession> {
+        self.lock().get(id).cloned()
+    }
+
+    pub fn snapshot(&self) -> SessionMap {
+        self.lock().clone()
+    }
+
+    /// Change the table in memory; the next flush writes it out.
+    /// Runs under the table's lock, so read-check-write in `f` is atomic.
+    pub fn update<R>(&self, f: impl FnOnce(&mut SessionMap) -> R) -> R {
+        let mut sessions = self.lock();
+        let out = f(&mut sessions);
+        self.dirty.store(true, Ordering::Release);
+        out
+    }
+
+    /// Write the table if anything changed since the last flush. Returns whether it wrote.
+    pub fn flush(&self) -> Result<bool> {
+        self.store.locked(&self.file, || {
+            // Cleared before the snapshot: a change racing this flush re-marks it for the next one
+            if !self.dirty.swap(false, Ordering::AcqRel) { return Ok(false); }
+            let sessions = self.snapshot();
+            self.store.save_sessions(&self.file, &sessions).inspect_err(|_| self.dirty.store(true, Ordering::Release))?;
+            Ok(true)
+        })
+    }
+
+    /// Stage the current table in `tx` and commit it, so the sessions land
+    /// together with whatever the caller staged (e.g. the new history record).
+    pub fn commit_with(&self, mut tx: Transaction<'_>) -> Result<()> {
+        self.store.locked(&self.file, || {
+            self.dirty.store(false, Ordering::Release);
+            tx.save_json(&self.file, &self.snapshot())?;
+            tx.commit().inspect_err(|_| self.dirty.store(true, Ordering::Release))
+        })
+    }
+}
+
+/// Write dirty session tables out every `interval`.
+pub async fn flush_task(table: Arc<SessionTable>, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        let t = Arc::clone(&table);
+        if let Ok(Err(e)) = tokio::task::spawn_blocking(move || t.flush()).await {
+            warn!("⚠️ Session flush failed: {e}");
+        }
+    }
+}
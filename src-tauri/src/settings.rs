@@ -0,0 +1,204 @@
+/// settings.rs — Saves from the settings page (config.json and bot.env).
+///
+/// Every save goes through one writer task, so two tabs saving at once can't
+/// interleave their writes. A save is validated before anything is written,
+/// diffed against the files on disk, and written aside then renamed into place.
+/// Most settings are read once at startup and need a restart; the ones in
+/// `LIVE_KEYS` are applied right away.
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::{config::{self, Config, LimitWarning}, logs::SharedLogs};
+
+/// config.json keys that take effect without a restart.
+const LIVE_KEYS: &[&str] = &["server.log_buffer_lines"];
+
+pub struct SaveRequest {
+    pub base_dir: PathBuf,
+    pub env_path: PathBuf,
+    pub config:   Option<Value>,
+    pub env:      Option<Map<String, Value>>,
+    pub logs:     SharedLogs,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SaveReport {
+    /// Dotted config.json keys whose value changed (docs excluded).
+    pub config_changed:   Vec<String>,
+    /// Doc and description ("_"-prefixed) keys that changed; saved, nothing to apply.
+    pub docs_changed:     Vec<String>,
+    /// bot.env keys added, changed or removed; values are never echoed.
+    pub env_changed:      Vec<String>,
+    pub applied:          Vec<String>,
+    pub restart_required: Vec<String>,
+    /// Keys in the saved config.json this build doesn't read.
+    pub unknown:          Vec<String>,
+    /// Values outside their safe range, and what the next start will use.
+    pub warnings:         Vec<LimitWarning>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Invalid(String),
+    Io(String),
+}
+
+type Job = (SaveRequest, oneshot::Sender<Result<SaveReport, SaveError>>);
+
+static WRITER: OnceLock<mpsc::Sender<Job>> = OnceLock::new();
+
+/// Queue a save behind any in progress and wait for its outcome.
+pub async fn save(req: SaveRequest) -> Result<SaveReport, SaveError> {
+    let tx = WRITER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(writer(rx));
+        tx
+    });
+    let (reply, rx) = oneshot::channel();
+    tx.send((req, reply)).await.map_err(|_| SaveError::Io("settings writer stopped".to_string()))?;
+    rx.await.map_err(|_| SaveError::Io("settings writer stopped".to_string()))?
+}
+
+async fn writer(mut rx: mpsc::Receiver<Job>) {
+    while let Some((req, reply)) = rx.recv().await {
+        let res = tokio::task::spawn_blocking(move || apply(req)).await
+            .unwrap_or_else(|e| Err(SaveError::Io(e.to_string())));
+        let _ = reply.send(res);
+    }
+}
+
+fn apply(req: SaveRequest) -> Result<SaveReport, SaveError> {
+    let cfg_path = req.base_dir.join("config.json");
+    let mut report = SaveReport::default();
+
+    // Validate both before writing either
+    let config_text = match &req.config {
+        None      => None,
+        Some(doc) => {
+            let text = serde_json::to_string_pretty(doc).map_err(|e| SaveError::Invalid(format!("config.json: {e}")))?;
+            let schema = config::check_text(&text).map_err(|e| SaveError::Invalid(format!("config.json: {e}")))?;
+            report.unknown = schema.unknown;
+            report.warnings = schema.limits;
+            let current: Value = std::fs::read_to_string(&cfg_path).ok()
+                .and_then(|s| serde_json::from_str(&s).ok()).unwrap_or(Value::Null);
+            let changed = changed_keys(&current, doc);
+            (report.docs_changed, report.config_changed) = changed.into_iter().partition(|k| is_doc(k));
+            Some(text)
+        }
+    };
+    let env_text = match &req.env {
+        None      => None,
+        Some(map) => {
+            let wanted = env_entries(map)?;
+            let current = parse_env(&req.env_path);
+            report.env_changed = wanted.keys().filter(|k| current.get(*k) != wanted.get(*k))
+                .chain(current.keys().filter(|k| !wanted.contains_key(*k)))
+                .cloned().collect();
+            report.env_changed.sort();
+            Some(wanted.iter().map(|(k, v)| format!("{k}={v}\n")).collect::<String>())
+        }
+    };
+
+    if let Some(text) = config_text.filter(|_| !report.config_changed.is_empty() || !report.docs_changed.is_empty()) {
+        write_aside(&cfg_path, &text).map_err(|e| SaveError::Io(format!("config.json: {e}")))?;
+    }
+    if let Some(text) = env_text.filter(|_| !report.env_changed.is_empty()) {
+        write_aside(&req.env_path, &text).map_err(|e| SaveError::Io(format!("bot.env: {e}")))?;
+    }
+
+    let (live, restart): (Vec<String>, Vec<String>) = report.config_changed.iter().cloned()
+        .partition(|k| LIVE_KEYS.contains(&k.as_str()));
+    if !live.is_empty() {
+        let cfg = Config::load(&req.base_dir);
+        req.logs.set_capacity(cfg.log_buffer_lines);
+    }
+    report.applied = live;
+    // bot.env is only read at startup: tokens and ids go into each profile's clients
+    report.restart_required = restart.into_iter().chain(report.env_changed.iter().cloned()).collect();
+    if !report.config_changed.is_empty() || !report.docs_changed.is_empty() || !report.env_changed.is_empty() {
+        info!("⚙️ Settings saved: {} config key(s), {} doc(s), {} env key(s) changed, {} applied live",
+            report.config_changed.len(), report.docs_changed.len(), report.env_changed.len(), report.applied.len());
+    }
+    if !report.unknown.is_empty() {
+        warn!("⚠️ Saved config.json has unknown key(s): {}", report.unknown.join(", "));
+    }
+    for w in &report.warnings {
+        warn!("⚠️ Saved {w}");
+    }
+    Ok(report)
+}
+
+/// KEY=value pairs for bot.env, refusing anything that wouldn't read back the same.
+fn env_entries(map: &Map<String, Value>) -> Result<BTreeMap<String, String>, SaveError> {
+    map.iter().map(|(k, v)| {
+        let key_ok = !k.is_empty() && !k.starts_with(|c: char| c.is_ascii_digit())
+            && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !key_ok { return Err(SaveError::Invalid(format!("bot.env: tên biến không hợp lệ: {k}"))); }
+        let value = match v {
+            Value::String(s) => s.trim().to_string(),
+            Value::Null      => String::new(),
+            other            => other.to_string(),
+        };
+        if value.contains(['\n', '\r']) {
+            return Err(SaveError::Invalid(format!("bot.env: giá trị của {k} không được xuống dòng")));
+        }
+        Ok((k.clone(), value))
+    }).collect()
+}
+
+/// A "_"-prefixed key: a doc or description next to the setting, never read.
+fn is_doc(path: &str) -> bool {
+    path.split('.').any(|k| k.starts_with('_'))
+}
+
+/// Dotted paths whose value differs between two config documents, docs included.
+fn changed_keys(old: &Value, new: &Value) -> Vec<String> {
+    let (mut a, mut b) = (BTreeMap::new(), BTreeMap::new());
+    flatten("", old, &mut a);
+    flatten("", new, &mut b);
+    let mut keys: Vec<String> = b.iter().filter(|(k, v)| a.get(*k) != Some(*v)).map(|(k, _)| k.clone())
+        .chain(a.keys().filter(|k| !b.contains_key(*k)).cloned())
+        .collect();
+    keys.sort();
+    keys
+}
+
+fn flatten<'a>(prefix: &str, v: &'a Value, out: &mut BTreeMap<String, &'a Value>) {
+    match v {
+        // Lists (lifecycle rules) count as one value
+        Value::Object(map) => for (k, v) in map {
+            let path = if prefix.is_empty() { k.clone() } else { format!("{prefix}.{k}") };
+            flatten(&path, v, out);
+        },
+        _ if prefix.is_empty() => {}
+        _ => { out.insert(prefix.to_string(), v); }
+    }
+}
+
+pub fn parse_env(path: &Path) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    if let Ok(s) = std::fs::read_to_string(path) {
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            if let Some((k, v)) = line.split_once('=') {
+                map.insert(k.trim().to_string(), v.trim().to_string());
+            }
+        }
+    }
+    map
+}
+
+/// Write next to `path` and rename over it, so a crash never leaves half a file.
+fn write_aside(path: &Path, text: &str) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, path)
+}
@@ -0,0 +1,237 @@
+/// shares.rs — Public share links and upload inboxes, and their access-control rules.
+use serde::Serialize;
+use std::net::IpAddr;
+
+use crate::storage::{Inbox, Share};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareDenied {
+    Expired,
+    Exhausted,
+    IpNotAllowed,
+}
+
+/// Random, URL-safe share token.
+pub fn new_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Check whether `share` may be used right now by a client at `ip`.
+/// Does not touch the download counter — callers bump it after a successful check.
+pub fn check_access(share: &Share, ip: IpAddr, now: i64) -> Result<(), ShareDenied> {
+    if let Some(exp) = share.expires_at {
+        if now >= exp { return Err(ShareDenied::Expired); }
+    }
+    if let Some(max) = share.max_downloads {
+        if share.download_count >= max { return Err(ShareDenied::Exhausted); }
+    }
+    if !ip_allowed(&share.allowed_ips, ip) {
+        return Err(ShareDenied::IpNotAllowed);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InboxDenied {
+    Expired,
+    Full,
+    TooLarge,
+    TypeNotAllowed,
+}
+
+/// Check whether `filename` (of `size` bytes, if known up front) may be uploaded
+/// to `inbox` now. Like shares, the counter is bumped by the caller.
+pub fn check_inbox(inbox: &Inbox, filename: &str, size: Option<u64>, now: i64) -> Result<(), InboxDenied> {
+    if now >= inbox.expires_at { return Err(InboxDenied::Expired); }
+    if let Some(max) = inbox.max_files {
+        if inbox.upload_count >= max { return Err(InboxDenied::Full); }
+    }
+    if size.is_some_and(|s| s > inbox.max_file_bytes) { return Err(InboxDenied::TooLarge); }
+    if !type_allowed(&inbox.allowed_types, filename) { return Err(InboxDenied::TypeNotAllowed); }
+    Ok(())
+}
+
+/// An empty list means any type; entries are extensions with or without the dot.
+pub fn type_allowed(types: &[String], filename: &str) -> bool {
+    let ext = std::path::Path::new(filename).extension()
+        .and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    types.is_empty() || types.iter().any(|t| t.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+}
+
+// ── Stale links ────────────────────────────────────────────────────────────────
+
+/// `active`, `expired`, `exhausted` (shares) / `full` (inboxes), or `orphaned`
+/// when the file or folder behind the link is gone.
+pub fn share_status(share: &Share, now: i64, file_exists: bool) -> &'static str {
+    if !file_exists { return "orphaned"; }
+    if share.expires_at.is_some_and(|e| now >= e) { return "expired"; }
+    if share.max_downloads.is_some_and(|m| share.download_count >= m) { return "exhausted"; }
+    "active"
+}
+
+pub fn inbox_status(inbox: &Inbox, now: i64, folder_exists: bool) -> &'static str {
+    if !folder_exists { return "orphaned"; }
+    if now >= inbox.expires_at { return "expired"; }
+    if inbox.max_files.is_some_and(|m| inbox.upload_count >= m) { return "full"; }
+    "active"
+}
+
+/// Whether a link with `status` matches a `?status=` filter; `stale` is anything not active.
+pub fn status_matches(filter: Option<&str>, status: &str) -> bool {
+    match filter {
+        None | Some("") | Some("all") => true,
+        Some("stale") => status != "active",
+        Some(f)       => f == status,
+    }
+}
+
+/// When a dead link becomes eligible for purging; None while it still works.
+pub fn purge_after(status: &str, expires_at: Option<i64>, ended_at: Option<i64>, grace_s: i64) -> Option<i64> {
+    match status {
+        "active"  => None,
+        "expired" => expires_at.map(|e| e + grace_s),
+        // Not stamped yet: the first purge pass starts the grace period
+        _         => ended_at.map(|e| e + grace_s),
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PurgeReport {
+    pub shares:  usize,               // removed
+    pub inboxes: usize,
+    pub pending: usize,               // dead but still inside the grace period
+}
+
+/// Drop links that have been dead for longer than `grace_s`, stamping `ended_at`
+/// on ones that just died.
+pub fn purge(
+    shares:        &mut Vec<Share>,
+    inboxes:       &mut Vec<Inbox>,
+    file_exists:   &dyn Fn(i64) -> bool,
+    folder_exists: &dyn Fn(&str) -> bool,
+    now:           i64,
+    grace_s:       i64,
+) -> PurgeReport {
+    let mut report = PurgeReport::default();
+    shares.retain_mut(|s| {
+        let status = share_status(s, now, file_exists(s.file_id));
+        if status != "active" && status != "expired" && s.ended_at.is_none() { s.ended_at = Some(now); }
+        match purge_after(status, s.expires_at, s.ended_at, grace_s) {
+            Some(at) if now >= at => { report.shares += 1; false }
+            Some(_)               => { report.pending += 1; true }
+            None                  => true,
+        }
+    });
+    inboxes.retain_mut(|i| {
+        let status = inbox_status(i, now, i.folder_id.is_empty() || folder_exists(&i.folder_id));
+        if status != "active" && status != "expired" && i.ended_at.is_none() { i.ended_at = Some(now); }
+        match purge_after(status, Some(i.expires_at), i.ended_at, grace_s) {
+            Some(at) if now >= at => { report.inboxes += 1; false }
+            Some(_)               => { report.pending += 1; true }
+            None                  => true,
+        }
+    });
+    report
+}
+
+/// An empty allowlist means "anyone". Entries are plain IPs or CIDRs (`10.0.0.0/8`).
+pub fn ip_allowed(rules: &[String], ip: IpAddr) -> bool {
+    rules.is_empty() || rules.iter().any(|r| rule_matches(r.trim(), ip))
+}
+
+/// Validate an allowlist entry so bad rules are rejected at creation time.
+pub fn is_valid_rule(rule: &str) -> bool {
+    parse_rule(rule.trim()).is_some()
+}
+
+fn rule_matches(rule: &str, ip: IpAddr) -> bool {
+    let Some((net, prefix)) = parse_rule(rule) else { return false };
+    // Compare IPv4-mapped IPv6 clients against IPv4 rules
+    let ip = match (net, ip) {
+        (IpAddr::V4(_), IpAddr::V6(v6)) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        _ => ip,
+    };
+    match (net, ip) {
+        (IpAddr::V4(n), IpAddr::V4(a)) => prefix_eq(&n.octets(), &a.octets(), prefix),
+        (IpAddr::V6(n), IpAddr::V6(a)) => prefix_eq(&n.octets(), &a.octets(), prefix),
+        _ => false,
+    }
+}
+
+fn parse_rule(rule: &str) -> Option<(IpAddr, u32)> {
+    let (addr, prefix) = match rule.split_once('/') {
+        Some((a, p)) => (a, Some(p)),
+        None         => (rule, None),
+    };
+    let net: IpAddr = addr.parse().ok()?;
+    let max = if net.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(p) => p.parse::<u32>().ok().filter(|p| *p <= max)?,
+        None    => max,
+    };
+    Some((net, prefix))
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u32) -> bool {
+    let full  = (prefix / 8) as usize;
+    let rest  = prefix % 8;
+    if a[..full] != b[..full] { return false; }
+    if rest == 0 { return true; }
+    let mask = 0xffu8 << (8 - rest);
+    (a[full] & mask) == (b[full] & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(rules: &[&str], ip: &str) -> bool {
+        let rules: Vec<String> = rules.iter().map(|r| r.to_string()).collect();
+        ip_allowed(&rules, ip.parse().unwrap())
+    }
+
+    #[test]
+    fn empty_allowlist_lets_anyone_in() {
+        assert!(allowed(&[], "203.0.113.7"));
+        assert!(allowed(&[], "2001:db8::1"));
+    }
+
+    #[test]
+    fn plain_ip_matches_only_itself() {
+        assert!(allowed(&["192.168.1.5"], "192.168.1.5"));
+        assert!(!allowed(&["192.168.1.5"], "192.168.1.6"));
+        assert!(allowed(&[" 192.168.1.5 "], "192.168.1.5"));
+    }
+
+    #[test]
+    fn cidr_prefixes_match_on_bit_boundaries() {
+        assert!(allowed(&["10.0.0.0/8"], "10.255.0.1"));
+        assert!(!allowed(&["10.0.0.0/8"], "11.0.0.1"));
+        assert!(allowed(&["172.16.0.0/12"], "172.31.255.254"));
+        assert!(!allowed(&["172.16.0.0/12"], "172.32.0.1"));
+        assert!(allowed(&["0.0.0.0/0"], "198.51.100.1"));
+        assert!(allowed(&["2001:db8::/32"], "2001:db8:ffff::1"));
+        assert!(!allowed(&["2001:db8::/32"], "2001:db9::1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_clients_match_ipv4_rules() {
+        assert!(allowed(&["10.0.0.0/8"], "::ffff:10.1.2.3"));
+        assert!(!allowed(&["10.0.0.0/8"], "::ffff:192.168.0.1"));
+        assert!(allowed(&["127.0.0.1"], "::ffff:127.0.0.1"));
+        // A plain IPv6 client never matches an IPv4 rule
+        assert!(!allowed(&["0.0.0.0/0"], "2001:db8::1"));
+    }
+
+    #[test]
+    fn bad_rules_are_rejected_and_match_nothing() {
+        for rule in ["10.0.0.0/33", "::/129", "10.0.0.0/", "not-an-ip", "10.0.0.0/-1"] {
+            assert!(!is_valid_rule(rule), "{rule}");
+            assert!(!allowed(&[rule], "10.0.0.1"), "{rule}");
+        }
+        assert!(is_valid_rule(" 10.0.0.0/8 "));
+        assert!(is_valid_rule("::1"));
+        assert_eq!(parse_rule("192.168.0.0/16"), Some(("192.168.0.0".parse().unwrap(), 16)));
+        assert_eq!(parse_rule("::1"), Some(("::1".parse().unwrap(), 128)));
+    }
+}
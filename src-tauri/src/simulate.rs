@@ -0,0 +1,160 @@
+/// simulate.rs — Local-disk stand-ins for Discord and Telegram (`--simulate`).
+///
+/// With `simulate.enabled` (or the `--simulate` flag) no bot logs in. Parts are
+/// still zipped and checked against the same size limits, then "sent" after
+/// `latency_ms` ± `jitter_ms`, failed at `error_rate` (retried like real sends) and
+/// written to `simulate.dir`; downloads read them back. Chunk sizes, RAM use,
+/// failure reports and resume can be tried on a real machine without either API.
+use anyhow::{anyhow, Context, Result};
+use serenity::model::id::{ChannelId, GuildId};
+use std::{path::PathBuf, sync::atomic::{AtomicI64, Ordering}, time::Duration};
+use tracing::warn;
+
+use crate::{config::Config, storage::{current_timestamp_ms, PartInfo, PartTiming}};
+
+/// Stands in for DISCORD_GUILD_ID when no bot is connected.
+pub const GUILD_ID: u64 = 1;
+const FILE_ID_PREFIX: &str = "sim-";
+
+/// Message/channel ids: unique per run and increasing, like snowflakes.
+static NEXT_ID: AtomicI64 = AtomicI64::new(0);
+
+fn next_id() -> i64 {
+    let now = current_timestamp_ms() << 12;
+    let mut prev = NEXT_ID.load(Ordering::SeqCst);
+    loop {
+        let id = now.max(prev + 1);
+        match NEXT_ID.compare_exchange(prev, id, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_)  => return id,
+            Err(p) => prev = p,
+        }
+    }
+}
+
+pub fn guild_id() -> GuildId { GuildId::new(GUILD_ID) }
+
+/// A fresh "channel" for an upload: (id, name).
+pub fn channel(filename: &str) -> (ChannelId, String) {
+    let name: String = filename.to_lowercase().chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .take(90).collect();
+    (ChannelId::new(next_id() as u64), format!("sim-{name}"))
+}
+
+pub fn file_id(message_id: i64) -> String { format!("{FILE_ID_PREFIX}{message_id}") }
+
+pub fn jump_url(channel_id: ChannelId, message_id: i64) -> String {
+    format!("sim://{GUILD_ID}/{}/{message_id}", channel_id.get())
+}
+
+fn path(cfg: &Config, platform: &str, message_id: i64, attachment: Option<u32>) -> PathBuf {
+    match attachment {
+        None    => cfg.sim_dir.join(format!("{platform}-{message_id}.zip")),
+        Some(i) => cfg.sim_dir.join(format!("{platform}-{message_id}-{i}.zip")),
+    }
+}
+
+fn part_path(cfg: &Config, info: &PartInfo) -> Result<PathBuf> {
+    let id = if info.platform == "telegram" {
+        info.file_id.as_deref().and_then(|f| f.strip_prefix(FILE_ID_PREFIX))
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| anyhow!("Telegram part {} was not stored by the simulator", info.part))?
+    } else {
+        info.message_id
+    };
+    Ok(path(cfg, &info.platform, id, info.attachment))
+}
+
+/// Configured latency ± jitter, using the uuid generator as the random source.
+async fn delay(cfg: &Config) {
+    let jitter = if cfg.sim_jitter_ms == 0 { 0 } else {
+        (uuid::Uuid::new_v4().as_u128() % (2 * cfg.sim_jitter_ms as u128 + 1)) as i64 - cfg.sim_jitter_ms as i64
+    };
+    let ms = (cfg.sim_latency_ms as i64 + jitter).max(0) as u64;
+    if ms > 0 { tokio::time::sleep(Duration::from_millis(ms)).await; }
+}
+
+fn roll_error(cfg: &Config) -> bool {
+    cfg.sim_error_rate > 0.0 && (uuid::Uuid::new_v4().as_u128() % 10_000) as f64 / 10_000.0 < cfg.sim_error_rate
+}
+
+/// Store one zipped part as `platform`, with the same retry policy as real sends.
+/// Returns the message id.
+pub async fn send_part(cfg: &Config, platform: &str, zip: &[u8], part_num: u32, timing: &mut PartTiming) -> Result<i64> {
+    send(cfg, platform, &[zip], false, part_num, timing).await
+}
+
+/// Store several zipped parts as the attachments of one Discord message (bundle mode).
+pub async fn send_bundle(cfg: &Config, zips: &[&[u8]], first_part: u32, timing: &mut PartTiming) -> Result<i64> {
+    send(cfg, "discord", zips, true, first_part, timing).await
+}
+
+async fn send(cfg: &Config, platform: &str, zips: &[&[u8]], bundled: bool, part_num: u32, timing: &mut PartTiming) -> Result<i64> {
+    let send_start = std::time::Instant::now();
+    let mut last_err = None;
+    for attempt in 0..cfg.discord_send_retries {
+        timing.retries = attempt;
+        delay(cfg).await;
+        let res = if roll_error(cfg) {
+            Err(anyhow!("simulated {platform} error"))
+        } else {
+            let id = next_id();
+            async {
+                tokio::fs::create_dir_all(&cfg.sim_dir).await?;
+                for (i, zip) in zips.iter().enumerate() {
+                    let path = path(cfg, platform, id, bundled.then_some(i as u32));
+                    tokio::fs::write(&path, zip).await.with_context(|| format!("write {}", path.display()))?;
+                }
+                anyhow::Ok(id)
+            }.await
+        };
+        match res {
+            Ok(id) => {
+                timing.send_ms = send_start.elapsed().as_millis() as u64;
+                return Ok(id);
+            }
+            Err(e) => {
+                if attempt < cfg.discord_send_retries - 1 {
+                    warn!("  ⚠️ [sim] part {part_num} retry {}/{}: {e}", attempt + 1, cfg.discord_send_retries);
+                    tokio::time::sleep(Duration::from_secs(cfg.discord_retry_base_s.pow(attempt))).await;
+                }
+                last_err = Some(e);
+            }
+        }
+    }
+    timing.send_ms = send_start.elapsed().as_millis() as u64;
+    Err(last_err.unwrap_or_else(|| anyhow!("simulated {platform} send failed")))
+}
+
+/// The stored (zipped) bytes of a part, with the same retry policy as real downloads.
+pub async fn fetch_part(cfg: &Config, info: &PartInfo) -> Result<Vec<u8>> {
+    let path = part_path(cfg, info)?;
+    let mut last_err = None;
+    for attempt in 0..cfg.download_retry {
+        delay(cfg).await;
+        if roll_error(cfg) {
+            last_err = Some(anyhow!("simulated {} download error", info.platform));
+        } else {
+            // A missing file is a deleted message: retrying won't bring it back
+            return tokio::fs::read(&path).await.with_context(|| format!("read {}", path.display()));
+        }
+        if attempt < cfg.download_retry - 1 {
+            warn!("  ⚠️ [sim] part {} download retry {}/{}", info.part, attempt + 1, cfg.download_retry);
+            tokio::time::sleep(Duration::from_secs(cfg.download_retry_base_s.pow(attempt))).await;
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("simulated download failed")))
+}
+
+pub async fn part_size(cfg: &Config, info: &PartInfo) -> Result<u64> {
+    let path = part_path(cfg, info)?;
+    Ok(tokio::fs::metadata(&path).await.with_context(|| format!("stat {}", path.display()))?.len())
+}
+
+pub async fn delete_part(cfg: &Config, info: &PartInfo) -> Result<()> {
+    let path = part_path(cfg, info)?;
+    match tokio::fs::remove_file(&path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).with_context(|| format!("remove {}", path.display())),
+        _ => Ok(()),
+    }
+}
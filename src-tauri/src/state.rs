@@ -1,24 +1,56 @@
 /// state.rs — Shared application state passed to every Axum handler.
 use serenity::http::Http;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::path::PathBuf;
+use tokio::sync::{Mutex, Notify};
 
 use crate::{
     config::Config,
-    storage::JsonStore,
-    upload::SenderMap,
+    dedup::Claims,
+    discord_bot::HttpPool,
+    disk_jobs::DiskJobs,
+    file_locks::FileLocks,
+    keyring::Keyring,
+    logs::SharedLogs,
+    offline::Connectivity,
+    preview_tokens::PreviewSigner,
+    sessions::SessionTable,
+    storage::Store,
+    upload::{SendGates, SenderMap},
 };
 
 #[derive(Clone)]
 pub struct AppState {
     pub cfg:           Arc<Config>,
-    pub store:         Arc<JsonStore>,
+    pub store:         Arc<dyn Store>,
+    pub sessions:      Arc<SessionTable>,  // upload sessions; in memory, flushed every session_flush_s
     pub http:          Arc<Http>,          // Discord HTTP client (from serenity)
+    pub send_pool:     Arc<HttpPool>,      // primary + extra bots, rotated for part uploads
+    pub send_gates:    Arc<SendGates>,     // per-platform send caps and pacing, shared by all uploads
     pub guild_id:      serenity::model::id::GuildId,
     pub tg_enabled:    bool,
     pub tg_token:      String,
     pub tg_chat_id:    String,
+    pub tg_topic_id:   Option<i64>,       // TELEGRAM_TOPIC_ID: default forum topic for parts
     pub sender_map:    SenderMap,
     pub base_dir:      PathBuf,
     pub thumbnail_dir: PathBuf,
+    pub part_cache_dir: PathBuf,          // parts fetched ahead by /prefetch
+    pub lifecycle_lock: Arc<Mutex<()>>,    // one lifecycle run at a time
+    pub thumb_inflight: ThumbInflight,
+    pub file_locks:    Arc<FileLocks>,     // downloads read-lock, deletes write-lock
+    pub disk_jobs:     Arc<DiskJobs>,      // download_to_disk (IPC) merges into local paths
+    pub profile:       String,             // profile name ("default" unless configured)
+    pub env_path:      PathBuf,            // this profile's bot.env
+    pub queue_notify:  Arc<Notify>,        // wakes the upload queue worker
+    pub logs:          SharedLogs,         // recent log lines (shared by all profiles)
+    pub preview_signer: Arc<PreviewSigner>, // signs ?token= for media tags
+    pub manifest_key:  Arc<Vec<u8>>,       // HMAC key for exported/imported .ddrive manifests
+    pub keyring:       Arc<Keyring>,       // wraps per-file data keys; unlocked via /api/keys/unlock
+    pub connectivity:  Arc<Connectivity>,  // gateway up or down; offline.rs holds Discord work back
+    pub part_claims:   Arc<Claims>,        // stored messages uploads in progress reuse; deletes leave them
 }
+
+/// Per-file gates so only one thumbnail generation runs for a given id at a time.
+pub type ThumbInflight = Arc<Mutex<HashMap<i64, Arc<Mutex<()>>>>>;
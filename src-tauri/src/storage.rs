@@ -1,9 +1,9 @@
-/// storage.rs — JSON persistence helpers.
-use anyhow::{Context, Result};
+/// storage.rs — Persistence: the Store trait and its JSON-file and in-memory backends.
+use anyhow::{anyhow, Context, Result};
 use chrono::{Local, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, fs, io::Write, path::{Path, PathBuf}, sync::{mpsc, Arc, Mutex, OnceLock, RwLock}};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Folder {
@@ -11,9 +11,42 @@ pub struct Folder {
     pub name:                String,
     pub discord_category_id: i64,
     pub created_at:          String,
+    /// Extra `<folder>-2`, `-3`… categories created once the primary one is full.
+    #[serde(default)]
+    pub overflow_category_ids: Vec<i64>,
+    /// Retention rules scoped to this folder, on top of the ones in config.json.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lifecycle: Vec<LifecycleRule>,
+    /// Telegram forum topic mirroring this folder (`telegram.folder_topics`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tg_topic_id: Option<i64>,
+    /// Enclosing folder; None at the top level (see folder_tree.rs).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<i64>,
+    /// Names from the top level down, "Work/photos/2024".
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Folder {
+    /// What the primary category is called. Categories are flat, so a subfolder's
+    /// carries its whole path.
+    pub fn category_name(&self) -> String {
+        match self.parent_id {
+            Some(_) if !self.path.is_empty() => self.path.replace('/', " "),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Primary category first, then overflow categories in creation order.
+    pub fn category_ids(&self) -> Vec<i64> {
+        std::iter::once(self.discord_category_id)
+            .chain(self.overflow_category_ids.iter().copied())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PartInfo {
     pub part:       u32,
     pub platform:   String,
@@ -21,9 +54,33 @@ pub struct PartInfo {
     pub channel_id: Option<String>,
     pub file_id:    Option<String>,
     pub jump_url:   Option<String>,
+    /// Bytes actually stored on the platform (zipped part). 0 = unknown (legacy).
+    #[serde(default)]
+    pub stored_bytes: u64,
+    /// Bytes of the part once downloaded and decoded (unzipped, decrypted): its
+    /// length in the merged file. 0 = unknown (legacy).
+    #[serde(default)]
+    pub raw_bytes:    u64,
+    /// Telegram forum topic the part was posted in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic_id:     Option<i64>,
+    /// Position among the message's attachments when several parts share one
+    /// Discord message (`upload.bundle_parts`). None = the message's only attachment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment:   Option<u32>,
+    /// BLAKE3 (hex) of the part's raw bytes, before zipping. None = legacy part.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3:       Option<String>,
+    /// The zipped bytes are AES-256-GCM sealed with `encryption.key`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted:    bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl PartInfo {
+    pub fn attachment_index(&self) -> usize { self.attachment.unwrap_or(0) as usize }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FileRecord {
     pub id:           i64,
     pub filename:     String,
@@ -40,6 +97,127 @@ pub struct FileRecord {
     pub message_ids:  Vec<i64>,
     pub jump_url:     Option<String>,
     pub sent_at:      String,
+    /// Sum of `PartInfo::stored_bytes`. 0 = unknown (legacy record).
+    #[serde(default)]
+    pub stored_bytes: u64,
+    /// Exact original size as counted by the sender. 0 = unknown (legacy record).
+    #[serde(default)]
+    pub size_bytes:   u64,
+    /// ZIP level the parts were written with. None = unknown (legacy record).
+    #[serde(default)]
+    pub zip_level:    Option<u32>,
+    /// User labels, sorted and unique.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags:         Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description:  Option<String>,
+    /// Unix seconds; past it the file is no longer served (`is_expired`) and the
+    /// lifecycle job moves it to the trash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at:   Option<i64>,
+    /// Set when the image was recompressed before upload; the parts hold the
+    /// optimized bytes and `size_bytes` is their size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optimized:    Option<ImageOptimization>,
+    /// What the uploading client said about itself (device, app version, original
+    /// path...), copied from the session.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata:     BTreeMap<String, String>,
+    /// Perceptual hash of the thumbnail (phash.rs), 16 hex digits. Kept apart from
+    /// `metadata`, which the client writes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phash:        Option<String>,
+    /// BLAKE3 (hex) of the whole file as stored, i.e. the concatenated raw parts.
+    /// None = legacy record, or finished through a part retry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3:       Option<String>,
+    /// The file's own data key, wrapped by the keyring (keyring.rs). None = the parts
+    /// are in clear or sealed with the static `encryption.key`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key:  Option<String>,
+    /// Every part is kept in the part cache and never evicted (`POST /api/files/:id/pin`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned:       bool,
+    /// Came from a .ddrive manifest: the messages belong to the drive that exported
+    /// it, so deleting the record only forgets it here.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub imported:     bool,
+}
+
+/// What upload-time optimization did to an image.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageOptimization {
+    pub format:          String,      // "jpeg" | "png"
+    pub original_bytes:  u64,
+    pub optimized_bytes: u64,
+    /// JPEG quality used; None for lossless PNG.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality:         Option<u8>,
+}
+
+impl FileRecord {
+    /// Any part is sealed, with the static key or the file's own.
+    pub fn is_encrypted(&self) -> bool {
+        self.wrapped_key.is_some() || self.parts_info.iter().any(|p| p.encrypted)
+    }
+
+    /// Past its own `expires_at`, whether or not the lifecycle job has trashed it yet.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= Utc::now().timestamp())
+    }
+
+    /// Physical footprint on Discord/Telegram; legacy records count at their logical size.
+    pub fn footprint_bytes(&self) -> u64 {
+        if self.stored_bytes > 0 { self.stored_bytes } else { (self.size_mb * 1024.0 * 1024.0) as u64 }
+    }
+
+    /// Exact byte size: as counted by the sender, or else the sum of the parts'
+    /// `raw_bytes` when every part has one (and its copies agree).
+    pub fn exact_size(&self) -> Option<u64> {
+        if self.size_bytes > 0 { return Some(self.size_bytes); }
+        let mut sizes: BTreeMap<u32, u64> = BTreeMap::new();
+        for p in &self.parts_info {
+            if p.raw_bytes == 0 || sizes.insert(p.part, p.raw_bytes).is_some_and(|s| s != p.raw_bytes) {
+                return None;
+            }
+        }
+        (!sizes.is_empty() && sizes.len() == self.parts as usize).then(|| sizes.values().sum())
+    }
+
+    /// `folder_id` is stored as a string by uploads but may be a number in older records.
+    pub fn folder_key(&self) -> Option<String> {
+        self.folder_id.as_ref().map(|v| v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string()))
+    }
+
+    pub fn in_folder(&self, folder_id: &str) -> bool {
+        self.folder_key().is_some_and(|k| k == folder_id)
+    }
+}
+
+/// Totals over a set of records, shared by /api/stats and the `/drive` slash command.
+#[derive(Debug, Clone, Default)]
+pub struct UsageSummary {
+    pub files:        usize,
+    pub total_mb:     f64,
+    pub stored_bytes: u64,
+    pub unmeasured:   usize,   // records without stored_bytes
+}
+
+impl UsageSummary {
+    pub fn of<'a>(records: impl IntoIterator<Item = &'a FileRecord>) -> Self {
+        let mut u = Self::default();
+        for f in records { u.add(f); }
+        u
+    }
+
+    pub fn add(&mut self, f: &FileRecord) {
+        self.files        += 1;
+        self.total_mb     += f.size_mb;
+        self.stored_bytes += f.footprint_bytes();
+        if f.stored_bytes == 0 { self.unmeasured += 1; }
+    }
+
+    pub fn stored_mb(&self) -> f64 { self.stored_bytes as f64 / 1024.0 / 1024.0 }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,47 +235,1036 @@ pub struct UploadSession {
     pub channel_name:    Option<String>,
     pub folder_name:     Option<String>,
     pub discord_result:  Option<Value>,
+    /// Last chunk or keepalive (RFC 3339); GC expires sessions idle past the TTL.
+    #[serde(default)]
+    pub last_activity:   Option<String>,
+    /// Issued by init_upload; chunk/complete/cancel/keepalive must echo it in `X-Upload-Secret`.
+    /// Empty for sessions created before secrets existed (or internally), which skip the check.
+    #[serde(default)]
+    pub secret:          String,
+    /// ZIP level the sender uses for this upload's parts.
+    #[serde(default)]
+    pub zip_level:       u32,
+    /// Client-split upload: each chunk is sent as one part, up to `max_part_bytes`.
+    #[serde(default)]
+    pub pre_split:       bool,
+    #[serde(default)]
+    pub max_part_bytes:  u64,
+    /// Recompress the image once all chunks are in (see optimize.rs).
+    #[serde(default)]
+    pub optimize_images: bool,
+    /// Client metadata from init_upload, handed on to the FileRecord.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata:        BTreeMap<String, String>,
+    /// The upload's data key, wrapped by the keyring; handed on to the FileRecord.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key:     Option<String>,
+}
+
+impl UploadSession {
+    /// Seconds since the last chunk/keepalive, falling back to creation time for old sessions.
+    pub fn idle_secs(&self, now: i64) -> Option<u64> {
+        let at = self.last_activity.as_deref().unwrap_or(&self.created_at);
+        chrono::DateTime::parse_from_rfc3339(at).ok()
+            .map(|t| now.saturating_sub(t.timestamp()).max(0) as u64)
+    }
+}
+
+/// Per-part send timings, kept after the session is gone for troubleshooting.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PartTiming {
+    pub part:          u32,
+    pub platform:      String,
+    pub bytes:         u64,
+    pub queue_wait_ms: u64,   // waiting for a send permit
+    pub zip_ms:        u64,
+    pub send_ms:       u64,   // all attempts combined
+    pub retries:       u32,
+    pub error:         Option<String>,
+    /// Stored before (same BLAKE3): the part points at that message, nothing was sent.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reused:        bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UploadDiagnostics {
+    pub session_id:  String,
+    pub filename:    String,
+    pub started_at:  String,
+    pub finished_at: Option<String>,
+    pub outcome:     String,   // "running" | "ok" | "error"
+    pub error:       Option<String>,
+    pub parts:       Vec<PartTiming>,
+    #[serde(default)]
+    pub degraded:    Vec<String>,  // platforms abandoned mid-upload after hitting the failure budget
+    #[serde(default)]
+    pub transport:   ChunkTransport,
+}
+
+/// How the chunk PUTs reached the server. Fewer connections than chunks means
+/// keep-alive (HTTP/1.1) or multiplexing (HTTP/2) is doing its job.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChunkTransport {
+    pub versions:    BTreeMap<String, u32>,  // "HTTP/1.1" | "HTTP/2.0" → chunk requests
+    pub connections: BTreeSet<String>,       // client ip:port, one per TCP connection
+}
+
+/// A server-side upload waiting for (or undergoing) ingest. `source_path` is the
+/// on-disk copy the worker reads from, so the item survives restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub id:          String,
+    pub source_path: String,
+    pub filename:    String,
+    pub file_size:   u64,
+    #[serde(default)]
+    pub folder_id:   String,
+    #[serde(default)]
+    pub message:     String,
+    pub status:      String,   // "queued" | "queued_offline" | "running" | "verifying" | "done" | "failed"
+    pub created_at:  String,
+    #[serde(default)]
+    pub error:       Option<String>,
+    #[serde(default)]
+    pub record_id:   Option<i64>,
+    /// Source is a temp file owned by the queue (e.g. an inbox upload): removed once ingested.
+    #[serde(default)]
+    pub delete_source: bool,
+    /// Delete/move the source only after every stored copy byte-matches it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_verify:  Option<SourceAction>,
+    /// Outcome of that check; None = not requested or not run yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified:      Option<bool>,
+}
+
+/// What to do with a queued file's source once the upload is verified.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum SourceAction {
+    Delete,
+    /// Move into this directory, keeping the file name.
+    Move { to: String },
+}
+
+/// One line of the append-only audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub ts:      String,          // RFC 3339
+    pub action:  String,          // "uploaded" | "renamed" | "moved" | "shared" | "downloaded" | "deleted" ...
+    #[serde(default)]
+    pub file_id: Option<i64>,
+    #[serde(default)]
+    pub detail:  Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Share {
+    pub token:          String,
+    pub file_id:        i64,
+    pub created_at:     String,
+    #[serde(default)]
+    pub expires_at:     Option<i64>,      // unix seconds, None = never
+    #[serde(default)]
+    pub max_downloads:  Option<u32>,      // None = unlimited, 1 = one-time link
+    #[serde(default)]
+    pub download_count: u32,
+    #[serde(default)]
+    pub allowed_ips:    Vec<String>,      // IPs or CIDRs, empty = anyone
+    /// When it stopped working for a reason other than expiry (unix seconds).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at:       Option<i64>,
+}
+
+/// Public upload link ("inbox"): anyone with the token may upload into `folder_id`
+/// until it expires or `max_files` uploads have been accepted.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Inbox {
+    pub token:          String,
+    #[serde(default)]
+    pub folder_id:      String,           // "" = root
+    #[serde(default)]
+    pub label:          String,
+    pub created_at:     String,
+    pub expires_at:     i64,              // unix seconds; inboxes always expire
+    pub max_file_bytes: u64,
+    #[serde(default)]
+    pub allowed_types:  Vec<String>,      // lowercase extensions, empty = any
+    #[serde(default)]
+    pub max_files:      Option<u32>,      // None = unlimited
+    #[serde(default)]
+    pub upload_count:   u32,
+    /// When it filled up or lost its folder (unix seconds).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at:       Option<i64>,
+}
+
+/// Auto-foldering: uploads without a folder whose name matches `pattern` go to `folder_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderRule {
+    pub id:         String,
+    pub pattern:    String,           // case-insensitive glob, e.g. "IMG_*.jpg"
+    pub folder_id:  String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleAction {
+    /// Move the record to the trash list (parts are kept).
+    Trash,
+    /// Re-upload the parts at `zip_level` and drop the old ones.
+    Recompress,
+}
+
+/// Retention rule: files matching every condition get `action`. A rule with no
+/// age/access condition never matches.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleRule {
+    pub name:            String,
+    /// Folder id or name; None = every file (ignored for per-folder rules).
+    #[serde(default)]
+    pub folder:          Option<String>,
+    #[serde(default)]
+    pub older_than_days: Option<u32>,
+    /// Days since the last download/preview (upload time if never opened).
+    #[serde(default)]
+    pub unaccessed_days: Option<u32>,
+    pub action:          LifecycleAction,
+    /// Target level for `recompress` (default 9).
+    #[serde(default)]
+    pub zip_level:       Option<u32>,
+}
+
+/// A record removed from history but kept for restore.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub record:     FileRecord,
+    pub trashed_at: i64,              // unix seconds
+    pub reason:     String,           // "deleted" or "lifecycle:<rule>"
+    /// Purging also deletes the file's channel.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub delete_channel: bool,
+}
+
+// ── Store trait ────────────────────────────────────────────────────────────────
+
+/// One mutex per document name, created on first use.
+#[derive(Default)]
+pub struct DocLocks(Mutex<HashMap<String, Arc<Mutex<()>>>>);
+
+impl DocLocks {
+    pub fn get(&self, file: &str) -> Arc<Mutex<()>> {
+        let mut map = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(map.entry(file.to_string()).or_default())
+    }
+}
+
+/// Persistence behind `AppState::store` (`data.backend` in config.json). A backend
+/// provides whole documents, the file history and append-only logs; the typed
+/// accessors default to one JSON document per file, so a backend only overrides
+/// what it keeps natively (e.g. sessions as rows).
+pub trait Store: Send + Sync {
+    /// Profile data dir. Scratch files (inbox downloads, extraction) go here for every backend.
+    fn base_dir(&self) -> &Path;
+
+    /// The lock behind `locked` for `file`.
+    fn doc_lock(&self, file: &str) -> Arc<Mutex<()>>;
+
+    /// A whole document; Null when it doesn't exist.
+    fn load_doc(&self, file: &str) -> Value;
+    fn save_doc(&self, file: &str, value: Value) -> Result<()>;
+
+    fn load_history(&self, file: &str) -> Vec<FileRecord>;
+    fn save_history(&self, file: &str, records: &[FileRecord]) -> Result<()>;
+
+    fn find_record(&self, file: &str, id: i64) -> Option<FileRecord> {
+        self.load_history(file).into_iter().find(|r| r.id == id)
+    }
+
+    /// Usage per folder id (`""` = root).
+    fn folder_usage(&self, file: &str) -> HashMap<String, UsageSummary> {
+        usage_by_folder(&self.load_history(file))
+    }
+
+    /// Save a transaction's history and documents as one: after a crash either all
+    /// of them are there or none.
+    fn commit_batch(&self, history: Option<(String, Vec<FileRecord>)>, docs: Vec<(String, Value)>) -> Result<()>;
+
+    /// JSONL logs: lines are appended and read back as raw text.
+    fn append_lines(&self, file: &str, lines: &[String]) -> Result<()>;
+    fn rewrite_lines(&self, file: &str, lines: &[String]) -> Result<()>;
+    fn load_lines(&self, file: &str) -> Vec<String>;
+
+    fn load_folders(&self, file: &str) -> Vec<Folder> { from_doc(file, self.load_doc(file)) }
+    fn save_folders(&self, file: &str, folders: &[Folder]) -> Result<()> {
+        self.save_doc(file, serde_json::to_value(folders)?)
+    }
+
+    fn load_sessions(&self, file: &str) -> HashMap<String, UploadSession> { from_doc(file, self.load_doc(file)) }
+    fn save_sessions(&self, file: &str, sessions: &HashMap<String, UploadSession>) -> Result<()> {
+        self.save_doc(file, serde_json::to_value(sessions)?)
+    }
+
+    fn get_session(&self, file: &str, id: &str) -> Option<UploadSession> {
+        self.load_sessions(file).remove(id)
+    }
+
+    /// Insert or replace one session. Callers serialize their own read-modify-write.
+    fn put_session(&self, file: &str, session: &UploadSession) -> Result<()> {
+        let mut sessions = self.load_sessions(file);
+        sessions.insert(session.session_id.clone(), session.clone());
+        self.save_sessions(file, &sessions)
+    }
+
+    fn remove_session(&self, file: &str, id: &str) -> Result<Option<UploadSession>> {
+        let mut sessions = self.load_sessions(file);
+        let removed = sessions.remove(id);
+        if removed.is_some() { self.save_sessions(file, &sessions)?; }
+        Ok(removed)
+    }
+
+    /// Apply saves left over from a crash. Call before anything reads the store.
+    fn replay_journal(&self) -> Result<JournalReport> { Ok(JournalReport::default()) }
+    /// Write out whatever is only journaled so far.
+    fn flush_journal(&self) -> Result<JournalReport> { Ok(JournalReport::default()) }
+
+    /// Load everything up front so the first requests are served from memory.
+    fn warm_up(&self, history_file: &str, files: &[&str]) -> WarmupReport {
+        let records = self.load_history(history_file).len();
+        WarmupReport { files: files.len() + 1, records, ms: 0 }
+    }
+
+    /// None until `warm_up` has finished; backends with nothing to load are ready at once.
+    fn warmup(&self) -> Option<WarmupReport> { Some(WarmupReport { files: 0, records: 0, ms: 0 }) }
+}
+
+fn from_doc<T: DeserializeOwned + Default>(file: &str, value: Value) -> T {
+    if value.is_null() { return T::default(); }
+    serde_json::from_value(value).unwrap_or_else(|e| {
+        eprintln!("⚠️  Failed to load {file}: {e}");
+        T::default()
+    })
+}
+
+fn usage_by_folder(records: &[FileRecord]) -> HashMap<String, UsageSummary> {
+    let mut out: HashMap<String, UsageSummary> = HashMap::new();
+    for f in records {
+        out.entry(f.folder_key().unwrap_or_default()).or_default().add(f);
+    }
+    out
+}
+
+/// Typed helpers over any backend.
+impl<'s> dyn Store + 's {
+    /// Run `f` holding `file`'s lock, so read-modify-write sequences on one document
+    /// don't interleave and drop each other's changes. Not reentrant: never lock the
+    /// same document again inside `f`, and never hold it across an `.await`.
+    pub fn locked<R>(&self, file: &str, f: impl FnOnce() -> R) -> R {
+        let lock = self.doc_lock(file);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    }
+
+    /// Load, change and save a document under its lock.
+    pub fn update_json<T: DeserializeOwned + Default + Serialize, R>(&self, file: &str, f: impl FnOnce(&mut T) -> R) -> Result<R> {
+        self.locked(file, || {
+            let mut doc = self.load_json(file);
+            let out = f(&mut doc);
+            self.save_json(file, &doc)?;
+            Ok(out)
+        })
+    }
+
+    /// Load, change and save the history under its lock. Edit the current records
+    /// here instead of saving a copy loaded before an `.await`.
+    pub fn update_history<R>(&self, file: &str, f: impl FnOnce(&mut Vec<FileRecord>) -> R) -> Result<R> {
+        self.locked(file, || {
+            let mut history = self.load_history(file);
+            let out = f(&mut history);
+            self.save_history(file, &history)?;
+            Ok(out)
+        })
+    }
+
+    pub fn load_json<T: DeserializeOwned + Default>(&self, file: &str) -> T {
+        from_doc(file, self.load_doc(file))
+    }
+
+    pub fn save_json<T: Serialize + ?Sized>(&self, file: &str, data: &T) -> Result<()> {
+        self.save_doc(file, serde_json::to_value(data)?)
+    }
+
+    /// Stage changes to several documents and make them durable together.
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction { store: self, docs: vec![], history: None }
+    }
+
+    /// Append one JSON value as a line (JSONL). Cheap for logs that only grow.
+    pub fn append_jsonl<T: Serialize>(&self, file: &str, item: &T) -> Result<()> {
+        let line = serde_json::to_string(item)?;
+        self.locked(file, || self.append_lines(file, &[line]))
+    }
+
+    /// Append several lines with one write.
+    pub fn append_jsonl_many<T: Serialize>(&self, file: &str, items: &[T]) -> Result<()> {
+        let lines = items.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?;
+        self.locked(file, || self.append_lines(file, &lines))
+    }
+
+    /// Load, change and rewrite a JSONL file under its lock, so no line appended
+    /// meanwhile is lost (used to drop old lines from a log). Lines that fail to
+    /// parse are dropped.
+    pub fn update_jsonl<T: DeserializeOwned + Serialize, R>(&self, file: &str, f: impl FnOnce(&mut Vec<T>) -> R) -> Result<R> {
+        self.locked(file, || {
+            let mut items = self.load_jsonl(file);
+            let out = f(&mut items);
+            let lines = items.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?;
+            self.rewrite_lines(file, &lines)?;
+            Ok(out)
+        })
+    }
+
+    /// Read a JSONL file, skipping lines that fail to parse (e.g. a torn last write).
+    pub fn load_jsonl<T: DeserializeOwned>(&self, file: &str) -> Vec<T> {
+        self.load_lines(file).iter().filter_map(|l| serde_json::from_str(l).ok()).collect()
+    }
+
+    pub fn load_shares(&self, file: &str) -> Vec<Share> { self.load_json(file) }
+    pub fn save_shares(&self, file: &str, shares: &[Share]) -> Result<()> { self.save_json(file, shares) }
+
+    pub fn load_inboxes(&self, file: &str) -> Vec<Inbox> { self.load_json(file) }
+    pub fn save_inboxes(&self, file: &str, inboxes: &[Inbox]) -> Result<()> { self.save_json(file, inboxes) }
+
+    pub fn load_folder_rules(&self, file: &str) -> Vec<FolderRule> { self.load_json(file) }
+
+    pub fn load_trash(&self, file: &str) -> Vec<TrashEntry> { self.load_json(file) }
+    pub fn save_trash(&self, file: &str, entries: &[TrashEntry]) -> Result<()> { self.save_json(file, entries) }
+
+    /// File id → last download/preview (unix seconds).
+    pub fn load_access(&self, file: &str) -> HashMap<String, i64> { self.load_json(file) }
+    pub fn save_access(&self, file: &str, access: &HashMap<String, i64>) -> Result<()> { self.save_json(file, access) }
+
+    pub fn load_diagnostics(&self, file: &str) -> Vec<UploadDiagnostics> { self.load_json(file) }
+    pub fn save_diagnostics(&self, file: &str, diags: &[UploadDiagnostics]) -> Result<()> { self.save_json(file, diags) }
+
+    pub fn load_queue(&self, file: &str) -> Vec<QueueItem> { self.load_json(file) }
+    pub fn save_queue(&self, file: &str, items: &[QueueItem]) -> Result<()> { self.save_json(file, items) }
+}
+
+// ── JSON files ─────────────────────────────────────────────────────────────────
+
+/// Parsed history plus an id → position index, rebuilt whenever history is saved.
+/// The records are shared so the writer can serialize them without holding the lock.
+struct HistoryCache {
+    file:    String,
+    records: Arc<Vec<FileRecord>>,
+    index:   HashMap<i64, usize>,
+}
+
+impl HistoryCache {
+    fn new(file: &str, records: Vec<FileRecord>) -> Self {
+        let index = records.iter().enumerate().map(|(i, r)| (r.id, i)).collect();
+        Self { file: file.to_string(), records: Arc::new(records), index }
+    }
+}
+
+/// Result of the startup warm-up, reported by /api/health.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WarmupReport {
+    pub files:   usize,
+    pub records: usize,
+    pub ms:      u64,
+}
+
+/// Write-ahead journal in each data dir. The writer thread appends every saved
+/// document as one line and fsyncs it; the pretty JSON files are rewritten lazily
+/// by `flush_journal`, and `replay_journal` repairs them after a crash.
+pub const JOURNAL_FILE: &str = "store.journal";
+/// Past this size the writer flushes the snapshots itself instead of waiting for the task.
+const JOURNAL_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+    file: String,
+    data: Value,
+}
+
+/// Line shapes in the journal: a transaction's documents share one line, so a torn
+/// write loses all of them and replay never applies half a transaction.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum JournalLine {
+    Batch { tx: Vec<JournalEntry> },
+    Single(JournalEntry),
+}
+
+#[derive(Serialize)]
+struct BatchRef<'a> {
+    tx: &'a [JournalEntry],
+}
+
+/// Documents saved to the journal but not yet rewritten, guarded together with the file.
+#[derive(Default)]
+struct Journal {
+    dirty:   BTreeSet<String>,
+    entries: usize,
+    bytes:   u64,
+}
+
+/// Result of a journal flush or replay.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JournalReport {
+    pub files:   usize,
+    pub entries: usize,
+}
+
+/// What the writer thread is asked to do.
+enum WriteOp {
+    /// These documents changed in the caches; the reply says whether they reached the journal.
+    Saved(Vec<String>, mpsc::Sender<Result<()>>),
+    /// Rewrite the snapshots once everything sent before is journaled.
+    Flush(mpsc::Sender<Result<JournalReport>>),
 }
 
+/// The default backend: one pretty JSON file per document in the data dir, cached
+/// in memory and saved through the write-ahead journal.
+///
+/// Saves update the caches and hand the document names to a writer thread, then
+/// wait for it. The writer drains whatever piled up while it was busy and journals
+/// it as one line: a burst of saves to the same document costs one write (and one
+/// fsync) of its latest content, and every save in it gets that write's result.
 pub struct JsonStore {
-    pub base_dir: PathBuf,
+    shared:       Arc<JsonFiles>,
+    writer:       mpsc::Sender<WriteOp>,
+    warmup:       OnceLock<WarmupReport>,
+    locks:        DocLocks,
+}
+
+/// The caches and the journal, shared with the writer thread.
+struct JsonFiles {
+    base_dir:     PathBuf,
+    history:      RwLock<Option<HistoryCache>>,
+    /// Parsed copies of the other JSON files; after warm-up reads never touch disk.
+    docs:         RwLock<HashMap<String, Value>>,
+    journal:      Mutex<Journal>,
 }
 
 impl JsonStore {
-    pub fn new(base_dir: PathBuf) -> Self { Self { base_dir } }
+    pub fn new(base_dir: PathBuf) -> Self {
+        let shared = Arc::new(JsonFiles {
+            base_dir, history: RwLock::new(None), docs: RwLock::default(), journal: Mutex::default(),
+        });
+        let (writer, ops) = mpsc::channel();
+        let files = Arc::clone(&shared);
+        std::thread::Builder::new()
+            .name("store-writer".to_string())
+            .spawn(move || files.run_writer(ops))
+            .expect("spawn store writer");
+        Self { shared, writer, warmup: OnceLock::new(), locks: DocLocks::default() }
+    }
 
+    /// Journal `files` and return once they are on disk. Should the writer be gone
+    /// (it panicked), the save is done on the caller's thread instead.
+    fn persist(&self, files: Vec<String>) -> Result<()> {
+        let (reply, done) = mpsc::channel();
+        match self.writer.send(WriteOp::Saved(files, reply)) {
+            Ok(()) => done.recv().unwrap_or_else(|_| Err(anyhow!("store writer stopped before saving"))),
+            Err(mpsc::SendError(WriteOp::Saved(files, _))) => self.shared.journal_files(&files),
+            Err(_) => unreachable!("only Saved was sent"),
+        }
+    }
+}
+
+impl JsonFiles {
     fn path(&self, filename: &str) -> PathBuf { self.base_dir.join(filename) }
 
-    pub fn load_json<T: for<'de> Deserialize<'de> + Default>(&self, filename: &str) -> T {
+    fn read_json<T: DeserializeOwned>(&self, filename: &str) -> Option<T> {
         let path = self.path(filename);
-        if !path.exists() { return T::default(); }
+        if !path.exists() { return None; }
         match fs::read_to_string(&path).and_then(|s|
             serde_json::from_str(&s).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
         ) {
-            Ok(v)  => v,
-            Err(e) => { eprintln!("⚠️  Failed to load {filename}: {e}"); T::default() }
+            Ok(v)  => Some(v),
+            Err(e) => { eprintln!("⚠️  Failed to load {filename}: {e}"); None }
         }
     }
 
-    pub fn save_json<T: Serialize + ?Sized>(&self, filename: &str, data: &T) -> Result<()> {
-        let path = self.path(filename);
+    /// Snapshots are replaced atomically and synced: the journal is truncated once
+    /// they are written, so they must be on disk first.
+    fn write_json<T: Serialize + ?Sized>(&self, filename: &str, data: &T) -> Result<()> {
         let json = serde_json::to_string_pretty(data)?;
-        fs::write(&path, json).context(format!("write {filename}"))?;
+        write_atomic(&self.path(filename), json.as_bytes()).context(format!("write {filename}"))
+    }
+
+    /// The writer thread: journal what was saved, a burst at a time, until the
+    /// store is dropped.
+    fn run_writer(&self, ops: mpsc::Receiver<WriteOp>) {
+        while let Ok(first) = ops.recv() {
+            let mut saved: BTreeSet<String> = BTreeSet::new();
+            let mut waiting = vec![];
+            let mut flushes = vec![];
+            for op in std::iter::once(first).chain(ops.try_iter()) {
+                match op {
+                    WriteOp::Saved(files, reply) => { saved.extend(files); waiting.push(reply); }
+                    WriteOp::Flush(reply) => flushes.push(reply),
+                }
+            }
+            if !saved.is_empty() {
+                let res = self.journal_files(&saved.into_iter().collect::<Vec<_>>());
+                if let Err(e) = &res { eprintln!("⚠️  Saving to the journal failed: {e:#}"); }
+                for reply in waiting {
+                    let _ = reply.send(match &res { Ok(()) => Ok(()), Err(e) => Err(anyhow!("{e:#}")) });
+                }
+            }
+            for reply in flushes {
+                let mut journal = self.journal.lock().unwrap_or_else(|e| e.into_inner());
+                let _ = reply.send(self.flush_locked(&mut journal));
+            }
+        }
+    }
+
+    /// Journal the cached content of `files` as one line. Both caches are read
+    /// together, so a transaction that updated several is never split.
+    fn journal_files(&self, files: &[String]) -> Result<()> {
+        let mut history = None;
+        let mut docs = Vec::with_capacity(files.len());
+        {
+            let history_cache = self.history.read().unwrap_or_else(|e| e.into_inner());
+            let doc_cache = self.docs.read().unwrap_or_else(|e| e.into_inner());
+            for file in files {
+                match history_cache.as_ref().filter(|c| &c.file == file) {
+                    Some(c) => history = Some((file.clone(), Arc::clone(&c.records))),
+                    None    => if let Some(v) = doc_cache.get(file) { docs.push(JournalEntry { file: file.clone(), data: v.clone() }) },
+                }
+            }
+        }
+        if let Some((file, records)) = history {
+            docs.insert(0, JournalEntry { file, data: serde_json::to_value(records.as_slice())? });
+        }
+        if docs.is_empty() { return Ok(()); }
+        self.log_docs(docs)
+    }
+
+    /// Journal several documents as one line. Without a journal every snapshot is
+    /// staged next to its file first and then renamed over it, which narrows the
+    /// window for a partial commit to the renames.
+    fn log_docs(&self, docs: Vec<JournalEntry>) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap_or_else(|e| e.into_inner());
+        let appended = (|| -> Result<u64> {
+            let line = match docs.as_slice() {
+                [only] => serde_json::to_string(only)?,
+                all    => serde_json::to_string(&BatchRef { tx: all })?,
+            };
+            let mut f = fs::OpenOptions::new().create(true).append(true).open(self.path(JOURNAL_FILE))?;
+            writeln!(f, "{line}")?;
+            f.sync_data()?;
+            Ok(line.len() as u64 + 1)
+        })();
+        match appended {
+            Ok(n) => {
+                journal.dirty.extend(docs.into_iter().map(|d| d.file));
+                journal.entries += 1;
+                journal.bytes   += n;
+                if journal.bytes > JOURNAL_MAX_BYTES {
+                    self.flush_locked(&mut journal)?;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                let names: Vec<&str> = docs.iter().map(|d| d.file.as_str()).collect();
+                eprintln!("⚠️  Journal append failed ({e}), writing {} directly", names.join(", "));
+                if let [only] = docs.as_slice() {
+                    return self.write_json(&only.file, &only.data);
+                }
+                for d in &docs {
+                    let json = serde_json::to_string_pretty(&d.data)?;
+                    write_synced(&self.path(&format!("{}.tx", d.file)), json.as_bytes())
+                        .context(format!("write {}", d.file))?;
+                }
+                for d in &docs {
+                    fs::rename(self.path(&format!("{}.tx", d.file)), self.path(&d.file))
+                        .context(format!("commit {}", d.file))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn flush_locked(&self, journal: &mut Journal) -> Result<JournalReport> {
+        if journal.dirty.is_empty() { return Ok(JournalReport::default()); }
+        for file in &journal.dirty {
+            let is_history = self.history.read().unwrap_or_else(|e| e.into_inner())
+                .as_ref().is_some_and(|c| &c.file == file);
+            if is_history {
+                let records = self.with_history(file, |c| Arc::clone(&c.records));
+                self.write_json(file, records.as_slice())?;
+            } else {
+                let value = self.docs.read().unwrap_or_else(|e| e.into_inner()).get(file).cloned();
+                if let Some(v) = value { self.write_json(file, &v)?; }
+            }
+        }
+        fs::File::create(self.path(JOURNAL_FILE)).context("truncate journal")?;
+        let report = JournalReport { files: journal.dirty.len(), entries: journal.entries };
+        *journal = Journal::default();
+        Ok(report)
+    }
+
+    /// Run `f` against the cached history, parsing the file only on first use.
+    fn with_history<R>(&self, file: &str, f: impl FnOnce(&HistoryCache) -> R) -> R {
+        {
+            let guard = self.history.read().unwrap_or_else(|e| e.into_inner());
+            if let Some(cache) = guard.as_ref().filter(|c| c.file == file) {
+                return f(cache);
+            }
+        }
+        let mut guard = self.history.write().unwrap_or_else(|e| e.into_inner());
+        if !matches!(guard.as_ref(), Some(c) if c.file == file) {
+            *guard = Some(HistoryCache::new(file, self.read_json(file).unwrap_or_default()));
+        }
+        f(guard.as_ref().expect("history cache just filled"))
+    }
+}
+
+/// The last journaled content of each document in `text`, and how many lines
+/// applied. A line that doesn't parse (a torn last write) is skipped whole.
+fn replay_lines(text: &str) -> (BTreeMap<String, Value>, usize) {
+    let mut latest: BTreeMap<String, Value> = BTreeMap::new();
+    let mut entries = 0;
+    for line in text.lines() {
+        match serde_json::from_str::<JournalLine>(line) {
+            Ok(JournalLine::Single(entry)) => { latest.insert(entry.file, entry.data); }
+            Ok(JournalLine::Batch { tx })  => latest.extend(tx.into_iter().map(|e| (e.file, e.data))),
+            Err(_) => continue,
+        }
+        entries += 1;
+    }
+    (latest, entries)
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut f = fs::File::create(path)?;
+    f.write_all(bytes)?;
+    f.sync_data()
+}
+
+/// Write to a temp file next to `path` and rename it over `path`, so readers and
+/// crashes see the old content or the new one, never a torn file.
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("data");
+    let tmp = path.with_file_name(format!(".{name}.{}.tmp", &uuid::Uuid::new_v4().simple().to_string()[..8]));
+    let res = write_synced(&tmp, bytes).and_then(|()| fs::rename(&tmp, path));
+    if res.is_err() { let _ = fs::remove_file(&tmp); }
+    res
+}
+
+impl Store for JsonStore {
+    fn base_dir(&self) -> &Path { &self.shared.base_dir }
+
+    fn doc_lock(&self, file: &str) -> Arc<Mutex<()>> { self.locks.get(file) }
+
+    fn load_doc(&self, file: &str) -> Value {
+        let cached = self.shared.docs.read().unwrap_or_else(|e| e.into_inner()).get(file).cloned();
+        cached.unwrap_or_else(|| {
+            let v = self.shared.read_json(file).unwrap_or(Value::Null);
+            self.shared.docs.write().unwrap_or_else(|e| e.into_inner()).insert(file.to_string(), v.clone());
+            v
+        })
+    }
+
+    /// Returns once the writer has journaled `value`.
+    fn save_doc(&self, file: &str, value: Value) -> Result<()> {
+        self.shared.docs.write().unwrap_or_else(|e| e.into_inner()).insert(file.to_string(), value);
+        self.persist(vec![file.to_string()])
+    }
+
+    fn load_history(&self, file: &str) -> Vec<FileRecord> {
+        self.shared.with_history(file, |c| c.records.to_vec())
+    }
+
+    fn save_history(&self, file: &str, records: &[FileRecord]) -> Result<()> {
+        *self.shared.history.write().unwrap_or_else(|e| e.into_inner()) = Some(HistoryCache::new(file, records.to_vec()));
+        self.persist(vec![file.to_string()])
+    }
+
+    /// Computed on the cached records, without cloning them.
+    fn folder_usage(&self, file: &str) -> HashMap<String, UsageSummary> {
+        self.shared.with_history(file, |c| usage_by_folder(&c.records))
+    }
+
+    /// O(1) lookup through the id index.
+    fn find_record(&self, file: &str, id: i64) -> Option<FileRecord> {
+        self.shared.with_history(file, |c| c.index.get(&id).map(|&i| c.records[i].clone()))
+    }
+
+    /// Both caches are updated under their write locks together, and the documents
+    /// reach the journal as one line.
+    fn commit_batch(&self, history: Option<(String, Vec<FileRecord>)>, docs: Vec<(String, Value)>) -> Result<()> {
+        let mut files = Vec::with_capacity(docs.len() + 1);
+        {
+            let mut history_cache = self.shared.history.write().unwrap_or_else(|e| e.into_inner());
+            let mut doc_cache = self.shared.docs.write().unwrap_or_else(|e| e.into_inner());
+            if let Some((file, records)) = history {
+                *history_cache = Some(HistoryCache::new(&file, records));
+                files.push(file);
+            }
+            for (file, data) in docs {
+                doc_cache.insert(file.clone(), data);
+                files.push(file);
+            }
+        }
+        self.persist(files)
+    }
+
+    fn append_lines(&self, file: &str, lines: &[String]) -> Result<()> {
+        let mut buf = String::new();
+        for line in lines {
+            buf.push_str(line);
+            buf.push('\n');
+        }
+        let mut f = fs::OpenOptions::new().create(true).append(true).open(self.shared.path(file))
+            .context(format!("open {file}"))?;
+        f.write_all(buf.as_bytes()).context(format!("append {file}"))?;
         Ok(())
     }
 
-    pub fn load_folders(&self, file: &str) -> Vec<Folder> { self.load_json(file) }
-    pub fn save_folders(&self, file: &str, folders: &[Folder]) -> Result<()> { self.save_json(file, folders) }
+    fn rewrite_lines(&self, file: &str, lines: &[String]) -> Result<()> {
+        let mut buf = String::new();
+        for line in lines {
+            buf.push_str(line);
+            buf.push('\n');
+        }
+        // A concurrent append between the read and this rename is lost; callers only prune
+        write_atomic(&self.shared.path(file), buf.as_bytes()).context(format!("rewrite {file}"))
+    }
+
+    fn load_lines(&self, file: &str) -> Vec<String> {
+        fs::read_to_string(self.shared.path(file))
+            .map(|s| s.lines().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    /// Rewrite every journaled document from the caches, then empty the journal.
+    /// Goes through the writer, so saves queued before it are included.
+    fn flush_journal(&self) -> Result<JournalReport> {
+        let (reply, report) = mpsc::channel();
+        if self.writer.send(WriteOp::Flush(reply)).is_ok() {
+            if let Ok(r) = report.recv() { return r; }
+        }
+        let mut journal = self.shared.journal.lock().unwrap_or_else(|e| e.into_inner());
+        self.shared.flush_locked(&mut journal)
+    }
+
+    /// Apply a journal left by a crash over the JSON snapshots. Call before anything
+    /// reads the store; a torn last line is ignored (the writer never finished it).
+    fn replay_journal(&self) -> Result<JournalReport> {
+        let path = self.shared.path(JOURNAL_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(JournalReport::default()),
+            Err(e) => return Err(e).context("read journal"),
+        };
+        let (latest, entries) = replay_lines(&text);
+        for (file, data) in &latest {
+            self.shared.write_json(file, data)?;
+        }
+        fs::File::create(&path).context("truncate journal")?;
+        Ok(JournalReport { files: latest.len(), entries })
+    }
+
+    /// Parse and index every store up front so the first requests are served from memory.
+    fn warm_up(&self, history_file: &str, files: &[&str]) -> WarmupReport {
+        let started = std::time::Instant::now();
+        let records = self.shared.with_history(history_file, |c| c.records.len());
+        for f in files {
+            let v = self.shared.read_json(f).unwrap_or(Value::Null);
+            self.shared.docs.write().unwrap_or_else(|e| e.into_inner()).insert(f.to_string(), v);
+        }
+        let report = WarmupReport { files: files.len() + 1, records, ms: started.elapsed().as_millis() as u64 };
+        let _ = self.warmup.set(report);
+        report
+    }
+
+    fn warmup(&self) -> Option<WarmupReport> { self.warmup.get().copied() }
+}
+
+// ── In memory ──────────────────────────────────────────────────────────────────
+
+/// Keeps everything in process memory and writes nothing (`data.backend: "memory"`).
+/// For tests and throwaway runs: all data is gone when the app exits.
+pub struct MemoryStore {
+    base_dir: PathBuf,
+    history:  RwLock<HashMap<String, Vec<FileRecord>>>,
+    docs:     RwLock<HashMap<String, Value>>,
+    lines:    RwLock<HashMap<String, Vec<String>>>,
+    locks:    DocLocks,
+}
+
+impl MemoryStore {
+    pub fn new(base_dir: PathBuf) -> Self {
+        Self { base_dir, history: RwLock::default(), docs: RwLock::default(), lines: RwLock::default(), locks: DocLocks::default() }
+    }
+}
+
+impl Store for MemoryStore {
+    fn base_dir(&self) -> &Path { &self.base_dir }
+
+    fn doc_lock(&self, file: &str) -> Arc<Mutex<()>> { self.locks.get(file) }
+
+    fn load_doc(&self, file: &str) -> Value {
+        self.docs.read().unwrap_or_else(|e| e.into_inner()).get(file).cloned().unwrap_or(Value::Null)
+    }
+
+    fn save_doc(&self, file: &str, value: Value) -> Result<()> {
+        self.docs.write().unwrap_or_else(|e| e.into_inner()).insert(file.to_string(), value);
+        Ok(())
+    }
+
+    fn load_history(&self, file: &str) -> Vec<FileRecord> {
+        self.history.read().unwrap_or_else(|e| e.into_inner()).get(file).cloned().unwrap_or_default()
+    }
+
+    fn save_history(&self, file: &str, records: &[FileRecord]) -> Result<()> {
+        self.history.write().unwrap_or_else(|e| e.into_inner()).insert(file.to_string(), records.to_vec());
+        Ok(())
+    }
+
+    fn commit_batch(&self, history: Option<(String, Vec<FileRecord>)>, docs: Vec<(String, Value)>) -> Result<()> {
+        // Both locks held so readers never see half of the transaction
+        let mut hist  = self.history.write().unwrap_or_else(|e| e.into_inner());
+        let mut cache = self.docs.write().unwrap_or_else(|e| e.into_inner());
+        if let Some((file, records)) = history { hist.insert(file, records); }
+        cache.extend(docs);
+        Ok(())
+    }
+
+    fn append_lines(&self, file: &str, lines: &[String]) -> Result<()> {
+        self.lines.write().unwrap_or_else(|e| e.into_inner())
+            .entry(file.to_string()).or_default().extend_from_slice(lines);
+        Ok(())
+    }
+
+    fn rewrite_lines(&self, file: &str, lines: &[String]) -> Result<()> {
+        self.lines.write().unwrap_or_else(|e| e.into_inner()).insert(file.to_string(), lines.to_vec());
+        Ok(())
+    }
+
+    fn load_lines(&self, file: &str) -> Vec<String> {
+        self.lines.read().unwrap_or_else(|e| e.into_inner()).get(file).cloned().unwrap_or_default()
+    }
+}
+
+// ── Transactions ───────────────────────────────────────────────────────────────
+
+/// Documents saved through `commit` are made durable together (one journal line
+/// for `JsonStore`): after a crash either all of them are replayed or none.
+/// Nothing is written before `commit`; dropping the transaction discards it.
+/// Callers still serialize their own read-modify-write (`Store::locked` on each
+/// document) until `commit` returns.
+pub struct Transaction<'a> {
+    store:   &'a dyn Store,
+    docs:    Vec<(String, Value)>,
+    history: Option<(String, Vec<FileRecord>)>,
+}
+
+impl Transaction<'_> {
+    pub fn save_json<T: Serialize + ?Sized>(&mut self, filename: &str, data: &T) -> Result<()> {
+        let value = serde_json::to_value(data)?;
+        self.docs.retain(|(f, _)| f != filename);
+        self.docs.push((filename.to_string(), value));
+        Ok(())
+    }
+
+    pub fn save_history(&mut self, file: &str, records: &[FileRecord]) {
+        self.history = Some((file.to_string(), records.to_vec()));
+    }
 
-    pub fn load_history(&self, file: &str) -> Vec<FileRecord> { self.load_json(file) }
-    pub fn save_history(&self, file: &str, records: &[FileRecord]) -> Result<()> { self.save_json(file, records) }
+    pub fn is_empty(&self) -> bool { self.docs.is_empty() && self.history.is_none() }
 
-    pub fn load_sessions(&self, file: &str) -> HashMap<String, UploadSession> { self.load_json(file) }
-    pub fn save_sessions(&self, file: &str, sessions: &HashMap<String, UploadSession>) -> Result<()> {
-        self.save_json(file, sessions)
+    pub fn commit(self) -> Result<()> {
+        if self.is_empty() { return Ok(()); }
+        self.store.commit_batch(self.history, self.docs)
     }
 }
 
 pub fn current_timestamp_ms() -> i64 { Utc::now().timestamp_millis() }
 pub fn current_datetime_display() -> String { Local::now().format("%d/%m/%Y %H:%M").to_string() }
 pub fn current_datetime_iso() -> String { Utc::now().to_rfc3339() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(id: i64) -> FileRecord {
+        FileRecord { id, filename: format!("{id}.bin"), ..Default::default() }
+    }
+
+    #[test]
+    fn transaction_commits_history_and_documents_together() {
+        let mem = MemoryStore::new(PathBuf::new());
+        let store: &dyn Store = &mem;
+        let mut tx = store.transaction();
+        tx.save_history("history.json", &[record(1)]);
+        tx.save_json("trash.json", &json!([{ "id": 2 }])).unwrap();
+        // Staged only: nothing is visible before commit
+        assert!(store.load_history("history.json").is_empty());
+        assert!(store.load_doc("trash.json").is_null());
+
+        tx.commit().unwrap();
+        assert_eq!(store.load_history("history.json").iter().map(|r| r.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(store.load_doc("trash.json"), json!([{ "id": 2 }]));
+    }
+
+    #[test]
+    fn dropped_transaction_writes_nothing() {
+        let mem = MemoryStore::new(PathBuf::new());
+        let store: &dyn Store = &mem;
+        store.save_json("shares.json", &json!(["kept"])).unwrap();
+        {
+            let mut tx = store.transaction();
+            tx.save_json("shares.json", &json!(["lost"])).unwrap();
+            tx.save_history("history.json", &[record(1)]);
+        }
+        assert_eq!(store.load_doc("shares.json"), json!(["kept"]));
+        assert!(store.load_history("history.json").is_empty());
+    }
+
+    #[test]
+    fn later_save_in_a_transaction_wins() {
+        let mem = MemoryStore::new(PathBuf::new());
+        let store: &dyn Store = &mem;
+        let mut tx = store.transaction();
+        tx.save_json("folders.json", &json!([1])).unwrap();
+        tx.save_json("folders.json", &json!([1, 2])).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(store.load_doc("folders.json"), json!([1, 2]));
+    }
+
+    #[test]
+    fn update_json_saves_the_change() {
+        let mem = MemoryStore::new(PathBuf::new());
+        let store: &dyn Store = &mem;
+        let len = store.update_json("rules.json", |rules: &mut Vec<String>| {
+            rules.push("*.iso".to_string());
+            rules.len()
+        }).unwrap();
+        assert_eq!(len, 1);
+        assert_eq!(store.load_json::<Vec<String>>("rules.json"), vec!["*.iso".to_string()]);
+    }
+
+    #[test]
+    fn replay_applies_latest_lines_and_skips_a_torn_one() {
+        let text = [
+            r#"{"file":"a.json","data":1}"#,
+            r#"{"tx":[{"file":"a.json","data":2},{"file":"b.json","data":[3]}]}"#,
+            r#"{"tx":[{"file":"a.json","data":4},{"file":"c.json","da"#,
+        ].join("\n");
+        let (latest, entries) = replay_lines(&text);
+        assert_eq!(entries, 2);
+        assert_eq!(latest.get("a.json"), Some(&json!(2)));
+        assert_eq!(latest.get("b.json"), Some(&json!([3])));
+        // Half a transaction is never applied
+        assert!(!latest.contains_key("c.json"));
+    }
+}
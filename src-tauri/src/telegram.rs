@@ -6,6 +6,8 @@ use serde::Deserialize;
 use tracing::{info, warn};
 
 use crate::config::Config;
+use crate::simulate;
+use crate::storage::PartTiming;
 use crate::zip_utils::zip_bytes;
 
 // ─── Telegram response shapes ──────────────────────────────────────────────────
@@ -20,6 +22,7 @@ struct TgResponse<T> {
 #[derive(Deserialize)]
 struct TgFile {
     file_path: Option<String>,
+    file_size: Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -33,27 +36,45 @@ struct TgMessage {
     document:   Option<TgDocument>,
 }
 
+#[derive(Deserialize)]
+struct TgForumTopic {
+    message_thread_id: i64,
+}
+
+/// Where parts go: the chat, and optionally a forum topic inside it.
+#[derive(Debug, Clone, Default)]
+pub struct Chat {
+    pub id:    String,
+    pub topic: Option<i64>,           // message_thread_id; None = General / non-forum chat
+}
+
 // ─── Public API ────────────────────────────────────────────────────────────────
 
-/// Send one part to Telegram. Returns (message_id, file_id).
+/// Send one part to Telegram. Returns (message_id, file_id, stored_bytes).
+/// Zip/send durations and retry count are written into `timing`.
+#[allow(clippy::too_many_arguments)]
 pub async fn send_part(
     client:   &Client,
     cfg:      &Config,
     tg_token: &str,
-    chat_id:  &str,
+    chat:     &Chat,
     buf_data: &[u8],
     part_num: u32,
     filename: &str,
     caption:  &str,
-) -> Result<(i64, String)> {
+    timing:   &mut PartTiming,
+) -> Result<(i64, String, u64)> {
     let part_name = format!("{filename}.part{part_num}");
     let zip_name  = format!("{part_name}.zip");
+    let zip_start = std::time::Instant::now();
     let zip_data  = tokio::task::spawn_blocking({
         let data = buf_data.to_vec();
         let pname = part_name.clone();
         let level = cfg.zip_compress_level;
-        move || zip_bytes(&data, &pname, level)
+        let password = cfg.zip_password.clone();
+        move || zip_bytes(&data, &pname, level, password.as_deref())
     }).await??;
+    timing.zip_ms = zip_start.elapsed().as_millis() as u64;
 
     let zip_size = zip_data.len() as u64;
     info!("  📨 Telegram part {part_num}: zip={:.1}MB", zip_size as f64 / 1024.0 / 1024.0);
@@ -66,11 +87,22 @@ pub async fn send_part(
         );
     }
 
+    if cfg.simulate {
+        let id = simulate::send_part(cfg, "telegram", &zip_data, part_num, timing).await?;
+        return Ok((id, simulate::file_id(id), zip_size));
+    }
+
+    let send_start = std::time::Instant::now();
     let mut last_err = None;
     for attempt in 0..cfg.discord_send_retries {
-        let form = reqwest::multipart::Form::new()
-            .text("chat_id",  chat_id.to_string())
-            .text("caption",  caption.to_string())
+        timing.retries = attempt;
+        let mut form = reqwest::multipart::Form::new()
+            .text("chat_id",  chat.id.clone())
+            .text("caption",  caption.to_string());
+        if let Some(topic) = chat.topic {
+            form = form.text("message_thread_id", topic.to_string());
+        }
+        let form = form
             .part(
                 "document",
                 reqwest::multipart::Part::bytes(zip_data.clone())
@@ -101,7 +133,8 @@ pub async fn send_part(
                     .as_ref()
                     .map(|d| d.file_id.clone())
                     .unwrap_or_default();
-                return Ok((msg.message_id, file_id));
+                timing.send_ms = send_start.elapsed().as_millis() as u64;
+                return Ok((msg.message_id, file_id, zip_size));
             }
             Err(e) => {
                 last_err = Some(anyhow!("{e}"));
@@ -114,9 +147,28 @@ pub async fn send_part(
         }
     }
 
+    timing.send_ms = send_start.elapsed().as_millis() as u64;
     Err(last_err.unwrap_or_else(|| anyhow!("Telegram send failed")))
 }
 
+/// Create a forum topic in `chat_id` (the bot needs the "Manage Topics" right).
+/// Returns its message_thread_id.
+pub async fn create_topic(client: &Client, tg_token: &str, chat_id: &str, name: &str) -> Result<i64> {
+    // Topic names are limited to 128 characters
+    let name: String = name.chars().take(128).collect();
+    let body: TgResponse<TgForumTopic> = client
+        .post(format!("https://api.telegram.org/bot{tg_token}/createForumTopic"))
+        .form(&[("chat_id", chat_id), ("name", name.as_str())])
+        .send().await?
+        .json().await.context("parse createForumTopic response")?;
+    if !body.ok {
+        anyhow::bail!("createForumTopic: {}", body.description.unwrap_or_default());
+    }
+    let topic = body.result.ok_or_else(|| anyhow!("No result in createForumTopic response"))?;
+    info!("🧵 Telegram topic '{name}' created ({})", topic.message_thread_id);
+    Ok(topic.message_thread_id)
+}
+
 /// Download one part from Telegram by file_id.
 pub async fn download_part(
     client:   &Client,
@@ -141,6 +193,57 @@ pub async fn download_part(
     Err(last_err.unwrap_or_else(|| anyhow!("Telegram download failed")))
 }
 
+/// Telegram refuses to delete messages older than this.
+pub const DELETE_WINDOW_MS: i64 = 48 * 3600 * 1000;
+
+/// Delete one previously sent part. Fails with Telegram's description (e.g. message too old).
+pub async fn delete_message(
+    client:     &Client,
+    cfg:        &Config,
+    tg_token:   &str,
+    chat_id:    &str,
+    message_id: i64,
+) -> Result<()> {
+    let r: TgResponse<bool> = client
+        .post(format!("https://api.telegram.org/bot{tg_token}/deleteMessage"))
+        .form(&[("chat_id", chat_id.to_string()), ("message_id", message_id.to_string())])
+        .timeout(std::time::Duration::from_secs(cfg.http_timeout_s))
+        .send().await?
+        .json().await?;
+    if !r.ok {
+        anyhow::bail!("{}", r.description.unwrap_or_else(|| "deleteMessage failed".to_string()));
+    }
+    Ok(())
+}
+
+/// Whether the bot token works and the Bot API answers (getMe).
+/// Transport errors come back without their URL: it holds the bot token.
+pub async fn ping(client: &Client, tg_token: &str, timeout: std::time::Duration) -> Result<()> {
+    let r: TgResponse<serde_json::Value> = client
+        .get(format!("https://api.telegram.org/bot{tg_token}/getMe"))
+        .timeout(timeout)
+        .send().await.map_err(reqwest::Error::without_url)?
+        .json().await.map_err(reqwest::Error::without_url)?;
+    if !r.ok {
+        anyhow::bail!("{}", r.description.unwrap_or_else(|| "getMe failed".to_string()));
+    }
+    Ok(())
+}
+
+/// Size Telegram reports for a stored document (getFile), without downloading it.
+pub async fn file_size(client: &Client, cfg: &Config, tg_token: &str, file_id: &str) -> Result<Option<u64>> {
+    let r: TgResponse<TgFile> = client
+        .get(format!("https://api.telegram.org/bot{tg_token}/getFile"))
+        .query(&[("file_id", file_id)])
+        .timeout(std::time::Duration::from_secs(cfg.http_timeout_s))
+        .send().await?
+        .json().await?;
+    if !r.ok {
+        anyhow::bail!("{}", r.description.unwrap_or_else(|| "getFile failed".to_string()));
+    }
+    Ok(r.result.and_then(|f| f.file_size))
+}
+
 async fn try_download(client: &Client, cfg: &Config, tg_token: &str, file_id: &str) -> Result<Vec<u8>> {
     let timeout = std::time::Duration::from_secs(cfg.http_timeout_s);
 
@@ -152,15 +255,65 @@ async fn try_download(client: &Client, cfg: &Config, tg_token: &str, file_id: &s
         .send().await?
         .json().await?;
 
-    let file_path = r.result
-        .and_then(|f| f.file_path)
+    let file = r.result.ok_or_else(|| anyhow!("No file_path for file_id {file_id}"))?;
+    let file_path = file.file_path
         .ok_or_else(|| anyhow!("No file_path for file_id {file_id}"))?;
 
     // Download
     let url = format!("https://api.telegram.org/file/bot{tg_token}/{file_path}");
+    if let Some(size) = file.file_size {
+        if cfg.tg_parallel_ranges > 1 && size >= MIN_RANGED_BYTES {
+            match try_download_ranged(client, &url, size, cfg.tg_parallel_ranges, timeout).await {
+                Ok(Some(data)) => return Ok(data),
+                Ok(None)       => info!("  ℹ️ Telegram CDN ignored Range → single-stream"),
+                Err(e)         => warn!("  ⚠️ Telegram ranged download failed ({e}) → single-stream"),
+            }
+        }
+    }
     let data = client.get(&url).timeout(timeout).send().await?.bytes().await?;
     if data.is_empty() {
         anyhow::bail!("Empty response from Telegram CDN");
     }
     Ok(data.to_vec())
 }
+
+/// Below this size a single request is faster than the extra round trips.
+const MIN_RANGED_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Fetch `url` as `ranges` concurrent byte ranges and reassemble them in order.
+/// Returns `Ok(None)` when the server does not honour Range (answers 200 instead of 206).
+async fn try_download_ranged(
+    client:  &Client,
+    url:     &str,
+    size:    u64,
+    ranges:  usize,
+    timeout: std::time::Duration,
+) -> Result<Option<Vec<u8>>> {
+    let span = size.div_ceil(ranges as u64);
+    let fetches = (0..ranges as u64)
+        .map(|i| (i * span, ((i + 1) * span).min(size)))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| async move {
+            let resp = client.get(url)
+                .header(reqwest::header::RANGE, format!("bytes={start}-{}", end - 1))
+                .timeout(timeout)
+                .send().await?;
+            if resp.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+                return Ok::<_, anyhow::Error>(None);
+            }
+            let data = resp.bytes().await?;
+            if data.len() as u64 != end - start {
+                anyhow::bail!("Range {start}-{end}: got {} bytes", data.len());
+            }
+            Ok(Some(data))
+        });
+
+    let mut out = Vec::with_capacity(size as usize);
+    for chunk in futures::future::try_join_all(fetches).await? {
+        match chunk {
+            Some(data) => out.extend_from_slice(&data),
+            None       => return Ok(None),
+        }
+    }
+    Ok(Some(out))
+}
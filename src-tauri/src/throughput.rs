@@ -0,0 +1,136 @@
+/// throughput.rs — Speed samples of finished transfers, bucketed over time.
+///
+/// Every completed upload or download appends one short line to
+/// `data.throughput_file`. Uploads are timed from the first chunk to the last part
+/// stored; downloads from the merge start to the last byte handed to the client.
+/// Each GC pass drops samples older than `data.throughput_keep_days`, so no
+/// `range` reaches further back than that.
+/// Bucketed averages make slow stretches stand out: a dip in uploads alone points
+/// at Discord/Telegram throttling, both directions dipping at the local link.
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::storage::Store;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "u")]
+    Upload,
+    #[serde(rename = "d")]
+    Download,
+}
+
+/// One finished transfer; short keys keep the file small.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub t:  i64,         // finished at, unix seconds
+    pub k:  Direction,
+    pub b:  u64,         // bytes moved
+    pub ms: u64,         // wall time
+}
+
+/// Record a transfer. Failures are logged, never propagated.
+pub fn record(store: &dyn Store, file: &str, k: Direction, bytes: u64, elapsed: Duration) {
+    let ms = elapsed.as_millis() as u64;
+    if bytes == 0 || ms == 0 { return; }
+    let sample = Sample { t: Utc::now().timestamp(), k, b: bytes, ms };
+    if let Err(e) = store.append_jsonl(file, &sample) {
+        warn!("⚠️ throughput log write failed: {e}");
+    }
+}
+
+/// Drop samples older than `keep_s`. Returns how many were removed.
+pub fn prune(store: &dyn Store, file: &str, keep_s: u64) -> anyhow::Result<usize> {
+    let cutoff = Utc::now().timestamp() - keep_s as i64;
+    store.update_jsonl(file, |samples: &mut Vec<Sample>| {
+        let before = samples.len();
+        samples.retain(|s| s.t >= cutoff);
+        before - samples.len()
+    })
+}
+
+/// `90m`, `24h`, `7d`, `2w` → seconds.
+pub fn parse_span(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let unit = match s.chars().last()? {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _   => return None,
+    };
+    let n: u64 = s[..s.len() - 1].parse().ok().filter(|&n| n > 0)?;
+    n.checked_mul(unit)
+}
+
+/// Hourly buckets up to two days, daily beyond.
+pub fn default_bucket(range_s: u64) -> u64 {
+    if range_s <= 2 * 86400 { 3600 } else { 86400 }
+}
+
+/// Transfers of one direction inside a bucket.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Stat {
+    pub transfers:   u32,
+    pub bytes:       u64,
+    pub ms:          u64,
+    /// Total bytes over total time, so large transfers weigh more than tiny ones.
+    pub avg_mb_s:    Option<f64>,
+    pub peak_mb_s:   Option<f64>,
+}
+
+impl Stat {
+    fn add(&mut self, s: &Sample) {
+        self.transfers += 1;
+        self.bytes     += s.b;
+        self.ms        += s.ms;
+        let speed = mb_s(s.b, s.ms);
+        self.avg_mb_s  = Some(mb_s(self.bytes, self.ms));
+        self.peak_mb_s = Some(self.peak_mb_s.map_or(speed, |p| p.max(speed)));
+    }
+}
+
+fn mb_s(bytes: u64, ms: u64) -> f64 {
+    let v = bytes as f64 / 1024.0 / 1024.0 / (ms.max(1) as f64 / 1000.0);
+    (v * 100.0).round() / 100.0
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Bucket {
+    pub start:    String,    // RFC 3339, UTC
+    pub upload:   Stat,
+    pub download: Stat,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Series {
+    pub range_s:  u64,
+    pub bucket_s: u64,
+    pub buckets:  Vec<Bucket>,
+    pub upload:   Stat,
+    pub download: Stat,
+}
+
+/// Fold samples from the last `range_s` seconds (ending `now`) into `bucket_s`
+/// buckets aligned to UTC, oldest first. Empty buckets are kept so charts have gaps.
+pub fn series(samples: &[Sample], now: i64, range_s: u64, bucket_s: u64) -> Series {
+    let bucket = bucket_s.max(1) as i64;
+    let first  = (now - range_s as i64).div_euclid(bucket) * bucket;
+    let count  = ((now - first) / bucket + 1) as usize;
+    let mut buckets: Vec<Bucket> = (0..count).map(|i| Bucket {
+        start:    Utc.timestamp_opt(first + i as i64 * bucket, 0).single().map(|t| t.to_rfc3339()).unwrap_or_default(),
+        upload:   Stat::default(),
+        download: Stat::default(),
+    }).collect();
+    let (mut upload, mut download) = (Stat::default(), Stat::default());
+    for s in samples.iter().filter(|s| s.t > now - range_s as i64 && s.t <= now) {
+        let b = &mut buckets[((s.t - first) / bucket) as usize];
+        match s.k {
+            Direction::Upload   => { b.upload.add(s);   upload.add(s); }
+            Direction::Download => { b.download.add(s); download.add(s); }
+        }
+    }
+    Series { range_s, bucket_s, buckets, upload, download }
+}
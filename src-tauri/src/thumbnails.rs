@@ -0,0 +1,146 @@
+/// thumbnails.rs — Grid thumbnails for files that are not pictures.
+///
+/// There is no PDF renderer or font rasterizer in the build, so both kinds are
+/// approximated from the leading bytes of the file:
+/// - PDF: the first sizable embedded JPEG (the page scan in scanned documents, the
+///   cover in most e-books); without one, a drawn page placeholder.
+/// - Text/code: a minimap of the first lines, one small block per character and
+///   colored by kind, the way editors draw theirs.
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+
+/// Bytes of a text file read for its minimap.
+pub const TEXT_PREFIX: usize = 64 * 1024;
+
+const TEXT_EXTS: &[&str] = &[
+    "txt", "md", "json", "xml", "yaml", "yml", "csv", "log", "ini", "cfg", "toml", "env",
+    "html", "htm", "css", "js", "ts", "py", "java", "c", "cpp", "h", "cs", "go", "rs", "php",
+    "rb", "sh", "bat", "ps1", "sql", "kt", "swift", "lua",
+];
+
+/// Minimap geometry: 40 lines × 80 columns of 3×4 px cells on a 256 px square.
+const LINES: usize = 40;
+const COLS: usize = 80;
+const CELL_W: u32 = 3;
+const CELL_H: u32 = 4;
+const LINE_H: u32 = 6;
+const MARGIN: u32 = 8;
+
+/// Embedded images smaller than this are logos/bullets, not a page.
+const MIN_COVER_PX: u32 = 96;
+/// JPEG streams tried before giving up on a PDF.
+const MAX_COVER_TRIES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Pdf,
+    Text,
+}
+
+pub fn kind_of(filename: &str) -> Option<Kind> {
+    let ext = std::path::Path::new(filename).extension()
+        .and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "pdf" => Some(Kind::Pdf),
+        e if TEXT_EXTS.contains(&e) => Some(Kind::Text),
+        _ => None,
+    }
+}
+
+/// Thumbnail image for the leading bytes `buf` of a file of `kind`.
+pub fn render(kind: Kind, buf: &[u8]) -> anyhow::Result<DynamicImage> {
+    match kind {
+        Kind::Pdf  => Ok(pdf_cover(buf).unwrap_or_else(page_placeholder)),
+        Kind::Text => {
+            let head = &buf[..buf.len().min(TEXT_PREFIX)];
+            // NULs mean a binary file behind a text extension
+            if head.iter().take(8192).any(|&b| b == 0) {
+                anyhow::bail!("không phải file văn bản");
+            }
+            Ok(DynamicImage::ImageRgb8(text_minimap(&String::from_utf8_lossy(head))))
+        }
+    }
+}
+
+/// First `/DCTDecode` stream that decodes to a page-sized image.
+fn pdf_cover(buf: &[u8]) -> Option<DynamicImage> {
+    let mut from = 0;
+    for _ in 0..MAX_COVER_TRIES {
+        let filter = from + find(&buf[from..], b"/DCTDecode")?;
+        let start  = filter + find(&buf[filter..], b"stream")? + b"stream".len();
+        // The keyword is followed by CRLF or LF before the data
+        let start  = match buf.get(start..start + 2) {
+            Some(b"\r\n") => start + 2,
+            Some([b'\n', _]) => start + 1,
+            _ => start,
+        };
+        let end = start + find(&buf[start..], b"endstream")?;
+        from = end;
+        let Ok(img) = image::load_from_memory_with_format(&buf[start..end], ImageFormat::Jpeg) else { continue };
+        if img.width() >= MIN_COVER_PX && img.height() >= MIN_COVER_PX {
+            return Some(img);
+        }
+    }
+    None
+}
+
+fn find(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    hay.windows(needle.len()).position(|w| w == needle)
+}
+
+/// A blank page with a red PDF band and grey text lines.
+fn page_placeholder() -> DynamicImage {
+    let (w, h) = (181u32, 256u32);
+    let mut img = RgbImage::from_pixel(w, h, Rgb([250, 250, 250]));
+    fill(&mut img, 0, 0, w, 36, Rgb([220, 53, 69]));
+    let mut y = 56;
+    let mut i = 0u32;
+    while y + 4 < h - 16 {
+        // Ragged right edge so it reads as a paragraph
+        let len = if i % 6 == 5 { w / 2 } else { w - 32 - (i * 37 % 23) };
+        fill(&mut img, 16, y, len, 4, Rgb([200, 200, 205]));
+        y += 10;
+        i += 1;
+    }
+    DynamicImage::ImageRgb8(img)
+}
+
+fn text_minimap(text: &str) -> RgbImage {
+    let size = MARGIN * 2 + COLS as u32 * CELL_W;
+    let mut img = RgbImage::from_pixel(size, size, Rgb([30, 30, 30]));
+    for (row, line) in text.lines().take(LINES).enumerate() {
+        let trimmed = line.trim_start();
+        let comment = ["//", "#", "--", ";", "/*", "*"].iter().any(|c| trimmed.starts_with(c));
+        let y = MARGIN + row as u32 * LINE_H;
+        let mut col = 0usize;
+        for ch in line.chars() {
+            if col >= COLS { break; }
+            match ch {
+                '\t' => { col += 4; continue; }
+                c if c.is_whitespace() => { col += 1; continue; }
+                _ => {}
+            }
+            let color = if comment {
+                Rgb([106, 153, 85])
+            } else if ch.is_ascii_digit() {
+                Rgb([181, 206, 168])
+            } else if ch == '"' || ch == '\'' || ch == '`' {
+                Rgb([206, 145, 120])
+            } else if ch.is_alphanumeric() || ch == '_' {
+                Rgb([190, 190, 190])
+            } else {
+                Rgb([86, 156, 214])
+            };
+            fill(&mut img, MARGIN + col as u32 * CELL_W, y, CELL_W - 1, CELL_H, color);
+            col += 1;
+        }
+    }
+    img
+}
+
+fn fill(img: &mut RgbImage, x: u32, y: u32, w: u32, h: u32, color: Rgb<u8>) {
+    for yy in y..(y + h).min(img.height()) {
+        for xx in x..(x + w).min(img.width()) {
+            img.put_pixel(xx, yy, color);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+/// trash.rs — Soft delete. Deleting a file moves its record to `trash_file` and
+/// leaves its parts where they are, so `POST /api/trash/:id/restore` can put it
+/// back as it was. The lifecycle job's `trash` action lands here too.
+///
+/// The GC purges entries older than `trash.ttl_days`: only then are the parts
+/// deleted, and the channel with them when the delete asked for it or
+/// `trash.delete_channel` is set. Trashed records still count as users of their
+/// parts (`dedup::live_records`), so deleting a copy never takes theirs. With the
+/// trash off, what the lifecycle job still moves here goes at the next GC pass.
+///
+/// Telegram only lets a bot delete messages for 48h. Parts of a file still in the
+/// trash past that stay in the chat for good; the delete reply counts them
+/// (`telegram_kept`) so the UI can say so.
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{
+    api::{self, ApiError},
+    audit,
+    offline,
+    state::AppState,
+    storage::{FileRecord, TrashEntry},
+    telegram,
+};
+
+/// Unix seconds at which `entry` is purged.
+pub fn purge_at(st: &AppState, entry: &TrashEntry) -> i64 {
+    entry.trashed_at + st.cfg.trash_ttl_s as i64
+}
+
+/// Telegram parts of `entry` that will be past the delete window when it is purged.
+pub fn telegram_kept(st: &AppState, entry: &TrashEntry) -> usize {
+    if purge_at(st, entry) * 1000 - entry.record.id < telegram::DELETE_WINDOW_MS { return 0; }
+    entry.record.parts_info.iter().filter(|p| p.platform == "telegram").count()
+}
+
+/// What a delete that went (or would go) to the trash answers.
+pub fn trashed_reply(st: &AppState, entry: &TrashEntry) -> Value {
+    let mut reply = json!({ "trashed": true, "purge_at": purge_at(st, entry) });
+    let kept = telegram_kept(st, entry);
+    if kept > 0 { reply["telegram_kept"] = json!(kept); }
+    reply
+}
+
+/// Move a record from history to the trash.
+pub fn trash_file(st: &AppState, file_id: i64, delete_channel: bool) -> Result<TrashEntry, ApiError> {
+    let now = chrono::Utc::now().timestamp();
+    let moved = st.store.locked(&st.cfg.history_file, || st.store.locked(&st.cfg.trash_file, || -> anyhow::Result<_> {
+        let mut history = st.store.load_history(&st.cfg.history_file);
+        let Some(at) = history.iter().position(|r| r.id == file_id) else { return Ok(None) };
+        let entry = TrashEntry {
+            record: history.remove(at), trashed_at: now, reason: "deleted".to_string(), delete_channel,
+        };
+        let mut trash = st.store.load_trash(&st.cfg.trash_file);
+        trash.push(entry.clone());
+        // One commit, journaled before it returns: a crash can't lose a record or leave it twice
+        let mut tx = st.store.transaction();
+        tx.save_history(&st.cfg.history_file, &history);
+        tx.save_json(&st.cfg.trash_file, &trash)?;
+        tx.commit()?;
+        Ok(Some(entry))
+    }));
+    match moved {
+        Ok(Some(entry)) => {
+            audit::record(&*st.store, &st.cfg.audit_file, Some(file_id), "trashed",
+                json!({ "delete_channel": delete_channel }));
+            info!("🗑️ {} ({file_id}) moved to the trash", entry.record.filename);
+            let kept = telegram_kept(st, &entry);
+            if kept > 0 {
+                warn!("⚠️ {}: {kept} Telegram part(s) will be older than 48h at purge and stay in the chat", entry.record.filename);
+            }
+            Ok(entry)
+        }
+        Ok(None) => Err(ApiError::new(StatusCode::NOT_FOUND, "File không tồn tại")),
+        Err(e)   => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Put a trashed record back into history. Its folder may be gone by now, in
+/// which case it comes back at the root.
+pub async fn restore(st: &AppState, file_id: i64) -> Result<FileRecord, ApiError> {
+    // A purge of the same entry holds this while it deletes the parts
+    let _lock = st.file_locks.write(file_id).await;
+    let folders = st.store.load_folders(&st.cfg.folders_file);
+    let restored = st.store.locked(&st.cfg.history_file, || st.store.locked(&st.cfg.trash_file, || -> anyhow::Result<_> {
+        let mut trash = st.store.load_trash(&st.cfg.trash_file);
+        let Some(at) = trash.iter().position(|t| t.record.id == file_id) else { return Ok(None) };
+        let mut record = trash.remove(at).record;
+        if record.folder_key().is_some_and(|k| !folders.iter().any(|f| f.id.to_string() == k)) {
+            record.folder_id   = None;
+            record.folder_name = None;
+        }
+        let mut history = st.store.load_history(&st.cfg.history_file);
+        // Newest first, like uploads are added
+        let pos = history.iter().position(|r| r.id < record.id).unwrap_or(history.len());
+        history.insert(pos, record.clone());
+        let mut tx = st.store.transaction();
+        tx.save_history(&st.cfg.history_file, &history);
+        tx.save_json(&st.cfg.trash_file, &trash)?;
+        tx.commit()?;
+        Ok(Some(record))
+    }));
+    match restored {
+        Ok(Some(record)) => {
+            audit::record(&*st.store, &st.cfg.audit_file, Some(file_id), "restored", json!({}));
+            info!("♻️ {} ({file_id}) restored from the trash", record.filename);
+            Ok(record)
+        }
+        Ok(None) => Err(ApiError::new(StatusCode::NOT_FOUND, "File không có trong thùng rác")),
+        Err(e)   => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Delete a trashed file for good: its parts (those no other record uses), the
+/// channel if asked for, and what this machine keeps for it. Returns the Telegram
+/// cleanup report.
+pub async fn purge(st: &AppState, file_id: i64) -> Result<Value, ApiError> {
+    let _lock = st.file_locks.write(file_id).await;
+    let trash = st.store.load_trash(&st.cfg.trash_file);
+    let Some(entry) = trash.iter().find(|t| t.record.id == file_id).cloned() else {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "File không có trong thùng rác"));
+    };
+    let mut others = st.store.load_history(&st.cfg.history_file);
+    others.extend(trash.into_iter().filter(|t| t.record.id != file_id).map(|t| t.record));
+    let delete_channel = entry.delete_channel || st.cfg.trash_delete_channel;
+    if !st.connectivity.is_online() {
+        return purge_offline(st, entry, delete_channel).await;
+    }
+    let (telegram, shared) = api::drop_stored(st, entry.record.clone(), &others, delete_channel).await;
+    st.store.update_json(&st.cfg.trash_file, |trash: &mut Vec<TrashEntry>| trash.retain(|t| t.record.id != file_id))
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let revoked = api::forget_file(st, file_id).await;
+    audit::record(&*st.store, &st.cfg.audit_file, Some(file_id), "deleted", json!({
+        "delete_channel": delete_channel,
+        "telegram":       telegram,
+        "shares_revoked": revoked,
+        "shared":         shared,
+        "from_trash":     true,
+    }));
+    info!("🗑️ {} ({file_id}) purged from the trash", entry.record.filename);
+    Ok(telegram)
+}
+
+/// `purge` with Discord away: the entry goes now, its parts once the bot is back.
+async fn purge_offline(st: &AppState, entry: TrashEntry, delete_channel: bool) -> Result<Value, ApiError> {
+    let file_id = entry.record.id;
+    st.store.update_json(&st.cfg.trash_file, |trash: &mut Vec<TrashEntry>| trash.retain(|t| t.record.id != file_id))
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let pending = offline::defer_delete(st, entry.record, delete_channel);
+    let revoked = api::forget_file(st, file_id).await;
+    audit::record(&*st.store, &st.cfg.audit_file, Some(file_id), "deleted", json!({
+        "delete_channel": delete_channel,
+        "shares_revoked": revoked,
+        "pending":        pending.id,
+        "from_trash":     true,
+    }));
+    Ok(json!({ "queued_offline": true, "pending": pending.id }))
+}
+
+/// Purge every entry past `trash.ttl_days`. Returns how many went. Waits while
+/// Discord is away: expired entries can sit in the trash a little longer.
+pub async fn purge_expired(st: &AppState) -> usize {
+    if !st.connectivity.is_online() { return 0; }
+    let now = chrono::Utc::now().timestamp();
+    let due: Vec<i64> = st.store.load_trash(&st.cfg.trash_file).iter()
+        .filter(|t| purge_at(st, t) <= now)
+        .map(|t| t.record.id)
+        .collect();
+    let mut purged = 0;
+    for id in due {
+        match purge(st, id).await {
+            Ok(_)  => purged += 1,
+            Err(e) => warn!("⚠️ Trash: purging {id} failed: {}", e.detail),
+        }
+    }
+    purged
+}
@@ -1,20 +1,30 @@
 /// upload.rs — Upload session management and streaming sender.
 use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
+use serde::{Deserialize, Serialize};
 use serenity::{http::Http, model::id::{ChannelId, GuildId}};
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
 use tokio::{
-    sync::{mpsc, oneshot, Mutex, Semaphore},
+    sync::{mpsc, oneshot, Mutex, Semaphore, SemaphorePermit},
     task::JoinHandle,
-    time::sleep,
+    time::{sleep, sleep_until},
 };
-use tracing::{info, warn};
+use tracing::{info, info_span, warn, Instrument};
 
 use crate::{
     config::Config,
-    discord_bot,
-    storage::{current_datetime_iso, current_timestamp_ms, JsonStore, PartInfo, UploadSession},
+    crypto,
+    dedup::PartIndex,
+    discord_bot::{self, HttpPool},
+    media_pool,
+    optimize,
+    progress,
+    sessions::{SessionMap, SessionTable},
+    simulate,
+    storage::{current_datetime_iso, current_timestamp_ms, FileRecord, ImageOptimization, PartInfo, PartTiming, Store,
+              Transaction, UploadDiagnostics, UploadSession},
     telegram,
+    throughput,
     zip_utils::zip_bytes,
 };
 
@@ -25,14 +35,190 @@ pub struct SenderResult {
     pub parts_info:  Vec<PartInfo>,
     pub message_ids: Vec<i64>,
     pub jump_urls:   Vec<String>,
+    pub total_bytes: u64,          // original bytes sent across all parts
+    pub optimized:   Option<ImageOptimization>,
+    pub blake3:      Option<String>, // whole file; only known when one sender saw every byte
+}
+
+/// A part that could not be stored anywhere. `offset`/`length` locate its raw
+/// bytes in the original file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedPart {
+    pub part:     u32,
+    pub platform: String,
+    pub error:    String,
+    pub offset:   u64,
+    pub length:   u64,
+}
+
+/// Where a failed upload stopped: parts already stored (they stay on
+/// Discord/Telegram), parts that failed, and the tail that was never split into
+/// parts because sending stopped at the first unrecoverable failure.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FailureReport {
+    pub target:      String,            // UploadTarget::as_str
+    pub part_bytes:  u64,               // raw bytes per part (the last one may be shorter)
+    pub succeeded:   Vec<PartInfo>,
+    pub failed:      Vec<FailedPart>,
+    pub dispatched:  u32,               // parts numbered so far; the unsent tail continues from here
+    pub unsent_from: u64,               // first byte not covered by any dispatched part
+    /// The parts hold recompressed bytes the client doesn't have, so nothing can be resent.
+    #[serde(default)]
+    pub optimized:   bool,
+}
+
+/// Sender error once parts have been dispatched; carries the report for the client.
+#[derive(Debug)]
+pub struct UploadFailed(pub FailureReport);
+
+impl std::fmt::Display for UploadFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let r = &self.0;
+        write!(f, "{} part(s) failed, {} stored", r.failed.len(), r.succeeded.len())?;
+        if let Some(first) = r.failed.first() {
+            write!(f, " — part {} ({}): {}", first.part, first.platform, first.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UploadFailed {}
+
+/// A part a failed upload still needs, with the platforms it must reach.
+#[derive(Debug, Clone, Serialize)]
+pub struct MissingPart {
+    pub part:      u32,
+    pub offset:    u64,
+    pub length:    u64,
+    pub platforms: Vec<String>,
+}
+
+impl FailureReport {
+    /// Failed parts (only the copies that failed), then the never-sent tail cut
+    /// into `part_bytes` parts (also for pre-split uploads), minus any that have
+    /// been resent since.
+    pub fn missing(&self, file_size: u64) -> Vec<MissingPart> {
+        let mut out: Vec<MissingPart> = vec![];
+        for f in &self.failed {
+            match out.last_mut() {
+                Some(m) if m.part == f.part => m.platforms.push(f.platform.clone()),
+                _ => out.push(MissingPart { part: f.part, offset: f.offset, length: f.length, platforms: vec![f.platform.clone()] }),
+            }
+        }
+        let target = UploadTarget::parse(&self.target).unwrap_or(UploadTarget::Discord);
+        let (mut part, mut offset) = (self.dispatched, self.unsent_from);
+        while self.part_bytes > 0 && offset < file_size {
+            part += 1;
+            let length = self.part_bytes.min(file_size - offset);
+            if !self.succeeded.iter().any(|p| p.part == part) {
+                let platforms = match target {
+                    UploadTarget::Mirror => vec![false, true],
+                    t => vec![FailureBudget::new(t, u32::MAX).route(part)],
+                };
+                out.push(MissingPart { part, offset, length,
+                    platforms: platforms.into_iter().map(|tg| platform_name(tg).to_string()).collect() });
+            }
+            offset += length;
+        }
+        out
+    }
+}
+
+/// What a sender hands back once every part is stored somewhere.
+pub fn sender_result(mut parts: Vec<PartInfo>, total_parts: u32, total_bytes: u64, mirror: bool) -> SenderResult {
+    parts.sort_by_key(|p| p.part);
+    // Method reflects where parts actually went, which differs from the plan after a reroute
+    let on_tg = parts.iter().filter(|p| p.platform == "telegram").count();
+    let method = if mirror { "mirror" }
+        else if total_parts == 1 { "direct" }
+        else if on_tg == 0 { "split" }
+        else if on_tg == parts.len() { "telegram" }
+        else { "dual" };
+    SenderResult {
+        method:      method.to_string(),
+        parts:       total_parts,
+        message_ids: parts.iter().map(|p| p.message_id).collect(),
+        jump_urls:   parts.iter().filter_map(|p| p.jump_url.clone()).collect(),
+        parts_info:  parts,
+        total_bytes,
+        optimized:   None,
+        blake3:      None,
+    }
+}
+
+/// BLAKE3 of a part's (or file's) raw bytes, as stored in `PartInfo::blake3`.
+pub fn content_hash(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
 }
 
 pub type ChunkTx = mpsc::Sender<(usize, Bytes)>;
 
+/// Live diagnostics shared between the sender task and the API while an upload runs.
+pub type DiagHandle = Arc<std::sync::Mutex<UploadDiagnostics>>;
+
 pub struct SenderEntry {
     pub chunk_tx:  ChunkTx,
     pub result_rx: oneshot::Receiver<Result<SenderResult>>,
     pub handle:    JoinHandle<()>,
+    pub diag:      DiagHandle,
+    pub window:    Arc<ChunkWindow>,
+    /// Set while a deferred channel waits for the first chunk to create it.
+    pub channel_tx: Option<oneshot::Sender<ChannelId>>,
+}
+
+/// The Discord channel a sender posts to: created at init, or by the first
+/// chunk for `defer_channel` uploads (dropping the sender side fails the upload).
+pub enum ChannelSlot {
+    Ready(ChannelId),
+    Deferred(oneshot::Receiver<ChannelId>),
+}
+
+impl ChannelSlot {
+    async fn resolve(self) -> Result<ChannelId> {
+        match self {
+            ChannelSlot::Ready(id)    => Ok(id),
+            ChannelSlot::Deferred(rx) => rx.await.map_err(|_| anyhow!("Discord channel for the upload was not created")),
+        }
+    }
+}
+
+/// How far past the sender's read position a client may send chunks.
+/// Starts at `parallel_chunks`; halves while parts queue up behind the send
+/// permits (the sender also stops reading then) and grows back by one per
+/// finished part.
+pub struct ChunkWindow {
+    max:           usize,
+    size:          AtomicUsize,
+    next_expected: AtomicUsize,
+}
+
+impl ChunkWindow {
+    pub fn new(max: usize) -> Arc<Self> {
+        let max = max.max(1);
+        Arc::new(Self { max, size: AtomicUsize::new(max), next_expected: AtomicUsize::new(0) })
+    }
+
+    pub fn size(&self) -> usize { self.size.load(Ordering::Relaxed) }
+
+    /// First chunk index the sender hasn't consumed yet.
+    pub fn next_expected(&self) -> usize { self.next_expected.load(Ordering::Relaxed) }
+
+    /// Chunks at or past `next_expected + size` are outside the window.
+    pub fn admits(&self, chunk_index: usize) -> bool {
+        chunk_index < self.next_expected() + self.size()
+    }
+
+    fn advance(&self, next_expected: usize) {
+        self.next_expected.store(next_expected, Ordering::Relaxed);
+    }
+
+    fn shrink(&self) {
+        let _ = self.size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |s| Some((s / 2).max(1)));
+    }
+
+    fn grow(&self) {
+        let _ = self.size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |s| Some((s + 1).min(self.max)));
+    }
 }
 
 pub type SenderMap = Arc<Mutex<HashMap<String, SenderEntry>>>;
@@ -43,26 +229,37 @@ pub fn new_sender_map() -> SenderMap {
 
 // ── Session helpers ────────────────────────────────────────────────────────────
 
-fn load_sessions(store: &JsonStore, file: &str) -> HashMap<String, UploadSession> {
-    store.load_sessions(file)
+/// Session lifecycle: `uploading` → `finalizing` → `done`, or `uploading` → `cancelled`.
+pub const STATUS_UPLOADING:  &str = "uploading";
+pub const STATUS_FINALIZING: &str = "finalizing";
+pub const STATUS_DONE:       &str = "done";
+pub const STATUS_CANCELLED:  &str = "cancelled";
+/// A part failed for good; the session keeps the failure report in `discord_result`.
+pub const STATUS_FAILED:     &str = "failed";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransitionError {
+    NotFound,
+    /// The session was in `actual` state, which does not allow the transition.
+    Conflict { actual: String },
 }
 
-fn save_sessions(store: &JsonStore, file: &str, sessions: &HashMap<String, UploadSession>) {
-    if let Err(e) = store.save_sessions(file, sessions) {
+/// Write out a change that must survive a crash instead of waiting for the flush task.
+fn flush_sessions(sessions: &SessionTable) {
+    if let Err(e) = sessions.flush() {
         eprintln!("Failed to save sessions: {e}");
     }
 }
 
 pub fn create_session(
-    store: &JsonStore, file: &str,
+    sessions: &SessionTable,
     filename: &str, file_size: u64, total_chunks: usize,
     folder_id: &str, message: &str,
 ) -> String {
     let hash_input = format!("{filename}{}", current_timestamp_ms());
     let digest = format!("{:x}", md5::compute(hash_input.as_bytes()));
     let session_id = digest[..12].to_string();
-    let mut sessions = load_sessions(store, file);
-    sessions.insert(session_id.clone(), UploadSession {
+    let session = UploadSession {
         session_id:      session_id.clone(),
         filename:        filename.to_string(),
         file_size,
@@ -70,72 +267,230 @@ pub fn create_session(
         received_chunks: vec![],
         folder_id:       folder_id.to_string(),
         message:         message.to_string(),
-        status:          "uploading".to_string(),
+        status:          STATUS_UPLOADING.to_string(),
         created_at:      current_datetime_iso(),
         channel_id:      None,
         channel_name:    None,
         folder_name:     None,
         discord_result:  None,
-    });
-    save_sessions(store, file, &sessions);
+        last_activity:   Some(current_datetime_iso()),
+        secret:          uuid::Uuid::new_v4().simple().to_string(),
+        zip_level:       0,
+        pre_split:       false,
+        max_part_bytes:  0,
+        optimize_images: false,
+        metadata:        Default::default(),
+        wrapped_key:     None,
+    };
+    sessions.update(|all| all.insert(session_id.clone(), session));
+    flush_sessions(sessions);
     info!("📋 Session created: {session_id} ({filename}, {total_chunks} chunks)");
     session_id
 }
 
-pub fn get_session(store: &JsonStore, file: &str, id: &str) -> Option<UploadSession> {
-    load_sessions(store, file).remove(id)
+pub fn get_session(sessions: &SessionTable, id: &str) -> Option<UploadSession> {
+    sessions.get(id)
+}
+
+/// Change a session's fields and save it right away.
+pub fn update_session(sessions: &SessionTable, id: &str, f: impl FnOnce(&mut UploadSession)) {
+    sessions.update(|all| if let Some(s) = all.get_mut(id) { f(s) });
+    flush_sessions(sessions);
+}
+
+/// Move `s` from one of `from` to `to` (the table lock is held by the caller).
+fn transition(all: &mut SessionMap, id: &str, from: &[&str], to: &str) -> Result<UploadSession, TransitionError> {
+    let s = all.get_mut(id).ok_or(TransitionError::NotFound)?;
+    if !from.contains(&s.status.as_str()) {
+        return Err(TransitionError::Conflict { actual: s.status.clone() });
+    }
+    s.status = to.to_string();
+    Ok(s.clone())
 }
 
-pub fn update_session(store: &JsonStore, file: &str, id: &str, f: impl FnOnce(&mut UploadSession)) {
-    let mut sessions = load_sessions(store, file);
-    if let Some(s) = sessions.get_mut(id) { f(s); }
-    save_sessions(store, file, &sessions);
+/// Atomically move a session from one of `from` to `to`.
+/// Exactly one of several racing callers wins; the others get `Conflict` with the state they lost to.
+pub fn transition_session(
+    sessions: &SessionTable, id: &str, from: &[&str], to: &str,
+) -> Result<UploadSession, TransitionError> {
+    let out = sessions.update(|all| transition(all, id, from, to))?;
+    flush_sessions(sessions);
+    Ok(out)
 }
 
-pub fn mark_chunk_received(store: &JsonStore, file: &str, id: &str, idx: usize) {
-    update_session(store, file, id, |s| {
-        if !s.received_chunks.contains(&idx) {
-            s.received_chunks.push(idx);
-            s.received_chunks.sort_unstable();
+/// `transition_session` committed in `tx` together with what the caller staged
+/// there (e.g. the new history record). A failed transition still commits the rest.
+pub fn transition_session_with(
+    tx: Transaction<'_>, sessions: &SessionTable, id: &str, from: &[&str], to: &str,
+) -> Result<UploadSession, TransitionError> {
+    let res = sessions.update(|all| transition(all, id, from, to));
+    if let Err(e) = sessions.commit_with(tx) {
+        eprintln!("Failed to commit session {id}: {e}");
+    }
+    res
+}
+
+/// Refresh `last_activity` of an uploading session so a paused client isn't GC'd.
+/// Left to the flush task: a lost keepalive only shortens the idle clock.
+pub fn touch_session(sessions: &SessionTable, id: &str) -> Result<UploadSession, TransitionError> {
+    sessions.update(|all| {
+        let s = all.get_mut(id).ok_or(TransitionError::NotFound)?;
+        if s.status != STATUS_UPLOADING {
+            return Err(TransitionError::Conflict { actual: s.status.clone() });
+        }
+        s.last_activity = Some(current_datetime_iso());
+        Ok(s.clone())
+    })
+}
+
+/// Hot path (once per chunk), so only the in-memory table changes; after a crash
+/// a resuming client re-sends the few chunks the last flush missed.
+pub fn mark_chunk_received(sessions: &SessionTable, id: &str, idx: usize) {
+    sessions.update(|all| if let Some(s) = all.get_mut(id) {
+        s.last_activity = Some(current_datetime_iso());
+        if let Err(pos) = s.received_chunks.binary_search(&idx) {
+            s.received_chunks.insert(pos, idx);
         }
     });
 }
 
-pub fn delete_session_record(store: &JsonStore, file: &str, id: &str) {
-    let mut sessions = load_sessions(store, file);
-    sessions.remove(id);
-    save_sessions(store, file, &sessions);
+/// Keep diagnostics for the most recent uploads only.
+const MAX_DIAGNOSTICS: usize = 200;
+
+pub fn new_diagnostics(session_id: &str, filename: &str) -> DiagHandle {
+    Arc::new(std::sync::Mutex::new(UploadDiagnostics {
+        session_id: session_id.to_string(),
+        filename:   filename.to_string(),
+        started_at: current_datetime_iso(),
+        outcome:    "running".to_string(),
+        ..Default::default()
+    }))
+}
+
+pub fn save_diagnostics(store: &dyn Store, file: &str, diag: UploadDiagnostics) {
+    let saved = store.update_json(file, |all: &mut Vec<UploadDiagnostics>| {
+        all.retain(|d| d.session_id != diag.session_id);
+        all.insert(0, diag);
+        all.truncate(MAX_DIAGNOSTICS);
+    });
+    if let Err(e) = saved {
+        eprintln!("Failed to save diagnostics: {e}");
+    }
+}
+
+pub fn get_diagnostics(store: &dyn Store, file: &str, id: &str) -> Option<UploadDiagnostics> {
+    store.load_diagnostics(file).into_iter().find(|d| d.session_id == id)
+}
+
+pub fn delete_session_record(sessions: &SessionTable, id: &str) {
+    if sessions.update(|all| all.remove(id)).is_some() {
+        flush_sessions(sessions);
+    }
 }
 
 // ── Sender task ────────────────────────────────────────────────────────────────
 
+/// Where an upload's parts go. `Dual` alternates parts between the platforms,
+/// `Mirror` sends every part to both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadTarget {
+    Discord,
+    Telegram,
+    Dual,
+    Mirror,
+}
+
+impl UploadTarget {
+    pub const ALL: [UploadTarget; 4] = [Self::Discord, Self::Telegram, Self::Dual, Self::Mirror];
+
+    /// Global default: alternate when Telegram is configured, otherwise Discord only.
+    pub fn default_for(tg_enabled: bool) -> Self {
+        if tg_enabled { Self::Dual } else { Self::Discord }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == s)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Discord  => "discord",
+            Self::Telegram => "telegram",
+            Self::Dual     => "dual",
+            Self::Mirror   => "mirror",
+        }
+    }
+
+    pub fn needs_telegram(self) -> bool { self != Self::Discord }
+
+    /// Where `record`'s parts went, to store another copy of it the same way.
+    pub fn of_record(record: &FileRecord) -> Self {
+        let on_tg = record.parts_info.iter().filter(|p| p.platform == "telegram").count();
+        if record.method_key == "mirror" { Self::Mirror }
+        else if on_tg == 0 { Self::Discord }
+        else if on_tg == record.parts_info.len() { Self::Telegram }
+        else { Self::Dual }
+    }
+}
+
 pub struct SenderArgs {
     pub session_id:   String,
     pub filename:     String,
     pub message:      String,
     pub total_chunks: usize,
-    pub channel_id:   ChannelId,
+    pub file_size:    u64,
+    pub channel:      ChannelSlot,
     pub http:         Arc<Http>,
+    pub send_pool:    Arc<HttpPool>,
+    pub gates:        Arc<SendGates>,
     pub guild_id:     GuildId,
     pub cfg:          Arc<Config>,
-    pub tg_enabled:   bool,
+    pub target:       UploadTarget,
+    pub pre_split:    bool,            // each chunk is one part, no re-buffering
+    pub optimize:     Option<optimize::Kind>,  // hold the whole image and recompress it first
     pub tg_token:     String,
-    pub tg_chat_id:   String,
+    pub tg_chat:      telegram::Chat,
     pub chunk_rx:     mpsc::Receiver<(usize, Bytes)>,
     pub result_tx:    oneshot::Sender<Result<SenderResult>>,
+    pub store:        Arc<dyn Store>,
+    pub diag:         DiagHandle,
+    pub window:       Arc<ChunkWindow>,
+    /// Parts already stored, to reference instead of sending again; None = dedup off.
+    pub known_parts:  Option<Arc<PartIndex>>,
 }
 
 pub fn spawn_sender(args: SenderArgs) -> JoinHandle<()> {
+    let span = info_span!("upload", session = %args.session_id);
     tokio::spawn(async move {
-        let res = streaming_sender(
-            &args.session_id, &args.filename, &args.message,
-            args.total_chunks, args.channel_id,
-            &args.http, args.guild_id, &args.cfg,
-            args.tg_enabled, &args.tg_token, &args.tg_chat_id,
-            args.chunk_rx,
-        ).await;
+        let job = progress::Job::start(progress::Kind::Upload, args.file_size);
+        let started = std::time::Instant::now();
+        let res = match args.channel.resolve().await {
+            Ok(channel_id) => streaming_sender(
+                &args.session_id, &args.filename, &args.message,
+                args.total_chunks, channel_id,
+                &args.http, &args.send_pool, &args.gates, args.guild_id, &args.cfg,
+                args.target, args.pre_split, args.optimize, &args.tg_token, &args.tg_chat,
+                args.chunk_rx, &args.diag, &args.window, &job, args.known_parts.as_ref(),
+            ).await,
+            Err(e) => Err(e),
+        };
+        let snapshot = {
+            let mut d = args.diag.lock().unwrap_or_else(|e| e.into_inner());
+            d.finished_at = Some(current_datetime_iso());
+            match &res {
+                Ok(_)  => d.outcome = "ok".to_string(),
+                Err(e) => { d.outcome = "error".to_string(); d.error = Some(e.to_string()); }
+            }
+            d.parts.sort_by_key(|p| p.part);
+            d.clone()
+        };
+        save_diagnostics(&*args.store, &args.cfg.diagnostics_file, snapshot);
+        if res.is_ok() {
+            throughput::record(&*args.store, &args.cfg.throughput_file, throughput::Direction::Upload,
+                args.file_size, started.elapsed());
+        }
         let _ = args.result_tx.send(res);
-    })
+    }.instrument(span))
 }
 
 fn guild_filesize_limit(premium_tier: serenity::model::guild::PremiumTier) -> u64 {
@@ -146,6 +501,25 @@ fn guild_filesize_limit(premium_tier: serenity::model::guild::PremiumTier) -> u6
     }
 }
 
+/// The guild's attachment limit (from its boost tier) and the raw bytes per part
+/// for `target`, leaving `discord_safe_ratio` headroom for the ZIP wrapper.
+pub async fn part_limits(http: &Http, guild_id: GuildId, cfg: &Config, target: UploadTarget) -> Result<(u64, u64)> {
+    let guild_file_limit = if cfg.simulate {
+        cfg.sim_guild_limit_bytes
+    } else {
+        let guild = guild_id.to_partial_guild(http).await.context("fetch guild")?;
+        guild_filesize_limit(guild.premium_tier)
+    };
+    let discord_max = (guild_file_limit as f64 * cfg.discord_safe_ratio) as u64;
+    let tg_max = (cfg.tg_file_limit_bytes as f64 * cfg.discord_safe_ratio) as u64;
+    let input_limit = match target {
+        UploadTarget::Discord  => discord_max,
+        UploadTarget::Telegram => tg_max,
+        UploadTarget::Dual | UploadTarget::Mirror => discord_max.min(tg_max),
+    };
+    Ok((guild_file_limit, input_limit))
+}
+
 async fn streaming_sender(
     _session_id:  &str,
     filename:     &str,
@@ -153,25 +527,28 @@ async fn streaming_sender(
     total_chunks: usize,
     channel_id:   ChannelId,
     http:         &Arc<Http>,
+    send_pool:    &Arc<HttpPool>,
+    gates:        &SendGates,
     guild_id:     GuildId,
     cfg:          &Arc<Config>,
-    tg_enabled:   bool,
+    target:       UploadTarget,
+    pre_split:    bool,
+    mut optimize: Option<optimize::Kind>,
     tg_token:     &str,
-    tg_chat_id:   &str,
+    tg_chat:      &telegram::Chat,
     mut chunk_rx: mpsc::Receiver<(usize, Bytes)>,
+    diag:         &DiagHandle,
+    window:       &ChunkWindow,
+    job:          &progress::Job,
+    known_parts:  Option<&Arc<PartIndex>>,
 ) -> Result<SenderResult> {
-    let guild = guild_id.to_partial_guild(http).await.context("fetch guild")?;
-    let guild_file_limit = guild_filesize_limit(guild.premium_tier);
-    let discord_max = (guild_file_limit as f64 * cfg.discord_safe_ratio) as u64;
-    let tg_max = if tg_enabled {
-        (cfg.tg_file_limit_bytes as f64 * cfg.discord_safe_ratio) as u64
-    } else { discord_max };
-    let input_limit = discord_max.min(tg_max) as usize;
+    let (guild_file_limit, input_limit) = part_limits(http, guild_id, cfg, target).await?;
+    let input_limit = input_limit as usize;
 
     info!("ℹ️  input_limit: {:.1}MB/part", input_limit as f64 / 1024.0 / 1024.0);
 
-    let discord_sem = Arc::new(Semaphore::new(cfg.discord_parallel_sends));
-    let tg_sem      = Arc::new(Semaphore::new(cfg.tg_parallel_sends));
+    let discord_sem = Arc::clone(&gates.discord);
+    let tg_sem      = Arc::clone(&gates.tg);
     let reqwest_client = reqwest::Client::builder()
         .timeout(Duration::from_secs(cfg.http_timeout_s))
         .build()?;
@@ -180,194 +557,690 @@ async fn streaming_sender(
     let mut pending_chunks: HashMap<usize, Bytes> = HashMap::new();
     let mut next_expected = 0usize;
     let mut total_parts = 0u32;
-    let mut pending_tasks: Vec<(u32, JoinHandle<Result<PartInfo>>)> = vec![];
+    let mut total_bytes = 0u64;
+    let mut dispatched_bytes = 0u64;
+    let mut closed = false;
+    let mut pending_tasks: Vec<InFlight> = vec![];
     let mut all_parts: Vec<PartInfo> = vec![];
-    let mut message_ids = vec![];
-    let mut jump_urls = vec![];
+    let mut parts_done: HashSet<u32> = HashSet::new();
+    let mut budget = FailureBudget::new(target, cfg.failure_budget);
+    let mirror = target == UploadTarget::Mirror;
+    // After an unrecoverable part, nothing new is dispatched; in-flight parts are
+    // awaited so the report says exactly what is stored.
+    let mut failed: Vec<FailedPart> = vec![];
+    let mut optimized: Option<ImageOptimization> = None;
+    // Fresh parts are numbered in file order, so hashing them as they go out covers the whole file
+    let mut file_hash = blake3::Hasher::new();
+
+    let sequential = cfg.sequential_sends;
+    // Parts beyond this many are waiting for a send permit, holding their bytes in RAM.
+    let send_slots = match target {
+        UploadTarget::Discord  => cfg.discord_parallel_sends,
+        UploadTarget::Telegram => cfg.tg_parallel_sends,
+        UploadTarget::Dual | UploadTarget::Mirror => cfg.discord_parallel_sends + cfg.tg_parallel_sends,
+    };
+    let mut backed_up = false;
+    let dispatch = |part_num: u32, offset: u64, part_data: Bytes, use_tg: bool, rerouted: bool| InFlight {
+        part_num,
+        offset,
+        use_tg,
+        rerouted,
+        data: part_data.clone(),
+        handle: dispatch_part(
+            part_num, part_data, filename, message,
+            channel_id, Arc::clone(send_pool),
+            Arc::clone(&discord_sem), Arc::clone(&tg_sem),
+            Arc::clone(cfg), use_tg,
+            tg_token.to_string(), tg_chat.clone(),
+            reqwest_client.clone(), guild_file_limit, Arc::clone(diag), known_parts.cloned(),
+        ),
+    };
+
+    // Bundle mode: Discord-only uploads post up to `bundle_parts` parts per message
+    let bundle_size = if target == UploadTarget::Discord { cfg.bundle_parts } else { 1 };
+    let mut bundling: Vec<(u32, u64, Bytes)> = vec![];
+    let dispatch_many = |parts: Vec<(u32, u64, Bytes)>| -> Vec<InFlight> {
+        let handles = dispatch_bundle(
+            parts.iter().map(|(n, _, d)| (*n, d.clone())).collect(), filename, message,
+            channel_id, Arc::clone(send_pool), Arc::clone(&discord_sem),
+            Arc::clone(cfg), guild_file_limit, Arc::clone(diag),
+        );
+        parts.into_iter().zip(handles).map(|((part_num, offset, data), handle)| InFlight {
+            part_num, offset, use_tg: false, rerouted: false, data, handle,
+        }).collect()
+    };
+    // A fresh part: mirrored if asked, then held for the next bundle or sent on its own
+    macro_rules! send_fresh {
+        ($part:expr, $offset:expr, $data:expr) => {{
+            let (part, offset, data): (u32, u64, Bytes) = ($part, $offset, $data);
+            file_hash.update(&data);
+            if mirror { pending_tasks.push(dispatch(part, offset, data.clone(), true, false)); }
+            // A part stored before goes through dispatch(), which references it
+            let known = bundle_size > 1 && known_parts.is_some_and(|k| {
+                k.find(&content_hash(&data), data.len() as u64, "discord", part).is_some()
+            });
+            if bundle_size > 1 && !known {
+                bundling.push((part, offset, data));
+                if bundling.len() >= bundle_size { pending_tasks.extend(dispatch_many(std::mem::take(&mut bundling))); }
+            } else {
+                pending_tasks.push(dispatch(part, offset, data, budget.route(part), false));
+            }
+        }};
+    }
 
-    info!("🚀 Streaming sender: {filename} ({total_chunks} chunks, dual={tg_enabled})");
+    info!("🚀 Streaming sender: {filename} ({total_chunks} chunks, target={}, sequential={sequential})", target.as_str());
 
     loop {
-        // Drain channel without blocking
-        loop {
-            match chunk_rx.try_recv() {
-                Ok((idx, data)) => { pending_chunks.insert(idx, data); }
-                Err(_) => break,
+        // While backed up, leave chunks in the channel so the window stays put
+        // and clients beyond it get rejected instead of growing our buffer.
+        let was_backed_up = backed_up;
+        backed_up = pending_tasks.len() > send_slots
+            || (sequential && buffer.len() >= input_limit && !pending_tasks.is_empty());
+        if backed_up && !was_backed_up {
+            window.shrink();
+            info!("  🐢 Sender backed up ({} parts queued) → chunk window {}", pending_tasks.len(), window.size());
+        }
+        if !backed_up {
+            // Drain channel without blocking
+            while let Ok((idx, data)) = chunk_rx.try_recv() {
+                pending_chunks.insert(idx, data);
+            }
+            if pre_split {
+                // The client chose the part boundaries: each ordered chunk goes out
+                // as-is, without passing through the buffer.
+                while failed.is_empty() && (!sequential || pending_tasks.is_empty()) {
+                    let Some(data) = pending_chunks.remove(&next_expected) else { break };
+                    next_expected += 1;
+                    total_parts   += 1;
+                    total_bytes   += data.len() as u64;
+                    let offset = dispatched_bytes;
+                    dispatched_bytes += data.len() as u64;
+                    send_fresh!(total_parts, offset, data);
+                }
+            } else {
+                // Move ordered chunks into buffer
+                while let Some(data) = pending_chunks.remove(&next_expected) {
+                    buffer.extend_from_slice(&data);
+                    total_bytes += data.len() as u64;
+                    next_expected += 1;
+                }
             }
+            window.advance(next_expected);
         }
-        // Move ordered chunks into buffer
-        while let Some(data) = pending_chunks.remove(&next_expected) {
-            buffer.extend_from_slice(&data);
-            next_expected += 1;
+
+        let all_in = closed || (next_expected >= total_chunks && pending_chunks.is_empty());
+
+        // An image to optimize is held whole, then swapped for its recompressed bytes
+        if all_in {
+            if let Some(kind) = optimize.take() {
+                let (quality, original) = (cfg.image_quality, std::mem::take(&mut buffer));
+                // Waits for a media worker; spawn_blocking starts at once, so it goes inside
+                let (original, res) = media_pool::pool().run(async move {
+                    tokio::task::spawn_blocking(move || {
+                        let res = optimize::optimize(kind, &original, quality);
+                        (original, res)
+                    }).await
+                }).await?;
+                match res {
+                    Some((smaller, summary)) => {
+                        info!("  🗜️ {filename}: image optimized {:.1}MB → {:.1}MB",
+                            summary.original_bytes as f64 / 1024.0 / 1024.0, summary.optimized_bytes as f64 / 1024.0 / 1024.0);
+                        total_bytes = smaller.len() as u64;
+                        buffer = smaller;
+                        optimized = Some(summary);
+                    }
+                    None => {
+                        info!("  🗜️ {filename}: optimizing would not save enough → sent as is");
+                        buffer = original;
+                    }
+                }
+            }
         }
+        let holding = optimize.is_some();
 
-        // Dispatch full parts
-        while buffer.len() >= input_limit {
+        // Dispatch full parts; in sequential mode only one part may be in flight.
+        while !holding && failed.is_empty() && buffer.len() >= input_limit && (!sequential || pending_tasks.is_empty()) {
             total_parts += 1;
-            let part_data: Vec<u8> = buffer.drain(..input_limit).collect();
-            let use_tg = tg_enabled && (total_parts % 2 == 0);
-            pending_tasks.push((total_parts, dispatch_part(
-                total_parts, part_data, filename, message,
-                channel_id, Arc::clone(http),
-                Arc::clone(&discord_sem), Arc::clone(&tg_sem),
-                Arc::clone(cfg), use_tg,
-                tg_token.to_string(), tg_chat_id.to_string(),
-                reqwest_client.clone(), guild_file_limit,
-            )));
+            let part_data = Bytes::from(buffer.drain(..input_limit).collect::<Vec<u8>>());
+            let offset = dispatched_bytes;
+            dispatched_bytes += part_data.len() as u64;
+            send_fresh!(total_parts, offset, part_data);
         }
 
-        let all_in = next_expected >= total_chunks && pending_chunks.is_empty();
-
-        // Flush final part
-        if all_in && !buffer.is_empty() && pending_tasks.is_empty() {
+        // Flush the final (short) part as soon as EOF is known — no need to wait
+        // for earlier parts to finish unless sending strictly sequentially.
+        if failed.is_empty() && all_in && !buffer.is_empty() && buffer.len() < input_limit
+            && (!sequential || pending_tasks.is_empty())
+        {
             total_parts += 1;
-            let part_data: Vec<u8> = buffer.drain(..).collect();
-            let use_tg = tg_enabled && (total_parts % 2 == 0);
-            pending_tasks.push((total_parts, dispatch_part(
-                total_parts, part_data, filename, message,
-                channel_id, Arc::clone(http),
-                Arc::clone(&discord_sem), Arc::clone(&tg_sem),
-                Arc::clone(cfg), use_tg,
-                tg_token.to_string(), tg_chat_id.to_string(),
-                reqwest_client.clone(), guild_file_limit,
-            )));
+            let part_data = Bytes::from(std::mem::take(&mut buffer));
+            let offset = dispatched_bytes;
+            dispatched_bytes += part_data.len() as u64;
+            send_fresh!(total_parts, offset, part_data);
         }
 
-        // Collect finished tasks
+        // The last bundle goes out short once no more parts can join it
+        if failed.is_empty() && all_in && buffer.is_empty() && !bundling.is_empty() {
+            pending_tasks.extend(dispatch_many(std::mem::take(&mut bundling)));
+        }
+
+        // Collect finished tasks; a failed part is resent once on the other platform.
         let mut still = vec![];
-        for (pn, handle) in pending_tasks {
-            if handle.is_finished() {
-                let pi = handle.await.map_err(|e| anyhow!("{e}"))??;
-                info!("  ✅ Part {} ({}) done", pi.part, pi.platform);
-                message_ids.push(pi.message_id);
-                if let Some(ref u) = pi.jump_url { jump_urls.push(u.clone()); }
-                all_parts.push(pi);
-            } else {
-                still.push((pn, handle));
+        for task in pending_tasks {
+            if !task.handle.is_finished() {
+                still.push(task);
+                continue;
+            }
+            let res = task.handle.await.map_err(|e| anyhow!("{e}")).and_then(|r| r);
+            match res {
+                Ok(pi) => {
+                    budget.record_success(task.use_tg);
+                    if let Some(k) = known_parts { k.learn(&pi); }
+                    // Mirror copies carry the same bytes; count each part once
+                    if parts_done.insert(pi.part) { job.add(task.data.len() as u64); }
+                    window.grow();
+                    info!("  ✅ Part {} ({}) done", pi.part, pi.platform);
+                    all_parts.push(pi);
+                }
+                Err(e) => {
+                    let from = platform_name(task.use_tg);
+                    let fallback = budget.record_failure(task.use_tg);
+                    if budget.newly_degraded(task.use_tg) {
+                        warn!("⚠️  {from} failed {} parts in a row → sending the rest of {filename} via {}",
+                            cfg.failure_budget, platform_name(!task.use_tg));
+                        diag.lock().unwrap_or_else(|e| e.into_inner()).degraded.push(from.to_string());
+                    }
+                    match fallback {
+                        Some(use_tg) if !task.rerouted => {
+                            warn!("  ⚠️ Part {} failed on {from}: {e} → resending via {}", task.part_num, platform_name(use_tg));
+                            still.push(dispatch(task.part_num, task.offset, task.data, use_tg, true));
+                        }
+                        _ => {
+                            warn!("  ❌ Part {} failed on {from}: {e:#}", task.part_num);
+                            failed.push(FailedPart {
+                                part:     task.part_num,
+                                platform: from.to_string(),
+                                error:    format!("{e:#}"),
+                                offset:   task.offset,
+                                length:   task.data.len() as u64,
+                            });
+                        }
+                    }
+                }
             }
         }
         pending_tasks = still;
 
-        if all_in && buffer.is_empty() && pending_tasks.is_empty() { break; }
+        if !failed.is_empty() && pending_tasks.is_empty() {
+            all_parts.sort_by_key(|p| p.part);
+            failed.sort_by_key(|p| p.part);
+            // Parts still waiting for a bundle were numbered but never sent
+            let (dispatched, unsent_from) = match bundling.first() {
+                Some(&(part, offset, _)) => (part - 1, offset),
+                None                     => (total_parts, dispatched_bytes),
+            };
+            return Err(UploadFailed(FailureReport {
+                target:      target.as_str().to_string(),
+                part_bytes:  input_limit as u64,
+                succeeded:   all_parts,
+                failed,
+                dispatched,
+                unsent_from,
+                optimized:   optimized.is_some(),
+            }).into());
+        }
+        if all_in && buffer.is_empty() && bundling.is_empty() && pending_tasks.is_empty() { break; }
 
-        if pending_tasks.is_empty() {
+        if pending_tasks.is_empty() && !all_in {
             // Block until next chunk arrives or channel closes
             match chunk_rx.recv().await {
                 Some((idx, data)) => { pending_chunks.insert(idx, data); }
-                None => {
-                    // Flush remaining
-                    if !buffer.is_empty() {
-                        total_parts += 1;
-                        let part_data: Vec<u8> = buffer.drain(..).collect();
-                        let use_tg = tg_enabled && (total_parts % 2 == 0);
-                        let h = dispatch_part(
-                            total_parts, part_data, filename, message,
-                            channel_id, Arc::clone(http),
-                            Arc::clone(&discord_sem), Arc::clone(&tg_sem),
-                            Arc::clone(cfg), use_tg,
-                            tg_token.to_string(), tg_chat_id.to_string(),
-                            reqwest_client.clone(), guild_file_limit,
-                        );
-                        let pi = h.await.map_err(|e| anyhow!("{e}"))??;
-                        message_ids.push(pi.message_id);
-                        if let Some(ref u) = pi.jump_url { jump_urls.push(u.clone()); }
-                        all_parts.push(pi);
-                    }
-                    break;
-                }
+                None => closed = true, // flush whatever is buffered on the next pass
             }
         } else {
             sleep(Duration::from_millis(50)).await;
         }
     }
 
-    all_parts.sort_by_key(|p| p.part);
-    let method = if total_parts == 1 { "direct" }
-        else if tg_enabled { "dual" }
-        else { "split" };
+    let mut result = sender_result(all_parts, total_parts, total_bytes, mirror);
+    result.optimized = optimized;
+    result.blake3    = Some(file_hash.finalize().to_hex().to_string());
+    info!("✅ Streaming sender done: {filename} ({total_parts} parts, method={})", result.method);
+    Ok(result)
+}
 
-    info!("✅ Streaming sender done: {filename} ({total_parts} parts, method={method})");
-    Ok(SenderResult {
-        method: method.to_string(),
-        parts: total_parts,
-        parts_info: all_parts,
-        message_ids,
-        jump_urls,
-    })
+/// A part being sent; `data` is kept so the part can be resent elsewhere if it fails.
+struct InFlight {
+    part_num: u32,
+    offset:   u64,     // of the part's first byte in the file
+    use_tg:   bool,
+    rerouted: bool,
+    data:     Bytes,
+    handle:   JoinHandle<Result<PartInfo>>,
+}
+
+fn platform_name(use_tg: bool) -> &'static str {
+    if use_tg { "telegram" } else { "discord" }
+}
+
+/// Consecutive-failure counters per platform (index 0 = Discord, 1 = Telegram).
+/// Once a platform reaches the budget it is degraded and every later part of this
+/// upload goes to the other one. Only dual mode reroutes: a single-platform or
+/// mirrored upload fails as soon as a part can't be sent where it was asked to go.
+struct FailureBudget {
+    target:    UploadTarget,
+    limit:     u32,
+    streak:    [u32; 2],
+    degraded:  [bool; 2],
+    announced: [bool; 2],
+}
+
+impl FailureBudget {
+    fn new(target: UploadTarget, limit: u32) -> Self {
+        Self { target, limit, streak: [0; 2], degraded: [false; 2], announced: [false; 2] }
+    }
+
+    /// Platform for a fresh part: even parts go to Telegram unless it is degraded.
+    /// In mirror mode this is the Discord copy; the Telegram copy is dispatched alongside.
+    fn route(&self, part_num: u32) -> bool {
+        match self.target {
+            UploadTarget::Discord | UploadTarget::Mirror => return false,
+            UploadTarget::Telegram => return true,
+            UploadTarget::Dual => {}
+        }
+        let use_tg = part_num % 2 == 0;
+        if self.degraded[use_tg as usize] { !use_tg } else { use_tg }
+    }
+
+    fn record_success(&mut self, use_tg: bool) {
+        self.streak[use_tg as usize] = 0;
+    }
+
+    /// Returns the platform to resend the failed part on, or None if there is none left.
+    fn record_failure(&mut self, use_tg: bool) -> Option<bool> {
+        if self.target != UploadTarget::Dual { return None; }
+        let i = use_tg as usize;
+        self.streak[i] += 1;
+        if self.streak[i] >= self.limit { self.degraded[i] = true; }
+        (!self.degraded[1 - i]).then_some(!use_tg)
+    }
+
+    /// True exactly once, right after `use_tg`'s platform became degraded.
+    fn newly_degraded(&mut self, use_tg: bool) -> bool {
+        let i = use_tg as usize;
+        let fresh = self.degraded[i] && !self.announced[i];
+        self.announced[i] |= fresh;
+        fresh
+    }
+}
+
+/// Send permits for one platform. Past the safe parallelism (`unsafe_overrides`)
+/// each permit also waits for its slot, `interval` after the previous one.
+pub struct SendGate {
+    permits:  Semaphore,
+    interval: Duration,
+    next:     std::sync::Mutex<Instant>,
+}
+
+impl SendGate {
+    pub fn new(permits: usize, interval_ms: u64) -> Self {
+        Self {
+            permits:  Semaphore::new(permits),
+            interval: Duration::from_millis(interval_ms),
+            next:     std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>> {
+        let permit = self.permits.acquire().await?;
+        if !self.interval.is_zero() {
+            let slot = {
+                let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+                let slot = (*next).max(Instant::now());
+                *next = slot + self.interval;
+                slot
+            };
+            sleep_until(slot.into()).await;
+        }
+        Ok(permit)
+    }
+}
+
+/// One profile's send gates, built once and shared by every upload and part
+/// resend, so the caps and pacing hold across all of them.
+pub struct SendGates {
+    pub discord: Arc<SendGate>,
+    pub tg:      Arc<SendGate>,
+}
+
+impl SendGates {
+    pub fn new(cfg: &Config, bots: usize) -> Self {
+        // Rate limits are per bot, so each extra bot adds its own share of parallel sends
+        let bots = bots.max(1);
+        Self {
+            discord: Arc::new(SendGate::new(cfg.discord_parallel_sends * bots, cfg.discord_send_interval_ms / bots as u64)),
+            tg:      Arc::new(SendGate::new(cfg.tg_parallel_sends, cfg.tg_send_interval_ms)),
+        }
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
 fn dispatch_part(
     part_num:    u32,
-    part_data:   Vec<u8>,
+    part_data:   Bytes,
     filename:    &str,
     message:     &str,
     channel_id:  ChannelId,
-    http:        Arc<Http>,
-    discord_sem: Arc<Semaphore>,
-    tg_sem:      Arc<Semaphore>,
+    send_pool:   Arc<HttpPool>,
+    discord_sem: Arc<SendGate>,
+    tg_sem:      Arc<SendGate>,
     cfg:         Arc<Config>,
     use_tg:      bool,
     tg_token:    String,
-    tg_chat_id:  String,
+    tg_chat:     telegram::Chat,
     http_client: reqwest::Client,
     guild_limit: u64,
+    diag:        DiagHandle,
+    known_parts: Option<Arc<PartIndex>>,
 ) -> JoinHandle<Result<PartInfo>> {
     let filename  = filename.to_string();
     let message   = message.to_string();
     tokio::spawn(async move {
         let caption   = build_caption(&filename, &message, part_num);
         let part_name = format!("{filename}.part{part_num}");
+        let mut timing = PartTiming {
+            part:     part_num,
+            platform: if use_tg { "telegram" } else { "discord" }.to_string(),
+            bytes:    part_data.len() as u64,
+            ..Default::default()
+        };
+        let queued = Instant::now();
+        let hash = content_hash(&part_data);
+        let raw_bytes = part_data.len() as u64;
 
-        if use_tg {
-            let _permit = tg_sem.acquire().await?;
-            let (msg_id, file_id) = telegram::send_part(
-                &http_client, &cfg, &tg_token, &tg_chat_id,
-                &part_data, part_num, &filename, &caption,
-            ).await?;
-            Ok(PartInfo {
-                part: part_num, platform: "telegram".to_string(),
-                message_id: msg_id, channel_id: None,
-                file_id: Some(file_id), jump_url: None,
-            })
-        } else {
-            let _permit = discord_sem.acquire().await?;
-            let zip_data = tokio::task::spawn_blocking({
-                let data = part_data.clone();
-                let pname = part_name.clone();
-                let level = cfg.zip_compress_level;
-                move || zip_bytes(&data, &pname, level)
-            }).await??;
-
-            if zip_data.len() as u64 > guild_limit {
-                anyhow::bail!("Part {part_num} ({:.1}MB) > guild limit. Reduce client_chunk_mb.",
-                    zip_data.len() as f64 / 1024.0 / 1024.0);
-            }
+        if let Some(stored) = known_parts.and_then(|k| k.find(&hash, raw_bytes, &timing.platform, part_num)) {
+            info!("  ♻️ Part {part_num} already stored (message {}) → referenced", stored.message_id);
+            timing.reused = true;
+            diag.lock().unwrap_or_else(|e| e.into_inner()).parts.push(timing);
+            return Ok(stored);
+        }
 
-            let mut last_err = None;
-            for attempt in 0..cfg.discord_send_retries {
-                match discord_bot::send_part(
-                    &http, channel_id,
-                    zip_data.clone(), format!("{part_name}.zip"), caption.clone(),
-                ).await {
-                    Ok((msg_id, jump_url)) => return Ok(PartInfo {
+        let res = async {
+            let (part_data, encrypted) = crypto::seal(&cfg, part_data.clone()).await?;
+            if use_tg {
+                let _permit = tg_sem.acquire().await?;
+                timing.queue_wait_ms = queued.elapsed().as_millis() as u64;
+                let (msg_id, file_id, stored_bytes) = telegram::send_part(
+                    &http_client, &cfg, &tg_token, &tg_chat,
+                    &part_data, part_num, &filename, &caption, &mut timing,
+                ).await?;
+                Ok(PartInfo {
+                    part: part_num, platform: "telegram".to_string(),
+                    message_id: msg_id, channel_id: None,
+                    file_id: Some(file_id), jump_url: None,
+                    stored_bytes, raw_bytes, topic_id: tg_chat.topic, attachment: None,
+                    blake3: Some(hash.clone()), encrypted,
+                })
+            } else {
+                let _permit = discord_sem.acquire().await?;
+                timing.queue_wait_ms = queued.elapsed().as_millis() as u64;
+                let zip_start = Instant::now();
+                let zip_data = tokio::task::spawn_blocking({
+                    let data = part_data.clone();
+                    let pname = part_name.clone();
+                    let level = cfg.zip_compress_level;
+                    let password = cfg.zip_password.clone();
+                    move || zip_bytes(&data, &pname, level, password.as_deref())
+                }).await??;
+                timing.zip_ms = zip_start.elapsed().as_millis() as u64;
+
+                if zip_data.len() as u64 > guild_limit {
+                    anyhow::bail!("Part {part_num} ({:.1}MB) > guild limit. Reduce client_chunk_mb.",
+                        zip_data.len() as f64 / 1024.0 / 1024.0);
+                }
+
+                if cfg.simulate {
+                    let msg_id = simulate::send_part(&cfg, "discord", &zip_data, part_num, &mut timing).await?;
+                    return Ok(PartInfo {
                         part: part_num, platform: "discord".to_string(),
                         message_id: msg_id,
                         channel_id: Some(channel_id.get().to_string()),
-                        file_id: None, jump_url: Some(jump_url),
-                    }),
+                        file_id: None, jump_url: Some(simulate::jump_url(channel_id, msg_id)),
+                        stored_bytes: zip_data.len() as u64, raw_bytes,
+                        topic_id: None, attachment: None, blake3: Some(hash.clone()), encrypted,
+                    });
+                }
+
+                let send_start = Instant::now();
+                let mut last_err = None;
+                for attempt in 0..cfg.discord_send_retries {
+                    timing.retries = attempt;
+                    // A retry goes out through the next bot, away from the one that failed
+                    match discord_bot::send_part(
+                        &send_pool.next(), channel_id,
+                        zip_data.clone(), format!("{part_name}.zip"), caption.clone(),
+                    ).await {
+                        Ok((msg_id, jump_url)) => {
+                            timing.send_ms = send_start.elapsed().as_millis() as u64;
+                            return Ok(PartInfo {
+                                part: part_num, platform: "discord".to_string(),
+                                message_id: msg_id,
+                                channel_id: Some(channel_id.get().to_string()),
+                                file_id: None, jump_url: Some(jump_url),
+                                stored_bytes: zip_data.len() as u64, raw_bytes,
+                                topic_id: None, attachment: None, blake3: Some(hash.clone()), encrypted,
+                            });
+                        }
+                        Err(e) => {
+                            last_err = Some(e);
+                            if attempt < cfg.discord_send_retries - 1 {
+                                warn!("  ⚠️ Discord retry {}/{}", attempt+1, cfg.discord_send_retries);
+                                sleep(Duration::from_secs(cfg.discord_retry_base_s.pow(attempt))).await;
+                            }
+                        }
+                    }
+                }
+                timing.send_ms = send_start.elapsed().as_millis() as u64;
+                Err(last_err.unwrap_or_else(|| anyhow!("Discord send failed")))
+            }
+        }.await;
+
+        if let Err(ref e) = res { timing.error = Some(e.to_string()); }
+        diag.lock().unwrap_or_else(|e| e.into_inner()).parts.push(timing);
+        res
+    }.instrument(info_span!("part", n = part_num)))
+}
+
+/// Bundle mode: zip each part as usual, then post them all as the attachments of
+/// one Discord message. Each part still gets its own handle, resolved with its
+/// `PartInfo` (carrying the attachment index) or with the bundle's error.
+#[allow(clippy::too_many_arguments)]
+fn dispatch_bundle(
+    parts:       Vec<(u32, Bytes)>,
+    filename:    &str,
+    message:     &str,
+    channel_id:  ChannelId,
+    send_pool:   Arc<HttpPool>,
+    discord_sem: Arc<SendGate>,
+    cfg:         Arc<Config>,
+    guild_limit: u64,
+    diag:        DiagHandle,
+) -> Vec<JoinHandle<Result<PartInfo>>> {
+    let (txs, rxs): (Vec<_>, Vec<_>) = parts.iter().map(|_| oneshot::channel::<Result<PartInfo>>()).unzip();
+    let (first, last) = (parts[0].0, parts[parts.len() - 1].0);
+    let filename = filename.to_string();
+    let message  = message.to_string();
+    tokio::spawn(async move {
+        let mut timings: Vec<PartTiming> = parts.iter().map(|(n, data)| PartTiming {
+            part:     *n,
+            platform: "discord".to_string(),
+            bytes:    data.len() as u64,
+            ..Default::default()
+        }).collect();
+        let res = send_bundle(&parts, &filename, &message, channel_id, &send_pool, &discord_sem, &cfg, guild_limit, &mut timings).await;
+        if let Err(ref e) = res {
+            for t in &mut timings { t.error = Some(e.to_string()); }
+        }
+        diag.lock().unwrap_or_else(|e| e.into_inner()).parts.extend(timings);
+        match res {
+            Ok(infos) => for (tx, info) in txs.into_iter().zip(infos) { let _ = tx.send(Ok(info)); },
+            Err(e) => {
+                let e = format!("{e:#}");
+                for tx in txs { let _ = tx.send(Err(anyhow!("{e}"))); }
+            }
+        }
+    }.instrument(info_span!("bundle", first, last)));
+    rxs.into_iter().map(|rx| tokio::spawn(async move {
+        rx.await.unwrap_or_else(|_| Err(anyhow!("bundle task ended without a result")))
+    })).collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_bundle(
+    parts:       &[(u32, Bytes)],
+    filename:    &str,
+    message:     &str,
+    channel_id:  ChannelId,
+    send_pool:   &HttpPool,
+    discord_sem: &SendGate,
+    cfg:         &Config,
+    guild_limit: u64,
+    timings:     &mut [PartTiming],
+) -> Result<Vec<PartInfo>> {
+    let queued = Instant::now();
+    let _permit = discord_sem.acquire().await?;
+    let mut files = Vec::with_capacity(parts.len());
+    let mut sealed = Vec::with_capacity(parts.len());
+    for ((part_num, data), timing) in parts.iter().zip(timings.iter_mut()) {
+        timing.queue_wait_ms = queued.elapsed().as_millis() as u64;
+        let zip_start = Instant::now();
+        let part_name = format!("{filename}.part{part_num}");
+        let (data, encrypted) = crypto::seal(cfg, data.clone()).await?;
+        let zip_data = tokio::task::spawn_blocking({
+            let (data, pname) = (data, part_name.clone());
+            let (level, password) = (cfg.zip_compress_level, cfg.zip_password.clone());
+            move || zip_bytes(&data, &pname, level, password.as_deref())
+        }).await??;
+        timing.zip_ms = zip_start.elapsed().as_millis() as u64;
+        if zip_data.len() as u64 > guild_limit {
+            anyhow::bail!("Part {part_num} ({:.1}MB) > guild limit. Reduce client_chunk_mb.",
+                zip_data.len() as f64 / 1024.0 / 1024.0);
+        }
+        files.push((zip_data, format!("{part_name}.zip")));
+        sealed.push(encrypted);
+    }
+    // Discord caps the whole message, not each attachment: once the zipped parts
+    // add up to the guild limit, the rest go out in another message
+    let mut groups: Vec<std::ops::Range<usize>> = vec![];
+    let (mut from, mut size) = (0, 0u64);
+    for (i, (zip, _)) in files.iter().enumerate() {
+        let len = zip.len() as u64;
+        if i > from && size + len > guild_limit {
+            groups.push(from..i);
+            (from, size) = (i, 0);
+        }
+        size += len;
+    }
+    groups.push(from..files.len());
+
+    let mut infos = Vec::with_capacity(parts.len());
+    for group in groups {
+        let (first, last) = (parts[group.start].0, parts[group.end - 1].0);
+        let caption = build_bundle_caption(filename, message, first, last);
+        let message_files = files[group.clone()].to_vec();
+        let mut shared = PartTiming::default();
+        let sent = if cfg.simulate {
+            let zips: Vec<&[u8]> = message_files.iter().map(|(z, _)| z.as_slice()).collect();
+            let msg_id = simulate::send_bundle(cfg, &zips, first, &mut shared).await?;
+            (msg_id, simulate::jump_url(channel_id, msg_id))
+        } else {
+            let send_start = Instant::now();
+            let mut last_err = None;
+            let mut sent = None;
+            for attempt in 0..cfg.discord_send_retries {
+                shared.retries = attempt;
+                // A retry goes out through the next bot, away from the one that failed
+                match discord_bot::send_bundle(&send_pool.next(), channel_id, message_files.clone(), caption.clone()).await {
+                    Ok(ok) => { sent = Some(ok); break; }
                     Err(e) => {
                         last_err = Some(e);
                         if attempt < cfg.discord_send_retries - 1 {
-                            warn!("  ⚠️ Discord retry {}/{}", attempt+1, cfg.discord_send_retries);
+                            warn!("  ⚠️ Discord retry {}/{} (parts {first}–{last})", attempt + 1, cfg.discord_send_retries);
                             sleep(Duration::from_secs(cfg.discord_retry_base_s.pow(attempt))).await;
                         }
                     }
                 }
             }
-            Err(last_err.unwrap_or_else(|| anyhow!("Discord send failed")))
+            shared.send_ms = send_start.elapsed().as_millis() as u64;
+            sent.ok_or_else(|| last_err.unwrap_or_else(|| anyhow!("Discord send failed")))?
+        };
+        for t in &mut timings[group.clone()] { t.send_ms = shared.send_ms; t.retries = shared.retries; }
+
+        let (msg_id, jump_url) = sent;
+        for (i, at) in group.enumerate() {
+            let (part_num, data) = &parts[at];
+            infos.push(PartInfo {
+                part: *part_num, platform: "discord".to_string(),
+                message_id: msg_id,
+                channel_id: Some(channel_id.get().to_string()),
+                file_id: None, jump_url: Some(jump_url.clone()),
+                stored_bytes: files[at].0.len() as u64, raw_bytes: data.len() as u64,
+                topic_id: None, attachment: Some(i as u32),
+                blake3: Some(content_hash(data)), encrypted: sealed[at],
+            });
         }
-    })
+    }
+    Ok(infos)
+}
+
+/// Everything needed to send single parts of a failed upload again.
+pub struct Resend {
+    pub filename:   String,
+    pub message:    String,
+    pub channel_id: ChannelId,
+    pub http:       Arc<Http>,
+    pub send_pool:  Arc<HttpPool>,
+    pub gates:      Arc<SendGates>,
+    pub guild_id:   GuildId,
+    pub cfg:        Arc<Config>,
+    pub target:     UploadTarget,
+    pub tg_token:   String,
+    pub tg_chat:    telegram::Chat,
+    pub diag:       DiagHandle,
+}
+
+/// Store one part on each platform in `platforms` ("discord" | "telegram"). In
+/// dual mode a copy that fails is tried once on the other platform.
+pub async fn resend_part(r: &Resend, part_num: u32, data: Bytes, platforms: &[String]) -> Result<Vec<PartInfo>> {
+    let (guild_limit, _) = part_limits(&r.http, r.guild_id, &r.cfg, r.target).await?;
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(r.cfg.http_timeout_s))
+        .build()?;
+    let send = |use_tg: bool| dispatch_part(
+        part_num, data.clone(), &r.filename, &r.message, r.channel_id, Arc::clone(&r.send_pool),
+        Arc::clone(&r.gates.discord), Arc::clone(&r.gates.tg), Arc::clone(&r.cfg), use_tg,
+        r.tg_token.clone(), r.tg_chat.clone(), client.clone(), guild_limit, Arc::clone(&r.diag), None,
+    );
+    let mut stored = vec![];
+    for platform in platforms {
+        let use_tg = platform == "telegram";
+        let res = match send(use_tg).await.map_err(|e| anyhow!("{e}")).and_then(|r| r) {
+            Err(e) if r.target == UploadTarget::Dual => {
+                warn!("  ⚠️ Part {part_num} failed again on {platform}: {e} → trying {}", platform_name(!use_tg));
+                send(!use_tg).await.map_err(|e| anyhow!("{e}")).and_then(|r| r)
+            }
+            res => res,
+        };
+        stored.push(res.with_context(|| format!("part {part_num} ({platform})"))?);
+    }
+    Ok(stored)
+}
+
+/// Same shape as `build_caption` with the part range, so renames still find the file name.
+fn build_bundle_caption(filename: &str, message: &str, first: u32, last: u32) -> String {
+    let mut c = format!("✂️ `{filename}` — Phần {first}–{last}");
+    if !message.is_empty() && first == 1 { c.push('\n'); c.push_str(message); }
+    c
 }
 
 fn build_caption(filename: &str, message: &str, part_num: u32) -> String {
@@ -375,3 +1248,100 @@ fn build_caption(filename: &str, message: &str, part_num: u32) -> String {
     if !message.is_empty() && part_num == 1 { c.push('\n'); c.push_str(message); }
     c
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Discord-only simulated setup under a fresh temp dir, one send attempt per
+    /// part and parts of about 54 KB.
+    fn sim_config(error_rate: f64) -> Config {
+        let dir = std::env::temp_dir().join(format!("upload-test-{}", uuid::Uuid::new_v4().simple()));
+        let mut cfg = Config::load(&dir);
+        cfg.simulate              = true;
+        cfg.sim_latency_ms        = 0;
+        cfg.sim_jitter_ms         = 0;
+        cfg.sim_error_rate        = error_rate;
+        cfg.sim_guild_limit_bytes = 64 * 1024;
+        cfg.discord_send_retries  = 1;
+        cfg
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    fn pool() -> Arc<HttpPool> {
+        Arc::new(HttpPool::new(Arc::new(Http::new("")), vec![]))
+    }
+
+    /// Feed `data` as one chunk and run the sender to the end.
+    async fn send(cfg: &Arc<Config>, channel_id: ChannelId, data: &[u8]) -> Result<SenderResult> {
+        let (tx, rx) = mpsc::channel(1);
+        tx.send((0, Bytes::copy_from_slice(data))).await.unwrap();
+        drop(tx);
+        let pool = pool();
+        streaming_sender(
+            "test", "sample.bin", "", 1, channel_id,
+            &Arc::new(Http::new("")), &pool, &SendGates::new(cfg, pool.len()), simulate::guild_id(), cfg,
+            UploadTarget::Discord, false, None, "", &telegram::Chat { id: String::new(), topic: None },
+            rx, &new_diagnostics("test", "sample.bin"), &ChunkWindow::new(4),
+            &progress::Job::start(progress::Kind::Upload, data.len() as u64), None,
+        ).await
+    }
+
+    #[tokio::test]
+    async fn simulated_upload_stores_every_part() {
+        let cfg = Arc::new(sim_config(0.0));
+        let data = sample(150_000);
+        let (channel_id, _) = simulate::channel("sample.bin");
+        let result = send(&cfg, channel_id, &data).await.unwrap();
+        assert_eq!(result.parts, 3);
+        assert_eq!(result.total_bytes, data.len() as u64);
+        assert_eq!(result.parts_info.iter().map(|p| p.raw_bytes).sum::<u64>(), data.len() as u64);
+        assert_eq!(result.blake3, Some(blake3::hash(&data).to_hex().to_string()));
+        let _ = std::fs::remove_dir_all(cfg.sim_dir.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_parts_are_reported_missing_and_resent() {
+        let cfg = Arc::new(sim_config(1.0));
+        let data = sample(150_000);
+        let (channel_id, _) = simulate::channel("sample.bin");
+        let err = send(&cfg, channel_id, &data).await.unwrap_err();
+        let UploadFailed(report) = err.downcast::<UploadFailed>().unwrap();
+        assert!(report.succeeded.is_empty());
+        assert_eq!(report.failed.len(), 3);
+
+        let missing = report.missing(data.len() as u64);
+        assert_eq!(missing.iter().map(|m| m.part).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(missing.iter().map(|m| m.length).sum::<u64>(), data.len() as u64);
+        assert!(missing.iter().all(|m| m.platforms == ["discord"]));
+
+        // The platform is back: each missing part goes through on retry
+        let retry_cfg = Arc::new(Config { sim_error_rate: 0.0, ..(*cfg).clone() });
+        let pool = pool();
+        let resend = Resend {
+            filename:   "sample.bin".to_string(),
+            message:    String::new(),
+            channel_id,
+            http:       Arc::new(Http::new("")),
+            gates:      Arc::new(SendGates::new(&retry_cfg, pool.len())),
+            send_pool:  pool,
+            guild_id:   simulate::guild_id(),
+            cfg:        Arc::clone(&retry_cfg),
+            target:     UploadTarget::Discord,
+            tg_token:   String::new(),
+            tg_chat:    telegram::Chat { id: String::new(), topic: None },
+            diag:       new_diagnostics("test", "sample.bin"),
+        };
+        for m in &missing {
+            let bytes = Bytes::copy_from_slice(&data[m.offset as usize..(m.offset + m.length) as usize]);
+            let stored = resend_part(&resend, m.part, bytes, &m.platforms).await.unwrap();
+            assert_eq!(stored.len(), 1);
+            assert_eq!(stored[0].part, m.part);
+            assert_eq!(stored[0].raw_bytes, m.length);
+        }
+        let _ = std::fs::remove_dir_all(cfg.sim_dir.parent().unwrap());
+    }
+}
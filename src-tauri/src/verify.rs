@@ -0,0 +1,303 @@
+/// verify.rs — Part health checks, optionally mirrored as ✅/⚠️ reactions on the
+/// part messages so storage health is visible when browsing the raw channels.
+///
+/// A shallow check asks the platform whether each stored copy is still there at its
+/// recorded size; a deep one downloads and unzips it. A part is healthy only when
+/// every copy passes, so a mirrored part that lost its Telegram copy is marked ⚠️.
+/// `verify_source` additionally compares the parts with the original file, for
+/// queue items that delete or move their source afterwards. `verify_hash`
+/// re-downloads the whole file and checks it against the BLAKE3 hashes recorded at upload.
+use serde::Serialize;
+use std::{collections::HashSet, path::Path, sync::Arc};
+use tokio::io::{AsyncReadExt, BufReader};
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    discord_bot, download,
+    keyring::{self, KeyError},
+    simulate,
+    state::AppState,
+    storage::{FileRecord, PartInfo},
+    telegram,
+    upload::content_hash,
+};
+
+#[derive(Debug, Serialize)]
+pub struct PartCheck {
+    pub part:       u32,
+    pub platform:   String,
+    pub message_id: i64,
+    pub ok:         bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error:      Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    pub file_id:  i64,
+    pub deep:     bool,
+    pub healthy:  bool,
+    pub degraded: Vec<u32>,           // part numbers with at least one bad copy
+    pub parts:    Vec<PartCheck>,
+    pub marked:   usize,              // Discord messages that got a status reaction
+}
+
+/// One part of an end-to-end check: `ok`, `corrupt` (downloaded, but no copy
+/// has the recorded hash/size) or `missing` (not recorded, or no copy could be fetched).
+#[derive(Debug, Serialize)]
+pub struct PartHash {
+    pub part:     u32,
+    pub status:   &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,  // the copy that passed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors:   Vec<String>,     // one per copy that failed
+}
+
+#[derive(Debug, Serialize)]
+pub struct HashReport {
+    pub file_id:        i64,
+    pub healthy:        bool,
+    pub expected_hash:  Option<String>,
+    pub actual_hash:    Option<String>, // None when a part couldn't be downloaded
+    pub hash_matches:   Option<bool>,   // None when either side is unknown
+    pub expected_bytes: u64,
+    pub actual_bytes:   u64,
+    pub corrupt:        Vec<u32>,
+    pub missing:        Vec<u32>,
+    pub parts:          Vec<PartHash>,
+}
+
+/// `read_cfg` carries the file's data key (keyring.rs); only deep checks need it.
+async fn check(
+    st: &AppState, p: &PartInfo, deep: bool, read_cfg: &Result<Arc<Config>, KeyError>, tg_client: &reqwest::Client,
+) -> anyhow::Result<()> {
+    if deep {
+        let cfg = read_cfg.as_ref().map_err(|e| anyhow::anyhow!("{e}"))?;
+        download::fetch_part(p, &st.http, cfg, tg_client, &st.tg_token).await?;
+        return Ok(());
+    }
+    let size = if st.cfg.simulate {
+        Some(simulate::part_size(&st.cfg, p).await?)
+    } else if p.platform == "telegram" {
+        let file_id = p.file_id.as_deref().ok_or_else(|| anyhow::anyhow!("no file_id"))?;
+        telegram::file_size(tg_client, &st.cfg, &st.tg_token, file_id).await?
+    } else {
+        let ch = p.channel_id.as_deref().and_then(|c| c.parse::<u64>().ok())
+            .ok_or_else(|| anyhow::anyhow!("no channel_id"))?;
+        Some(discord_bot::attachment_size(&st.http, ch, p.message_id as u64, p.attachment_index()).await?)
+    };
+    match size {
+        Some(n) if p.stored_bytes > 0 && n != p.stored_bytes =>
+            anyhow::bail!("size {n} ≠ recorded {}", p.stored_bytes),
+        _ => Ok(()),
+    }
+}
+
+/// Check every stored copy of every part; with `st.cfg.status_reactions` the
+/// Discord messages are marked with the outcome.
+pub async fn verify(st: &AppState, record: &FileRecord, deep: bool) -> VerifyReport {
+    run(st, record, deep, st.cfg.status_reactions).await
+}
+
+/// Same checks without touching the messages, for parts this profile does not own
+/// yet (manifest imports).
+pub async fn probe(st: &AppState, record: &FileRecord, deep: bool) -> VerifyReport {
+    run(st, record, deep, false).await
+}
+
+async fn run(st: &AppState, record: &FileRecord, deep: bool, react: bool) -> VerifyReport {
+    let tg_client = reqwest::Client::new();
+    let read_cfg = keyring::config_for(st, record.wrapped_key.as_deref());
+    let mut report = VerifyReport {
+        file_id: record.id, deep, healthy: true, degraded: vec![], parts: vec![], marked: 0,
+    };
+    let mut flagged = HashSet::new();
+    for copies in download::part_copies(record) {
+        let mut part_ok = true;
+        for p in &copies {
+            let res = check(st, p, deep, &read_cfg, &tg_client).await;
+            part_ok &= res.is_ok();
+            report.parts.push(PartCheck {
+                part: p.part, platform: p.platform.clone(), message_id: p.message_id,
+                ok: res.is_ok(), error: res.err().map(|e| e.to_string()),
+            });
+        }
+        if !part_ok {
+            report.healthy = false;
+            report.degraded.push(copies[0].part);
+        }
+        if react {
+            report.marked += mark(st, &copies, part_ok, &mut flagged).await;
+        }
+    }
+    if report.healthy {
+        info!("🩺 {} verified: {} part(s) healthy", record.filename, report.parts.len());
+    } else {
+        warn!("🩺 {} degraded: parts {:?}", record.filename, report.degraded);
+    }
+    report
+}
+
+/// Deep-check every copy of `record` against the file it was uploaded from: each
+/// part must download, unzip and byte-match its slice of `source`, and together the
+/// parts must cover the whole file.
+pub async fn verify_source(st: &AppState, record: &FileRecord, source: &Path) -> anyhow::Result<VerifyReport> {
+    let cfg = keyring::config_for(st, record.wrapped_key.as_deref()).map_err(|e| anyhow::anyhow!("{e}"))?;
+    let tg_client = reqwest::Client::new();
+    let mut src = BufReader::new(tokio::fs::File::open(source).await?);
+    let mut report = VerifyReport {
+        file_id: record.id, deep: true, healthy: true, degraded: vec![], parts: vec![], marked: 0,
+    };
+    let mut flagged = HashSet::new();
+    // Once a part can't be fetched from any copy, later parts can't be lined up with the source
+    let mut aligned = true;
+    for copies in download::part_copies(record) {
+        let mut expected: Option<Vec<u8>> = None;
+        let mut part_ok = true;
+        for p in &copies {
+            let res = match download::fetch_part(p, &st.http, &cfg, &tg_client, &st.tg_token).await {
+                Err(e) => Err(e.to_string()),
+                Ok(data) => match &expected {
+                    Some(exp) if *exp != data => Err("nội dung khác bản sao đầu tiên".to_string()),
+                    Some(_) => Ok(()),
+                    None if !aligned => Err("không so được: part trước đó không tải được".to_string()),
+                    None => {
+                        let mut slice = vec![0u8; data.len()];
+                        let res = match src.read_exact(&mut slice).await {
+                            Ok(_) if slice == data => Ok(()),
+                            Ok(_)  => Err("nội dung không khớp file nguồn".to_string()),
+                            Err(_) => Err("dài hơn file nguồn".to_string()),
+                        };
+                        expected = Some(data);
+                        res
+                    }
+                },
+            };
+            part_ok &= res.is_ok();
+            report.parts.push(PartCheck {
+                part: p.part, platform: p.platform.clone(), message_id: p.message_id,
+                ok: res.is_ok(), error: res.err(),
+            });
+        }
+        aligned &= expected.is_some();
+        if !part_ok {
+            report.healthy = false;
+            report.degraded.push(copies[0].part);
+        }
+        if st.cfg.status_reactions {
+            report.marked += mark(st, &copies, part_ok, &mut flagged).await;
+        }
+    }
+    // Bytes left over mean the upload is missing a tail
+    if aligned && src.read(&mut [0u8; 1]).await? > 0 {
+        report.healthy = false;
+        if let Some(last) = report.parts.last_mut() {
+            last.ok = false;
+            last.error = Some("ngắn hơn file nguồn".to_string());
+            if report.degraded.last() != Some(&last.part) { report.degraded.push(last.part); }
+        }
+    }
+    if report.healthy {
+        info!("🩺 {} matches its source: {} copies checked", record.filename, report.parts.len());
+    } else {
+        warn!("🩺 {} does not match its source: parts {:?}", record.filename, report.degraded);
+    }
+    Ok(report)
+}
+
+/// React on the Discord copies of one part. Returns how many were marked.
+/// `flagged` holds messages already marked degraded in this run: a bundle message
+/// stays ⚠️ if any of its parts is bad.
+async fn mark(st: &AppState, copies: &[PartInfo], healthy: bool, flagged: &mut HashSet<i64>) -> usize {
+    let mut marked = 0;
+    if st.cfg.simulate { return marked; }
+    for p in copies.iter().filter(|p| p.platform != "telegram") {
+        if healthy && flagged.contains(&p.message_id) { continue; }
+        if !healthy { flagged.insert(p.message_id); }
+        let Some(ch) = p.channel_id.as_deref().and_then(|c| c.parse::<u64>().ok()) else { continue };
+        match discord_bot::set_status_reaction(&st.http, ch, p.message_id as u64, healthy).await {
+            Ok(())  => marked += 1,
+            // A deleted message can't carry a mark; the report already says why
+            Err(e)  => warn!("  ⚠️ Could not mark part {}: {e}", p.part),
+        }
+    }
+    marked
+}
+
+/// Download every part, hash the reassembled file and compare it with the BLAKE3
+/// recorded at upload. Parts carrying their own hash are checked one by one, so a
+/// bad part is named even when a mirror copy still makes the file whole; legacy
+/// parts without one can only fail the whole-file comparison.
+pub async fn verify_hash(st: &AppState, record: &FileRecord) -> Result<HashReport, KeyError> {
+    let cfg = keyring::config_for(st, record.wrapped_key.as_deref())?;
+    let tg_client = reqwest::Client::new();
+    let mut hasher = Some(blake3::Hasher::new());
+    let mut report = HashReport {
+        file_id: record.id, healthy: true,
+        expected_hash: record.blake3.clone(), actual_hash: None, hash_matches: None,
+        expected_bytes: record.exact_size().unwrap_or(0), actual_bytes: 0,
+        corrupt: vec![], missing: vec![], parts: vec![],
+    };
+    let mut next = 1u32;
+    for copies in download::part_copies(record) {
+        let part = copies[0].part;
+        // Numbers skipped in the record: the part was never stored
+        for gap in next..part { unrecorded(&mut report, gap); }
+        next = part + 1;
+        let mut entry = PartHash { part, status: "missing", platform: None, errors: vec![] };
+        let mut good: Option<Vec<u8>> = None;
+        for p in &copies {
+            match download::fetch_part(p, &st.http, &cfg, &tg_client, &st.tg_token).await {
+                Err(e) => entry.errors.push(format!("{}: {e}", p.platform)),
+                Ok(data) => match p.blake3.as_deref() {
+                    Some(want) if content_hash(&data) != want => {
+                        entry.status = "corrupt";
+                        entry.errors.push(format!("{}: hash không khớp", p.platform));
+                    }
+                    _ => {
+                        entry.platform = Some(p.platform.clone());
+                        good = Some(data);
+                        break;
+                    }
+                },
+            }
+        }
+        match good {
+            Some(data) => {
+                entry.status = "ok";
+                report.actual_bytes += data.len() as u64;
+                if let Some(h) = hasher.as_mut() { h.update(&data); }
+            }
+            None => {
+                if entry.status == "corrupt" { report.corrupt.push(part) } else { report.missing.push(part) }
+                hasher = None;
+            }
+        }
+        report.parts.push(entry);
+    }
+    for gap in next..=record.parts { unrecorded(&mut report, gap); }
+    if !report.missing.is_empty() { hasher = None; }
+    report.actual_hash = hasher.map(|h| h.finalize().to_hex().to_string());
+    if let (Some(want), Some(got)) = (&report.expected_hash, &report.actual_hash) {
+        report.hash_matches = Some(want == got);
+    }
+    let size_ok = report.expected_bytes == 0 || !report.missing.is_empty() || report.actual_bytes == report.expected_bytes;
+    report.healthy = report.corrupt.is_empty() && report.missing.is_empty()
+        && report.hash_matches != Some(false) && size_ok;
+    if report.healthy {
+        info!("🩺 {} hash verified: {} part(s), {} bytes", record.filename, report.parts.len(), report.actual_bytes);
+    } else {
+        warn!("🩺 {} failed hash verification: corrupt {:?}, missing {:?}, hash match {:?}",
+            record.filename, report.corrupt, report.missing, report.hash_matches);
+    }
+    Ok(report)
+}
+
+fn unrecorded(report: &mut HashReport, part: u32) {
+    report.missing.push(part);
+    report.parts.push(PartHash {
+        part, status: "missing", platform: None, errors: vec!["không có trong bản ghi".to_string()],
+    });
+}
@@ -1,10 +1,11 @@
 /// zip_utils.rs — ZIP pack/unpack helpers.
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use std::io::{Cursor, Read, Write};
-use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+use zip::{write::FileOptions, AesMode, CompressionMethod, ZipArchive, ZipWriter};
 
 /// Pack `data` into a ZIP archive containing a single entry named `entry_name`.
-pub fn zip_bytes(data: &[u8], entry_name: &str, compress_level: u32) -> Result<Vec<u8>> {
+/// With a `password` the entry is AES-256 encrypted.
+pub fn zip_bytes(data: &[u8], entry_name: &str, compress_level: u32, password: Option<&str>) -> Result<Vec<u8>> {
     let buf = Vec::with_capacity(data.len() + 512);
     let cursor = Cursor::new(buf);
     let mut zip = ZipWriter::new(cursor);
@@ -18,6 +19,10 @@ pub fn zip_bytes(data: &[u8], entry_name: &str, compress_level: u32) -> Result<V
     let opts: FileOptions<()> = FileOptions::default()
         .compression_method(method)
         .compression_level(if compress_level == 0 { None } else { Some(compress_level as i64) });
+    let opts = match password {
+        Some(pw) => opts.with_aes_encryption(AesMode::Aes256, pw),
+        None     => opts,
+    };
 
     zip.start_file(entry_name, opts)?;
     zip.write_all(data)?;
@@ -27,14 +32,22 @@ pub fn zip_bytes(data: &[u8], entry_name: &str, compress_level: u32) -> Result<V
 
 /// Unpack a ZIP archive and return the first entry's bytes.
 /// If `data` is not a ZIP, returns it unchanged (backward compat).
-pub fn unzip_or_raw(data: Vec<u8>) -> Result<Vec<u8>> {
+/// Encrypted entries are opened with `password`; plain entries ignore it, so
+/// parts uploaded before a password was configured still download.
+pub fn unzip_or_raw(data: Vec<u8>, password: Option<&str>) -> Result<Vec<u8>> {
     // PK magic
     if data.len() < 4 || &data[..4] != b"PK\x03\x04" {
         return Ok(data);
     }
     let cursor = Cursor::new(&data);
     let mut archive = ZipArchive::new(cursor).context("open zip")?;
-    let mut entry = archive.by_index(0).context("read zip entry")?;
+    let encrypted = archive.by_index_raw(0).context("read zip entry")?.encrypted();
+    let mut entry = if encrypted {
+        let pw = password.ok_or_else(|| anyhow!("Part is password-protected but upload.zip_password is not set"))?;
+        archive.by_index_decrypt(0, pw.as_bytes()).context("decrypt zip entry (wrong zip_password?)")?
+    } else {
+        archive.by_index(0).context("read zip entry")?
+    };
     let mut out = Vec::with_capacity(entry.size() as usize);
     entry.read_to_end(&mut out).context("read zip entry data")?;
     Ok(out)